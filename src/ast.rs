@@ -1,14 +1,105 @@
 use crate::lexer::Token;
 use std::boxed::Box;
 use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Void,
     Int,
     Float,
     Bool,
-    StringType,
+    String,
+    // [type; length]
+    Array(Box<Type>, usize),
+    // []type
+    Slice(Box<Type>),
+    // Struct or enum declared by the user, referred to by its name
+    UserDefined(String),
+    // &type or &mut type
+    Reference(Box<Type>, bool),
+    // ?type, either a value of the type or none
+    Optional(Box<Type>),
+    // type!error, either a value of the first type or an error of the second
+    Result(Box<Type>, Box<Type>),
+    // Type parameter of a generic function, e.g. `T` in `fn max<T>(T a, T b): T`
+    Generic(String),
+    // fn(type, type): type, a function or a closure
+    Function(Vec<Type>, Box<Type>),
+}
+
+impl Type {
+    // Field access and method calls see through one level of reference
+    pub fn dereferenced(&self) -> &Type {
+        match self {
+            Type::Reference(referenced_type, _) => referenced_type,
+            t => t,
+        }
+    }
+
+    // Spells the type with characters that are valid in C names:
+    // `?[]&mut Point` is `option_slice_refmut_5Point`. Names of user types are
+    // prefixed with their length, so no name spells another type: a struct
+    // `slice_int` is `9slice_int`, unlike `[]int`
+    pub fn mangled(&self) -> String {
+        match self {
+            Type::Void => "void".to_string(),
+            Type::Int => "int".to_string(),
+            Type::Float => "float".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::String => "string".to_string(),
+            Type::UserDefined(name) | Type::Generic(name) => format!("{}{}", name.len(), name),
+            Type::Array(element_type, length) => {
+                format!("array{}_{}", length, element_type.mangled())
+            }
+            Type::Slice(element_type) => format!("slice_{}", element_type.mangled()),
+            Type::Reference(referenced_type, false) => format!("ref_{}", referenced_type.mangled()),
+            Type::Reference(referenced_type, true) => {
+                format!("refmut_{}", referenced_type.mangled())
+            }
+            Type::Optional(value_type) => format!("option_{}", value_type.mangled()),
+            Type::Result(value_type, error_type) => {
+                format!("result_{}_{}", value_type.mangled(), error_type.mangled())
+            }
+            // fn(int, int): bool is `fn_int_int_to_bool`
+            Type::Function(parameter_types, return_type) => {
+                let mut parts = vec!["fn".to_string()];
+                parts.extend(parameter_types.iter().map(Type::mangled));
+                parts.push("to".to_string());
+                parts.push(return_type.mangled());
+                parts.join("_")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Array(element_type, length) => write!(f, "[{}; {}]", element_type, length),
+            Type::Slice(element_type) => write!(f, "[]{}", element_type),
+            Type::UserDefined(name) => write!(f, "{}", name),
+            Type::Reference(referenced_type, true) => write!(f, "&mut {}", referenced_type),
+            Type::Reference(referenced_type, false) => write!(f, "&{}", referenced_type),
+            Type::Optional(value_type) => write!(f, "?{}", value_type),
+            Type::Result(value_type, error_type) => write!(f, "{}!{}", value_type, error_type),
+            Type::Generic(name) => write!(f, "{}", name),
+            Type::Function(parameter_types, return_type) => {
+                let parameter_types: Vec<String> =
+                    parameter_types.iter().map(Type::to_string).collect();
+                write!(f, "fn({})", parameter_types.join(", "))?;
+                match **return_type {
+                    Type::Void => Ok(()),
+                    ref return_type => write!(f, ": {}", return_type),
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -16,15 +107,225 @@ pub enum Expression {
     Float(f64),
     Int(i32),
     StringLit(String),
-    Char(char),
     Boolean(bool),
+    // none, the absent value of any optional type
+    NoneLit,
+    Identifier(String),
     // FIXME: Performance issues with Box
     BinaryExpr(Box<Expression>, Token, Box<Expression>),
+    // expression as type
+    Cast(Box<Expression>, Type),
+    // [expression, expression, ...]
+    Array(Vec<Expression>),
+    // array[index]
+    Index(Box<Expression>, Box<Expression>),
+    // name(argument, argument, ...)
+    FunctionCall(String, Vec<Expression>),
+    // Name { field: expression, field: expression, ... }
+    StructLiteral(String, Vec<(String, Expression)>),
+    // expression.field
+    FieldAccess(Box<Expression>, String),
+    // expression.method(argument, argument, ...)
+    MethodCall(Box<Expression>, String, Vec<Expression>),
+    // Enum::Variant(expression, expression, ...)
+    EnumVariant(String, String, Vec<Expression>),
+    // match expression { pattern => expression, ... }
+    Match(Box<Expression>, Vec<MatchArm>),
+    // &expression or &mut expression
+    AddressOf(Box<Expression>, bool),
+    // *expression
+    Dereference(Box<Expression>),
+    // err(expression), the failure of any result with that error type
+    Err(Box<Expression>),
+    // expression?, the value of a result or an early return of its error
+    Try(Box<Expression>),
+    // name: expression, only as an argument of a call
+    NamedArgument(String, Box<Expression>),
+    // fn(type name, ...): type { ... }
+    Closure(Closure),
+    // Call the type checker resolved to a function, overload, instance or
+    // method, by its unique name, with an argument for each parameter
+    Call(String, Vec<Expression>),
+    // Function the type checker resolved a name used as a value to
+    Function(String),
+}
+
+impl Expression {
+    fn fmt_operand(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::BinaryExpr(..)
+            | Expression::Cast(..)
+            | Expression::AddressOf(..)
+            | Expression::Dereference(..) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+// Prints an expression back as idk source code, so diagnostics can quote it
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Float(value) => write!(f, "{:?}", value),
+            Expression::Int(value) => write!(f, "{}", value),
+            Expression::StringLit(value) => write!(f, "\"{}\"", value),
+            Expression::Boolean(value) => write!(f, "{}", value),
+            Expression::NoneLit => write!(f, "none"),
+            Expression::Identifier(name) | Expression::Function(name) => write!(f, "{}", name),
+            Expression::BinaryExpr(lhs, operator, rhs) => {
+                lhs.fmt_operand(f)?;
+                write!(f, " {} ", operator)?;
+                rhs.fmt_operand(f)
+            }
+            Expression::Cast(expression, target_type) => {
+                expression.fmt_operand(f)?;
+                write!(f, " as {}", target_type)
+            }
+            Expression::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Expression::FunctionCall(name, arguments) | Expression::Call(name, arguments) => {
+                let arguments: Vec<String> = arguments
+                    .iter()
+                    .map(|argument| argument.to_string())
+                    .collect();
+                write!(f, "{}({})", name, arguments.join(", "))
+            }
+            Expression::StructLiteral(name, fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, value)| format!("{}: {}", field, value))
+                    .collect();
+                write!(f, "{} {{ {} }}", name, fields.join(", "))
+            }
+            Expression::FieldAccess(expression, field) => {
+                expression.fmt_operand(f)?;
+                write!(f, ".{}", field)
+            }
+            Expression::MethodCall(receiver, method, arguments) => {
+                receiver.fmt_operand(f)?;
+                let arguments: Vec<String> = arguments
+                    .iter()
+                    .map(|argument| argument.to_string())
+                    .collect();
+                write!(f, ".{}({})", method, arguments.join(", "))
+            }
+            Expression::Index(array, index) => {
+                array.fmt_operand(f)?;
+                write!(f, "[{}]", index)
+            }
+            Expression::EnumVariant(name, variant, arguments) => {
+                write!(f, "{}::{}", name, variant)?;
+                if !arguments.is_empty() {
+                    let arguments: Vec<String> = arguments
+                        .iter()
+                        .map(|argument| argument.to_string())
+                        .collect();
+                    write!(f, "({})", arguments.join(", "))?;
+                }
+                Ok(())
+            }
+            Expression::AddressOf(expression, true) => {
+                write!(f, "&mut ")?;
+                expression.fmt_operand(f)
+            }
+            Expression::AddressOf(expression, false) => {
+                write!(f, "&")?;
+                expression.fmt_operand(f)
+            }
+            Expression::Dereference(expression) => {
+                write!(f, "*")?;
+                expression.fmt_operand(f)
+            }
+            Expression::Err(expression) => write!(f, "err({})", expression),
+            Expression::NamedArgument(name, expression) => write!(f, "{}: {}", name, expression),
+            Expression::Closure(closure) => {
+                let parameters: Vec<String> = closure
+                    .parameters
+                    .iter()
+                    .map(|parameter| format!("{} {}", parameter.parameter_type, parameter.name))
+                    .collect();
+                write!(f, "fn({})", parameters.join(", "))?;
+                if let Some(return_type) = &closure.return_type {
+                    write!(f, ": {}", return_type)?;
+                }
+                write!(f, " {{ ... }}")
+            }
+            Expression::Try(expression) => {
+                expression.fmt_operand(f)?;
+                write!(f, "?")
+            }
+            Expression::Match(subject, arms) => {
+                let arms: Vec<String> = arms
+                    .iter()
+                    .map(|arm| format!("{} => {}", arm.pattern, arm.value))
+                    .collect();
+                write!(f, "match {} {{ {} }}", subject, arms.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    // Enum::Variant(binding, binding, ...)
+    Variant(String, String, Vec<String>),
+    // _
+    Wildcard,
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Variant(name, variant, bindings) if bindings.is_empty() => {
+                write!(f, "{}::{}", name, variant)
+            }
+            Pattern::Variant(name, variant, bindings) => {
+                write!(f, "{}::{}({})", name, variant, bindings.join(", "))
+            }
+            Pattern::Wildcard => write!(f, "_"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<Type>,
+    pub body: Block,
+    // Variables of the enclosing functions the body uses, found by the type
+    // checker. They are copied into the closure when it is created
+    pub captures: Vec<(String, Type)>,
+}
+
+impl Closure {
+    pub fn new(parameters: Vec<Parameter>, return_type: Option<Type>, body: Block) -> Self {
+        Self {
+            parameters,
+            return_type,
+            body,
+            captures: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub value: Expression,
+}
+
+impl MatchArm {
+    pub fn new(pattern: Pattern, value: Expression) -> Self {
+        Self { pattern, value }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Assignment {
-    pub var_type: Type,
+    // None on `let x = ...` until the type checker infers it
+    pub var_type: Option<Type>,
     pub name: String,
     pub value: Expression,
 }
@@ -32,18 +333,82 @@ pub struct Assignment {
 impl Assignment {
     pub fn new(var_type: Type, name: String, value: Expression) -> Self {
         Self {
-            var_type,
+            var_type: Some(var_type),
+            name,
+            value,
+        }
+    }
+
+    pub fn inferred(name: String, value: Expression) -> Self {
+        Self {
+            var_type: None,
             name,
             value,
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reassignment {
+    pub target: Expression,
+    pub value: Expression,
+}
+
+impl Reassignment {
+    pub fn new(target: Expression, value: Expression) -> Self {
+        Self { target, value }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct For {
+    pub variable: String,
+    pub iterable: Expression,
+    pub body: Block,
+}
+
+impl For {
+    pub fn new(variable: String, iterable: Expression, body: Block) -> Self {
+        Self {
+            variable,
+            iterable,
+            body,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    // if expression { ... }
+    Expression(Expression),
+    // if let name = optional { ... }, where `name` holds the value when present
+    Let(String, Expression),
+}
+
+// `elif` is an else branch holding another If
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    pub condition: Condition,
+    pub body: Block,
+    pub else_body: Option<Block>,
+}
+
+impl If {
+    pub fn new(condition: Condition, body: Block, else_body: Option<Block>) -> Self {
+        Self {
+            condition,
+            body,
+            else_body,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Parameter {
     pub parameter_type: Type,
     pub name: String,
-    // TODO: add optional default value for a parameter
+    // Constant passed when the argument is left out
+    pub default: Option<Expression>,
 }
 
 impl Parameter {
@@ -51,8 +416,149 @@ impl Parameter {
         Self {
             parameter_type,
             name,
+            default: None,
+        }
+    }
+}
+
+// Methods become plain functions that take the receiver first: `Point_len(Point self)`
+pub fn mangle_method(type_name: &str, method: &str) -> String {
+    format!("{}_{}", type_name, method)
+}
+
+// Matches the arguments of a call with the parameters they are passed to.
+// Positional arguments come first, then named ones in any order. Returns the
+// position of the argument of each parameter, or None if it's left out
+pub fn bind_arguments(
+    name: &str,
+    parameters: &[Parameter],
+    arguments: &[Expression],
+) -> Result<Vec<Option<usize>>, String> {
+    let arity_error = || {
+        format!(
+            "Function '{}' takes {} arguments but {} were given",
+            name,
+            parameters.len(),
+            arguments.len()
+        )
+    };
+
+    let mut bound: Vec<Option<usize>> = vec![None; parameters.len()];
+    let mut named = false;
+    for (position, argument) in arguments.iter().enumerate() {
+        let index = match argument {
+            Expression::NamedArgument(parameter, _) => {
+                named = true;
+                parameters
+                    .iter()
+                    .position(|candidate| candidate.name == *parameter)
+                    .ok_or(format!(
+                        "Function '{}' has no parameter named '{}'",
+                        name, parameter
+                    ))?
+            }
+            _ if named => {
+                return Err(format!(
+                    "Positional argument `{}` follows a named argument in call to '{}'",
+                    argument, name
+                ))
+            }
+            _ if position >= parameters.len() => return Err(arity_error()),
+            _ => position,
+        };
+        if bound[index].is_some() {
+            return Err(format!(
+                "Parameter '{}' of function '{}' is given more than once",
+                parameters[index].name, name
+            ));
+        }
+        bound[index] = Some(position);
+    }
+
+    for (parameter, argument) in parameters.iter().zip(bound.iter()) {
+        if argument.is_none() && parameter.default.is_none() {
+            if !named
+                && parameters
+                    .iter()
+                    .all(|parameter| parameter.default.is_none())
+            {
+                return Err(arity_error());
+            }
+            return Err(format!(
+                "Missing argument for parameter '{}' of function '{}'",
+                parameter.name, name
+            ));
         }
     }
+    Ok(bound)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub field_type: Type,
+    pub name: String,
+}
+
+impl Field {
+    pub fn new(field_type: Type, name: String) -> Self {
+        Self { field_type, name }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<Field>,
+    // Declared with `pub`, visible to the modules that import this one
+    pub public: bool,
+}
+
+impl Struct {
+    pub fn new(name: String, fields: Vec<Field>) -> Self {
+        Self {
+            name,
+            fields,
+            public: false,
+        }
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+// Variant of an enum with the types of the values it carries: `Rect(float, float)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Type>,
+}
+
+impl Variant {
+    pub fn new(name: String, fields: Vec<Type>) -> Self {
+        Self { name, fields }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<Variant>,
+    pub public: bool,
+}
+
+impl Enum {
+    pub fn new(name: String, variants: Vec<Variant>) -> Self {
+        Self {
+            name,
+            variants,
+            public: false,
+        }
+    }
+
+    pub fn get_variant(&self, name: &str) -> Option<&Variant> {
+        self.variants.iter().find(|variant| variant.name == name)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -70,13 +576,30 @@ impl Block {
     }
 }
 
-// TODO: Implement return instruction on function body
+// T or T: Trait + Trait, where the type argument must implement every trait
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeParameter {
+    pub name: String,
+    pub bounds: Vec<String>,
+}
+
+impl TypeParameter {
+    pub fn new(name: String, bounds: Vec<String>) -> Self {
+        Self { name, bounds }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub name: String,
+    // Type parameters of a generic function, empty otherwise
+    pub type_parameters: Vec<TypeParameter>,
     pub parameters: Vec<Parameter>,
     pub body: Block,
     pub return_type: Option<Type>,
+    pub public: bool,
+    // Declared with `export`, callable from C by its name
+    pub exported: bool,
 }
 
 impl Function {
@@ -88,13 +611,114 @@ impl Function {
     ) -> Self {
         Self {
             name,
+            type_parameters: vec![],
             parameters,
             body,
             return_type,
+            public: false,
+            exported: false,
+        }
+    }
+}
+
+// Signature of a function declared without a body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prototype {
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<Type>,
+}
+
+impl Prototype {
+    pub fn new(name: String, parameters: Vec<Parameter>, return_type: Option<Type>) -> Self {
+        Self {
+            name,
+            parameters,
+            return_type,
         }
     }
 }
 
+// C function declared in idk. The C name defaults to the idk one, a
+// different C name lets several C functions share an idk name as overloads
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    pub prototype: Prototype,
+    pub c_name: String,
+}
+
+impl Extern {
+    pub fn new(prototype: Prototype) -> Self {
+        Self {
+            c_name: prototype.name.clone(),
+            prototype,
+        }
+    }
+
+    // Externs with their own C name can be overloaded
+    pub fn is_renamed(&self) -> bool {
+        self.c_name != self.prototype.name
+    }
+}
+
+// Methods a type must have to implement the trait. Inside the trait, `self`
+// and `Self` stand for the implementing type
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trait {
+    pub name: String,
+    pub methods: Vec<Prototype>,
+    pub public: bool,
+}
+
+impl Trait {
+    pub fn new(name: String, methods: Vec<Prototype>) -> Self {
+        Self {
+            name,
+            methods,
+            public: false,
+        }
+    }
+
+    pub fn get_method(&self, name: &str) -> Option<&Prototype> {
+        self.methods.iter().find(|method| method.name == name)
+    }
+}
+
+// Methods of a struct or an enum. Each method takes the value it is called
+// on as its first parameter, `self`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Impl {
+    pub type_name: String,
+    // impl Trait for Type
+    pub trait_name: Option<String>,
+    pub methods: Vec<Function>,
+}
+
+impl Impl {
+    pub fn new(type_name: String, methods: Vec<Function>) -> Self {
+        Self {
+            type_name,
+            trait_name: None,
+            methods,
+        }
+    }
+}
+
+// `import module;` or `use module::item;`, resolved relative to the file
+// that contains it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub module: String,
+    // The item brought into scope by `use`
+    pub item: Option<String>,
+}
+
+impl Import {
+    pub fn new(module: String, item: Option<String>) -> Self {
+        Self { module, item }
+    }
+}
+
 // All possible nodes for an AST
 //
 // 1. Assignment
@@ -104,8 +728,72 @@ impl Function {
 //    fn sum(int a, int b): int {
 //        return a + b;
 //    }
+//
+// 3. Return
+//    return a + b;
+//
+// 4. Reassignment
+//    numbers[0] = 12;
+//
+// 5. Expression (only function calls are allowed as statements)
+//    push(numbers, 12);
+//
+// 6. For
+//    for number in numbers {
+//        sum = sum + number;
+//    }
+//
+// 7. Struct
+//    struct Point {
+//        float x;
+//        float y;
+//    }
+//
+// 8. Enum
+//    enum Shape {
+//        Circle(float),
+//        Rect(float, float),
+//    }
+//
+// 9. Impl
+//    impl Point {
+//        fn len(self): float {
+//            return sqrt(self.x * self.x + self.y * self.y);
+//        }
+//    }
+//
+// 10. If
+//    if let value = maybe {
+//        return value;
+//    } else {
+//        return 0;
+//    }
+//
+// 11. Trait
+//    trait Shape {
+//        fn area(self): float;
+//    }
+//
+// 12. Import
+//    import math;
+//    use math::sqrt;
+//
+// 13. Extern, a C function called from idk
+//    extern "C" fn puts(string s): int;
+//    extern "C" fn print(string text) as "idk_print";
 #[derive(Debug, Clone, PartialEq)]
 pub enum Ast {
     Assignment(Assignment),
     Function(Function),
+    Return(Option<Expression>),
+    Reassignment(Reassignment),
+    Expression(Expression),
+    For(For),
+    Struct(Struct),
+    Enum(Enum),
+    Impl(Impl),
+    If(If),
+    Trait(Trait),
+    Import(Import),
+    Extern(Extern),
 }
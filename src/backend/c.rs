@@ -1,107 +1,1613 @@
-use crate::ast::*;
-use crate::backend::{evaluate_ast, CodeGenerator};
+use crate::ast::{Enum, Struct, Type};
+use crate::backend::{evaluate, CodeGenerator};
+use crate::hir::*;
+use crate::lexer::Token;
+use crate::opt::{fold::fold, visit_expression};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::env;
 use std::fs::File;
 use std::io::Write;
+use std::process::Command;
+
+// Aborts the program when an index is out of bounds. Only emitted when
+// runtime bounds checks are enabled
+const BOUNDS_CHECK_FUNCTION: &str = "static int idk_check_bounds(int index, int length) {
+\tif (index < 0 || index >= length) {
+\t\tfprintf(stderr, \"Index out of bounds: the length is %d but the index is %d\\n\", length, index);
+\t\texit(1);
+\t}
+\treturn index;
+}
+
+";
+
+// Shared libraries are built with hidden visibility, so only the functions
+// marked with this are exported
+const VISIBLE: &str = "__attribute__((visibility(\"default\"))) ";
+
+// Runtime support written next to `code.c` whenever the program needs it
+const RUNTIME_HEADER: &str = include_str!("runtime/idk_runtime.h");
+const RUNTIME_SOURCE: &str = include_str!("runtime/idk_runtime.c");
+
+// Operators C doesn't have, which the runtime computes
+fn is_runtime_operator(operator: &Token, lhs: &Expression) -> bool {
+    *operator == Token::Power || *operator == Token::Mod && lhs.expression_type == Type::Float
+}
+
+fn compiler() -> String {
+    env::var("CC").unwrap_or("cc".to_string())
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let status = command
+        .status()
+        .map_err(|err| format!("Unable to run {}: {}", program, err))?;
+    if !status.success() {
+        return Err(format!("{} couldn't build the generated code", program));
+    }
+    Ok(())
+}
 
 pub struct C {
-    source_code: File,
+    bounds_checks: bool,
+    // Built into a library, which only exports the functions declared with
+    // `export`
+    library: bool,
+    // Declarations of the program, and of the temporaries generated for it
+    declarations: Vec<Declaration>,
+    // Parameters of each function and extern, for the wrappers that call them
+    parameters: HashMap<DeclId, Vec<DeclId>>,
+    structs: HashMap<String, Struct>,
+    enums: HashMap<String, Enum>,
+    depth: usize,
+    // Counter for the variables matches are lowered to
+    temporaries: usize,
+    uses_runtime: Cell<bool>,
+    // Optional and result types used by the program, each one gets its own struct
+    wrappers: RefCell<Vec<Type>>,
+    // Return type of the function being generated, which `?` returns errors as
+    return_type: Type,
+    // Functions generated for closures and for functions used as values:
+    // name, signature and body. They go after the rest of the code
+    closures: RefCell<Vec<(String, String, String)>>,
+    // Structs closures keep their captured variables in
+    environments: Vec<(String, Vec<DeclId>)>,
+    // Modules of the program, the root last. With more than one, each gets
+    // its own C file and header
+    modules: Vec<String>,
+    // Modules each module imports, directly or not, whose headers its C
+    // file includes
+    imports: Vec<Vec<String>>,
+    // Headers of the C functions the program declares with `extern`
+    includes: Vec<String>,
+    // C files written, to be compiled together
+    sources: Vec<String>,
+    // Prototypes of the exported functions, for the header C programs include
+    exports: String,
+}
+
+// Code generated for a module
+#[derive(Default)]
+struct Unit {
+    // Prototypes of the functions other modules can call
+    header: String,
+    // Prototypes of the functions only the module can call
+    prototypes: String,
+    code: String,
+    closures: Vec<(String, String, String)>,
+    definitions: String,
 }
 
 impl CodeGenerator for C {
-    fn generate(&mut self, ast: Vec<Ast>) -> Result<(), String> {
-        self.generate_c_code(ast)
+    fn generate(&mut self, program: Program) -> Result<(), String> {
+        self.generate_c_code(program)
     }
 }
 
 impl C {
-    pub fn new() -> Self {
+    pub fn new(
+        bounds_checks: bool,
+        library: bool,
+        modules: Vec<String>,
+        imports: Vec<Vec<String>>,
+        includes: Vec<String>,
+    ) -> Self {
         Self {
-            // FIXME: Avoid unwrap
-            source_code: File::create("code.c").unwrap(),
+            bounds_checks,
+            library,
+            declarations: vec![],
+            parameters: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            depth: 0,
+            temporaries: 0,
+            uses_runtime: Cell::new(false),
+            wrappers: RefCell::new(vec![]),
+            return_type: Type::Void,
+            closures: RefCell::new(vec![]),
+            environments: vec![],
+            modules,
+            imports,
+            includes,
+            sources: vec![],
+            exports: String::new(),
         }
     }
 
-    fn setup_code(&mut self) {
-        let libraries = vec!["stdio.h", "stdlib.h"];
-        for library in libraries.iter() {
-            self.source_code
-                .write_all(format!("#include \"{}\"\n", library).as_bytes());
+    fn name(&self, id: DeclId) -> &str {
+        &self.declarations[id].name
+    }
+
+    fn declaration_type(&self, id: DeclId) -> &Type {
+        &self.declarations[id].declaration_type
+    }
+
+    // C functions of the runtime declared with `extern`, like the ones behind
+    // the prelude. Its header declares them
+    fn is_runtime_function(&self, id: DeclId) -> bool {
+        self.declarations[id].kind == DeclarationKind::Extern && self.name(id).starts_with("idk_")
+    }
+
+    // Variables the generated code needs that the program doesn't declare
+    fn declare(&mut self, name: String, variable_type: Type) -> DeclId {
+        self.declarations.push(Declaration {
+            name,
+            kind: DeclarationKind::Local,
+            declaration_type: variable_type,
+        });
+        self.declarations.len() - 1
+    }
+
+    fn get_slice_element_type(&self, slice: &Expression) -> Result<Type, String> {
+        match &slice.expression_type {
+            Type::Slice(element_type) => Ok(*element_type.clone()),
+            t => Err(format!("Expected a slice, found {}", t)),
         }
-        self.source_code.write_all("\n".as_bytes());
     }
 
-    fn get_c_type(&self, type_: &Type) -> Result<&str, String> {
+    fn write(&self, path: &str, code: &str) -> Result<(), String> {
+        File::create(path)
+            .and_then(|mut file| file.write_all(code.as_bytes()))
+            .map_err(|err| format!("Unable to write {}: {}", path, err))
+    }
+
+    fn setup_code(&self) -> String {
+        let mut code = String::new();
+
+        let libraries = ["stdio.h", "stdlib.h", "stdbool.h"];
+        for library in libraries
+            .iter()
+            .copied()
+            .chain(self.includes.iter().map(String::as_str))
+        {
+            code += &format!("#include \"{}\"\n", library);
+        }
+        if self.uses_runtime.get() {
+            code += "#include \"idk_runtime.h\"\n";
+        }
+        code += "\n";
+
+        if self.bounds_checks {
+            code += BOUNDS_CHECK_FUNCTION;
+        }
+        code
+    }
+
+    fn get_c_type(&self, type_: &Type) -> Result<String, String> {
         match type_ {
-            Type::Int => Ok("int"),
-            Type::Float => Ok("float"),
-            Type::Bool => Ok("bool"),
-            Type::StringType => Ok("char[]"),
+            Type::Int => Ok("int".to_string()),
+            // C functions taking floats, like the ones of libm, take doubles
+            Type::Float => Ok("double".to_string()),
+            Type::Bool => Ok("bool".to_string()),
+            Type::String => Ok("char*".to_string()),
+            // Copies of a slice share its items
+            Type::Slice(_) => {
+                self.uses_runtime.set(true);
+                Ok("idk_slice*".to_string())
+            }
+            Type::UserDefined(name) => Ok(name.clone()),
+            Type::Reference(referenced_type, _) => {
+                Ok(format!("{}*", self.get_c_type(referenced_type)?))
+            }
+            Type::Optional(_) | Type::Result(..) | Type::Function(..) => {
+                let mut wrappers = self.wrappers.borrow_mut();
+                if !wrappers.contains(type_) {
+                    wrappers.push(type_.clone());
+                }
+                Ok(format!("idk_{}", type_.mangled()))
+            }
             _ => Err("Can't get parameter type".to_string()),
         }
     }
 
-    fn get_function_parameters(&self, parameters: &Vec<Parameter>) -> Result<String, String> {
+    fn get_c_return_type(&self, return_type: &Type) -> Result<String, String> {
+        match return_type {
+            Type::Void => Ok("void".to_string()),
+            t => self.get_c_type(t),
+        }
+    }
+
+    // C declarations put the array length after the name: `int name[3][2]`
+    fn build_c_declaration(&self, type_: &Type, name: &str) -> Result<String, String> {
+        match type_ {
+            Type::Array(element_type, length) => {
+                self.build_c_declaration(element_type, &format!("{}[{}]", name, length))
+            }
+            t => Ok(format!("{} {}", self.get_c_type(t)?, name)),
+        }
+    }
+
+    fn build_c_variable_declaration(&self, id: DeclId) -> Result<String, String> {
+        self.build_c_declaration(self.declaration_type(id), self.name(id))
+    }
+
+    fn get_c_operator(&self, operator: &Token) -> Result<&str, String> {
+        match operator {
+            Token::Plus => Ok("+"),
+            Token::Minus => Ok("-"),
+            Token::Times => Ok("*"),
+            Token::Divides => Ok("/"),
+            Token::Mod => Ok("%"),
+            _ => Err(format!("Operator not supported in C: {:?}", operator)),
+        }
+    }
+
+    // Arrays are passed as pointers to their first element, like any C array
+    fn get_function_parameters(&self, parameters: &[DeclId]) -> Result<String, String> {
         let mut c_parameters = String::new();
 
         for parameter in parameters {
-            let parameter_type = self.get_c_type(&parameter.parameter_type)?;
-            c_parameters += &format!("{} {}, ", parameter_type, parameter.name);
+            c_parameters += &format!("{}, ", self.build_c_variable_declaration(*parameter)?);
         }
         c_parameters = c_parameters.trim_end_matches(", ").to_string();
         Ok(c_parameters)
     }
 
-    fn build_c_block(&self, block: Block) -> Result<String, String> {
-        let mut statements = String::new();
+    // Function values carry an environment for the variables closures capture,
+    // so functions used as values are called through a wrapper that takes it:
+    // static int idk_function_sum(void *idk_env, int a, int b) { return sum(a, b); }
+    fn build_c_function_value(&self, id: DeclId) -> Result<String, String> {
+        let name = self.name(id);
+        let function_type = self.declaration_type(id);
+        let wrapper = format!("idk_function_{}", name);
+        if self.is_runtime_function(id) {
+            self.uses_runtime.set(true);
+        }
 
-        for statement in block.itens.iter() {
-            match statement {
-                Ast::Assignment(assignment) => {
-                    statements += &self.build_c_assignment(assignment.clone())?
+        if !self
+            .closures
+            .borrow()
+            .iter()
+            .any(|(closure, _, _)| *closure == wrapper)
+        {
+            let parameters = &self.parameters[&id];
+            let mut c_parameters = vec!["void *idk_env".to_string()];
+            if !parameters.is_empty() {
+                c_parameters.push(self.get_function_parameters(parameters)?);
+            }
+            let arguments: Vec<&str> = parameters
+                .iter()
+                .map(|parameter| self.name(*parameter))
+                .collect();
+            let call = format!("{}({})", name, arguments.join(", "));
+            let (return_type, body) = match function_type {
+                Type::Function(_, return_type) if **return_type == Type::Void => {
+                    ("void".to_string(), format!("\t{};\n", call))
                 }
-                _ => return Err("Unable to generate C block".to_string()),
+                Type::Function(_, return_type) => (
+                    self.get_c_type(return_type)?,
+                    format!("\treturn {};\n", call),
+                ),
+                t => return Err(format!("Function '{}' has type {}", name, t)),
             };
+            let signature = format!(
+                "static {} {}({})",
+                return_type,
+                wrapper,
+                c_parameters.join(", ")
+            );
+            self.closures
+                .borrow_mut()
+                .push((wrapper.clone(), signature, body));
+        }
+        Ok(format!(
+            "({}){{NULL, {}}}",
+            self.get_c_type(function_type)?,
+            wrapper
+        ))
+    }
+
+    // static int idk_closure1(void *idk_env, int x) {
+    //     idk_closure1_env *idk_captures = idk_env;
+    //     int k = idk_captures->k;
+    //     ...
+    // }
+    fn build_c_closure(&mut self, name: &str, closure: Closure) -> Result<(), String> {
+        let c_return_type = self.get_c_return_type(&closure.return_type)?;
+        let mut c_parameters = vec!["void *idk_env".to_string()];
+        if !closure.parameters.is_empty() {
+            c_parameters.push(self.get_function_parameters(&closure.parameters)?);
+        }
+
+        let outer_return_type = std::mem::replace(&mut self.return_type, closure.return_type);
+        let outer_depth = std::mem::replace(&mut self.depth, 1);
+        let environment = format!("{}_env", name);
+        let mut body = String::new();
+        if !closure.captures.is_empty() {
+            body += &format!("\t{} *idk_captures = idk_env;\n", environment);
+        }
+        for capture in closure.captures.iter() {
+            body += &format!(
+                "\t{} = idk_captures->{};\n",
+                self.build_c_variable_declaration(*capture)?,
+                self.name(*capture)
+            );
+        }
+        let block = self.build_c_block(&closure.body);
+        self.depth = outer_depth;
+        self.return_type = outer_return_type;
+
+        let signature = format!(
+            "static {} {}({})",
+            c_return_type,
+            name,
+            c_parameters.join(", ")
+        );
+        self.closures
+            .borrow_mut()
+            .push((name.to_string(), signature, body + &block?));
+        if !closure.captures.is_empty() {
+            self.environments.push((environment, closure.captures));
+        }
+        Ok(())
+    }
+
+    // Nested binary expressions are always parenthesized, so the C code keeps
+    // the same evaluation order as the tree built by the parser
+    fn build_c_operand(&self, expression: &Expression) -> Result<String, String> {
+        let operand = self.build_c_expression(expression)?;
+        match expression.kind {
+            ExpressionKind::Binary(..) => Ok(format!("({})", operand)),
+            _ => Ok(operand),
+        }
+    }
+
+    fn build_c_arguments(&self, arguments: &[Expression]) -> Result<String, String> {
+        let mut c_arguments: Vec<String> = vec![];
+        for argument in arguments.iter() {
+            c_arguments.push(self.build_c_expression(argument)?);
+        }
+        Ok(c_arguments.join(", "))
+    }
+
+    fn build_c_slice(&self, items: &[Expression], slice_type: &Type) -> Result<String, String> {
+        let element_type = match slice_type {
+            Type::Slice(element_type) => self.get_c_type(element_type)?,
+            t => return Err(format!("Expected a slice, found {}", t)),
+        };
+
+        if items.is_empty() {
+            return Ok(format!("idk_slice_from(sizeof({}), 0, NULL)", element_type));
+        }
+        Ok(format!(
+            "idk_slice_from(sizeof({}), {}, ({}[]){{{}}})",
+            element_type,
+            items.len(),
+            element_type,
+            self.build_c_arguments(items)?
+        ))
+    }
+
+    fn build_c_builtin(
+        &self,
+        builtin: &Builtin,
+        arguments: &[Expression],
+    ) -> Result<String, String> {
+        match builtin {
+            Builtin::Len => match &arguments[0].expression_type {
+                Type::Array(_, length) => Ok(length.to_string()),
+                Type::String => {
+                    self.uses_runtime.set(true);
+                    Ok(format!(
+                        "idk_string_length({})",
+                        self.build_c_expression(&arguments[0])?
+                    ))
+                }
+                _ => Ok(format!("{}->length", self.build_c_operand(&arguments[0])?)),
+            },
+            Builtin::Push => {
+                let element_type = self.get_slice_element_type(&arguments[0])?;
+                Ok(format!(
+                    "idk_slice_push({}, ({}[]){{{}}})",
+                    self.build_c_expression(&arguments[0])?,
+                    self.get_c_type(&element_type)?,
+                    self.build_c_expression(&arguments[1])?
+                ))
+            }
+            Builtin::Pop => {
+                let element_type = self.get_slice_element_type(&arguments[0])?;
+                Ok(format!(
+                    "(*({}*)idk_slice_pop({}))",
+                    self.get_c_type(&element_type)?,
+                    self.build_c_expression(&arguments[0])?
+                ))
+            }
+        }
+    }
+
+    fn build_c_expression(&self, expression: &Expression) -> Result<String, String> {
+        match &expression.kind {
+            ExpressionKind::Int(value) => Ok(value.to_string()),
+            ExpressionKind::Float(value) => Ok(format!("{:?}", value)),
+            ExpressionKind::String(value) => Ok(format!("\"{}\"", value)),
+            ExpressionKind::Bool(value) => Ok(value.to_string()),
+            ExpressionKind::Name(id) => match self.declarations[*id].kind {
+                DeclarationKind::Function | DeclarationKind::Extern => {
+                    self.build_c_function_value(*id)
+                }
+                _ => Ok(self.name(*id).to_string()),
+            },
+            // C has no `**` and its `%` only takes ints, the runtime does the
+            // rest
+            ExpressionKind::Binary(lhs, operator, rhs) if is_runtime_operator(operator, lhs) => {
+                let is_float = lhs.expression_type == Type::Float;
+                let function = match (operator, is_float) {
+                    (Token::Power, false) => "idk_pow_int",
+                    (Token::Power, true) => "idk_pow",
+                    _ => "idk_mod_float",
+                };
+                self.uses_runtime.set(true);
+                Ok(format!(
+                    "{}({}, {})",
+                    function,
+                    self.build_c_expression(lhs)?,
+                    self.build_c_expression(rhs)?
+                ))
+            }
+            ExpressionKind::Binary(lhs, operator, rhs) => Ok(format!(
+                "{} {} {}",
+                self.build_c_operand(lhs)?,
+                self.get_c_operator(operator)?,
+                self.build_c_operand(rhs)?
+            )),
+            ExpressionKind::Slice(items) => self.build_c_slice(items, &expression.expression_type),
+            ExpressionKind::Wrap(_) | ExpressionKind::NoneLit | ExpressionKind::Err(_) => {
+                Ok(format!(
+                    "({}){}",
+                    self.get_c_type(&expression.expression_type)?,
+                    self.build_c_initializer(expression)?
+                ))
+            }
+            ExpressionKind::Cast(inner) => Ok(format!(
+                "({}){}",
+                self.get_c_type(&expression.expression_type)?,
+                self.build_c_operand(inner)?
+            )),
+            ExpressionKind::Array(items) => Ok(format!("{{{}}}", self.build_c_arguments(items)?)),
+            ExpressionKind::Index(array, index) => {
+                let c_index = self.build_c_expression(index)?;
+                match &array.expression_type {
+                    // Slices are always checked by the runtime
+                    Type::Slice(element_type) => Ok(format!(
+                        "(*({}*)idk_slice_at({}, {}))",
+                        self.get_c_type(element_type)?,
+                        self.build_c_expression(array)?,
+                        c_index
+                    )),
+                    // Constant indexes were already checked by the type checker
+                    Type::Array(_, length) if self.bounds_checks && evaluate(index).is_err() => {
+                        Ok(format!(
+                            "{}[idk_check_bounds({}, {})]",
+                            self.build_c_operand(array)?,
+                            c_index,
+                            length
+                        ))
+                    }
+                    _ => Ok(format!("{}[{}]", self.build_c_operand(array)?, c_index)),
+                }
+            }
+            ExpressionKind::Call(id, arguments) => {
+                if self.is_runtime_function(*id) {
+                    self.uses_runtime.set(true);
+                }
+                Ok(format!(
+                    "{}({})",
+                    self.name(*id),
+                    self.build_c_arguments(arguments)?
+                ))
+            }
+            // callee.call(callee.env, argument, ...)
+            ExpressionKind::CallValue(callee, arguments) => {
+                let callee = self.build_c_operand(callee)?;
+                let mut c_arguments = vec![format!("{}.env", callee)];
+                if !arguments.is_empty() {
+                    c_arguments.push(self.build_c_arguments(arguments)?);
+                }
+                Ok(format!("{}.call({})", callee, c_arguments.join(", ")))
+            }
+            ExpressionKind::Builtin(builtin, arguments) => self.build_c_builtin(builtin, arguments),
+            ExpressionKind::Struct(name, fields) => {
+                Ok(format!("({}){}", name, self.build_c_struct_fields(fields)?))
+            }
+            ExpressionKind::Field(base, field) => match base.expression_type {
+                Type::Reference(..) => Ok(format!("{}->{}", self.build_c_operand(base)?, field)),
+                _ => Ok(format!("{}.{}", self.build_c_operand(base)?, field)),
+            },
+            ExpressionKind::AddressOf(place) => Ok(format!("&{}", self.build_c_operand(place)?)),
+            ExpressionKind::Dereference(reference) => {
+                Ok(format!("(*{})", self.build_c_operand(reference)?))
+            }
+            ExpressionKind::Variant(name, variant, arguments) => Ok(format!(
+                "({}){}",
+                name,
+                self.build_c_variant(name, variant, arguments)?
+            )),
+            ExpressionKind::Match(..) => Err("Match should have been lowered before".to_string()),
+            ExpressionKind::Try(_) => Err("`?` should have been lowered before".to_string()),
+            ExpressionKind::Closure(_) => {
+                Err("Closure should have been lowered before".to_string())
+            }
+        }
+    }
+
+    // {.tag = Shape_Rect, .as.Rect = {1.0, 2.0}}
+    fn build_c_variant(
+        &self,
+        name: &str,
+        variant: &str,
+        arguments: &[Expression],
+    ) -> Result<String, String> {
+        let tag = format!(".tag = {}_{}", name, variant);
+        if arguments.is_empty() {
+            return Ok(format!("{{{}}}", tag));
+        }
+
+        let mut values: Vec<String> = vec![];
+        for argument in arguments.iter() {
+            values.push(self.build_c_initializer(argument)?);
+        }
+        Ok(format!(
+            "{{{}, .as.{} = {{{}}}}}",
+            tag,
+            variant,
+            values.join(", ")
+        ))
+    }
+
+    // {.x = 1.0, .y = 2.0}
+    fn build_c_struct_fields(&self, fields: &[(String, Expression)]) -> Result<String, String> {
+        let mut c_fields: Vec<String> = vec![];
+        for (name, value) in fields.iter() {
+            c_fields.push(format!(".{} = {}", name, self.build_c_initializer(value)?));
+        }
+        Ok(format!("{{{}}}", c_fields.join(", ")))
+    }
+
+    // Literals written directly in a declaration use plain initializer lists
+    // instead of compound literals, which C doesn't accept on globals
+    fn build_c_initializer(&self, expression: &Expression) -> Result<String, String> {
+        match &expression.kind {
+            ExpressionKind::Struct(_, fields) => self.build_c_struct_fields(fields),
+            ExpressionKind::Variant(name, variant, arguments) => {
+                self.build_c_variant(name, variant, arguments)
+            }
+            // {.present = true, .value = 1}
+            ExpressionKind::NoneLit => Ok("{.present = false}".to_string()),
+            ExpressionKind::Wrap(value) => match expression.expression_type {
+                Type::Optional(_) => Ok(format!(
+                    "{{.present = true, .value = {}}}",
+                    self.build_c_initializer(value)?
+                )),
+                _ => Ok(format!(
+                    "{{.ok = true, .value = {}}}",
+                    self.build_c_initializer(value)?
+                )),
+            },
+            // {.ok = false, .error = "empty"}
+            ExpressionKind::Err(error) => Ok(format!(
+                "{{.ok = false, .error = {}}}",
+                self.build_c_initializer(error)?
+            )),
+            ExpressionKind::Array(items) => {
+                let mut c_items: Vec<String> = vec![];
+                for item in items.iter() {
+                    c_items.push(self.build_c_initializer(item)?);
+                }
+                Ok(format!("{{{}}}", c_items.join(", ")))
+            }
+            _ => self.build_c_expression(expression),
+        }
+    }
+
+    fn indentation(&self) -> String {
+        "\t".repeat(self.depth)
+    }
+
+    // type *idk_items1 = iterable;
+    // for (int idk_index = 0; idk_index < length; idk_index++) {
+    //     type variable = idk_items1[idk_index];
+    //     ...
+    // }
+    //
+    // The iterable is evaluated once, before the loop, so a variable named
+    // like it can't hide it
+    fn build_c_for(
+        &mut self,
+        variable: DeclId,
+        iterable: &Expression,
+        body: &Block,
+    ) -> Result<String, String> {
+        let indentation = self.indentation();
+        let mut iterable = iterable.clone();
+        let mut code = self.hoist(&mut iterable)?;
+        let temporary = self.new_temporary("items");
+        let items = self.declare(temporary, iterable.expression_type.clone());
+        let (declaration, length) = match &iterable.expression_type {
+            // Arrays are used through a pointer to their first element
+            Type::Array(element_type, length) => (
+                self.build_c_declaration(element_type, &format!("(*{})", self.name(items)))?,
+                length.to_string(),
+            ),
+            Type::Slice(_) => (
+                self.build_c_variable_declaration(items)?,
+                format!("{}->length", self.name(items)),
+            ),
+            t => return Err(format!("Can't iterate over {}", t)),
+        };
+        code += &format!(
+            "{}{} = {};\n",
+            indentation,
+            declaration,
+            self.build_c_expression(&iterable)?
+        );
+
+        let index = self.declare(format!("idk_index{}", self.depth), Type::Int);
+        let element = Expression::new(
+            ExpressionKind::Index(
+                Box::new(Expression::new(
+                    ExpressionKind::Name(items),
+                    iterable.expression_type.clone(),
+                )),
+                Box::new(Expression::new(ExpressionKind::Name(index), Type::Int)),
+            ),
+            self.declaration_type(variable).clone(),
+        );
+        let variable = format!(
+            "{} = {};",
+            self.build_c_variable_declaration(variable)?,
+            self.build_c_expression(&element)?
+        );
+
+        self.depth += 1;
+        let variable = format!("{}{}\n", self.indentation(), variable);
+        let body = self.build_c_block(body);
+        self.depth -= 1;
+
+        let index = self.name(index);
+        Ok(code
+            + &format!(
+                "{}for (int {} = 0; {} < {}; {}++) {{\n{}{}{}}}\n",
+                indentation, index, index, length, index, variable, body?, indentation
+            ))
+    }
+
+    // if (condition) {
+    //     ...
+    // } else {
+    //     ...
+    // }
+    //
+    // `if let` stores the optional or the result first and only declares its
+    // value inside the branch:
+    // idk_option_int idk_option1 = value;
+    // if (idk_option1.present) {
+    //     int x = idk_option1.value;
+    //     ...
+    // }
+    fn build_c_if(
+        &mut self,
+        condition: &Condition,
+        body: &Block,
+        else_body: &Option<Block>,
+    ) -> Result<String, String> {
+        let indentation = self.indentation();
+        let (mut code, condition, binding) = match condition.clone() {
+            Condition::Expression(mut condition) => {
+                let hoisted = self.hoist(&mut condition)?;
+                (hoisted, self.build_c_expression(&condition)?, None)
+            }
+            Condition::Let(value, mut optional) => {
+                let mut code = self.hoist(&mut optional)?;
+                let (prefix, flag) = match &optional.expression_type {
+                    Type::Optional(_) => ("option", "present"),
+                    Type::Result(..) => ("result", "ok"),
+                    t => return Err(format!("Expected an optional or a result, found {}", t)),
+                };
+                let temporary = self.new_temporary(prefix);
+                code += &format!(
+                    "{}{} {} = {};\n",
+                    indentation,
+                    self.get_c_type(&optional.expression_type)?,
+                    temporary,
+                    self.build_c_initializer(&optional)?
+                );
+                let value = format!(
+                    "{} = {}.value;",
+                    self.build_c_variable_declaration(value)?,
+                    temporary
+                );
+                (code, format!("{}.{}", temporary, flag), Some(value))
+            }
+        };
+
+        self.depth += 1;
+        let mut c_body = String::new();
+        if let Some(value) = binding {
+            c_body += &format!("{}{}\n", self.indentation(), value);
+        }
+        let block = self.build_c_block(body);
+        self.depth -= 1;
+        code += &format!(
+            "{}if ({}) {{\n{}{}{}}}",
+            indentation, condition, c_body, block?, indentation
+        );
+
+        if let Some(else_body) = else_body {
+            self.depth += 1;
+            let block = self.build_c_block(else_body);
+            self.depth -= 1;
+            code += &format!(" else {{\n{}{}}}", block?, indentation);
+        }
+        Ok(code + "\n")
+    }
+
+    fn new_temporary(&mut self, prefix: &str) -> String {
+        self.temporaries += 1;
+        format!("idk_{}{}", prefix, self.temporaries)
+    }
+
+    // C has no switch expression nor a way to return from inside one, so every
+    // match and `?` inside `expression` is lowered to code that runs before the
+    // statement and stores its result in a variable, which then takes the
+    // place of the expression. Returns that code
+    fn hoist(&mut self, expression: &mut Expression) -> Result<String, String> {
+        match &mut expression.kind {
+            // idk_result_int_string idk_result1 = parse(text);
+            // if (!idk_result1.ok) {
+            //     return (idk_result_int_string){.ok = false, .error = idk_result1.error};
+            // }
+            // int idk_value2 = idk_result1.value;
+            ExpressionKind::Try(inner) => {
+                let indentation = self.indentation();
+                let mut code = self.hoist(inner)?;
+                let result = self.new_temporary("result");
+                code += &format!(
+                    "{}{} {} = {};\n",
+                    indentation,
+                    self.get_c_type(&inner.expression_type)?,
+                    result,
+                    self.build_c_initializer(inner)?
+                );
+                code += &format!(
+                    "{}if (!{}.ok) {{\n{}\treturn ({}){{.ok = false, .error = {}.error}};\n{}}}\n",
+                    indentation,
+                    result,
+                    indentation,
+                    self.get_c_type(&self.return_type)?,
+                    result,
+                    indentation
+                );
+
+                let name = self.new_temporary("value");
+                let value = self.declare(name, expression.expression_type.clone());
+                code += &format!(
+                    "{}{} = {}.value;\n",
+                    indentation,
+                    self.build_c_variable_declaration(value)?,
+                    result
+                );
+                expression.kind = ExpressionKind::Name(value);
+                Ok(code)
+            }
+            // idk_closure1_env *idk_env2 = malloc(sizeof(idk_closure1_env));
+            // idk_env2->k = k;
+            // idk_fn_int_to_int idk_function3 = {idk_env2, idk_closure1};
+            ExpressionKind::Closure(closure) => {
+                let closure = closure.clone();
+                let name = self.new_temporary("closure");
+                let captures: Vec<String> = closure
+                    .captures
+                    .iter()
+                    .map(|capture| self.name(*capture).to_string())
+                    .collect();
+                self.build_c_closure(&name, closure)?;
+
+                let indentation = self.indentation();
+                let mut code = String::new();
+                let environment = if captures.is_empty() {
+                    "NULL".to_string()
+                } else {
+                    let environment = self.new_temporary("env");
+                    code += &format!(
+                        "{}{}_env *{} = malloc(sizeof({}_env));\n",
+                        indentation, name, environment, name
+                    );
+                    for capture in captures.iter() {
+                        code += &format!(
+                            "{}{}->{} = {};\n",
+                            indentation, environment, capture, capture
+                        );
+                    }
+                    environment
+                };
+
+                let value_name = self.new_temporary("function");
+                let value = self.declare(value_name, expression.expression_type.clone());
+                code += &format!(
+                    "{}{} = {{{}, {}}};\n",
+                    indentation,
+                    self.build_c_variable_declaration(value)?,
+                    environment,
+                    name
+                );
+                expression.kind = ExpressionKind::Name(value);
+                Ok(code)
+            }
+            ExpressionKind::Match(subject, arms) => {
+                let (subject, arms) = (*subject.clone(), arms.clone());
+                let name = self.new_temporary("match");
+                let result = self.declare(name.clone(), expression.expression_type.clone());
+                let switch = self.build_c_match(subject, arms, Some(&name))?;
+
+                let declaration = self.build_c_variable_declaration(result)?;
+                expression.kind = ExpressionKind::Name(result);
+                Ok(format!(
+                    "{}{};\n{}",
+                    self.indentation(),
+                    declaration,
+                    switch
+                ))
+            }
+            ExpressionKind::Binary(lhs, _, rhs) | ExpressionKind::Index(lhs, rhs) => {
+                Ok(self.hoist(lhs)? + &self.hoist(rhs)?)
+            }
+            ExpressionKind::Cast(inner)
+            | ExpressionKind::Field(inner, _)
+            | ExpressionKind::AddressOf(inner)
+            | ExpressionKind::Dereference(inner)
+            | ExpressionKind::Wrap(inner)
+            | ExpressionKind::Err(inner) => self.hoist(inner),
+            ExpressionKind::CallValue(callee, arguments) => {
+                let mut code = self.hoist(callee)?;
+                for argument in arguments.iter_mut() {
+                    code += &self.hoist(argument)?;
+                }
+                Ok(code)
+            }
+            ExpressionKind::Array(items)
+            | ExpressionKind::Slice(items)
+            | ExpressionKind::Call(_, items)
+            | ExpressionKind::Builtin(_, items)
+            | ExpressionKind::Variant(_, _, items) => {
+                let mut code = String::new();
+                for item in items.iter_mut() {
+                    code += &self.hoist(item)?;
+                }
+                Ok(code)
+            }
+            ExpressionKind::Struct(_, fields) => {
+                let mut code = String::new();
+                for (_, value) in fields.iter_mut() {
+                    code += &self.hoist(value)?;
+                }
+                Ok(code)
+            }
+            ExpressionKind::Int(_)
+            | ExpressionKind::Float(_)
+            | ExpressionKind::String(_)
+            | ExpressionKind::Bool(_)
+            | ExpressionKind::NoneLit
+            | ExpressionKind::Name(_) => Ok(String::new()),
         }
-        Ok(statements)
     }
 
-    pub fn build_c_assignment(&self, assignment: Assignment) -> Result<String, String> {
-        let var_type = self.get_c_type(&assignment.var_type)?;
-        let name = assignment.name;
-        let value = evaluate_ast(assignment.value)?.to_string();
+    // Shape idk_subject1 = shape;
+    // switch (idk_subject1.tag) {
+    // case Shape_Circle: {
+    //     double r = idk_subject1.as.Circle._0;
+    //     result = 3.14 * r * r;
+    //     break;
+    // }
+    // ...
+    // }
+    fn build_c_match(
+        &mut self,
+        mut subject: Expression,
+        arms: Vec<MatchArm>,
+        result: Option<&str>,
+    ) -> Result<String, String> {
+        let indentation = self.indentation();
+        let mut code = self.hoist(&mut subject)?;
+
+        let enum_node = match &subject.expression_type {
+            Type::UserDefined(name) => self
+                .enums
+                .get(name)
+                .cloned()
+                .ok_or(format!("Unknown enum: {}", name))?,
+            t => return Err(format!("Can't match on {}", t)),
+        };
+        let subject_name = self.new_temporary("subject");
+        code += &format!(
+            "{}{} {} = {};\n{}switch ({}.tag) {{\n",
+            indentation,
+            enum_node.name,
+            subject_name,
+            self.build_c_expression(&subject)?,
+            indentation,
+            subject_name
+        );
 
-        Ok(format!("\t{} {} = {};\n", var_type, name, value))
+        for arm in arms.into_iter() {
+            self.depth += 1;
+            let case = self.build_c_case(&enum_node, &subject_name, arm, result);
+            self.depth -= 1;
+            code += &case?;
+        }
+        code += &format!("{}}}\n", indentation);
+        Ok(code)
     }
 
-    fn build_c_function(&self, function_node: Function) -> Result<String, String> {
-        let parameters = self.get_function_parameters(&function_node.parameters)?;
-        let return_type = match function_node.return_type {
-            Some(t) => self.get_c_type(&t)?,
-            None => "void",
+    fn build_c_case(
+        &mut self,
+        enum_node: &Enum,
+        subject: &str,
+        arm: MatchArm,
+        result: Option<&str>,
+    ) -> Result<String, String> {
+        let indentation = self.indentation();
+        let mut code = match &arm.pattern {
+            Pattern::Wildcard => format!("{}default: {{\n", "\t".repeat(self.depth - 1)),
+            Pattern::Variant(variant, _) => format!(
+                "{}case {}_{}: {{\n",
+                "\t".repeat(self.depth - 1),
+                enum_node.name,
+                variant
+            ),
         };
 
-        let block = self.build_c_block(function_node.body)?;
+        if let Pattern::Variant(variant, bindings) = &arm.pattern {
+            for (position, binding) in bindings.iter().enumerate() {
+                let binding = match binding {
+                    Some(binding) => *binding,
+                    None => continue,
+                };
+                // Arrays can't be copied in C, so the binding points into the subject
+                let name = self.name(binding);
+                let c_binding = match self.declaration_type(binding) {
+                    Type::Array(element_type, _) => {
+                        self.build_c_declaration(element_type, &format!("(*{})", name))?
+                    }
+                    t => self.build_c_declaration(t, name)?,
+                };
+                code += &format!(
+                    "{}{} = {}.as.{}._{};\n",
+                    indentation, c_binding, subject, variant, position
+                );
+            }
+        }
+
+        let mut value = arm.value;
+        code += &self.hoist(&mut value)?;
+        let c_value = self.build_c_expression(&value)?;
+        code += &match result {
+            Some(result) => format!("{}{} = {};\n", indentation, result, c_value),
+            None => format!("{}{};\n", indentation, c_value),
+        };
+        code += &format!("{}break;\n{}}}\n", indentation, "\t".repeat(self.depth - 1));
+        Ok(code)
+    }
 
+    fn build_c_statement(&mut self, statement: &Statement) -> Result<String, String> {
+        let indentation = self.indentation();
+
+        match statement.clone() {
+            Statement::Let(variable, mut value) => {
+                let hoisted = self.hoist(&mut value)?;
+                Ok(format!(
+                    "{}{}{}\n",
+                    hoisted,
+                    indentation,
+                    self.build_c_variable(variable, &value)?
+                ))
+            }
+            Statement::Return(Some(mut expression)) => {
+                let hoisted = self.hoist(&mut expression)?;
+                Ok(format!(
+                    "{}{}return {};\n",
+                    hoisted,
+                    indentation,
+                    self.build_c_expression(&expression)?
+                ))
+            }
+            Statement::Return(None) => Ok(format!("{}return;\n", indentation)),
+            Statement::Assign(mut target, mut value) => {
+                let hoisted = self.hoist(&mut target)? + &self.hoist(&mut value)?;
+                Ok(format!(
+                    "{}{}{} = {};\n",
+                    hoisted,
+                    indentation,
+                    self.build_c_expression(&target)?,
+                    self.build_c_expression(&value)?
+                ))
+            }
+            // A match used as a statement doesn't need a variable for its result
+            Statement::Expression(Expression {
+                kind: ExpressionKind::Match(subject, arms),
+                ..
+            }) => self.build_c_match(*subject, arms, None),
+            Statement::Expression(mut expression) => {
+                let hoisted = self.hoist(&mut expression)?;
+                Ok(format!(
+                    "{}{}{};\n",
+                    hoisted,
+                    indentation,
+                    self.build_c_expression(&expression)?
+                ))
+            }
+            Statement::For(variable, iterable, body) => {
+                self.build_c_for(variable, &iterable, &body)
+            }
+            Statement::If(condition, body, else_body) => {
+                self.build_c_if(&condition, &body, &else_body)
+            }
+        }
+    }
+
+    fn build_c_block(&mut self, block: &Block) -> Result<String, String> {
+        let mut statements = String::new();
+        for statement in block.statements.iter() {
+            statements += &self.build_c_statement(statement)?;
+        }
+        Ok(statements)
+    }
+
+    fn build_c_variable(&self, variable: DeclId, value: &Expression) -> Result<String, String> {
         Ok(format!(
-            "{} {}({}) {{\n{}}}",
-            return_type, function_node.name, parameters, block
+            "{} = {};",
+            self.build_c_variable_declaration(variable)?,
+            self.build_c_initializer(value)?
         ))
     }
 
-    pub fn generate_c_code(&mut self, ast: Vec<Ast>) -> Result<(), String> {
-        self.setup_code();
+    // struct Name {
+    //     type field;
+    // };
+    fn build_c_struct(&self, struct_node: &Struct) -> Result<String, String> {
+        let mut fields = String::new();
+        for field in struct_node.fields.iter() {
+            fields += &format!(
+                "\t{};\n",
+                self.build_c_declaration(&field.field_type, &field.name)?
+            );
+        }
+        Ok(format!("struct {} {{\n{}}};\n\n", struct_node.name, fields))
+    }
+
+    // typedef enum Name_Tag {
+    //     Name_Variant,
+    // } Name_Tag;
+    //
+    // struct Name {
+    //     Name_Tag tag;
+    //     union {
+    //         struct {
+    //             type _0;
+    //         } Variant;
+    //     } as;
+    // };
+    fn build_c_enum(&self, enum_node: &Enum) -> Result<String, String> {
+        let name = &enum_node.name;
+        let mut tags = String::new();
+        let mut members = String::new();
+        for variant in enum_node.variants.iter() {
+            tags += &format!("\t{}_{},\n", name, variant.name);
+            if variant.fields.is_empty() {
+                continue;
+            }
+
+            let mut fields = String::new();
+            for (position, field_type) in variant.fields.iter().enumerate() {
+                fields += &format!(
+                    "\t\t\t{};\n",
+                    self.build_c_declaration(field_type, &format!("_{}", position))?
+                );
+            }
+            members += &format!("\t\tstruct {{\n{}\t\t}} {};\n", fields, variant.name);
+        }
+
+        let mut code = format!("typedef enum {}_Tag {{\n{}}} {}_Tag;\n\n", name, tags, name);
+        code += &format!("struct {} {{\n\t{}_Tag tag;\n", name, name);
+        // C doesn't allow empty unions
+        if !members.is_empty() {
+            code += &format!("\tunion {{\n{}\t}} as;\n", members);
+        }
+        Ok(code + "};\n\n")
+    }
+
+    // C needs the complete type of a struct or an enum before it is used by
+    // value, so types are emitted after the ones they store
+    fn build_c_types(&self, name: &str, emitted: &mut Vec<String>) -> Result<String, String> {
+        if emitted.iter().any(|emitted| emitted == name) {
+            return Ok(String::new());
+        }
+        emitted.push(name.to_string());
+
+        let (stored_types, declaration) = if let Some(struct_node) = self.structs.get(name) {
+            let stored_types: Vec<&Type> = struct_node
+                .fields
+                .iter()
+                .map(|field| &field.field_type)
+                .collect();
+            (stored_types, self.build_c_struct(struct_node)?)
+        } else if let Some(enum_node) = self.enums.get(name) {
+            let stored_types: Vec<&Type> = enum_node
+                .variants
+                .iter()
+                .flat_map(|variant| variant.fields.iter())
+                .collect();
+            (stored_types, self.build_c_enum(enum_node)?)
+        } else {
+            return Err(format!("Unknown type: {}", name));
+        };
 
-        for node in ast.iter() {
-            match node {
-                Ast::Function(function) => {
-                    self.source_code
-                        .write_all(self.build_c_function(function.clone())?.as_bytes());
+        let mut code = String::new();
+        for stored_type in stored_types.into_iter() {
+            code += &self.build_c_dependencies(stored_type, emitted)?;
+        }
+        Ok(code + &declaration)
+    }
+
+    fn build_c_dependencies(
+        &self,
+        stored_type: &Type,
+        emitted: &mut Vec<String>,
+    ) -> Result<String, String> {
+        match stored_type {
+            Type::Array(element_type, _) => self.build_c_dependencies(element_type, emitted),
+            Type::UserDefined(name) => self.build_c_types(name, emitted),
+            Type::Optional(value_type) => {
+                let name = self.get_c_type(stored_type)?;
+                if emitted.contains(&name) {
+                    return Ok(String::new());
+                }
+                emitted.push(name.clone());
+                Ok(self.build_c_dependencies(value_type, emitted)?
+                    + &format!(
+                        "struct {} {{\n\tbool present;\n\t{};\n}};\n\n",
+                        name,
+                        self.build_c_declaration(value_type, "value")?
+                    ))
+            }
+            Type::Result(value_type, error_type) => {
+                let name = self.get_c_type(stored_type)?;
+                if emitted.contains(&name) {
+                    return Ok(String::new());
                 }
+                emitted.push(name.clone());
+                Ok(self.build_c_dependencies(value_type, emitted)?
+                    + &self.build_c_dependencies(error_type, emitted)?
+                    + &format!(
+                        "struct {} {{\n\tbool ok;\n\tunion {{\n\t\t{};\n\t\t{};\n\t}};\n}};\n\n",
+                        name,
+                        self.build_c_declaration(value_type, "value")?,
+                        self.build_c_declaration(error_type, "error")?
+                    ))
+            }
+            // Only pointers to the other types are stored, so they don't need
+            // to be defined first
+            Type::Function(parameter_types, return_type) => {
+                let name = self.get_c_type(stored_type)?;
+                if emitted.contains(&name) {
+                    return Ok(String::new());
+                }
+                emitted.push(name.clone());
+                let mut c_parameter_types = vec!["void *".to_string()];
+                for parameter_type in parameter_types.iter() {
+                    c_parameter_types.push(self.get_c_type(parameter_type)?);
+                }
+                Ok(format!(
+                    "struct {} {{\n\tvoid *env;\n\t{} (*call)({});\n}};\n\n",
+                    name,
+                    self.get_c_return_type(return_type)?,
+                    c_parameter_types.join(", ")
+                ))
+            }
+            _ => Ok(String::new()),
+        }
+    }
+
+    // Signature of a function, without the body. C spells an empty parameter
+    // list `(void)`
+    fn build_c_prototype(&self, name: &str, function: &Function) -> Result<String, String> {
+        if name == "main" {
+            return Ok("int main(void)".to_string());
+        }
+        let parameters = match function.parameters.is_empty() {
+            true => "void".to_string(),
+            false => self.get_function_parameters(&function.parameters)?,
+        };
+        Ok(format!(
+            "{} {}({})",
+            self.get_c_return_type(&function.return_type)?,
+            name,
+            parameters
+        ))
+    }
+
+    // Exported functions of modules other than the root are called from C
+    // through a function without the module prefix:
+    // int area(int w, int h) { return geometry__area(w, h); }
+    fn build_c_export(&mut self, function: &Function) -> Result<String, String> {
+        let name = self.name(function.id).to_string();
+        let export = self
+            .modules
+            .iter()
+            .find_map(|module| name.strip_prefix(&format!("{}__", module)))
+            .unwrap_or(&name);
+        let prototype = self.build_c_prototype(export, function)?;
+        self.exports += &format!("{};\n", prototype);
+        if export == name {
+            return Ok(String::new());
+        }
+        let prototype = VISIBLE.to_string() + &prototype;
+
+        let arguments: Vec<&str> = function
+            .parameters
+            .iter()
+            .map(|parameter| self.name(*parameter))
+            .collect();
+        let call = format!("{}({})", name, arguments.join(", "));
+        match function.return_type {
+            Type::Void => Ok(format!("{} {{\n\t{};\n}}\n\n", prototype, call)),
+            _ => Ok(format!("{} {{\n\treturn {};\n}}\n\n", prototype, call)),
+        }
+    }
+
+    // idk never changes the strings it passes to C: `int puts(const char* s)`
+    fn build_c_extern(&self, extern_node: &Extern) -> Result<String, String> {
+        let mut parameters: Vec<String> = vec![];
+        for parameter in extern_node.parameters.iter() {
+            parameters.push(match self.declaration_type(*parameter) {
+                Type::String => format!("const char* {}", self.name(*parameter)),
+                _ => self.build_c_variable_declaration(*parameter)?,
+            });
+        }
+        if parameters.is_empty() {
+            parameters.push("void".to_string());
+        }
+        Ok(format!(
+            "{} {}({})",
+            self.get_c_return_type(&extern_node.return_type)?,
+            self.name(extern_node.id),
+            parameters.join(", ")
+        ))
+    }
+
+    fn build_c_function(&mut self, function: &Function) -> Result<String, String> {
+        let prototype = self.build_c_prototype(self.name(function.id), function)?;
+        self.return_type = function.return_type.clone();
+
+        self.depth += 1;
+        let block = self.build_c_block(&function.body);
+        self.depth -= 1;
+        let block = block?;
+
+        Ok(format!("{} {{\n{}}}\n\n", prototype, block))
+    }
 
-                _ => return Err("Unable to generate C code".to_string()),
+    pub fn generate_c_code(&mut self, program: Program) -> Result<(), String> {
+        self.declarations = program.declarations;
+        for function in program.functions.iter() {
+            self.parameters
+                .insert(function.id, function.parameters.clone());
+        }
+        for extern_node in program.externs.iter() {
+            self.parameters
+                .insert(extern_node.id, extern_node.parameters.clone());
+        }
+        for struct_node in program.structs.iter() {
+            self.structs
+                .insert(struct_node.name.clone(), struct_node.clone());
+        }
+        for enum_node in program.enums.iter() {
+            self.enums.insert(enum_node.name.clone(), enum_node.clone());
+        }
+
+        let mut units: Vec<Unit> = self.modules.iter().map(|_| Unit::default()).collect();
+        let mut externs = String::new();
+        for extern_node in program.externs.iter() {
+            externs += &self.build_c_extern_prototype(extern_node)?;
+        }
+        if !externs.is_empty() {
+            externs += "\n";
+        }
+        for global in program.globals.iter() {
+            let index = self.get_unit(self.name(global.id));
+            units[index].code += &self.build_c_global(global)?;
+        }
+        for function in program.functions.iter() {
+            let index = self.get_unit(self.name(function.id));
+            // Trampolines are shared by the code of a unit
+            *self.closures.borrow_mut() = std::mem::take(&mut units[index].closures);
+            let code = self.build_c_item(function, &mut units[index]);
+            units[index].closures = self.closures.take();
+            units[index].code += &code?;
+        }
+
+        // Types go after the functions are generated, which is when every
+        // optional the program uses is known
+        let names: Vec<&String> = program
+            .structs
+            .iter()
+            .map(|struct_node| &struct_node.name)
+            .chain(program.enums.iter().map(|enum_node| &enum_node.name))
+            .collect();
+        let mut types = String::new();
+        let mut emitted: Vec<String> = vec![];
+        for name in names.iter() {
+            types += &self.build_c_types(name, &mut emitted)?;
+        }
+        // Building a wrapper may use new ones
+        let mut index = 0;
+        while index < self.wrappers.borrow().len() {
+            let wrapper = self.wrappers.borrow()[index].clone();
+            types += &self.build_c_dependencies(&wrapper, &mut emitted)?;
+            index += 1;
+        }
+        for (name, captures) in self.environments.iter() {
+            let mut fields = String::new();
+            for capture in captures.iter() {
+                types +=
+                    &self.build_c_dependencies(self.declaration_type(*capture), &mut emitted)?;
+                fields += &format!("\t{};\n", self.build_c_variable_declaration(*capture)?);
             }
+            types += &format!("struct {} {{\n{}}};\n\n", name, fields);
+        }
+
+        // Every type is declared up front, so types can refer to each other
+        // through references before they are defined
+        let mut declarations = String::new();
+        for name in names.iter() {
+            declarations += &format!("typedef struct {} {};\n", name, name);
+        }
+        for wrapper in self.wrappers.borrow().iter() {
+            let name = format!("idk_{}", wrapper.mangled());
+            declarations += &format!("typedef struct {} {};\n", name, name);
+        }
+        for (name, _) in self.environments.iter() {
+            declarations += &format!("typedef struct {} {};\n", name, name);
+        }
+        if !declarations.is_empty() {
+            declarations += "\n";
+        }
+
+        // Closures are declared before the code that creates them and defined
+        // after the functions they may call
+        for unit in units.iter_mut() {
+            for (_, signature, body) in unit.closures.iter() {
+                unit.prototypes += &format!("{};\n", signature);
+                unit.definitions += &format!("{} {{\n{}}}\n\n", signature, body);
+            }
+            if !unit.prototypes.is_empty() {
+                unit.prototypes += "\n";
+            }
+            if !unit.header.is_empty() {
+                unit.header += "\n";
+            }
+        }
+
+        if self.uses_runtime.get() {
+            self.write("idk_runtime.h", RUNTIME_HEADER)?;
+            self.write("idk_runtime.c", RUNTIME_SOURCE)?;
+            self.sources.push("idk_runtime.c".to_string());
+        }
+        // The header of the program as a C library is named after it, the
+        // headers of its modules end with `.idk.h` and the module loader
+        // rejects module names starting with `idk_`
+        if !self.exports.is_empty() {
+            let root = self.modules.last().unwrap();
+            let guard = format!("{}_H", root.to_uppercase());
+            self.write(
+                &format!("{}.h", root),
+                &format!(
+                    "#ifndef {}\n#define {}\n\n#include \"stdbool.h\"\n\n{}\n#endif\n",
+                    guard, guard, self.exports
+                ),
+            )?;
+        }
+
+        if units.len() == 1 {
+            let unit = units.remove(0);
+            let code = declarations
+                + &types
+                + &externs
+                + &unit.prototypes
+                + &unit.code
+                + &unit.definitions;
+            self.sources.push("code.c".to_string());
+            return self.write("code.c", &(self.setup_code() + &code));
         }
 
+        // Types and C functions are shared by every module, each module gets
+        // its own header with the functions the others can call
+        self.write(
+            "idk_types.h",
+            &format!(
+                "#ifndef IDK_TYPES_H\n#define IDK_TYPES_H\n\n{}{}{}{}#endif\n",
+                self.setup_code(),
+                declarations,
+                types,
+                externs
+            ),
+        )?;
+        for (index, unit) in units.iter().enumerate() {
+            let module = &self.modules[index];
+            let guard = format!("{}_IDK_H", module.to_uppercase());
+            self.write(
+                &format!("{}.idk.h", module),
+                &format!(
+                    "#ifndef {}\n#define {}\n\n#include \"idk_types.h\"\n\n{}#endif\n",
+                    guard, guard, unit.header
+                ),
+            )?;
+
+            // A module only calls the modules it imports and the ones their
+            // inlined functions call, which it imports through them
+            let mut includes = String::from("#include \"idk_types.h\"\n");
+            for import in self.imports[index].iter().chain(std::iter::once(module)) {
+                includes += &format!("#include \"{}.idk.h\"\n", import);
+            }
+            let code = format!(
+                "{}\n{}{}{}",
+                includes, unit.prototypes, unit.code, unit.definitions
+            );
+            self.write(&format!("{}.c", module), &code)?;
+            self.sources.push(format!("{}.c", module));
+        }
         Ok(())
     }
+
+    // Builds the generated code with the system C compiler, `cc` unless the
+    // CC environment variable names another one
+    pub fn compile(&self, output: &str, libraries: &[String]) -> Result<(), String> {
+        let mut command = Command::new(compiler());
+        command.args(&self.sources).arg("-o").arg(output);
+        self.link_libraries(&mut command, libraries);
+        run(&mut command)
+    }
+
+    // A static library is an archive of the compiled sources, a shared one is
+    // linked against the libraries it uses
+    pub fn build_library(
+        &self,
+        output: &str,
+        shared: bool,
+        libraries: &[String],
+    ) -> Result<(), String> {
+        if shared {
+            let mut command = Command::new(compiler());
+            command
+                .args(["-shared", "-fPIC", "-fvisibility=hidden"])
+                .args(&self.sources)
+                .arg("-o")
+                .arg(output);
+            self.link_libraries(&mut command, libraries);
+            return run(&mut command);
+        }
+
+        let mut objects: Vec<String> = vec![];
+        for source in self.sources.iter() {
+            let object = source.replace(".c", ".o");
+            run(Command::new(compiler())
+                .arg("-c")
+                .arg(source)
+                .arg("-o")
+                .arg(&object))?;
+            objects.push(object);
+        }
+        run(Command::new("ar").arg("rcs").arg(output).args(&objects))
+    }
+
+    // The runtime's math functions are in libm
+    fn link_libraries(&self, command: &mut Command, libraries: &[String]) {
+        for library in libraries.iter() {
+            command.arg(format!("-l{}", library));
+        }
+        if self.uses_runtime.get() {
+            command.arg("-lm");
+        }
+    }
+
+    // Items belong to the module their name is prefixed with, the root
+    // module's have no prefix
+    fn get_unit(&self, name: &str) -> usize {
+        self.modules
+            .iter()
+            .position(|module| name.starts_with(&format!("{}__", module)))
+            .unwrap_or(self.modules.len() - 1)
+    }
+
+    // Every module can call the C functions, so they are declared with the
+    // types. The headers given with `--include` declare them, and their
+    // prototypes may differ from ours in ways C rejects, like
+    // `size_t strlen(const char *)`
+    fn build_c_extern_prototype(&self, extern_node: &Extern) -> Result<String, String> {
+        if self.is_runtime_function(extern_node.id) || !self.includes.is_empty() {
+            return Ok(String::new());
+        }
+        Ok(format!("{};\n", self.build_c_extern(extern_node)?))
+    }
+
+    // Globals are private to their module and to the library. C initializes
+    // them with constants, so the operators the runtime computes are folded
+    fn build_c_global(&self, global: &Global) -> Result<String, String> {
+        let mut value = global.value.clone();
+        let mut constant = true;
+        visit_expression(&mut value, false, &mut |expression| {
+            fold(expression);
+            if let ExpressionKind::Binary(lhs, operator, _) = &expression.kind {
+                constant &= !is_runtime_operator(operator, lhs);
+            }
+        });
+        if !constant {
+            return Err(format!(
+                "Initializer of global '{}' can't be computed while compiling",
+                self.name(global.id)
+            ));
+        }
+        let global = self.build_c_variable(global.id, &value)?;
+        match self.modules.len() > 1 || self.library {
+            true => Ok(format!("static {}\n\n", global)),
+            false => Ok(format!("{}\n\n", global)),
+        }
+    }
+
+    // Functions that aren't `pub` are only visible to their own module when
+    // there's more than one, and a library with a single module only shows
+    // the exported ones. Every function is declared before any is defined,
+    // so functions can call the ones defined after them
+    fn build_c_item(&mut self, function: &Function, unit: &mut Unit) -> Result<String, String> {
+        let separate = self.modules.len() > 1;
+        let name = self.name(function.id).to_string();
+        let mut code = self.build_c_function(function)?;
+        if name == "main" {
+            return Ok(code);
+        }
+
+        let prototype = self.build_c_prototype(&name, function)?;
+        if function.exported {
+            let export = self.build_c_export(function)?;
+            // Functions of the root module are exported with their own name
+            if export.is_empty() {
+                code = VISIBLE.to_string() + &code;
+            }
+            code += &export;
+        }
+        let private = match separate {
+            true => !function.public,
+            false => self.library && !function.exported,
+        };
+        if private {
+            unit.prototypes += &format!("static {};\n", prototype);
+            return Ok(format!("static {}", code));
+        }
+        match separate {
+            true => unit.header += &format!("{};\n", prototype),
+            false => unit.prototypes += &format!("{};\n", prototype),
+        }
+        Ok(code)
+    }
 }
@@ -1,8 +1,57 @@
 pub mod c;
 
-use crate::ast::{Ast, Expression};
+use crate::ast::{Expression, Type};
+use crate::hir::{self, ExpressionKind, Program};
 use crate::lexer::Token;
 
+// Whether an expression evaluates to an int, which decides how `/`, `%` and
+// `**` round. Checked expressions have casts wherever ints become floats
+fn is_integer(expression: &Expression) -> bool {
+    match expression {
+        Expression::Int(_) | Expression::Boolean(_) => true,
+        Expression::Cast(_, target_type) => *target_type == Type::Int,
+        Expression::BinaryExpr(lhs, _, rhs) => is_integer(lhs) && is_integer(rhs),
+        _ => false,
+    }
+}
+
+// Integer power, which panics at runtime on a negative exponent and wraps
+// around when it overflows
+fn integer_power(base: f64, exponent: f64) -> Result<f64, String> {
+    if exponent < 0.0 {
+        return Err(format!(
+            "Negative exponent {} in integer power, use a float base",
+            exponent
+        ));
+    }
+    // On ints, since a power can be too large for an f64 to hold exactly
+    match (base as i32).checked_pow(exponent as u32) {
+        Some(value) => Ok(f64::from(value)),
+        None => Err(format!("Integer overflow in {} ** {}", base, exponent)),
+    }
+}
+
+fn apply_operator(left: f64, operation: &Token, right: f64, integer: bool) -> Result<f64, String> {
+    match operation {
+        Token::Plus => Ok(left + right),
+        Token::Minus => Ok(left - right),
+        Token::Times => Ok(left * right),
+        Token::Divides | Token::Mod if integer && right == 0.0 => {
+            Err("Division by zero".to_string())
+        }
+        Token::Divides if integer => Ok((left / right).trunc()),
+        Token::Divides => Ok(left / right),
+        // The remainder has the sign of the dividend, like C's `%` and `fmod`
+        Token::Mod => Ok(left % right),
+        Token::Power if integer => integer_power(left, right),
+        Token::Power => Ok(left.powf(right)),
+        _ => Err(format!(
+            "Operator not implemented or invalid: {:?}",
+            operation
+        )),
+    }
+}
+
 // TODO: The result of an expression is not always an f64
 pub fn evaluate_ast(expression: Expression) -> Result<f64, String> {
     match expression {
@@ -10,25 +59,56 @@ pub fn evaluate_ast(expression: Expression) -> Result<f64, String> {
 
         Expression::Float(value) => Ok(value),
 
+        Expression::Boolean(value) => Ok(if value { 1.0 } else { 0.0 }),
+
+        Expression::Cast(expression, target_type) => {
+            let value = evaluate_ast(*expression)?;
+            match target_type {
+                Type::Int => Ok(value.trunc()),
+                Type::Float => Ok(value),
+                t => Err(format!("Cast not implemented: {:?}", t)),
+            }
+        }
+
         Expression::BinaryExpr(lhs, operation, rhs) => {
+            // Ints divide like in C, rounding towards zero
+            let integer = is_integer(&lhs) && is_integer(&rhs);
             let left = evaluate_ast(*lhs)?;
             let right = evaluate_ast(*rhs)?;
+            apply_operator(left, &operation, right, integer)
+        }
+        _ => Err(format!("Expression not implemented: {:?}", expression)),
+    }
+}
+
+// Same as `evaluate_ast`, on the HIR, where the types of the operands are known
+pub fn evaluate(expression: &hir::Expression) -> Result<f64, String> {
+    match &expression.kind {
+        ExpressionKind::Int(value) => Ok(f64::from(*value)),
+
+        ExpressionKind::Float(value) => Ok(*value),
 
-            match operation {
-                Token::Plus => Ok(left + right),
-                Token::Minus => Ok(left - right),
-                Token::Times => Ok(left * right),
-                Token::Divides => Ok(left / right),
-                _ => Err(format!(
-                    "Operator not implemented or invalid: {:?}",
-                    operation
-                )),
+        ExpressionKind::Bool(value) => Ok(if *value { 1.0 } else { 0.0 }),
+
+        ExpressionKind::Cast(inner) => {
+            let value = evaluate(inner)?;
+            match expression.expression_type {
+                Type::Int => Ok(value.trunc()),
+                Type::Float => Ok(value),
+                ref t => Err(format!("Cast not implemented: {:?}", t)),
             }
         }
-        _ => Err(format!("Expression not implemented: {:?}", expression)),
+
+        ExpressionKind::Binary(lhs, operation, rhs) => apply_operator(
+            evaluate(lhs)?,
+            operation,
+            evaluate(rhs)?,
+            lhs.expression_type != Type::Float,
+        ),
+        kind => Err(format!("Expression not implemented: {:?}", kind)),
     }
 }
 
 pub trait CodeGenerator {
-    fn generate(&mut self, ast: Vec<Ast>) -> Result<(), String>;
+    fn generate(&mut self, program: Program) -> Result<(), String>;
 }
@@ -0,0 +1,180 @@
+#include "idk_runtime.h"
+
+#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void idk_panic(const char *message) {
+	fprintf(stderr, "%s\n", message);
+	exit(1);
+}
+
+static void idk_slice_reserve(idk_slice *slice, int capacity) {
+	if (capacity <= slice->capacity) {
+		return;
+	}
+
+	void *items = realloc(slice->items, (size_t)capacity * slice->item_size);
+	if (items == NULL) {
+		idk_panic("Out of memory");
+	}
+	slice->items = items;
+	slice->capacity = capacity;
+}
+
+idk_slice *idk_slice_from(int item_size, int length, const void *items) {
+	idk_slice *slice = malloc(sizeof(idk_slice));
+	if (slice == NULL) {
+		idk_panic("Out of memory");
+	}
+	*slice = (idk_slice){NULL, 0, 0, item_size};
+
+	if (length > 0) {
+		idk_slice_reserve(slice, length);
+		memcpy(slice->items, items, (size_t)length * item_size);
+		slice->length = length;
+	}
+	return slice;
+}
+
+void idk_slice_push(idk_slice *slice, const void *item) {
+	if (slice->length == slice->capacity) {
+		idk_slice_reserve(slice, slice->capacity == 0 ? 4 : slice->capacity * 2);
+	}
+
+	memcpy((char *)slice->items + (size_t)slice->length * slice->item_size, item, slice->item_size);
+	slice->length++;
+}
+
+void *idk_slice_pop(idk_slice *slice) {
+	if (slice->length == 0) {
+		idk_panic("Can't pop from an empty slice");
+	}
+
+	slice->length--;
+	return (char *)slice->items + (size_t)slice->length * slice->item_size;
+}
+
+void *idk_slice_at(const idk_slice *slice, int index) {
+	if (index < 0 || index >= slice->length) {
+		fprintf(stderr, "Index out of bounds: the length is %d but the index is %d\n", slice->length, index);
+		exit(1);
+	}
+
+	return (char *)slice->items + (size_t)index * slice->item_size;
+}
+
+void idk_print(const char *text) {
+	fputs(text, stdout);
+}
+
+void idk_print_int(int value) {
+	printf("%d", value);
+}
+
+void idk_print_float(double value) {
+	printf("%g", value);
+}
+
+void idk_print_bool(bool value) {
+	fputs(value ? "true" : "false", stdout);
+}
+
+void idk_println(const char *text) {
+	puts(text);
+}
+
+void idk_println_int(int value) {
+	printf("%d\n", value);
+}
+
+void idk_println_float(double value) {
+	printf("%g\n", value);
+}
+
+void idk_println_bool(bool value) {
+	puts(value ? "true" : "false");
+}
+
+char *idk_read_line(void) {
+	int capacity = 64;
+	int length = 0;
+	char *line = malloc(capacity);
+	if (line == NULL) {
+		idk_panic("Out of memory");
+	}
+
+	int c;
+	while ((c = getchar()) != EOF && c != '\n') {
+		if (length + 1 == capacity) {
+			capacity *= 2;
+			char *grown = realloc(line, capacity);
+			if (grown == NULL) {
+				idk_panic("Out of memory");
+			}
+			line = grown;
+		}
+		line[length++] = (char)c;
+	}
+	line[length] = '\0';
+	return line;
+}
+
+double idk_sqrt(double x) {
+	return sqrt(x);
+}
+
+double idk_pow(double base, double exponent) {
+	return pow(base, exponent);
+}
+
+int idk_abs_int(int x) {
+	return x < 0 ? -x : x;
+}
+
+double idk_abs_float(double x) {
+	return fabs(x);
+}
+
+int idk_min_int(int a, int b) {
+	return a < b ? a : b;
+}
+
+double idk_min_float(double a, double b) {
+	return a < b ? a : b;
+}
+
+int idk_max_int(int a, int b) {
+	return a > b ? a : b;
+}
+
+double idk_max_float(double a, double b) {
+	return a > b ? a : b;
+}
+
+int idk_string_length(const char *text) {
+	return (int)strlen(text);
+}
+
+int idk_pow_int(int base, int exponent) {
+	if (exponent < 0) {
+		idk_panic("Negative exponent in integer power");
+	}
+
+	// Unsigned, so overflows wrap around instead of being undefined
+	unsigned int result = 1;
+	unsigned int factor = (unsigned int)base;
+	while (exponent > 0) {
+		if (exponent & 1) {
+			result *= factor;
+		}
+		factor *= factor;
+		exponent >>= 1;
+	}
+	return (int)result;
+}
+
+double idk_mod_float(double a, double b) {
+	return fmod(a, b);
+}
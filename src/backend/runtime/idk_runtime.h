@@ -0,0 +1,49 @@
+#ifndef IDK_RUNTIME_H
+#define IDK_RUNTIME_H
+
+#include <stdbool.h>
+
+// Growable array behind idk slices ([]T). Slices are pointers to it, so every
+// copy of a slice, like a parameter, sees the items pushed through the others.
+// Items are stored untyped and the generated code casts them back to the
+// element type.
+typedef struct {
+	void *items;
+	int length;
+	int capacity;
+	int item_size;
+} idk_slice;
+
+idk_slice *idk_slice_from(int item_size, int length, const void *items);
+void idk_slice_push(idk_slice *slice, const void *item);
+// The returned item is only valid until the next push
+void *idk_slice_pop(idk_slice *slice);
+void *idk_slice_at(const idk_slice *slice, int index);
+
+// Functions behind the prelude
+void idk_print(const char *text);
+void idk_print_int(int value);
+void idk_print_float(double value);
+void idk_print_bool(bool value);
+void idk_println(const char *text);
+void idk_println_int(int value);
+void idk_println_float(double value);
+void idk_println_bool(bool value);
+// Reads a line from the standard input without its newline, empty at the end
+// of the input
+char *idk_read_line(void);
+double idk_sqrt(double x);
+double idk_pow(double base, double exponent);
+int idk_abs_int(int x);
+double idk_abs_float(double x);
+int idk_min_int(int a, int b);
+double idk_min_float(double a, double b);
+int idk_max_int(int a, int b);
+double idk_max_float(double a, double b);
+int idk_string_length(const char *text);
+
+// Operators C doesn't have. Integer powers panic on negative exponents
+int idk_pow_int(int base, int exponent);
+double idk_mod_float(double a, double b);
+
+#endif
@@ -6,7 +6,7 @@ pub enum TargetLanguage<'a> {
     Unknown(&'a str),
 }
 
-pub fn get_target_language(selected_language: &str) -> TargetLanguage {
+pub fn get_target_language(selected_language: &str) -> TargetLanguage<'_> {
     match selected_language {
         "C" => TargetLanguage::C,
         "JavaScript" => TargetLanguage::JavaScript,
@@ -14,6 +14,48 @@ pub fn get_target_language(selected_language: &str) -> TargetLanguage {
     }
 }
 
+pub enum LibraryKind<'a> {
+    Static,
+    Shared,
+    Unknown(&'a str),
+}
+
+pub fn get_library_kind(kind: &str) -> LibraryKind<'_> {
+    match kind {
+        "static" => LibraryKind::Static,
+        "shared" => LibraryKind::Shared,
+        unknown_kind => LibraryKind::Unknown(unknown_kind),
+    }
+}
+
+pub enum Emit<'a> {
+    Mir,
+    Unknown(&'a str),
+}
+
+pub fn get_emit(emit: &str) -> Emit<'_> {
+    match emit {
+        "mir" => Emit::Mir,
+        unknown_emit => Emit::Unknown(unknown_emit),
+    }
+}
+
+pub enum OptLevel<'a> {
+    O0,
+    O1,
+    O2,
+    Unknown(&'a str),
+}
+
+pub fn get_opt_level(level: &str) -> OptLevel<'_> {
+    match level {
+        "0" => OptLevel::O0,
+        "1" => OptLevel::O1,
+        "2" => OptLevel::O2,
+        unknown_level => OptLevel::Unknown(unknown_level),
+    }
+}
+
 #[derive(ClapParser, Debug)]
 #[clap(author="Hícaro Dânrlley", version="0.1", about="A general purpose and open-source programming language", long_about = None)]
 pub struct Args {
@@ -24,4 +66,35 @@ pub struct Args {
     /// Target language (C, JavaScript)
     #[clap(short = 't', long = "target", value_parser)]
     pub target_language: String,
+
+    /// Check array indexes at runtime
+    #[clap(long = "bounds-checks", action)]
+    pub bounds_checks: bool,
+
+    /// C header declaring the functions declared with `extern "C"`, in place
+    /// of the prototypes generated for them
+    #[clap(long = "include", value_parser)]
+    pub includes: Vec<String>,
+
+    /// Library to link the executable built with --output against
+    #[clap(short = 'l', long = "link", value_parser)]
+    pub libraries: Vec<String>,
+
+    /// Compile the generated C into an executable with the system compiler,
+    /// or into the library built with --library
+    #[clap(short = 'o', long = "output", value_parser)]
+    pub output: Option<String>,
+
+    /// Build a C library (static, shared) with the exported functions
+    #[clap(long = "library", value_parser)]
+    pub library: Option<String>,
+
+    /// Print an intermediate representation (mir) instead of generating code
+    #[clap(long = "emit", value_parser)]
+    pub emit: Option<String>,
+
+    /// Optimization level (0, 1, 2): 0 keeps the generated code readable,
+    /// 2 inlines small functions too
+    #[clap(short = 'O', default_value = "0", value_parser)]
+    pub opt_level: String,
 }
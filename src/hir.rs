@@ -0,0 +1,988 @@
+use crate::ast::{self, mangle_method, Ast, Enum, Struct, Type};
+use crate::lexer::Token;
+use std::collections::HashMap;
+
+// Typed intermediate representation the backends generate code from. It is
+// built from the tree the type checker accepted: every expression carries its
+// type, every name is the declaration it refers to, and overloads, methods,
+// default and named arguments and implicit conversions are spelled out
+
+// Position of a declaration in `Program::declarations`
+pub type DeclId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclarationKind {
+    // Variable, parameter or value bound by a pattern
+    Local,
+    Global,
+    Function,
+    // C function declared with `extern "C"`
+    Extern,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    // Functions have unique names: overloads are named after their parameter
+    // types, methods after their type and externs after their C name
+    pub name: String,
+    pub kind: DeclarationKind,
+    // Functions and externs have a function type
+    pub declaration_type: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Builtin {
+    Len,
+    Push,
+    Pop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub expression_type: Type,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, expression_type: Type) -> Self {
+        Self {
+            kind,
+            expression_type,
+        }
+    }
+
+    pub fn int(value: i32) -> Self {
+        Self::new(ExpressionKind::Int(value), Type::Int)
+    }
+
+    // Variable an assigned or borrowed place is part of, if it isn't behind a reference
+    pub fn root(&self) -> Option<DeclId> {
+        match &self.kind {
+            ExpressionKind::Name(id) => Some(*id),
+            ExpressionKind::Field(base, _)
+                if !matches!(base.expression_type, Type::Reference(..)) =>
+            {
+                base.root()
+            }
+            ExpressionKind::Index(base, _) => base.root(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionKind {
+    Int(i32),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    // Variable, or a function used as a value
+    Name(DeclId),
+    // Both operands have the type of the expression
+    Binary(Box<Expression>, Token, Box<Expression>),
+    // Conversion between numbers, to the type of the expression
+    Cast(Box<Expression>),
+    Array(Vec<Expression>),
+    // Slice holding a copy of the items
+    Slice(Vec<Expression>),
+    Index(Box<Expression>, Box<Expression>),
+    // Arguments follow the parameters, with the defaults of the ones left
+    // out. Methods take the value they are called on first
+    Call(DeclId, Vec<Expression>),
+    // Call of a function value
+    CallValue(Box<Expression>, Vec<Expression>),
+    Builtin(Builtin, Vec<Expression>),
+    Struct(String, Vec<(String, Expression)>),
+    Field(Box<Expression>, String),
+    // Enum, variant and the values it carries
+    Variant(String, String, Vec<Expression>),
+    Match(Box<Expression>, Vec<MatchArm>),
+    AddressOf(Box<Expression>),
+    Dereference(Box<Expression>),
+    // Value stored in the optional or the result type of the expression
+    Wrap(Box<Expression>),
+    // Absent value of the optional type of the expression
+    NoneLit,
+    // Error stored in the result type of the expression
+    Err(Box<Expression>),
+    Try(Box<Expression>),
+    Closure(Closure),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    // Variant and the declarations of the values it binds, None for `_`
+    Variant(String, Vec<Option<DeclId>>),
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    pub parameters: Vec<DeclId>,
+    pub return_type: Type,
+    pub body: Block,
+    // Variables of the enclosing functions copied into the closure
+    pub captures: Vec<DeclId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Expression(Expression),
+    // if let name = optional, where the declaration holds the value when present
+    Let(DeclId, Expression),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    // Declaration of a local variable
+    Let(DeclId, Expression),
+    Assign(Expression, Expression),
+    Expression(Expression),
+    Return(Option<Expression>),
+    If(Condition, Block, Option<Block>),
+    // Loop variable, iterable and body
+    For(DeclId, Expression, Block),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub id: DeclId,
+    pub parameters: Vec<DeclId>,
+    pub return_type: Type,
+    pub body: Block,
+    pub public: bool,
+    pub exported: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    pub id: DeclId,
+    pub parameters: Vec<DeclId>,
+    pub return_type: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub id: DeclId,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub declarations: Vec<Declaration>,
+    pub structs: Vec<Struct>,
+    pub enums: Vec<Enum>,
+    pub externs: Vec<Extern>,
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+}
+
+impl Program {
+    pub fn declare(&mut self, name: &str, kind: DeclarationKind, declaration_type: Type) -> DeclId {
+        self.declarations.push(Declaration {
+            name: name.to_string(),
+            kind,
+            declaration_type,
+        });
+        self.declarations.len() - 1
+    }
+
+    pub fn name(&self, id: DeclId) -> &str {
+        &self.declarations[id].name
+    }
+}
+
+// Turns the `return;` of a function into `return 0;`, leaving the closures
+// it creates alone
+fn return_zero(block: &mut Block) {
+    for statement in block.statements.iter_mut() {
+        match statement {
+            Statement::Return(value @ None) => *value = Some(Expression::int(0)),
+            Statement::For(_, _, body) => return_zero(body),
+            Statement::If(_, body, else_body) => {
+                return_zero(body);
+                if let Some(else_body) = else_body {
+                    return_zero(else_body);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn function_type(parameters: &[ast::Parameter], return_type: &Option<Type>) -> Type {
+    Type::Function(
+        parameters
+            .iter()
+            .map(|parameter| parameter.parameter_type.clone())
+            .collect(),
+        Box::new(return_type.clone().unwrap_or(Type::Void)),
+    )
+}
+
+// Builds the HIR of a program the type checker accepted
+pub fn lower(ast: Vec<Ast>) -> Result<Program, String> {
+    let mut lowerer = Lowerer::new();
+    lowerer.declare_items(&ast);
+    lowerer.lower_items(ast)?;
+    Ok(lowerer.program)
+}
+
+struct Lowerer {
+    program: Program,
+    // Variables in scope, innermost last. The first scope holds the globals
+    scopes: Vec<HashMap<String, DeclId>>,
+    // Functions, methods and externs by the unique name the type checker
+    // resolved calls to
+    functions: HashMap<String, DeclId>,
+    structs: HashMap<String, Struct>,
+    enums: HashMap<String, Enum>,
+    // Declarations of the functions and methods in the order they appear
+    bodies: Vec<DeclId>,
+}
+
+impl Lowerer {
+    fn new() -> Self {
+        Self {
+            program: Program::default(),
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            bodies: vec![],
+        }
+    }
+
+    // Declares every item first, so functions can use the ones after them.
+    // The type checker gave overloads and instances their own names
+    fn declare_items(&mut self, ast: &[Ast]) {
+        for node in ast.iter() {
+            match node {
+                Ast::Function(function) => {
+                    // `fn main()` exits with 0 unless it returns a value
+                    let return_type = match function.name.as_str() {
+                        "main" => Some(function.return_type.clone().unwrap_or(Type::Int)),
+                        _ => function.return_type.clone(),
+                    };
+                    let id = self.program.declare(
+                        &function.name,
+                        DeclarationKind::Function,
+                        function_type(&function.parameters, &return_type),
+                    );
+                    self.functions.insert(function.name.clone(), id);
+                    self.bodies.push(id);
+                }
+                Ast::Impl(impl_node) => {
+                    for method in impl_node.methods.iter() {
+                        let name = mangle_method(&impl_node.type_name, &method.name);
+                        let id = self.program.declare(
+                            &name,
+                            DeclarationKind::Function,
+                            function_type(&method.parameters, &method.return_type),
+                        );
+                        self.functions.insert(name, id);
+                        self.bodies.push(id);
+                    }
+                }
+                // Modules may declare the same C function more than once
+                Ast::Extern(extern_node) => {
+                    let prototype = &extern_node.prototype;
+                    if self.functions.contains_key(&extern_node.c_name) {
+                        continue;
+                    }
+                    let id = self.program.declare(
+                        &extern_node.c_name,
+                        DeclarationKind::Extern,
+                        function_type(&prototype.parameters, &prototype.return_type),
+                    );
+                    let parameters = prototype
+                        .parameters
+                        .iter()
+                        .map(|parameter| {
+                            self.program.declare(
+                                &parameter.name,
+                                DeclarationKind::Local,
+                                parameter.parameter_type.clone(),
+                            )
+                        })
+                        .collect();
+                    self.program.externs.push(Extern {
+                        id,
+                        parameters,
+                        return_type: prototype.return_type.clone().unwrap_or(Type::Void),
+                    });
+                    self.functions.insert(extern_node.c_name.clone(), id);
+                }
+                Ast::Assignment(assignment) => {
+                    let global_type = assignment.var_type.clone().unwrap_or(Type::Void);
+                    let id = self.program.declare(
+                        &assignment.name,
+                        DeclarationKind::Global,
+                        global_type,
+                    );
+                    self.scopes[0].insert(assignment.name.clone(), id);
+                }
+                Ast::Struct(struct_node) => {
+                    self.structs
+                        .insert(struct_node.name.clone(), struct_node.clone());
+                    self.program.structs.push(struct_node.clone());
+                }
+                Ast::Enum(enum_node) => {
+                    self.enums.insert(enum_node.name.clone(), enum_node.clone());
+                    self.program.enums.push(enum_node.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn lower_items(&mut self, ast: Vec<Ast>) -> Result<(), String> {
+        let mut bodies = std::mem::take(&mut self.bodies).into_iter();
+        for node in ast.into_iter() {
+            match node {
+                Ast::Function(function) => {
+                    let id = bodies.next().ok_or("Function wasn't declared")?;
+                    let main = function.name == "main" && function.return_type.is_none();
+                    let mut function = self.lower_function(id, function)?;
+                    if main {
+                        return_zero(&mut function.body);
+                        function
+                            .body
+                            .statements
+                            .push(Statement::Return(Some(Expression::int(0))));
+                    }
+                    self.program.functions.push(function);
+                }
+                // Methods are reached through their type, from any module
+                Ast::Impl(impl_node) => {
+                    for mut method in impl_node.methods.into_iter() {
+                        let id = bodies.next().ok_or("Method wasn't declared")?;
+                        method.public = true;
+                        let method = self.lower_function(id, method)?;
+                        self.program.functions.push(method);
+                    }
+                }
+                Ast::Assignment(assignment) => {
+                    let id = self.scopes[0][&assignment.name];
+                    let value = self.lower_expression(&assignment.value)?;
+                    self.program.globals.push(Global { id, value });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn lower_function(&mut self, id: DeclId, function: ast::Function) -> Result<Function, String> {
+        self.scopes.push(HashMap::new());
+        let parameters = self.declare_parameters(&function.parameters);
+        let body = self.lower_block(&function.body);
+        self.scopes.pop();
+
+        let return_type = match &self.program.declarations[id].declaration_type {
+            Type::Function(_, return_type) => *return_type.clone(),
+            t => return Err(format!("Function with type {}", t)),
+        };
+        Ok(Function {
+            id,
+            parameters,
+            return_type,
+            body: body?,
+            public: function.public,
+            exported: function.exported,
+        })
+    }
+
+    // A variable hiding another one gets a name of its own, since C would
+    // read the new one in its initializer. The type checker rejects names
+    // starting with `idk_`
+    fn declare(&mut self, name: &str, variable_type: Type) -> DeclId {
+        let unique = match self.get_variable(name) {
+            Some(_) => format!("idk_shadow{}_{}", self.program.declarations.len(), name),
+            None => name.to_string(),
+        };
+        let id = self
+            .program
+            .declare(&unique, DeclarationKind::Local, variable_type);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), id);
+        }
+        id
+    }
+
+    fn declare_parameters(&mut self, parameters: &[ast::Parameter]) -> Vec<DeclId> {
+        parameters
+            .iter()
+            .map(|parameter| self.declare(&parameter.name, parameter.parameter_type.clone()))
+            .collect()
+    }
+
+    fn get_variable(&self, name: &str) -> Option<DeclId> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn lower_block(&mut self, block: &ast::Block) -> Result<Block, String> {
+        self.scopes.push(HashMap::new());
+        let statements: Result<Vec<Statement>, String> = block
+            .itens
+            .iter()
+            .map(|statement| self.lower_statement(statement))
+            .collect();
+        self.scopes.pop();
+        Ok(Block {
+            statements: statements?,
+        })
+    }
+
+    // Scope for a branch or a loop body that binds a value first
+    fn lower_binding_block(
+        &mut self,
+        name: &str,
+        binding_type: Type,
+        block: &ast::Block,
+    ) -> Result<(DeclId, Block), String> {
+        self.scopes.push(HashMap::new());
+        let id = self.declare(name, binding_type);
+        let block = self.lower_block(block);
+        self.scopes.pop();
+        Ok((id, block?))
+    }
+
+    fn lower_statement(&mut self, statement: &Ast) -> Result<Statement, String> {
+        match statement {
+            // The value can't see the variable it initializes
+            Ast::Assignment(assignment) => {
+                let value = self.lower_expression(&assignment.value)?;
+                let variable_type = assignment
+                    .var_type
+                    .clone()
+                    .ok_or(format!("Type of '{}' wasn't inferred", assignment.name))?;
+                Ok(Statement::Let(
+                    self.declare(&assignment.name, variable_type),
+                    value,
+                ))
+            }
+            Ast::Reassignment(reassignment) => Ok(Statement::Assign(
+                self.lower_expression(&reassignment.target)?,
+                self.lower_expression(&reassignment.value)?,
+            )),
+            Ast::Return(value) => Ok(Statement::Return(match value {
+                Some(value) => Some(self.lower_expression(value)?),
+                None => None,
+            })),
+            Ast::Expression(expression) => {
+                Ok(Statement::Expression(self.lower_expression(expression)?))
+            }
+            Ast::For(for_loop) => {
+                let iterable = self.lower_expression(&for_loop.iterable)?;
+                let element_type = match &iterable.expression_type {
+                    Type::Array(element_type, _) | Type::Slice(element_type) => {
+                        *element_type.clone()
+                    }
+                    t => return Err(format!("Can't iterate over {}", t)),
+                };
+                let (variable, body) =
+                    self.lower_binding_block(&for_loop.variable, element_type, &for_loop.body)?;
+                Ok(Statement::For(variable, iterable, body))
+            }
+            Ast::If(if_node) => {
+                let (condition, body) = match &if_node.condition {
+                    ast::Condition::Expression(condition) => (
+                        Condition::Expression(self.lower_expression(condition)?),
+                        self.lower_block(&if_node.body)?,
+                    ),
+                    ast::Condition::Let(name, optional) => {
+                        let optional = self.lower_expression(optional)?;
+                        let value_type = match &optional.expression_type {
+                            Type::Optional(value_type) | Type::Result(value_type, _) => {
+                                *value_type.clone()
+                            }
+                            t => {
+                                return Err(format!(
+                                    "Expected an optional or a result, found {}",
+                                    t
+                                ))
+                            }
+                        };
+                        let (value, body) =
+                            self.lower_binding_block(name, value_type, &if_node.body)?;
+                        (Condition::Let(value, optional), body)
+                    }
+                };
+                let else_body = match &if_node.else_body {
+                    Some(else_body) => Some(self.lower_block(else_body)?),
+                    None => None,
+                };
+                Ok(Statement::If(condition, body, else_body))
+            }
+            statement => Err(format!("Unexpected statement: {:?}", statement)),
+        }
+    }
+
+    fn lower_expressions(
+        &mut self,
+        expressions: &[ast::Expression],
+    ) -> Result<Vec<Expression>, String> {
+        expressions
+            .iter()
+            .map(|expression| self.lower_expression(expression))
+            .collect()
+    }
+
+    // Calls through a variable holding a function and to the builtins, the
+    // type checker resolved every other call
+    fn lower_call(
+        &mut self,
+        name: &str,
+        arguments: &[ast::Expression],
+    ) -> Result<Expression, String> {
+        let values = self.lower_expressions(arguments)?;
+        if let Some(id) = self.get_variable(name) {
+            let callee_type = self.program.declarations[id].declaration_type.clone();
+            let return_type = match &callee_type {
+                Type::Function(_, return_type) => *return_type.clone(),
+                _ => return Err(format!("Variable '{}' can't be called", name)),
+            };
+            let callee = Expression::new(ExpressionKind::Name(id), callee_type);
+            return Ok(Expression::new(
+                ExpressionKind::CallValue(Box::new(callee), values),
+                return_type,
+            ));
+        }
+
+        let (builtin, builtin_type) = match name {
+            "len" => (Builtin::Len, Type::Int),
+            "push" => (Builtin::Push, Type::Void),
+            "pop" => match &values[0].expression_type {
+                Type::Slice(element_type) => (Builtin::Pop, *element_type.clone()),
+                t => return Err(format!("Expected a slice, found {}", t)),
+            },
+            _ => return Err(format!("Call to '{}' wasn't resolved", name)),
+        };
+        Ok(Expression::new(
+            ExpressionKind::Builtin(builtin, values),
+            builtin_type,
+        ))
+    }
+
+    fn get_function(&self, name: &str) -> Result<DeclId, String> {
+        self.functions
+            .get(name)
+            .copied()
+            .ok_or(format!("Unknown function: {}", name))
+    }
+
+    fn lower_match(
+        &mut self,
+        subject: &ast::Expression,
+        arms: &[ast::MatchArm],
+    ) -> Result<Expression, String> {
+        let subject = self.lower_expression(subject)?;
+        let enum_node = match &subject.expression_type {
+            Type::UserDefined(name) => self
+                .enums
+                .get(name)
+                .cloned()
+                .ok_or(format!("Unknown enum: {}", name))?,
+            t => return Err(format!("Can't match on {}", t)),
+        };
+
+        let mut lowered_arms: Vec<MatchArm> = vec![];
+        for arm in arms.iter() {
+            self.scopes.push(HashMap::new());
+            let pattern = match &arm.pattern {
+                ast::Pattern::Wildcard => Pattern::Wildcard,
+                ast::Pattern::Variant(_, variant, bindings) => {
+                    let fields = enum_node
+                        .get_variant(variant)
+                        .map(|declaration| declaration.fields.clone())
+                        .unwrap_or_default();
+                    let bindings = bindings
+                        .iter()
+                        .zip(fields)
+                        .map(|(binding, field_type)| match binding.as_str() {
+                            "_" => None,
+                            binding => Some(self.declare(binding, field_type)),
+                        })
+                        .collect();
+                    Pattern::Variant(variant.clone(), bindings)
+                }
+            };
+            let value = self.lower_expression(&arm.value);
+            self.scopes.pop();
+            lowered_arms.push(MatchArm {
+                pattern,
+                value: value?,
+            });
+        }
+
+        let match_type = match lowered_arms.last() {
+            Some(arm) => arm.value.expression_type.clone(),
+            None => Type::Void,
+        };
+        Ok(Expression::new(
+            ExpressionKind::Match(Box::new(subject), lowered_arms),
+            match_type,
+        ))
+    }
+
+    fn lower_closure(&mut self, closure: &ast::Closure) -> Result<Expression, String> {
+        let return_type = closure.return_type.clone().unwrap_or(Type::Void);
+        self.scopes.push(HashMap::new());
+        let parameters = self.declare_parameters(&closure.parameters);
+        let body = self.lower_block(&closure.body);
+        self.scopes.pop();
+
+        let mut captures: Vec<DeclId> = vec![];
+        for (capture, _) in closure.captures.iter() {
+            captures.push(
+                self.get_variable(capture)
+                    .ok_or(format!("Unknown captured variable: {}", capture))?,
+            );
+        }
+        Ok(Expression::new(
+            ExpressionKind::Closure(Closure {
+                parameters,
+                return_type,
+                body: body?,
+                captures,
+            }),
+            function_type(&closure.parameters, &closure.return_type),
+        ))
+    }
+
+    fn lower_expression(&mut self, expression: &ast::Expression) -> Result<Expression, String> {
+        match expression {
+            ast::Expression::Int(value) => Ok(Expression::int(*value)),
+            ast::Expression::Float(value) => {
+                Ok(Expression::new(ExpressionKind::Float(*value), Type::Float))
+            }
+            ast::Expression::StringLit(value) => Ok(Expression::new(
+                ExpressionKind::String(value.clone()),
+                Type::String,
+            )),
+            ast::Expression::Boolean(value) => {
+                Ok(Expression::new(ExpressionKind::Bool(*value), Type::Bool))
+            }
+            ast::Expression::Identifier(name) => {
+                let id = self
+                    .get_variable(name)
+                    .ok_or(format!("Unknown variable: {}", name))?;
+                Ok(Expression::new(
+                    ExpressionKind::Name(id),
+                    self.program.declarations[id].declaration_type.clone(),
+                ))
+            }
+            ast::Expression::Function(name) => {
+                let id = self.get_function(name)?;
+                Ok(Expression::new(
+                    ExpressionKind::Name(id),
+                    self.program.declarations[id].declaration_type.clone(),
+                ))
+            }
+            ast::Expression::BinaryExpr(lhs, operator, rhs) => {
+                let lhs = self.lower_expression(lhs)?;
+                let rhs = self.lower_expression(rhs)?;
+                let binary_type = lhs.expression_type.clone();
+                Ok(Expression::new(
+                    ExpressionKind::Binary(Box::new(lhs), operator.clone(), Box::new(rhs)),
+                    binary_type,
+                ))
+            }
+            // The type checker marks every implicit conversion with a cast
+            ast::Expression::Cast(inner, target_type) => {
+                let kind = match (&**inner, target_type) {
+                    (ast::Expression::NoneLit, Type::Optional(_)) => ExpressionKind::NoneLit,
+                    (ast::Expression::Err(error), Type::Result(..)) => {
+                        ExpressionKind::Err(Box::new(self.lower_expression(error)?))
+                    }
+                    (_, Type::Optional(_) | Type::Result(..)) => {
+                        ExpressionKind::Wrap(Box::new(self.lower_expression(inner)?))
+                    }
+                    (ast::Expression::Array(items), Type::Slice(_)) => {
+                        ExpressionKind::Slice(self.lower_expressions(items)?)
+                    }
+                    _ => ExpressionKind::Cast(Box::new(self.lower_expression(inner)?)),
+                };
+                Ok(Expression::new(kind, target_type.clone()))
+            }
+            ast::Expression::Array(items) => {
+                let items = self.lower_expressions(items)?;
+                let element_type = match items.first() {
+                    Some(item) => item.expression_type.clone(),
+                    None => return Err("Can't get type of an empty array".to_string()),
+                };
+                let length = items.len();
+                Ok(Expression::new(
+                    ExpressionKind::Array(items),
+                    Type::Array(Box::new(element_type), length),
+                ))
+            }
+            ast::Expression::Index(array, index) => {
+                let array = self.lower_expression(array)?;
+                let element_type = match &array.expression_type {
+                    Type::Array(element_type, _) | Type::Slice(element_type) => {
+                        *element_type.clone()
+                    }
+                    t => return Err(format!("Type {} can't be indexed", t)),
+                };
+                let index = self.lower_expression(index)?;
+                Ok(Expression::new(
+                    ExpressionKind::Index(Box::new(array), Box::new(index)),
+                    element_type,
+                ))
+            }
+            ast::Expression::FunctionCall(name, arguments) => self.lower_call(name, arguments),
+            ast::Expression::Call(name, arguments) => {
+                let id = self.get_function(name)?;
+                let return_type = match &self.program.declarations[id].declaration_type {
+                    Type::Function(_, return_type) => *return_type.clone(),
+                    t => return Err(format!("Function '{}' has type {}", name, t)),
+                };
+                Ok(Expression::new(
+                    ExpressionKind::Call(id, self.lower_expressions(arguments)?),
+                    return_type,
+                ))
+            }
+            ast::Expression::MethodCall(..) => {
+                Err(format!("Method call `{}` wasn't resolved", expression))
+            }
+            ast::Expression::StructLiteral(name, fields) => {
+                let mut lowered_fields: Vec<(String, Expression)> = vec![];
+                for (field, value) in fields.iter() {
+                    lowered_fields.push((field.clone(), self.lower_expression(value)?));
+                }
+                Ok(Expression::new(
+                    ExpressionKind::Struct(name.clone(), lowered_fields),
+                    Type::UserDefined(name.clone()),
+                ))
+            }
+            ast::Expression::FieldAccess(base, field) => {
+                let base = self.lower_expression(base)?;
+                let field_type = match base.expression_type.dereferenced() {
+                    Type::UserDefined(name) => self
+                        .structs
+                        .get(name)
+                        .and_then(|s| s.get_field(field))
+                        .map(|field| field.field_type.clone())
+                        .ok_or(format!("Unknown field: {}.{}", name, field))?,
+                    t => return Err(format!("Type {} has no fields", t)),
+                };
+                Ok(Expression::new(
+                    ExpressionKind::Field(Box::new(base), field.clone()),
+                    field_type,
+                ))
+            }
+            ast::Expression::EnumVariant(name, variant, arguments) => Ok(Expression::new(
+                ExpressionKind::Variant(
+                    name.clone(),
+                    variant.clone(),
+                    self.lower_expressions(arguments)?,
+                ),
+                Type::UserDefined(name.clone()),
+            )),
+            ast::Expression::Match(subject, arms) => self.lower_match(subject, arms),
+            ast::Expression::AddressOf(place, mutable) => {
+                let place = self.lower_expression(place)?;
+                let reference_type =
+                    Type::Reference(Box::new(place.expression_type.clone()), *mutable);
+                Ok(Expression::new(
+                    ExpressionKind::AddressOf(Box::new(place)),
+                    reference_type,
+                ))
+            }
+            ast::Expression::Dereference(reference) => {
+                let reference = self.lower_expression(reference)?;
+                let referenced_type = match &reference.expression_type {
+                    Type::Reference(referenced_type, _) => *referenced_type.clone(),
+                    t => return Err(format!("Type {} can't be dereferenced", t)),
+                };
+                Ok(Expression::new(
+                    ExpressionKind::Dereference(Box::new(reference)),
+                    referenced_type,
+                ))
+            }
+            ast::Expression::Try(inner) => {
+                let inner = self.lower_expression(inner)?;
+                let value_type = match &inner.expression_type {
+                    Type::Result(value_type, _) => *value_type.clone(),
+                    t => return Err(format!("Expected a result, found {}", t)),
+                };
+                Ok(Expression::new(
+                    ExpressionKind::Try(Box::new(inner)),
+                    value_type,
+                ))
+            }
+            ast::Expression::Closure(closure) => self.lower_closure(closure),
+            ast::Expression::NoneLit => {
+                Err("`none` should have been converted to an optional".to_string())
+            }
+            ast::Expression::Err(_) => Err(format!(
+                "`{}` should have been converted to a result",
+                expression
+            )),
+            ast::Expression::NamedArgument(..) => {
+                Err(format!("Named argument `{}` outside of a call", expression))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{check, find};
+
+    fn lower_source(input: &str) -> Result<Program, String> {
+        lower(check(input)?)
+    }
+
+    #[test]
+    fn test_lower_calls() {
+        let program = lower_source(
+            "fn sum(int a, int b = 2): int { return a + b; }
+            fn sum(float a, float b): float { return a + b; }
+            struct P { int x; }
+            impl P { fn get(self, int d = 1): int { return self.x + d; } }
+            fn main(): int { P p = P { x: 1 }; &P r = &p; return sum(b: 3, a: 1) + r.get(); }",
+        )
+        .unwrap();
+
+        let names: Vec<&str> = program
+            .functions
+            .iter()
+            .map(|function| program.name(function.id))
+            .collect();
+        assert_eq!(
+            names,
+            vec!["sum_int_int", "sum_float_float", "P_get", "main"]
+        );
+
+        let main = find(&program, "main");
+        let value = match &main.body.statements[2] {
+            Statement::Return(Some(value)) => value,
+            statement => panic!("Expected a return, found {:?}", statement),
+        };
+        let (sum, get) = match &value.kind {
+            ExpressionKind::Binary(sum, Token::Plus, get) => (sum, get),
+            kind => panic!("Expected a sum, found {:?}", kind),
+        };
+        match &sum.kind {
+            ExpressionKind::Call(id, arguments) => {
+                assert_eq!(program.name(*id), "sum_int_int");
+                assert_eq!(arguments[0], Expression::int(1));
+                assert_eq!(arguments[1], Expression::int(3));
+            }
+            kind => panic!("Expected a call, found {:?}", kind),
+        }
+        match &get.kind {
+            ExpressionKind::Call(id, arguments) => {
+                assert_eq!(program.name(*id), "P_get");
+                assert!(matches!(arguments[0].kind, ExpressionKind::Dereference(_)));
+                assert_eq!(
+                    arguments[0].expression_type,
+                    Type::UserDefined("P".to_string())
+                );
+                assert_eq!(arguments[1], Expression::int(1));
+            }
+            kind => panic!("Expected a call, found {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_lower_names() {
+        let program = lower_source(
+            "int x = 1;
+            fn main() { int y = x; if true { float x = 2.0; y = x as int; } return; }",
+        )
+        .unwrap();
+
+        let global = program.globals[0].id;
+        let main = find(&program, "main");
+        assert_eq!(
+            program.declarations[main.id].declaration_type,
+            Type::Function(vec![], Box::new(Type::Int))
+        );
+        let y = match &main.body.statements[0] {
+            Statement::Let(y, value) => {
+                assert_eq!(value.kind, ExpressionKind::Name(global));
+                *y
+            }
+            statement => panic!("Expected a let, found {:?}", statement),
+        };
+        match &main.body.statements[1] {
+            Statement::If(_, body, None) => match &body.statements[..] {
+                [Statement::Let(x, _), Statement::Assign(target, value)] => {
+                    // The x of the branch hides the global in C too
+                    assert_eq!(program.name(*x), format!("idk_shadow{}_x", x));
+                    assert_eq!(target.kind, ExpressionKind::Name(y));
+                    match &value.kind {
+                        ExpressionKind::Cast(inner) => {
+                            assert_ne!(inner.kind, ExpressionKind::Name(global));
+                            assert_eq!(inner.expression_type, Type::Float);
+                        }
+                        kind => panic!("Expected a cast, found {:?}", kind),
+                    }
+                }
+                statements => panic!("Expected a let and an assignment, found {:?}", statements),
+            },
+            statement => panic!("Expected an if, found {:?}", statement),
+        }
+        assert_eq!(
+            main.body.statements[2..],
+            [
+                Statement::Return(Some(Expression::int(0))),
+                Statement::Return(Some(Expression::int(0)))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lower_conversions() {
+        let program = lower_source(
+            "fn f(): ?int { return none; }
+            fn g(): int!string { return err(\"bad\"); }
+            fn h(int a): []int { float x = a; return [1, 2]; }",
+        )
+        .unwrap();
+
+        let returned = |name: &str| match &find(&program, name).body.statements.last() {
+            Some(Statement::Return(Some(value))) => value.clone(),
+            statement => panic!("Expected a return, found {:?}", statement),
+        };
+        assert_eq!(returned("f").kind, ExpressionKind::NoneLit);
+        assert!(matches!(returned("g").kind, ExpressionKind::Err(_)));
+        match returned("h").kind {
+            ExpressionKind::Slice(items) => assert_eq!(items.len(), 2),
+            kind => panic!("Expected a slice, found {:?}", kind),
+        }
+        match &find(&program, "h").body.statements[0] {
+            Statement::Let(_, value) => {
+                let parameter = find(&program, "h").parameters[0];
+                assert_eq!(
+                    value.kind,
+                    ExpressionKind::Cast(Box::new(Expression::new(
+                        ExpressionKind::Name(parameter),
+                        Type::Int
+                    )))
+                );
+                assert_eq!(value.expression_type, Type::Float);
+            }
+            statement => panic!("Expected a let, found {:?}", statement),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::ast::Type;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 #[derive(PartialEq)]
 pub enum Associativity {
@@ -26,9 +27,31 @@ pub enum Token {
     KeywordFloat,  // float
     KeywordBool,   // bool
     KeywordString, // string
+    KeywordAs,     // as
+    KeywordTrue,   // true
+    KeywordFalse,  // false
+    KeywordFor,    // for
+    KeywordIn,     // in
+    KeywordStruct, // struct
+    KeywordEnum,   // enum
+    KeywordMatch,  // match
+    KeywordImpl,   // impl
+    KeywordTrait,  // trait
+    KeywordMut,    // mut
+    KeywordLet,    // let
+    KeywordNone,   // none
+    KeywordErr,    // err
+    KeywordImport, // import
+    KeywordUse,    // use
+    KeywordPub,    // pub
+    KeywordExtern, // extern
+    KeywordExport, // export
 
     // Special characters
     Colon,
+    DoubleColon, // ::
+    FatArrow,    // =>
+    QuestionMark,
     LeftBracket,
     RightBracket,
     LeftCurly,
@@ -38,6 +61,7 @@ pub enum Token {
     Semicolon,
     EqualSign,
     Comma,
+    Dot,
 
     // Operator
     Plus,
@@ -82,7 +106,7 @@ impl Token {
             Token::Times,
             Token::Power,
         ]);
-        operators.get(self).is_some()
+        operators.contains(self)
     }
 
     pub fn is_data_type_keyword(&self) -> bool {
@@ -93,7 +117,7 @@ impl Token {
             Token::KeywordString,
         ]);
 
-        data_types.get(self).is_some()
+        data_types.contains(self)
     }
 
     pub fn as_type(&self) -> Result<Type, String> {
@@ -101,18 +125,18 @@ impl Token {
             Token::KeywordInt => Ok(Type::Int),
             Token::KeywordFloat => Ok(Type::Float),
             Token::KeywordBool => Ok(Type::Bool),
-            Token::KeywordString => Ok(Type::StringType),
+            Token::KeywordString => Ok(Type::String),
             token => Err(format!("Can't parse type: {:?}", token)),
         }
     }
 
-    pub fn is_number(&self) -> bool {
-        matches!(self, Token::IntNumber(_) | Token::FloatNumber(_))
-    }
-
     pub fn get_associativity(&self) -> Associativity {
         match self {
-            Token::Plus | Token::Minus | Token::Times | Token::Divides => Associativity::Left,
+            Token::Plus | Token::Minus | Token::Times | Token::Divides | Token::Mod => {
+                Associativity::Left
+            }
+            // 2 ** 3 ** 2 is 2 ** (3 ** 2)
+            Token::Power => Associativity::Right,
             _ => Associativity::Undefined,
         }
     }
@@ -131,11 +155,85 @@ impl Token {
             Token::Minus => 1,
             Token::Times => 2,
             Token::Divides => 2,
+            Token::Mod => 2,
+            Token::Power => 3,
             _ => -1,
         }
     }
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::IntNumber(value) | Token::FloatNumber(value) | Token::Identifier(value) => value,
+            Token::StringValue(value) => return write!(f, "\"{}\"", value),
+            Token::KeywordFn => "fn",
+            Token::KeywordIf => "if",
+            Token::KeywordElif => "elif",
+            Token::KeywordElse => "else",
+            Token::KeywordReturn => "return",
+            Token::KeywordInt => "int",
+            Token::KeywordFloat => "float",
+            Token::KeywordBool => "bool",
+            Token::KeywordString => "string",
+            Token::KeywordAs => "as",
+            Token::KeywordTrue => "true",
+            Token::KeywordFalse => "false",
+            Token::KeywordFor => "for",
+            Token::KeywordIn => "in",
+            Token::KeywordStruct => "struct",
+            Token::KeywordEnum => "enum",
+            Token::KeywordMatch => "match",
+            Token::KeywordImpl => "impl",
+            Token::KeywordTrait => "trait",
+            Token::KeywordMut => "mut",
+            Token::KeywordLet => "let",
+            Token::KeywordNone => "none",
+            Token::KeywordErr => "err",
+            Token::KeywordImport => "import",
+            Token::KeywordUse => "use",
+            Token::KeywordPub => "pub",
+            Token::KeywordExtern => "extern",
+            Token::KeywordExport => "export",
+            Token::Colon => ":",
+            Token::DoubleColon => "::",
+            Token::FatArrow => "=>",
+            Token::QuestionMark => "?",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::LeftCurly => "{",
+            Token::RightCurly => "}",
+            Token::LeftPar => "(",
+            Token::RightPar => ")",
+            Token::Semicolon => ";",
+            Token::EqualSign => "=",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Mod => "%",
+            Token::Divides => "/",
+            Token::Times => "*",
+            Token::Power => "**",
+            Token::GreaterThan => ">",
+            Token::LessThan => "<",
+            Token::GreaterThanOrEqual => ">=",
+            Token::LessThanOrEqual => "<=",
+            Token::NotEqual => "!=",
+            Token::EqualTo => "==",
+            Token::Increment => "++",
+            Token::Decrement => "--",
+            Token::Not => "!",
+            Token::LogicAnd => "&&",
+            Token::LogicOr => "||",
+            Token::BitwiseAnd => "&",
+            Token::BitwiseOr => "|",
+            Token::Eof => "end of file",
+        };
+        write!(f, "{}", text)
+    }
+}
+
 pub struct Lexer {
     source_code: Vec<char>,
     position: usize,
@@ -163,6 +261,7 @@ impl Lexer {
             self.position += 1;
         } else {
             self.is_end_of_file = true;
+            self.current_char = '\0';
         }
     }
 
@@ -188,6 +287,25 @@ impl Lexer {
             ("int", Token::KeywordInt),
             ("float", Token::KeywordFloat),
             ("string", Token::KeywordString),
+            ("as", Token::KeywordAs),
+            ("true", Token::KeywordTrue),
+            ("false", Token::KeywordFalse),
+            ("for", Token::KeywordFor),
+            ("in", Token::KeywordIn),
+            ("struct", Token::KeywordStruct),
+            ("enum", Token::KeywordEnum),
+            ("match", Token::KeywordMatch),
+            ("impl", Token::KeywordImpl),
+            ("trait", Token::KeywordTrait),
+            ("mut", Token::KeywordMut),
+            ("let", Token::KeywordLet),
+            ("none", Token::KeywordNone),
+            ("err", Token::KeywordErr),
+            ("import", Token::KeywordImport),
+            ("use", Token::KeywordUse),
+            ("pub", Token::KeywordPub),
+            ("extern", Token::KeywordExtern),
+            ("export", Token::KeywordExport),
         ]);
 
         match keywords.get(identifier) {
@@ -229,31 +347,46 @@ impl Lexer {
         }
     }
 
-    fn get_string(&mut self) -> Token {
+    fn get_string(&mut self) -> Result<Token, String> {
         self.advance();
         let mut string = String::new();
 
-        println!("READING STRING: {}", self.current_char);
+        eprintln!("READING STRING: {}", self.current_char);
         while self.current_char != '"' {
+            if self.is_end_of_file {
+                return Err("Error: Unterminated string literal".to_string());
+            }
             string.push(self.current_char);
             self.advance();
         }
 
         self.advance();
-        Token::StringValue(string)
+        Ok(Token::StringValue(string))
     }
 
-    fn get_token(&mut self) -> Token {
+    fn get_token(&mut self) -> Result<Token, String> {
         self.skip_any_whitespace();
 
-        match self.current_char {
-            letter if letter.is_alphabetic() => self.get_identifier(),
+        if self.is_end_of_file {
+            return Ok(Token::Eof);
+        }
+
+        let token = match self.current_char {
+            letter if letter.is_alphabetic() || letter == '_' => self.get_identifier(),
 
             digit if digit.is_ascii_digit() => self.get_number(),
 
-            '"' => self.get_string(),
+            '"' => self.get_string()?,
+
+            ':' => {
+                self.advance();
 
-            ':' => self.consume_and_advance(Token::Colon),
+                if self.current_char == ':' {
+                    return Ok(self.consume_and_advance(Token::DoubleColon));
+                }
+
+                Token::Colon
+            }
 
             '(' => self.consume_and_advance(Token::LeftPar),
 
@@ -267,6 +400,10 @@ impl Lexer {
 
             ',' => self.consume_and_advance(Token::Comma),
 
+            '.' => self.consume_and_advance(Token::Dot),
+
+            '?' => self.consume_and_advance(Token::QuestionMark),
+
             '[' => self.consume_and_advance(Token::LeftBracket),
 
             ']' => self.consume_and_advance(Token::RightBracket),
@@ -275,7 +412,11 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '=' {
-                    return self.consume_and_advance(Token::EqualTo);
+                    return Ok(self.consume_and_advance(Token::EqualTo));
+                }
+
+                if self.current_char == '>' {
+                    return Ok(self.consume_and_advance(Token::FatArrow));
                 }
 
                 Token::EqualSign
@@ -285,7 +426,7 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '=' {
-                    return self.consume_and_advance(Token::GreaterThanOrEqual);
+                    return Ok(self.consume_and_advance(Token::GreaterThanOrEqual));
                 }
 
                 Token::GreaterThan
@@ -295,7 +436,7 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '=' {
-                    return self.consume_and_advance(Token::LessThanOrEqual);
+                    return Ok(self.consume_and_advance(Token::LessThanOrEqual));
                 }
 
                 Token::LessThan
@@ -305,7 +446,7 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '+' {
-                    return self.consume_and_advance(Token::Increment);
+                    return Ok(self.consume_and_advance(Token::Increment));
                 }
 
                 Token::Plus
@@ -315,7 +456,7 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '-' {
-                    return self.consume_and_advance(Token::Decrement);
+                    return Ok(self.consume_and_advance(Token::Decrement));
                 }
                 Token::Minus
             }
@@ -324,7 +465,7 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '=' {
-                    return self.consume_and_advance(Token::NotEqual);
+                    return Ok(self.consume_and_advance(Token::NotEqual));
                 }
                 Token::Not
             }
@@ -333,7 +474,7 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '|' {
-                    return self.consume_and_advance(Token::LogicOr);
+                    return Ok(self.consume_and_advance(Token::LogicOr));
                 }
                 Token::BitwiseOr
             }
@@ -342,7 +483,7 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '&' {
-                    return self.consume_and_advance(Token::LogicAnd);
+                    return Ok(self.consume_and_advance(Token::LogicAnd));
                 }
                 Token::BitwiseAnd
             }
@@ -353,40 +494,35 @@ impl Lexer {
                 self.advance();
 
                 if self.current_char == '*' {
-                    return self.consume_and_advance(Token::Power);
+                    return Ok(self.consume_and_advance(Token::Power));
                 }
                 Token::Times
             }
 
             '%' => self.consume_and_advance(Token::Mod),
 
-            _ => {
-                eprintln!("Error: Invalid token '{:?}'", self.current_char);
-                std::process::exit(1);
-            }
-        }
+            _ => return Err(format!("Error: Invalid token '{}'", self.current_char)),
+        };
+        Ok(token)
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    // Fails on the first character that doesn't start a token
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
         let mut tokens: Vec<Token> = vec![];
 
         self.advance();
 
-        while !self.is_eof() {
-            let token = self.get_token();
-
-            if let Token::Identifier(ref ident) = token {
-                tokens.push(self.classify_identifier(ident));
-            } else {
-                tokens.push(token);
-            }
+        while !self.is_end_of_file {
+            let token = self.get_token()?;
 
-            if self.is_end_of_file {
-                break;
+            match token {
+                Token::Eof => break,
+                Token::Identifier(ref ident) => tokens.push(self.classify_identifier(ident)),
+                token => tokens.push(token),
             }
         }
         tokens.push(Token::Eof);
-        tokens
+        Ok(tokens)
     }
 }
 
@@ -398,7 +534,7 @@ mod tests {
     fn test_variable_declaration_tokens() {
         let input = "int variable_name = 12;\n".chars().collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
 
         let expected_result: Vec<Token> = vec![
             Token::KeywordInt,
@@ -418,7 +554,7 @@ mod tests {
             .chars()
             .collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
 
         let expected_result: Vec<Token> = vec![
             Token::GreaterThan,
@@ -447,9 +583,11 @@ mod tests {
 
     #[test]
     fn test_special_characters() {
-        let input = "{ } [ ] ( ) = , ; :".chars().collect::<Vec<char>>();
+        let input = "{ } [ ] ( ) = , ; : . :: => ?"
+            .chars()
+            .collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
 
         let expected_result: Vec<Token> = vec![
             Token::LeftCurly,
@@ -462,6 +600,10 @@ mod tests {
             Token::Comma,
             Token::Semicolon,
             Token::Colon,
+            Token::Dot,
+            Token::DoubleColon,
+            Token::FatArrow,
+            Token::QuestionMark,
             Token::Eof,
         ];
 
@@ -472,11 +614,26 @@ mod tests {
     fn test_string() {
         let input = "\"my string here\"\n".chars().collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
 
         let expected_result: Vec<Token> =
             vec![Token::StringValue("my string here".to_string()), Token::Eof];
 
         assert_eq!(tokens, expected_result);
     }
+
+    #[test]
+    fn test_lexing_errors() {
+        let mut lexer = Lexer::new("string s = \"no end;\n".chars().collect());
+        assert_eq!(
+            lexer.tokenize(),
+            Err("Error: Unterminated string literal".to_string())
+        );
+
+        let mut lexer = Lexer::new("int a = 1 $ 2;\n".chars().collect());
+        assert_eq!(
+            lexer.tokenize(),
+            Err("Error: Invalid token '$'".to_string())
+        );
+    }
 }
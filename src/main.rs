@@ -1,62 +1,147 @@
 mod ast;
 mod backend;
 mod cli;
+mod hir;
 mod lexer;
+mod mir;
+mod modules;
+mod monomorphize;
+mod opt;
 mod parser;
+mod semantic;
+#[cfg(test)]
+mod testing;
 
 use backend::*;
 use clap::Parser as ClapParser;
-use cli::{get_target_language, Args, TargetLanguage};
-use lexer::Lexer;
-use parser::Parser;
+use cli::{
+    get_emit, get_library_kind, get_opt_level, get_target_language, Args, Emit, LibraryKind,
+    OptLevel, TargetLanguage,
+};
+use modules::ModuleGraph;
+use semantic::TypeChecker;
 use std::{fs, io, path::Path};
 
-fn get_source_code(path: &str) -> io::Result<Vec<char>> {
-    return Ok(fs::read_to_string(path)?.chars().collect::<Vec<char>>());
-}
-
 fn main() -> io::Result<()> {
     let args = Args::parse();
 
+    // Whether the library is a shared one
+    let library = match args.library.as_deref().map(get_library_kind) {
+        None => None,
+        Some(LibraryKind::Static) => Some(false),
+        Some(LibraryKind::Shared) => Some(true),
+        Some(LibraryKind::Unknown(unknown_kind)) => {
+            eprintln!("Unknown library kind: {}", unknown_kind);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(Emit::Unknown(unknown_emit)) = args.emit.as_deref().map(get_emit) {
+        eprintln!("Unknown representation: {}", unknown_emit);
+        std::process::exit(1);
+    }
+
+    let opt_level = match get_opt_level(&args.opt_level) {
+        OptLevel::O0 => 0,
+        OptLevel::O1 => 1,
+        OptLevel::O2 => 2,
+        OptLevel::Unknown(unknown_level) => {
+            eprintln!("Unknown optimization level: {}", unknown_level);
+            std::process::exit(1);
+        }
+    };
+
     let path = Path::new(&args.file_name);
     if !path.exists() {
         eprintln!("Error: No such file or directory");
         std::process::exit(1);
     }
 
-    let source_code = get_source_code(&args.file_name)?;
-
-    if source_code.is_empty() {
+    if fs::read_to_string(path)?.is_empty() {
         std::process::exit(1);
     }
 
-    println!("--STARTING LEXER--");
-    let mut lexer = Lexer::new(source_code);
-    let tokens = lexer.tokenize();
+    // Lexes and parses the file and every module it imports
+    eprintln!("--STARTING PARSER--");
+    let graph = match ModuleGraph::load(path) {
+        Ok(graph) => graph,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("--ENDING PARSER--");
+
+    eprintln!("--STARTING MODULE RESOLUTION--");
+    let modules: Vec<String> = graph
+        .modules
+        .iter()
+        .map(|module| module.name.clone())
+        .collect();
+    let imports = graph.imports();
+    let root = modules.last().unwrap().clone();
+    let mut ast = match graph.link() {
+        Ok(tree) => tree,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("--ENDING MODULE RESOLUTION--\n");
 
-    for token in tokens.iter() {
-        println!("{:?}", token);
+    eprintln!("--STARTING SEMANTIC ANALYSIS--");
+    let mut type_checker = TypeChecker::new();
+    if let Err(e) = type_checker.check(&mut ast) {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
-    println!("--ENDING LEXER--\n");
+    eprintln!("--ENDING SEMANTIC ANALYSIS--\n");
+
+    eprintln!("--STARTING HIR LOWERING--");
+    let mut program = match hir::lower(ast) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("--ENDING HIR LOWERING--\n");
 
-    println!("--STARTING PARSER--");
-    let mut parser = Parser::new(tokens);
+    if opt_level > 0 {
+        eprintln!("--STARTING OPTIMIZATION--");
+        opt::PassManager::new(opt_level).run(&mut program);
+        eprintln!("--ENDING OPTIMIZATION--\n");
+    }
 
-    let ast = match parser.generate_ast() {
-        Ok(tree) => tree,
+    // The optimised program is only translated when its MIR verifies
+    eprintln!("--STARTING MIR BUILDING--");
+    let mir = match mir::build(&program).and_then(|mir| mir::verify(&mir).map(|_| mir)) {
+        Ok(mir) => mir,
         Err(e) => {
             eprintln!("{}", e);
             std::process::exit(1);
         }
     };
+    eprintln!("--ENDING MIR BUILDING--\n");
 
-    println!("--ENDING PARSER--");
+    // --emit mir prints the MIR and stops before code generation. Everything
+    // else the compiler reports goes to stderr, so stdout only has the MIR
+    if args.emit.is_some() {
+        print!("{}", mir);
+        return Ok(());
+    }
 
-    println!("--STARTING CODE GENERATION--");
+    eprintln!("--STARTING CODE GENERATION--");
     let selected_language = get_target_language(&args.target_language);
 
     let mut code_generator = match selected_language {
-        TargetLanguage::C => c::C::new(),
+        TargetLanguage::C => c::C::new(
+            args.bounds_checks,
+            library.is_some(),
+            modules,
+            imports,
+            args.includes.clone(),
+        ),
         TargetLanguage::JavaScript => unimplemented!(),
         TargetLanguage::Unknown(unknown_language) => {
             eprintln!("Unknown target language: {}", unknown_language);
@@ -64,7 +149,31 @@ fn main() -> io::Result<()> {
         }
     };
 
-    code_generator.generate(ast);
-    println!("--STARTING CODE GENERATION--");
+    if let Err(e) = code_generator.generate(program) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    eprintln!("--ENDING CODE GENERATION--");
+
+    if let Some(shared) = library {
+        let extension = if shared { "so" } else { "a" };
+        let output = match &args.output {
+            Some(output) => output.clone(),
+            None => format!("lib{}.{}", root, extension),
+        };
+        eprintln!("--STARTING C COMPILATION--");
+        if let Err(e) = code_generator.build_library(&output, shared, &args.libraries) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        eprintln!("--ENDING C COMPILATION--");
+    } else if let Some(output) = &args.output {
+        eprintln!("--STARTING C COMPILATION--");
+        if let Err(e) = code_generator.compile(output, &args.libraries) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        eprintln!("--ENDING C COMPILATION--");
+    }
     Ok(())
 }
@@ -0,0 +1,1053 @@
+use super::*;
+use crate::hir::{
+    self, Condition, DeclId, DeclarationKind, Expression, ExpressionKind, Pattern, Program,
+    Statement,
+};
+use std::collections::{HashMap, HashSet};
+
+// Builds SSA directly while lowering, following "Simple and Efficient
+// Construction of Static Single Assignment Form" (Braun et al.): the value of
+// a variable is looked up in the block it is read in and then through its
+// predecessors, adding phis where they join. Loop headers are sealed once
+// their back edge is known
+
+// Declaration, or a variable the builder adds itself, numbered after them
+type Variable = usize;
+
+// Body storing the initial values of the globals
+pub const GLOBALS: &str = "<globals>";
+
+pub fn build(program: &Program) -> Result<Mir, String> {
+    let mut mir = Mir::default();
+    for global in program.globals.iter() {
+        mir.globals.push((
+            program.name(global.id).to_string(),
+            program.declarations[global.id].declaration_type.clone(),
+        ));
+    }
+
+    if !program.globals.is_empty() {
+        let mut builder = Builder::new(program, GLOBALS, Type::Void);
+        builder.start(&[])?;
+        for global in program.globals.iter() {
+            let value = builder.lower_value(&global.value)?;
+            let place = Place::Global(program.name(global.id).to_string());
+            builder.emit(Operation::Store(place, value), Type::Void)?;
+        }
+        mir.bodies.extend(builder.finish());
+    }
+
+    for function in program.functions.iter() {
+        mir.bodies.extend(build_body(
+            program,
+            program.name(function.id),
+            &function.parameters,
+            &function.return_type,
+            &function.body,
+        )?);
+    }
+    Ok(mir)
+}
+
+// Body of a function or a closure, followed by the bodies of its closures
+fn build_body(
+    program: &Program,
+    name: &str,
+    parameters: &[DeclId],
+    return_type: &Type,
+    body: &hir::Block,
+) -> Result<Vec<Body>, String> {
+    let mut builder = Builder::new(program, name, return_type.clone());
+    find_memory(body, &mut builder.memory);
+    builder.start(parameters)?;
+    builder.lower_block(body)?;
+    Ok(builder.finish())
+}
+
+// Variables that have to live in memory: the ones whose address is taken,
+// that are changed in part or that `push` and `pop` change. Closures are
+// bodies of their own and are left out
+fn find_memory(block: &hir::Block, memory: &mut HashSet<DeclId>) {
+    for statement in block.statements.iter() {
+        match statement {
+            Statement::Let(_, value)
+            | Statement::Expression(value)
+            | Statement::Return(Some(value)) => find_memory_in_expression(value, memory),
+            Statement::Assign(target, value) => {
+                if !matches!(target.kind, ExpressionKind::Name(_)) {
+                    memory.extend(target.root());
+                }
+                find_memory_in_expression(target, memory);
+                find_memory_in_expression(value, memory);
+            }
+            Statement::Return(None) => {}
+            Statement::If(condition, then_body, else_body) => {
+                match condition {
+                    Condition::Expression(value) | Condition::Let(_, value) => {
+                        find_memory_in_expression(value, memory)
+                    }
+                }
+                find_memory(then_body, memory);
+                if let Some(else_body) = else_body {
+                    find_memory(else_body, memory);
+                }
+            }
+            Statement::For(_, iterable, body) => {
+                find_memory_in_expression(iterable, memory);
+                find_memory(body, memory);
+            }
+        }
+    }
+}
+
+fn find_memory_in_expression(expression: &Expression, memory: &mut HashSet<DeclId>) {
+    let children: Vec<&Expression> = match &expression.kind {
+        ExpressionKind::Int(_)
+        | ExpressionKind::Float(_)
+        | ExpressionKind::String(_)
+        | ExpressionKind::Bool(_)
+        | ExpressionKind::Name(_)
+        | ExpressionKind::NoneLit
+        | ExpressionKind::Closure(_) => vec![],
+        ExpressionKind::Binary(lhs, _, rhs) | ExpressionKind::Index(lhs, rhs) => {
+            vec![lhs, rhs]
+        }
+        ExpressionKind::AddressOf(place) => {
+            memory.extend(place.root());
+            vec![place]
+        }
+        ExpressionKind::Builtin(builtin, arguments) => {
+            if *builtin != hir::Builtin::Len {
+                memory.extend(arguments.first().and_then(Expression::root));
+            }
+            arguments.iter().collect()
+        }
+        ExpressionKind::Cast(inner)
+        | ExpressionKind::Field(inner, _)
+        | ExpressionKind::Dereference(inner)
+        | ExpressionKind::Wrap(inner)
+        | ExpressionKind::Err(inner)
+        | ExpressionKind::Try(inner) => vec![inner],
+        ExpressionKind::Array(items)
+        | ExpressionKind::Slice(items)
+        | ExpressionKind::Call(_, items)
+        | ExpressionKind::Variant(_, _, items) => items.iter().collect(),
+        ExpressionKind::CallValue(callee, arguments) => {
+            std::iter::once(&**callee).chain(arguments).collect()
+        }
+        ExpressionKind::Struct(_, fields) => fields.iter().map(|(_, value)| value).collect(),
+        ExpressionKind::Match(subject, arms) => std::iter::once(&**subject)
+            .chain(arms.iter().map(|arm| &arm.value))
+            .collect(),
+    };
+    for child in children {
+        find_memory_in_expression(child, memory);
+    }
+}
+
+// Phis whose incoming values are all the same value, or the phi itself, are
+// replaced by that value until none is left
+fn remove_trivial_phis(body: &mut Body) {
+    loop {
+        let mut trivial = None;
+        'blocks: for (block, basic_block) in body.blocks.iter().enumerate() {
+            for (position, phi) in basic_block.phis.iter().enumerate() {
+                let mut values = phi
+                    .incoming
+                    .iter()
+                    .map(|(_, value)| *value)
+                    .filter(|value| *value != phi.result);
+                if let Some(first) = values.next() {
+                    if values.all(|value| value == first) {
+                        trivial = Some((block, position, first));
+                        break 'blocks;
+                    }
+                }
+            }
+        }
+
+        match trivial {
+            Some((block, position, value)) => {
+                let phi = body.blocks[block].phis.remove(position);
+                body.replace_uses(phi.result, value);
+            }
+            None => break,
+        }
+    }
+}
+
+// Numbers the values left from 0, in the order they are defined
+fn renumber(body: &mut Body) {
+    let mut numbers: HashMap<Value, Value> = HashMap::new();
+    let mut values: Vec<Type> = vec![];
+    let defined = body
+        .parameters
+        .iter()
+        .copied()
+        .chain(body.blocks.iter().flat_map(|block| {
+            block.phis.iter().map(|phi| phi.result).chain(
+                block
+                    .instructions
+                    .iter()
+                    .filter_map(|instruction| instruction.result),
+            )
+        }));
+    for value in defined {
+        numbers.insert(value, values.len());
+        values.push(body.values[value].clone());
+    }
+
+    let mut number = |value: &mut Value| {
+        if let Some(new) = numbers.get(value) {
+            *value = *new;
+        }
+    };
+    body.parameters.iter_mut().for_each(&mut number);
+    for block in body.blocks.iter_mut() {
+        for phi in block.phis.iter_mut() {
+            number(&mut phi.result);
+            phi.incoming.iter_mut().for_each(|(_, value)| number(value));
+        }
+        for instruction in block.instructions.iter_mut() {
+            if let Some(result) = instruction.result.as_mut() {
+                number(result);
+            }
+            instruction.operation.visit_values(&mut number);
+        }
+        block.terminator.visit_values(&mut number);
+    }
+    body.values = values;
+}
+
+struct Builder<'a> {
+    program: &'a Program,
+    body: Body,
+    // Bodies of the closures the body creates
+    closures: Vec<Body>,
+    closure_count: usize,
+    // Block instructions are added to, None after a terminator
+    current: Option<BlockId>,
+    predecessors: Vec<Vec<BlockId>>,
+    // Blocks whose predecessors are all known
+    sealed: HashSet<BlockId>,
+    // Value of each variable at the end of a block
+    definitions: HashMap<(Variable, BlockId), Value>,
+    // Phis of blocks that aren't sealed yet, completed when they are
+    incomplete: HashMap<BlockId, Vec<(Variable, Value)>>,
+    // Variables kept in memory, and the slots the ones used so far got
+    memory: HashSet<DeclId>,
+    slots: HashMap<DeclId, SlotId>,
+    // Types of the variables the builder adds
+    variables: Vec<Type>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(program: &'a Program, name: &str, return_type: Type) -> Self {
+        Self {
+            program,
+            body: Body::new(name, return_type),
+            closures: vec![],
+            closure_count: 0,
+            current: None,
+            predecessors: vec![],
+            sealed: HashSet::new(),
+            definitions: HashMap::new(),
+            incomplete: HashMap::new(),
+            memory: HashSet::new(),
+            slots: HashMap::new(),
+            variables: vec![],
+        }
+    }
+
+    // Adds the entry block, where the parameters are defined
+    fn start(&mut self, parameters: &[DeclId]) -> Result<(), String> {
+        let entry = self.new_block();
+        self.seal(entry)?;
+        self.current = Some(entry);
+        for parameter in parameters.iter() {
+            let value = self.new_value(self.variable_type(*parameter));
+            self.body.parameters.push(value);
+            self.define(*parameter, value)?;
+        }
+        Ok(())
+    }
+
+    // Functions without a value return at their end
+    fn finish(mut self) -> Vec<Body> {
+        if self.body.return_type == Type::Void {
+            self.terminate(Terminator::Return(None));
+        } else {
+            self.terminate(Terminator::Unreachable);
+        }
+        remove_trivial_phis(&mut self.body);
+        renumber(&mut self.body);
+
+        let mut bodies = vec![self.body];
+        bodies.extend(self.closures);
+        bodies
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.body.blocks.push(BasicBlock {
+            phis: vec![],
+            instructions: vec![],
+            terminator: Terminator::Unreachable,
+        });
+        self.predecessors.push(vec![]);
+        self.body.blocks.len() - 1
+    }
+
+    fn new_value(&mut self, value_type: Type) -> Value {
+        self.body.values.push(value_type);
+        self.body.values.len() - 1
+    }
+
+    fn new_variable(&mut self, variable_type: Type) -> Variable {
+        self.variables.push(variable_type);
+        self.program.declarations.len() + self.variables.len() - 1
+    }
+
+    fn variable_type(&self, variable: Variable) -> Type {
+        match self.program.declarations.get(variable) {
+            Some(declaration) => declaration.declaration_type.clone(),
+            None => self.variables[variable - self.program.declarations.len()].clone(),
+        }
+    }
+
+    // Operations of type void have no value
+    fn emit(&mut self, operation: Operation, result_type: Type) -> Result<Option<Value>, String> {
+        let block = self
+            .current
+            .ok_or(format!("Unreachable instruction: {}", operation))?;
+        let result = match result_type {
+            Type::Void => None,
+            result_type => Some(self.new_value(result_type)),
+        };
+        self.body.blocks[block]
+            .instructions
+            .push(Instruction { result, operation });
+        Ok(result)
+    }
+
+    fn emit_value(&mut self, operation: Operation, result_type: Type) -> Result<Value, String> {
+        let description = operation.to_string();
+        self.emit(operation, result_type)?
+            .ok_or(format!("Operation has no value: {}", description))
+    }
+
+    fn terminate_block(&mut self, block: BlockId, terminator: Terminator) {
+        for successor in terminator.successors() {
+            self.predecessors[successor].push(block);
+        }
+        self.body.blocks[block].terminator = terminator;
+    }
+
+    // Ends the current block, what follows is unreachable until another starts
+    fn terminate(&mut self, terminator: Terminator) {
+        if let Some(block) = self.current.take() {
+            self.terminate_block(block, terminator);
+        }
+    }
+
+    // Block the blocks that didn't end in a terminator continue in, a single
+    // one just goes on
+    fn join(&mut self, ends: Vec<BlockId>) -> Result<(), String> {
+        match ends[..] {
+            [] => return Ok(()),
+            [end] => {
+                self.current = Some(end);
+                return Ok(());
+            }
+            _ => {}
+        }
+        let join = self.new_block();
+        for end in ends {
+            self.terminate_block(end, Terminator::Goto(join));
+        }
+        self.seal(join)?;
+        self.current = Some(join);
+        Ok(())
+    }
+
+    fn write_variable(&mut self, variable: Variable, value: Value) -> Result<(), String> {
+        let block = self.current.ok_or("Unreachable assignment".to_string())?;
+        self.definitions.insert((variable, block), value);
+        Ok(())
+    }
+
+    fn read_variable(&mut self, variable: Variable, block: BlockId) -> Result<Value, String> {
+        if let Some(value) = self.definitions.get(&(variable, block)) {
+            return Ok(*value);
+        }
+
+        let value = if !self.sealed.contains(&block) {
+            let phi = self.new_phi(variable, block);
+            self.incomplete
+                .entry(block)
+                .or_default()
+                .push((variable, phi));
+            phi
+        } else if self.predecessors[block].len() == 1 {
+            self.read_variable(variable, self.predecessors[block][0])?
+        } else if self.predecessors[block].is_empty() {
+            let name = match self.program.declarations.get(variable) {
+                Some(declaration) => declaration.name.clone(),
+                None => format!("<{}>", variable),
+            };
+            return Err(format!("Variable used before it is defined: {}", name));
+        } else {
+            // Defined before the operands are read to break cycles through loops
+            let phi = self.new_phi(variable, block);
+            self.definitions.insert((variable, block), phi);
+            self.add_phi_operands(variable, block, phi)?;
+            phi
+        };
+        self.definitions.insert((variable, block), value);
+        Ok(value)
+    }
+
+    fn new_phi(&mut self, variable: Variable, block: BlockId) -> Value {
+        let result = self.new_value(self.variable_type(variable));
+        self.body.blocks[block].phis.push(Phi {
+            result,
+            incoming: vec![],
+        });
+        result
+    }
+
+    fn add_phi_operands(
+        &mut self,
+        variable: Variable,
+        block: BlockId,
+        phi: Value,
+    ) -> Result<(), String> {
+        for predecessor in self.predecessors[block].clone() {
+            let value = self.read_variable(variable, predecessor)?;
+            if let Some(phi) = self.body.blocks[block]
+                .phis
+                .iter_mut()
+                .find(|candidate| candidate.result == phi)
+            {
+                phi.incoming.push((predecessor, value));
+            }
+        }
+        Ok(())
+    }
+
+    // Called once every predecessor of the block is known
+    fn seal(&mut self, block: BlockId) -> Result<(), String> {
+        for (variable, phi) in self.incomplete.remove(&block).unwrap_or_default() {
+            self.add_phi_operands(variable, block, phi)?;
+        }
+        self.sealed.insert(block);
+        Ok(())
+    }
+
+    fn slot(&mut self, id: DeclId) -> Option<SlotId> {
+        let declaration = &self.program.declarations[id];
+        if declaration.kind != DeclarationKind::Local || !self.memory.contains(&id) {
+            return None;
+        }
+        if let Some(slot) = self.slots.get(&id) {
+            return Some(*slot);
+        }
+        self.body.slots.push((
+            declaration.name.clone(),
+            declaration.declaration_type.clone(),
+        ));
+        self.slots.insert(id, self.body.slots.len() - 1);
+        Some(self.body.slots.len() - 1)
+    }
+
+    // Gives a local variable a value
+    fn define(&mut self, id: DeclId, value: Value) -> Result<(), String> {
+        match self.slot(id) {
+            Some(slot) => {
+                self.emit(Operation::Store(Place::Slot(slot), value), Type::Void)?;
+                Ok(())
+            }
+            None => self.write_variable(id, value),
+        }
+    }
+
+    fn read_name(&mut self, id: DeclId) -> Result<Value, String> {
+        let declaration = &self.program.declarations[id];
+        let declaration_type = declaration.declaration_type.clone();
+        match declaration.kind {
+            DeclarationKind::Function | DeclarationKind::Extern => self.emit_value(
+                Operation::Function(declaration.name.clone()),
+                declaration_type,
+            ),
+            DeclarationKind::Global => self.emit_value(
+                Operation::Load(Place::Global(declaration.name.clone())),
+                declaration_type,
+            ),
+            DeclarationKind::Local => match self.slot(id) {
+                Some(slot) => self.emit_value(Operation::Load(Place::Slot(slot)), declaration_type),
+                None => {
+                    let block = self
+                        .current
+                        .ok_or("Unreachable variable read".to_string())?;
+                    self.read_variable(id, block)
+                }
+            },
+        }
+    }
+
+    fn lower_block(&mut self, block: &hir::Block) -> Result<(), String> {
+        for statement in block.statements.iter() {
+            // Statements after a return are never run
+            if self.current.is_none() {
+                break;
+            }
+            self.lower_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn lower_statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Let(id, value) => {
+                let value = self.lower_value(value)?;
+                self.define(*id, value)
+            }
+            Statement::Assign(target, value) => {
+                let value = self.lower_value(value)?;
+                match self.lower_place(target)? {
+                    Some(place) => {
+                        self.emit(Operation::Store(place, value), Type::Void)?;
+                        Ok(())
+                    }
+                    None => match target.kind {
+                        ExpressionKind::Name(id) => self.write_variable(id, value),
+                        _ => Err("Can't assign to a value".to_string()),
+                    },
+                }
+            }
+            Statement::Expression(expression) => {
+                self.lower_expression(expression)?;
+                Ok(())
+            }
+            Statement::Return(value) => {
+                let value = match value {
+                    Some(value) => Some(self.lower_value(value)?),
+                    None => None,
+                };
+                self.terminate(Terminator::Return(value));
+                Ok(())
+            }
+            Statement::If(condition, then_body, else_body) => {
+                self.lower_if(condition, then_body, else_body)
+            }
+            Statement::For(variable, iterable, body) => self.lower_for(*variable, iterable, body),
+        }
+    }
+
+    fn lower_if(
+        &mut self,
+        condition: &Condition,
+        then_body: &hir::Block,
+        else_body: &Option<hir::Block>,
+    ) -> Result<(), String> {
+        let (flag, optional) = match condition {
+            Condition::Expression(condition) => (self.lower_value(condition)?, None),
+            Condition::Let(id, optional) => {
+                let optional = self.lower_value(optional)?;
+                let flag = self.emit_value(Operation::Test(optional), Type::Bool)?;
+                (flag, Some((*id, optional)))
+            }
+        };
+
+        let then_block = self.new_block();
+        let else_block = self.new_block();
+        self.terminate(Terminator::Branch(flag, then_block, else_block));
+        self.seal(then_block)?;
+        self.seal(else_block)?;
+
+        let mut ends = vec![];
+        self.current = Some(then_block);
+        if let Some((id, optional)) = optional {
+            let value = self.emit_value(Operation::Unwrap(optional), self.variable_type(id))?;
+            self.define(id, value)?;
+        }
+        self.lower_block(then_body)?;
+        ends.extend(self.current.take());
+
+        self.current = Some(else_block);
+        if let Some(else_body) = else_body {
+            self.lower_block(else_body)?;
+        }
+        ends.extend(self.current.take());
+        self.join(ends)
+    }
+
+    // for x in items { ... } counts an index up to the length of the items:
+    //
+    // header:
+    //     %i = phi [entry: 0, body: %next]
+    //     br %i < length, body, exit
+    fn lower_for(
+        &mut self,
+        variable: DeclId,
+        iterable: &Expression,
+        body: &hir::Block,
+    ) -> Result<(), String> {
+        let place = self.lower_place(iterable)?;
+        let items = match place {
+            Some(_) => None,
+            None => Some(self.lower_value(iterable)?),
+        };
+
+        let index = self.new_variable(Type::Int);
+        let zero = self.emit_value(Operation::Constant(Constant::Int(0)), Type::Int)?;
+        self.write_variable(index, zero)?;
+
+        let header = self.new_block();
+        self.terminate(Terminator::Goto(header));
+        self.current = Some(header);
+        let current_index = self.read_variable(index, header)?;
+        // Slices are measured on every iteration, like the C loop does
+        let length = match &iterable.expression_type {
+            Type::Array(_, length) => self.emit_value(
+                Operation::Constant(Constant::Int(*length as i32)),
+                Type::Int,
+            )?,
+            Type::Slice(_) => {
+                let slice = match (&place, items) {
+                    (Some(place), _) => self.emit_value(
+                        Operation::Load(place.clone()),
+                        iterable.expression_type.clone(),
+                    )?,
+                    (None, Some(items)) => items,
+                    (None, None) => unreachable!(),
+                };
+                self.emit_value(
+                    Operation::Builtin(hir::Builtin::Len, vec![slice]),
+                    Type::Int,
+                )?
+            }
+            t => return Err(format!("Can't iterate over {}", t)),
+        };
+        let condition = self.emit_value(
+            Operation::Binary(Token::LessThan, current_index, length),
+            Type::Bool,
+        )?;
+
+        let body_block = self.new_block();
+        let exit = self.new_block();
+        self.terminate(Terminator::Branch(condition, body_block, exit));
+        self.seal(body_block)?;
+
+        self.current = Some(body_block);
+        let element_type = self.variable_type(variable);
+        let element = match (place, items) {
+            (Some(place), _) => self.emit_value(
+                Operation::Load(Place::Index(Box::new(place), current_index)),
+                element_type,
+            )?,
+            (None, Some(items)) => {
+                self.emit_value(Operation::Index(items, current_index), element_type)?
+            }
+            (None, None) => unreachable!(),
+        };
+        self.define(variable, element)?;
+        self.lower_block(body)?;
+        if self.current.is_some() {
+            let one = self.emit_value(Operation::Constant(Constant::Int(1)), Type::Int)?;
+            let next = self.emit_value(
+                Operation::Binary(Token::Plus, current_index, one),
+                Type::Int,
+            )?;
+            self.write_variable(index, next)?;
+            self.terminate(Terminator::Goto(header));
+        }
+        self.seal(header)?;
+        self.seal(exit)?;
+        self.current = Some(exit);
+        Ok(())
+    }
+
+    // Branches to a block per arm, the arms with a value store it in a
+    // variable read after they join
+    fn lower_match(
+        &mut self,
+        subject: &Expression,
+        arms: &[hir::MatchArm],
+        match_type: &Type,
+    ) -> Result<Option<Value>, String> {
+        let subject = self.lower_value(subject)?;
+        let result = match match_type {
+            Type::Void => None,
+            match_type => Some(self.new_variable(match_type.clone())),
+        };
+
+        let mut targets = vec![];
+        let mut default = None;
+        let mut blocks = vec![];
+        for arm in arms.iter() {
+            let block = self.new_block();
+            match &arm.pattern {
+                Pattern::Variant(variant, _) => targets.push((variant.clone(), block)),
+                Pattern::Wildcard => default = Some(block),
+            }
+            blocks.push(block);
+        }
+        self.terminate(Terminator::Switch(subject, targets, default));
+
+        let mut ends = vec![];
+        for (arm, block) in arms.iter().zip(blocks) {
+            self.seal(block)?;
+            self.current = Some(block);
+            if let Pattern::Variant(variant, bindings) = &arm.pattern {
+                for (position, binding) in bindings.iter().enumerate() {
+                    if let Some(id) = binding {
+                        let value = self.emit_value(
+                            Operation::VariantField(subject, variant.clone(), position),
+                            self.variable_type(*id),
+                        )?;
+                        self.define(*id, value)?;
+                    }
+                }
+            }
+            let value = self.lower_expression(&arm.value)?;
+            if let (Some(result), Some(value)) = (result, value) {
+                self.write_variable(result, value)?;
+            }
+            ends.extend(self.current.take());
+        }
+        self.join(ends)?;
+
+        match (result, self.current) {
+            (Some(result), Some(block)) => Ok(Some(self.read_variable(result, block)?)),
+            _ => Ok(None),
+        }
+    }
+
+    // Only runs the right side when the left one doesn't decide the result
+    fn lower_logic(
+        &mut self,
+        lhs: &Expression,
+        operator: &Token,
+        rhs: &Expression,
+    ) -> Result<Value, String> {
+        let result = self.new_variable(Type::Bool);
+        let lhs = self.lower_value(lhs)?;
+        self.write_variable(result, lhs)?;
+
+        let rhs_block = self.new_block();
+        let join = self.new_block();
+        let terminator = match operator {
+            Token::LogicAnd => Terminator::Branch(lhs, rhs_block, join),
+            _ => Terminator::Branch(lhs, join, rhs_block),
+        };
+        self.terminate(terminator);
+        self.seal(rhs_block)?;
+
+        self.current = Some(rhs_block);
+        let rhs = self.lower_value(rhs)?;
+        self.write_variable(result, rhs)?;
+        let end = self
+            .current
+            .take()
+            .ok_or("Unreachable expression".to_string())?;
+        self.terminate_block(end, Terminator::Goto(join));
+        self.seal(join)?;
+        self.current = Some(join);
+        self.read_variable(result, join)
+    }
+
+    // `value?` returns the error when there's one
+    fn lower_try(&mut self, inner: &Expression, value_type: &Type) -> Result<Value, String> {
+        let error_type = match &inner.expression_type {
+            Type::Result(_, error_type) => (**error_type).clone(),
+            t => return Err(format!("Can't use `?` on {}", t)),
+        };
+        let result = self.lower_value(inner)?;
+        let ok = self.emit_value(Operation::Test(result), Type::Bool)?;
+
+        let success = self.new_block();
+        let failure = self.new_block();
+        self.terminate(Terminator::Branch(ok, success, failure));
+        self.seal(success)?;
+        self.seal(failure)?;
+
+        self.current = Some(failure);
+        let error = self.emit_value(Operation::UnwrapErr(result), error_type)?;
+        let returned = self.emit_value(Operation::Err(error), self.body.return_type.clone())?;
+        self.terminate(Terminator::Return(Some(returned)));
+
+        self.current = Some(success);
+        self.emit_value(Operation::Unwrap(result), value_type.clone())
+    }
+
+    fn lower_closure(&mut self, closure: &hir::Closure) -> Result<Value, String> {
+        let name = format!("{}.closure{}", self.body.name, self.closure_count);
+        self.closure_count += 1;
+        let parameters: Vec<DeclId> = closure
+            .captures
+            .iter()
+            .chain(closure.parameters.iter())
+            .copied()
+            .collect();
+        let bodies = build_body(
+            self.program,
+            &name,
+            &parameters,
+            &closure.return_type,
+            &closure.body,
+        )?;
+        self.closures.extend(bodies);
+
+        let mut captures = vec![];
+        for capture in closure.captures.iter() {
+            captures.push(self.read_name(*capture)?);
+        }
+        let parameter_types = closure
+            .parameters
+            .iter()
+            .map(|parameter| self.variable_type(*parameter))
+            .collect();
+        self.emit_value(
+            Operation::Closure(name, captures),
+            Type::Function(parameter_types, Box::new(closure.return_type.clone())),
+        )
+    }
+
+    fn lower_values(&mut self, expressions: &[Expression]) -> Result<Vec<Value>, String> {
+        let mut values = vec![];
+        for expression in expressions.iter() {
+            values.push(self.lower_value(expression)?);
+        }
+        Ok(values)
+    }
+
+    fn lower_value(&mut self, expression: &Expression) -> Result<Value, String> {
+        self.lower_expression(expression)?
+            .ok_or(format!("Expression has no value: {:?}", expression.kind))
+    }
+
+    // Memory the expression refers to, None for values and SSA variables
+    fn lower_place(&mut self, expression: &Expression) -> Result<Option<Place>, String> {
+        match &expression.kind {
+            ExpressionKind::Name(id) => match self.program.declarations[*id].kind {
+                DeclarationKind::Global => {
+                    Ok(Some(Place::Global(self.program.name(*id).to_string())))
+                }
+                DeclarationKind::Local => Ok(self.slot(*id).map(Place::Slot)),
+                _ => Ok(None),
+            },
+            ExpressionKind::Field(base, field) => {
+                let base = match base.expression_type {
+                    Type::Reference(..) => Some(Place::Deref(self.lower_value(base)?)),
+                    _ => self.lower_place(base)?,
+                };
+                Ok(base.map(|base| Place::Field(Box::new(base), field.clone())))
+            }
+            ExpressionKind::Index(base, index) => match self.lower_place(base)? {
+                Some(base) => {
+                    let index = self.lower_value(index)?;
+                    Ok(Some(Place::Index(Box::new(base), index)))
+                }
+                None => Ok(None),
+            },
+            ExpressionKind::Dereference(reference) => {
+                Ok(Some(Place::Deref(self.lower_value(reference)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Values without a place are stored in a slot of their own
+    fn lower_address(&mut self, expression: &Expression) -> Result<Value, String> {
+        let place = match self.lower_place(expression)? {
+            Some(place) => place,
+            None => {
+                let value = self.lower_value(expression)?;
+                self.body
+                    .slots
+                    .push(("".to_string(), expression.expression_type.clone()));
+                let place = Place::Slot(self.body.slots.len() - 1);
+                self.emit(Operation::Store(place.clone(), value), Type::Void)?;
+                place
+            }
+        };
+        self.emit_value(
+            Operation::AddressOf(place),
+            Type::Reference(Box::new(expression.expression_type.clone()), true),
+        )
+    }
+
+    fn lower_expression(&mut self, expression: &Expression) -> Result<Option<Value>, String> {
+        let expression_type = expression.expression_type.clone();
+        let operation = match &expression.kind {
+            ExpressionKind::Int(value) => Operation::Constant(Constant::Int(*value)),
+            ExpressionKind::Float(value) => Operation::Constant(Constant::Float(*value)),
+            ExpressionKind::String(value) => Operation::Constant(Constant::String(value.clone())),
+            ExpressionKind::Bool(value) => Operation::Constant(Constant::Bool(*value)),
+            ExpressionKind::Name(id) => return Ok(Some(self.read_name(*id)?)),
+            ExpressionKind::Binary(lhs, operator @ (Token::LogicAnd | Token::LogicOr), rhs) => {
+                return Ok(Some(self.lower_logic(lhs, operator, rhs)?))
+            }
+            ExpressionKind::Binary(lhs, operator, rhs) => {
+                let lhs = self.lower_value(lhs)?;
+                let rhs = self.lower_value(rhs)?;
+                let binary_type = binary_type(operator, &expression_type);
+                return Ok(Some(self.emit_value(
+                    Operation::Binary(operator.clone(), lhs, rhs),
+                    binary_type,
+                )?));
+            }
+            ExpressionKind::Cast(inner) => Operation::Cast(self.lower_value(inner)?),
+            ExpressionKind::Array(items) => Operation::Array(self.lower_values(items)?),
+            ExpressionKind::Slice(items) => Operation::Slice(self.lower_values(items)?),
+            ExpressionKind::Field(base, field) => match self.lower_place(expression)? {
+                Some(place) => Operation::Load(place),
+                None => Operation::Field(self.lower_value(base)?, field.clone()),
+            },
+            ExpressionKind::Index(base, index) => match self.lower_place(expression)? {
+                Some(place) => Operation::Load(place),
+                None => {
+                    let base = self.lower_value(base)?;
+                    Operation::Index(base, self.lower_value(index)?)
+                }
+            },
+            ExpressionKind::Call(id, arguments) => Operation::Call(
+                self.program.name(*id).to_string(),
+                self.lower_values(arguments)?,
+            ),
+            ExpressionKind::CallValue(callee, arguments) => {
+                let callee = self.lower_value(callee)?;
+                Operation::CallValue(callee, self.lower_values(arguments)?)
+            }
+            ExpressionKind::Builtin(hir::Builtin::Len, arguments) => {
+                Operation::Builtin(hir::Builtin::Len, self.lower_values(arguments)?)
+            }
+            ExpressionKind::Builtin(builtin, arguments) => {
+                let mut values = vec![self.lower_address(&arguments[0])?];
+                values.extend(self.lower_values(&arguments[1..])?);
+                Operation::Builtin(builtin.clone(), values)
+            }
+            ExpressionKind::Struct(name, fields) => {
+                let mut values = vec![];
+                for (field, value) in fields.iter() {
+                    values.push((field.clone(), self.lower_value(value)?));
+                }
+                Operation::Struct(name.clone(), values)
+            }
+            ExpressionKind::Variant(name, variant, values) => {
+                Operation::Variant(name.clone(), variant.clone(), self.lower_values(values)?)
+            }
+            ExpressionKind::Match(subject, arms) => {
+                return self.lower_match(subject, arms, &expression_type)
+            }
+            ExpressionKind::AddressOf(place) => return Ok(Some(self.lower_address(place)?)),
+            ExpressionKind::Dereference(reference) => {
+                Operation::Load(Place::Deref(self.lower_value(reference)?))
+            }
+            ExpressionKind::Wrap(inner) => Operation::Wrap(self.lower_value(inner)?),
+            ExpressionKind::NoneLit => Operation::NoneLit,
+            ExpressionKind::Err(inner) => Operation::Err(self.lower_value(inner)?),
+            ExpressionKind::Try(inner) => {
+                return Ok(Some(self.lower_try(inner, &expression_type)?))
+            }
+            ExpressionKind::Closure(closure) => return Ok(Some(self.lower_closure(closure)?)),
+        };
+        self.emit(operation, expression_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hir::lower, testing::check};
+
+    fn build_source(input: &str) -> Result<Mir, String> {
+        let mir = build(&lower(check(input)?)?)?;
+        verify(&mir)?;
+        Ok(mir)
+    }
+
+    fn find<'a>(mir: &'a Mir, name: &str) -> &'a Body {
+        mir.bodies.iter().find(|body| body.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_build_dump() {
+        let mir = build_source(
+            "fn pick(bool b, int x): int { int y = x; if b { y = x * 2; } return y; }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            find(&mir, "pick").to_string(),
+            "fn pick(%0: bool, %1: int): int {
+bb0:
+    br %0, bb1, bb2
+bb1: ; preds bb0
+    %2: int = 2
+    %3: int = %1 * %2
+    goto bb3
+bb2: ; preds bb0
+    goto bb3
+bb3: ; preds bb1, bb2
+    %4: int = phi [bb1: %3, bb2: %1]
+    return %4
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_build_loop() {
+        let mir = build_source(
+            "fn sum([]int xs): int { int total = 0; for x in xs { total = total + x; } return total; }",
+        )
+        .unwrap();
+        let body = find(&mir, "sum");
+
+        // The header joins the entry and the back edge, for the index and the total
+        assert_eq!(body.blocks.len(), 4);
+        let header = &body.blocks[1];
+        assert_eq!(header.phis.len(), 2);
+        for phi in header.phis.iter() {
+            let blocks: Vec<BlockId> = phi.incoming.iter().map(|(block, _)| *block).collect();
+            assert_eq!(blocks, vec![0, 2]);
+        }
+        assert!(matches!(body.blocks[2].terminator, Terminator::Goto(1)));
+        assert_eq!(
+            body.blocks[3].terminator,
+            Terminator::Return(Some(header.phis[1].result))
+        );
+    }
+
+    #[test]
+    fn test_build_memory() {
+        let mir = build_source(
+            "enum E { A(int), B }
+            fn get(E e): int { return match e { E::A(x) => x, E::B => 0 }; }
+            fn main(): int { []int xs = [1]; push(xs, 2); int n = 3; return len(xs) + n; }",
+        )
+        .unwrap();
+
+        let get = find(&mir, "get");
+        assert!(matches!(
+            &get.blocks[0].terminator,
+            Terminator::Switch(_, targets, None) if targets.len() == 2
+        ));
+        assert_eq!(get.blocks[3].phis.len(), 1);
+
+        // Only the slice that `push` changes lives in memory
+        let main = find(&mir, "main");
+        assert_eq!(
+            main.slots,
+            vec![("xs".to_string(), Type::Slice(Box::new(Type::Int)))]
+        );
+        let operations: Vec<&Operation> = main.blocks[0]
+            .instructions
+            .iter()
+            .map(|instruction| &instruction.operation)
+            .collect();
+        assert!(operations.contains(&&Operation::AddressOf(Place::Slot(0))));
+        assert!(operations.contains(&&Operation::Load(Place::Slot(0))));
+    }
+}
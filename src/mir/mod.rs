@@ -0,0 +1,457 @@
+pub mod build;
+pub mod verify;
+
+use crate::ast::Type;
+use crate::hir::Builtin;
+use crate::lexer::Token;
+use std::fmt;
+
+pub use build::build;
+pub use verify::verify;
+
+// Mid-level intermediate representation: functions are control-flow graphs
+// of basic blocks, and values are SSA temporaries defined once, joined by phi
+// nodes where control flow merges. Variables whose address is taken or that
+// are changed in part live in memory slots instead. It's built from the
+// optimised HIR and verified before code generation
+
+// Position of a value in `Body::values`
+pub type Value = usize;
+// Position of a block in `Body::blocks`, the entry block is 0
+pub type BlockId = usize;
+// Position of a slot in `Body::slots`
+pub type SlotId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+// Memory read with `load`, written with `store` and referenced with `&`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Place {
+    Slot(SlotId),
+    Global(String),
+    // Memory a reference points to
+    Deref(Value),
+    Field(Box<Place>, String),
+    Index(Box<Place>, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Constant(Constant),
+    // Both operands have the same type. `<` compares ints, for loops
+    Binary(Token, Value, Value),
+    // Conversion between numbers, to the type of the result
+    Cast(Value),
+    // Function used as a value
+    Function(String),
+    Array(Vec<Value>),
+    Slice(Vec<Value>),
+    Struct(String, Vec<(String, Value)>),
+    // Enum, variant and the values it carries
+    Variant(String, String, Vec<Value>),
+    Field(Value, String),
+    Index(Value, Value),
+    // Value carried by a variant, at a position
+    VariantField(Value, String, usize),
+    // Value stored in the optional or result type of the result
+    Wrap(Value),
+    NoneLit,
+    Err(Value),
+    // Whether an optional is present or a result holds a value
+    Test(Value),
+    Unwrap(Value),
+    // Error of a result
+    UnwrapErr(Value),
+    Call(String, Vec<Value>),
+    CallValue(Value, Vec<Value>),
+    // `push` and `pop` take a reference to the slice they change
+    Builtin(Builtin, Vec<Value>),
+    // Closure body and the values it captures, its first parameters
+    Closure(String, Vec<Value>),
+    AddressOf(Place),
+    Load(Place),
+    Store(Place, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    // None for operations without a value, like stores and calls of void functions
+    pub result: Option<Value>,
+    pub operation: Operation,
+}
+
+// Value of the incoming edge control came through
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phi {
+    pub result: Value,
+    pub incoming: Vec<(BlockId, Value)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Goto(BlockId),
+    // Condition, block when true, block when false
+    Branch(Value, BlockId, BlockId),
+    // Jumps to the block of the variant of an enum, or to the default one
+    Switch(Value, Vec<(String, BlockId)>, Option<BlockId>),
+    Return(Option<Value>),
+    // End of a block that control never leaves, like a function missing a return
+    Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub phis: Vec<Phi>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub name: String,
+    pub parameters: Vec<Value>,
+    pub return_type: Type,
+    // Name and type of the variables kept in memory
+    pub slots: Vec<(String, Type)>,
+    // Type of each value
+    pub values: Vec<Type>,
+    pub blocks: Vec<BasicBlock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mir {
+    pub globals: Vec<(String, Type)>,
+    pub bodies: Vec<Body>,
+}
+
+// Comparisons and logic operators give a bool, the others the type of their operands
+pub fn binary_type(operator: &Token, operand_type: &Type) -> Type {
+    match operator {
+        Token::GreaterThan
+        | Token::LessThan
+        | Token::GreaterThanOrEqual
+        | Token::LessThanOrEqual
+        | Token::NotEqual
+        | Token::EqualTo
+        | Token::LogicAnd
+        | Token::LogicOr => Type::Bool,
+        _ => operand_type.clone(),
+    }
+}
+
+impl Place {
+    fn visit_values(&mut self, visit: &mut impl FnMut(&mut Value)) {
+        match self {
+            Place::Slot(_) | Place::Global(_) => {}
+            Place::Deref(value) => visit(value),
+            Place::Field(place, _) => place.visit_values(visit),
+            Place::Index(place, index) => {
+                place.visit_values(visit);
+                visit(index);
+            }
+        }
+    }
+}
+
+impl Operation {
+    pub fn visit_values(&mut self, visit: &mut impl FnMut(&mut Value)) {
+        match self {
+            Operation::Constant(_) | Operation::Function(_) | Operation::NoneLit => {}
+            Operation::Binary(_, lhs, rhs) | Operation::Index(lhs, rhs) => {
+                visit(lhs);
+                visit(rhs);
+            }
+            Operation::Cast(value)
+            | Operation::Field(value, _)
+            | Operation::VariantField(value, _, _)
+            | Operation::Wrap(value)
+            | Operation::Err(value)
+            | Operation::Test(value)
+            | Operation::Unwrap(value)
+            | Operation::UnwrapErr(value) => visit(value),
+            Operation::Array(values)
+            | Operation::Slice(values)
+            | Operation::Variant(_, _, values)
+            | Operation::Call(_, values)
+            | Operation::Builtin(_, values)
+            | Operation::Closure(_, values) => values.iter_mut().for_each(visit),
+            Operation::Struct(_, fields) => fields.iter_mut().for_each(|(_, value)| visit(value)),
+            Operation::CallValue(callee, arguments) => {
+                visit(callee);
+                arguments.iter_mut().for_each(visit);
+            }
+            Operation::AddressOf(place) | Operation::Load(place) => place.visit_values(visit),
+            Operation::Store(place, value) => {
+                place.visit_values(visit);
+                visit(value);
+            }
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Value> {
+        let mut operands = vec![];
+        self.clone()
+            .visit_values(&mut |value| operands.push(*value));
+        operands
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Goto(target) => vec![*target],
+            Terminator::Branch(_, then_block, else_block) => vec![*then_block, *else_block],
+            Terminator::Switch(_, targets, default) => targets
+                .iter()
+                .map(|(_, target)| *target)
+                .chain(*default)
+                .collect(),
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn visit_values(&mut self, visit: &mut impl FnMut(&mut Value)) {
+        match self {
+            Terminator::Branch(value, _, _)
+            | Terminator::Switch(value, _, _)
+            | Terminator::Return(Some(value)) => visit(value),
+            Terminator::Goto(_) | Terminator::Return(None) | Terminator::Unreachable => {}
+        }
+    }
+}
+
+impl Body {
+    pub fn new(name: &str, return_type: Type) -> Self {
+        Self {
+            name: name.to_string(),
+            parameters: vec![],
+            return_type,
+            slots: vec![],
+            values: vec![],
+            blocks: vec![],
+        }
+    }
+
+    // Blocks each block is reached from, in the order of the blocks
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![vec![]; self.blocks.len()];
+        for (block, basic_block) in self.blocks.iter().enumerate() {
+            for successor in basic_block.terminator.successors() {
+                if let Some(predecessors) = predecessors.get_mut(successor) {
+                    predecessors.push(block);
+                }
+            }
+        }
+        predecessors
+    }
+
+    // Makes every use of `old` use `new` instead
+    pub fn replace_uses(&mut self, old: Value, new: Value) {
+        let mut replace = |value: &mut Value| {
+            if *value == old {
+                *value = new;
+            }
+        };
+        for block in self.blocks.iter_mut() {
+            for phi in block.phis.iter_mut() {
+                phi.incoming
+                    .iter_mut()
+                    .for_each(|(_, value)| replace(value));
+            }
+            for instruction in block.instructions.iter_mut() {
+                instruction.operation.visit_values(&mut replace);
+            }
+            block.terminator.visit_values(&mut replace);
+        }
+    }
+}
+
+fn join(values: &[Value]) -> String {
+    let values: Vec<String> = values.iter().map(|value| format!("%{}", value)).collect();
+    values.join(", ")
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Float(value) => write!(f, "{:?}", value),
+            Constant::Bool(value) => write!(f, "{}", value),
+            Constant::String(value) => write!(f, "\"{}\"", value),
+        }
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Place::Slot(slot) => write!(f, "${}", slot),
+            Place::Global(name) => write!(f, "@{}", name),
+            Place::Deref(value) => write!(f, "*%{}", value),
+            Place::Field(place, field) => write!(f, "{}.{}", place, field),
+            Place::Index(place, index) => write!(f, "{}[%{}]", place, index),
+        }
+    }
+}
+
+// `%3 = %1 + %2`, `store $0.x, %4`
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Constant(constant) => write!(f, "{}", constant),
+            Operation::Binary(operator, lhs, rhs) => write!(f, "%{} {} %{}", lhs, operator, rhs),
+            Operation::Cast(value) => write!(f, "cast %{}", value),
+            Operation::Function(name) => write!(f, "fn {}", name),
+            Operation::Array(items) => write!(f, "[{}]", join(items)),
+            Operation::Slice(items) => write!(f, "slice [{}]", join(items)),
+            Operation::Struct(name, fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, value)| format!("{}: %{}", field, value))
+                    .collect();
+                write!(f, "{} {{ {} }}", name, fields.join(", "))
+            }
+            Operation::Variant(name, variant, values) if values.is_empty() => {
+                write!(f, "{}::{}", name, variant)
+            }
+            Operation::Variant(name, variant, values) => {
+                write!(f, "{}::{}({})", name, variant, join(values))
+            }
+            Operation::Field(value, field) => write!(f, "%{}.{}", value, field),
+            Operation::Index(value, index) => write!(f, "%{}[%{}]", value, index),
+            Operation::VariantField(value, variant, position) => {
+                write!(f, "%{} as {}.{}", value, variant, position)
+            }
+            Operation::Wrap(value) => write!(f, "wrap %{}", value),
+            Operation::NoneLit => write!(f, "none"),
+            Operation::Err(value) => write!(f, "err %{}", value),
+            Operation::Test(value) => write!(f, "test %{}", value),
+            Operation::Unwrap(value) => write!(f, "unwrap %{}", value),
+            Operation::UnwrapErr(value) => write!(f, "unwrap_err %{}", value),
+            Operation::Call(name, arguments) => write!(f, "call {}({})", name, join(arguments)),
+            Operation::CallValue(callee, arguments) => {
+                write!(f, "call %{}({})", callee, join(arguments))
+            }
+            Operation::Builtin(builtin, arguments) => {
+                let name = match builtin {
+                    Builtin::Len => "len",
+                    Builtin::Push => "push",
+                    Builtin::Pop => "pop",
+                };
+                write!(f, "{}({})", name, join(arguments))
+            }
+            Operation::Closure(name, captures) => {
+                write!(f, "closure {} [{}]", name, join(captures))
+            }
+            Operation::AddressOf(place) => write!(f, "&{}", place),
+            Operation::Load(place) => write!(f, "load {}", place),
+            Operation::Store(place, value) => write!(f, "store {}, %{}", place, value),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Goto(target) => write!(f, "goto bb{}", target),
+            Terminator::Branch(condition, then_block, else_block) => {
+                write!(f, "br %{}, bb{}, bb{}", condition, then_block, else_block)
+            }
+            Terminator::Switch(value, targets, default) => {
+                let targets: Vec<String> = targets
+                    .iter()
+                    .map(|(variant, target)| format!("{}: bb{}", variant, target))
+                    .collect();
+                write!(f, "switch %{} [{}]", value, targets.join(", "))?;
+                match default {
+                    Some(default) => write!(f, ", default bb{}", default),
+                    None => Ok(()),
+                }
+            }
+            Terminator::Return(Some(value)) => write!(f, "return %{}", value),
+            Terminator::Return(None) => write!(f, "return"),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+// fn sum(%0: int, %1: int): int {
+// bb0:
+//     %2: int = %0 + %1
+//     return %2
+// }
+impl fmt::Display for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(|parameter| format!("%{}: {}", parameter, self.values[*parameter]))
+            .collect();
+        write!(f, "fn {}({})", self.name, parameters.join(", "))?;
+        if self.return_type != Type::Void {
+            write!(f, ": {}", self.return_type)?;
+        }
+        writeln!(f, " {{")?;
+        for (slot, (name, slot_type)) in self.slots.iter().enumerate() {
+            writeln!(f, "    slot ${} {}: {}", slot, name, slot_type)?;
+        }
+
+        let predecessors = self.predecessors();
+        for (block, basic_block) in self.blocks.iter().enumerate() {
+            write!(f, "bb{}:", block)?;
+            if !predecessors[block].is_empty() {
+                let predecessors: Vec<String> = predecessors[block]
+                    .iter()
+                    .map(|predecessor| format!("bb{}", predecessor))
+                    .collect();
+                write!(f, " ; preds {}", predecessors.join(", "))?;
+            }
+            writeln!(f)?;
+            for phi in basic_block.phis.iter() {
+                let incoming: Vec<String> = phi
+                    .incoming
+                    .iter()
+                    .map(|(block, value)| format!("bb{}: %{}", block, value))
+                    .collect();
+                writeln!(
+                    f,
+                    "    %{}: {} = phi [{}]",
+                    phi.result,
+                    self.values[phi.result],
+                    incoming.join(", ")
+                )?;
+            }
+            for instruction in basic_block.instructions.iter() {
+                match instruction.result {
+                    Some(result) => writeln!(
+                        f,
+                        "    %{}: {} = {}",
+                        result, self.values[result], instruction.operation
+                    )?,
+                    None => writeln!(f, "    {}", instruction.operation)?,
+                }
+            }
+            writeln!(f, "    {}", basic_block.terminator)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Mir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, global_type) in self.globals.iter() {
+            writeln!(f, "global @{}: {}", name, global_type)?;
+        }
+        if !self.globals.is_empty() {
+            writeln!(f)?;
+        }
+        let bodies: Vec<String> = self.bodies.iter().map(Body::to_string).collect();
+        write!(f, "{}", bodies.join("\n"))
+    }
+}
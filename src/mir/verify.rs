@@ -0,0 +1,404 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+// Checks that the MIR is well formed, so passes can be tested on their own:
+// blocks jump to blocks that exist, values are defined once and before they
+// are used, phis have a value per predecessor and operands have the types
+// their operations expect
+
+pub fn verify(mir: &Mir) -> Result<(), String> {
+    for body in mir.bodies.iter() {
+        verify_body(body).map_err(|e| format!("Invalid MIR in {}: {}", body.name, e))?;
+    }
+    Ok(())
+}
+
+// Block a value is defined in and its position there: parameters and phis
+// come first, then the instructions
+#[derive(Clone, Copy)]
+struct Definition {
+    block: BlockId,
+    position: usize,
+}
+
+// Blocks every path from the entry to each block goes through. Blocks that
+// can't be reached have none
+fn dominators(body: &Body, predecessors: &[Vec<BlockId>]) -> Vec<HashSet<BlockId>> {
+    let mut reachable: HashSet<BlockId> = HashSet::from([0]);
+    let mut stack = vec![0];
+    while let Some(block) = stack.pop() {
+        for successor in body.blocks[block].terminator.successors() {
+            if reachable.insert(successor) {
+                stack.push(successor);
+            }
+        }
+    }
+
+    let mut dominators: Vec<HashSet<BlockId>> = (0..body.blocks.len())
+        .map(|block| match block {
+            0 => HashSet::from([0]),
+            block if reachable.contains(&block) => reachable.clone(),
+            _ => HashSet::new(),
+        })
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in 1..body.blocks.len() {
+            if !reachable.contains(&block) {
+                continue;
+            }
+            let mut dominating: Option<HashSet<BlockId>> = None;
+            for predecessor in predecessors[block].iter() {
+                if !reachable.contains(predecessor) {
+                    continue;
+                }
+                dominating = Some(match dominating {
+                    None => dominators[*predecessor].clone(),
+                    Some(dominating) => dominating
+                        .intersection(&dominators[*predecessor])
+                        .copied()
+                        .collect(),
+                });
+            }
+            let mut dominating = dominating.unwrap_or_default();
+            dominating.insert(block);
+            if dominating != dominators[block] {
+                dominators[block] = dominating;
+                changed = true;
+            }
+        }
+    }
+    dominators
+}
+
+fn verify_body(body: &Body) -> Result<(), String> {
+    if body.blocks.is_empty() {
+        return Err("Body has no blocks".to_string());
+    }
+    for (block, basic_block) in body.blocks.iter().enumerate() {
+        for successor in basic_block.terminator.successors() {
+            if successor >= body.blocks.len() {
+                return Err(format!(
+                    "bb{} jumps to missing block bb{}",
+                    block, successor
+                ));
+            }
+        }
+    }
+    let predecessors = body.predecessors();
+    if !predecessors[0].is_empty() || !body.blocks[0].phis.is_empty() {
+        return Err("The entry block can't be jumped to".to_string());
+    }
+
+    let mut definitions: HashMap<Value, Definition> = HashMap::new();
+    let mut define = |value: Value, block: BlockId, position: usize| {
+        if value >= body.values.len() {
+            return Err(format!("%{} has no type", value));
+        }
+        match definitions.insert(value, Definition { block, position }) {
+            Some(_) => Err(format!("%{} is defined more than once", value)),
+            None => Ok(()),
+        }
+    };
+    for parameter in body.parameters.iter() {
+        define(*parameter, 0, 0)?;
+    }
+    for (block, basic_block) in body.blocks.iter().enumerate() {
+        for phi in basic_block.phis.iter() {
+            define(phi.result, block, 0)?;
+        }
+        for (position, instruction) in basic_block.instructions.iter().enumerate() {
+            if let Some(result) = instruction.result {
+                define(result, block, position + 1)?;
+            }
+        }
+    }
+
+    let dominators = dominators(body, &predecessors);
+    // Uses in unreachable blocks only need a definition
+    let check_use = |value: Value, block: BlockId, position: usize| {
+        let definition = definitions.get(&value).ok_or(format!(
+            "bb{} uses %{}, which is never defined",
+            block, value
+        ))?;
+        let dominated = match definition.block == block {
+            true => definition.position < position,
+            false => dominators[block].contains(&definition.block),
+        };
+        if !dominated && !dominators[block].is_empty() {
+            return Err(format!(
+                "bb{} uses %{} where its definition doesn't dominate",
+                block, value
+            ));
+        }
+        Ok(())
+    };
+
+    for (block, basic_block) in body.blocks.iter().enumerate() {
+        for phi in basic_block.phis.iter() {
+            let mut incoming: Vec<BlockId> = phi.incoming.iter().map(|(block, _)| *block).collect();
+            let mut expected = predecessors[block].clone();
+            incoming.sort_unstable();
+            expected.sort_unstable();
+            if incoming != expected {
+                return Err(format!(
+                    "Phi %{} doesn't have one value per predecessor of bb{}",
+                    phi.result, block
+                ));
+            }
+            for (predecessor, value) in phi.incoming.iter() {
+                // Values of an edge are used at the end of the block it leaves
+                check_use(*value, *predecessor, usize::MAX)?;
+                if !fits(&body.values[*value], &body.values[phi.result]) {
+                    return Err(format!(
+                        "Phi %{} of type {} joins %{} of type {}",
+                        phi.result, body.values[phi.result], value, body.values[*value]
+                    ));
+                }
+            }
+        }
+
+        for (position, instruction) in basic_block.instructions.iter().enumerate() {
+            for operand in instruction.operation.operands() {
+                check_use(operand, block, position + 1)?;
+            }
+            verify_instruction(body, instruction)?;
+        }
+
+        let terminator = &basic_block.terminator;
+        let mut operands = vec![];
+        terminator
+            .clone()
+            .visit_values(&mut |value| operands.push(*value));
+        for operand in operands {
+            check_use(operand, block, basic_block.instructions.len() + 1)?;
+        }
+        verify_terminator(body, block, terminator)?;
+    }
+    Ok(())
+}
+
+// Mutable references are also shared ones
+fn fits(value_type: &Type, expected: &Type) -> bool {
+    match (value_type, expected) {
+        (Type::Reference(value_type, _), Type::Reference(expected, false)) => {
+            value_type == expected
+        }
+        _ => value_type == expected,
+    }
+}
+
+fn verify_place(body: &Body, place: &Place) -> Result<(), String> {
+    match place {
+        Place::Slot(slot) if *slot >= body.slots.len() => Err(format!("Missing slot ${}", slot)),
+        Place::Slot(_) | Place::Global(_) => Ok(()),
+        Place::Deref(value) => match body.values[*value] {
+            Type::Reference(..) => Ok(()),
+            ref t => Err(format!("Can't dereference %{} of type {}", value, t)),
+        },
+        Place::Field(place, _) => verify_place(body, place),
+        Place::Index(place, index) => {
+            verify_place(body, place)?;
+            match body.values[*index] {
+                Type::Int => Ok(()),
+                ref t => Err(format!("Index %{} has type {}", index, t)),
+            }
+        }
+    }
+}
+
+fn verify_instruction(body: &Body, instruction: &Instruction) -> Result<(), String> {
+    let result_type = instruction.result.map(|result| &body.values[result]);
+    match &instruction.operation {
+        Operation::Binary(operator, lhs, rhs) => {
+            let operand_type = &body.values[*lhs];
+            if *operand_type != body.values[*rhs] {
+                return Err(format!(
+                    "Operands of {} have types {} and {}",
+                    instruction.operation, operand_type, body.values[*rhs]
+                ));
+            }
+            if result_type != Some(&binary_type(operator, operand_type)) {
+                return Err(format!(
+                    "{} doesn't give a value of type {}",
+                    instruction.operation,
+                    binary_type(operator, operand_type)
+                ));
+            }
+        }
+        Operation::Test(value) => {
+            if !matches!(body.values[*value], Type::Optional(_) | Type::Result(..)) {
+                return Err(format!(
+                    "Can't test %{} of type {}",
+                    value, body.values[*value]
+                ));
+            }
+            if result_type != Some(&Type::Bool) {
+                return Err(format!("{} doesn't give a bool", instruction.operation));
+            }
+        }
+        Operation::AddressOf(place) | Operation::Load(place) | Operation::Store(place, _) => {
+            verify_place(body, place)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn verify_terminator(body: &Body, block: BlockId, terminator: &Terminator) -> Result<(), String> {
+    match terminator {
+        Terminator::Branch(condition, _, _) if body.values[*condition] != Type::Bool => {
+            Err(format!(
+                "bb{} branches on %{} of type {}",
+                block, condition, body.values[*condition]
+            ))
+        }
+        Terminator::Switch(value, _, _) if !matches!(body.values[*value], Type::UserDefined(_)) => {
+            Err(format!(
+                "bb{} switches on %{} of type {}",
+                block, value, body.values[*value]
+            ))
+        }
+        Terminator::Return(Some(value)) if !fits(&body.values[*value], &body.return_type) => {
+            Err(format!(
+                "bb{} returns %{} of type {} from a function returning {}",
+                block, value, body.values[*value], body.return_type
+            ))
+        }
+        Terminator::Return(None) if body.return_type != Type::Void => Err(format!(
+            "bb{} returns no value from a function returning {}",
+            block, body.return_type
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(instructions: Vec<Instruction>, terminator: Terminator) -> BasicBlock {
+        BasicBlock {
+            phis: vec![],
+            instructions,
+            terminator,
+        }
+    }
+
+    fn constant(result: Value, value: i32) -> Instruction {
+        Instruction {
+            result: Some(result),
+            operation: Operation::Constant(Constant::Int(value)),
+        }
+    }
+
+    // fn f(%0: bool): int, branching to bb1 and bb2 that join in bb3
+    fn diamond(then_block: BasicBlock, else_block: BasicBlock, join: BasicBlock) -> Body {
+        let mut body = Body::new("f", Type::Int);
+        body.parameters = vec![0];
+        body.values = vec![Type::Bool, Type::Int, Type::Int, Type::Int];
+        body.blocks = vec![
+            block(vec![], Terminator::Branch(0, 1, 2)),
+            then_block,
+            else_block,
+            join,
+        ];
+        body
+    }
+
+    fn verify_body(body: Body) -> Result<(), String> {
+        verify(&Mir {
+            globals: vec![],
+            bodies: vec![body],
+        })
+    }
+
+    #[test]
+    fn test_verify_phi() {
+        let join = BasicBlock {
+            phis: vec![Phi {
+                result: 3,
+                incoming: vec![(1, 1), (2, 2)],
+            }],
+            instructions: vec![],
+            terminator: Terminator::Return(Some(3)),
+        };
+        let body = diamond(
+            block(vec![constant(1, 1)], Terminator::Goto(3)),
+            block(vec![constant(2, 2)], Terminator::Goto(3)),
+            join,
+        );
+        assert_eq!(verify_body(body.clone()), Ok(()));
+
+        // A value per predecessor
+        let mut missing = body.clone();
+        missing.blocks[3].phis[0].incoming.pop();
+        assert_eq!(
+            verify_body(missing),
+            Err(
+                "Invalid MIR in f: Phi %3 doesn't have one value per predecessor of bb3"
+                    .to_string()
+            )
+        );
+
+        // Values of an edge come from the block it leaves
+        let mut swapped = body;
+        swapped.blocks[3].phis[0].incoming = vec![(1, 2), (2, 1)];
+        assert_eq!(
+            verify_body(swapped),
+            Err("Invalid MIR in f: bb1 uses %2 where its definition doesn't dominate".to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_definitions() {
+        // %1 is defined in bb1 only, and used after bb1 and bb2 join
+        let body = diamond(
+            block(vec![constant(1, 1)], Terminator::Goto(3)),
+            block(vec![], Terminator::Goto(3)),
+            block(vec![], Terminator::Return(Some(1))),
+        );
+        assert_eq!(
+            verify_body(body),
+            Err("Invalid MIR in f: bb3 uses %1 where its definition doesn't dominate".to_string())
+        );
+
+        let body = diamond(
+            block(
+                vec![constant(1, 1), constant(1, 2)],
+                Terminator::Return(Some(1)),
+            ),
+            block(vec![], Terminator::Return(Some(2))),
+            block(vec![], Terminator::Unreachable),
+        );
+        assert_eq!(
+            verify_body(body),
+            Err("Invalid MIR in f: %1 is defined more than once".to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_types() {
+        let mut body = diamond(
+            block(vec![constant(1, 1)], Terminator::Return(Some(1))),
+            block(vec![], Terminator::Return(Some(0))),
+            block(vec![], Terminator::Unreachable),
+        );
+        assert_eq!(
+            verify_body(body.clone()),
+            Err(
+                "Invalid MIR in f: bb2 returns %0 of type bool from a function returning int"
+                    .to_string()
+            )
+        );
+
+        body.blocks[2].terminator = Terminator::Branch(1, 1, 3);
+        body.blocks[1].instructions.clear();
+        body.blocks[0].instructions.push(constant(1, 1));
+        assert_eq!(
+            verify_body(body),
+            Err("Invalid MIR in f: bb2 branches on %1 of type int".to_string())
+        );
+    }
+}
@@ -0,0 +1,849 @@
+use crate::ast::*;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A source file and the items it declares. Items of the root module keep
+// their names, the others are prefixed with the module name: `math__sqrt`
+pub struct Module {
+    pub name: String,
+    pub path: PathBuf,
+    pub ast: Vec<Ast>,
+    // Top-level items and whether they are `pub`
+    pub symbols: HashMap<String, bool>,
+    // Functions declared with `export`, which keep their names in C
+    pub exports: HashSet<String>,
+}
+
+impl Module {
+    fn new(name: String, path: PathBuf, ast: Vec<Ast>) -> Self {
+        let mut symbols = HashMap::new();
+        let mut exports = HashSet::new();
+        for node in ast.iter() {
+            match node {
+                Ast::Function(function) => {
+                    symbols.insert(function.name.clone(), function.public);
+                    if function.exported {
+                        exports.insert(function.name.clone());
+                    }
+                }
+                Ast::Struct(struct_node) => {
+                    symbols.insert(struct_node.name.clone(), struct_node.public);
+                }
+                Ast::Enum(enum_node) => {
+                    symbols.insert(enum_node.name.clone(), enum_node.public);
+                }
+                Ast::Trait(trait_node) => {
+                    symbols.insert(trait_node.name.clone(), trait_node.public);
+                }
+                // Globals are private to their module
+                Ast::Assignment(assignment) => {
+                    symbols.insert(assignment.name.clone(), false);
+                }
+                _ => {}
+            }
+        }
+        Self {
+            name,
+            path,
+            ast,
+            symbols,
+            exports,
+        }
+    }
+
+    fn imports(&self) -> impl Iterator<Item = &Import> {
+        self.ast.iter().filter_map(|node| match node {
+            Ast::Import(import) => Some(import),
+            _ => None,
+        })
+    }
+}
+
+// The compiler names the files it generates for the runtime and the types
+// modules share with this prefix, and the runtime and the generated code
+// name their functions, types and variables with it
+pub const RESERVED_PREFIX: &str = "idk_";
+
+// Every module reachable from the root file, with the imported ones before
+// the modules that import them and the root last
+pub struct ModuleGraph {
+    pub modules: Vec<Module>,
+}
+
+impl ModuleGraph {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut graph = Self { modules: vec![] };
+        let name = module_name(path)?;
+        graph.load_module(name, path.to_path_buf(), &mut vec![])?;
+        Ok(graph)
+    }
+
+    // `loading` is the chain of imports that led to this module
+    fn load_module(
+        &mut self,
+        name: String,
+        path: PathBuf,
+        loading: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if name.starts_with(RESERVED_PREFIX) {
+            return Err(format!(
+                "Module name '{}' is reserved, names starting with '{}' are used by generated files",
+                name, RESERVED_PREFIX
+            ));
+        }
+        if let Some(position) = loading.iter().position(|module| *module == name) {
+            let mut cycle = loading[position..].to_vec();
+            cycle.push(name);
+            return Err(format!("Import cycle: {}", cycle.join(" -> ")));
+        }
+        if let Some(module) = self.get(&name) {
+            if module.path != path {
+                return Err(format!(
+                    "Modules {} and {} have the same name '{}'",
+                    module.path.display(),
+                    path.display(),
+                    name
+                ));
+            }
+            return Ok(());
+        }
+
+        eprintln!("LOADING MODULE {}: {}", name, path.display());
+        let source_code = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Couldn't read module '{}' at {}: {}",
+                name,
+                path.display(),
+                e
+            )
+        })?;
+        let tokens = Lexer::new(source_code.chars().collect())
+            .tokenize()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for token in tokens.iter() {
+            eprintln!("{:?}", token);
+        }
+        let ast = Parser::new(tokens)
+            .generate_ast()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let module = Module::new(name.clone(), path, ast);
+
+        let directory = module.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        loading.push(name);
+        for import in module.imports() {
+            let import_path = directory.join(format!("{}.idk", import.module));
+            if !import_path.exists() {
+                return Err(format!(
+                    "Module '{}' imported by '{}' not found: expected {}",
+                    import.module,
+                    module.name,
+                    import_path.display()
+                ));
+            }
+            self.load_module(import.module.clone(), import_path, loading)?;
+        }
+        loading.pop();
+
+        self.modules.push(module);
+        Ok(())
+    }
+
+    // Modules each module imports, directly or through the modules it
+    // imports, in the order of `modules`
+    pub fn imports(&self) -> Vec<Vec<String>> {
+        let mut imports: Vec<Vec<String>> = vec![];
+        for module in self.modules.iter() {
+            let mut reachable: HashSet<&str> = HashSet::new();
+            for import in module.imports() {
+                reachable.insert(&import.module);
+                if let Some(position) = self.position(&import.module) {
+                    reachable.extend(imports[position].iter().map(String::as_str));
+                }
+            }
+            imports.push(
+                self.modules
+                    .iter()
+                    .map(|other| other.name.clone())
+                    .filter(|name| reachable.contains(name.as_str()))
+                    .collect(),
+            );
+        }
+        imports
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.modules.iter().position(|module| module.name == name)
+    }
+
+    fn get(&self, name: &str) -> Option<&Module> {
+        self.modules.iter().find(|module| module.name == name)
+    }
+
+    fn root(&self) -> &str {
+        &self.modules.last().unwrap().name
+    }
+
+    // Exported functions keep their names in C, where they must be unique
+    fn check_exports(&self) -> Result<(), String> {
+        let root = self.modules.last().unwrap();
+        let mut exported: HashMap<&str, &str> = HashMap::new();
+        for module in self.modules.iter() {
+            for name in module.exports.iter() {
+                if let Some(other) = exported.insert(name, &module.name) {
+                    return Err(format!(
+                        "Function '{}' is exported by both modules '{}' and '{}'",
+                        name, other, module.name
+                    ));
+                }
+                if module.name != root.name && root.symbols.contains_key(name) {
+                    return Err(format!(
+                        "Function '{}' exported by module '{}' conflicts with '{}' of module '{}'",
+                        name, module.name, name, root.name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the imports by the items they name and merges every module
+    // into a single program, the imported modules first
+    pub fn link(self) -> Result<Vec<Ast>, String> {
+        self.check_exports()?;
+        let root = self.modules.last().unwrap();
+        let mut program: Vec<Ast> = prelude()?
+            .into_iter()
+            .filter(|node| !is_hidden(node, root))
+            .collect();
+        for module in self.modules.iter() {
+            let in_module = |e: String| format!("{}: {}", module.path.display(), e);
+            let mut resolver = Resolver::new(&self, module).map_err(in_module)?;
+            for node in module.ast.iter() {
+                if let Ast::Import(_) = node {
+                    continue;
+                }
+                let mut node = node.clone();
+                resolver.resolve_item(&mut node).map_err(in_module)?;
+                program.push(node);
+            }
+        }
+        Ok(program)
+    }
+}
+
+// Functions every program can call, declared in idk and implemented by the
+// C runtime
+const PRELUDE: &str = include_str!("prelude.idk");
+
+fn prelude() -> Result<Vec<Ast>, String> {
+    let tokens = Lexer::new(PRELUDE.chars().collect())
+        .tokenize()
+        .map_err(|e| format!("prelude: {}", e))?;
+    Parser::new(tokens)
+        .generate_ast()
+        .map_err(|e| format!("prelude: {}", e))
+}
+
+// The program's own functions hide the prelude functions they would clash
+// with: the ones with the same parameter types, or every one if generic
+fn is_hidden(node: &Ast, root: &Module) -> bool {
+    let prototype = match node {
+        Ast::Extern(extern_node) => &extern_node.prototype,
+        _ => return false,
+    };
+    let parameter_types = |parameters: &[Parameter]| -> Vec<Type> {
+        parameters
+            .iter()
+            .map(|parameter| parameter.parameter_type.clone())
+            .collect()
+    };
+    root.ast.iter().any(|node| match node {
+        Ast::Function(function) if function.name == prototype.name => {
+            !function.type_parameters.is_empty()
+                || parameter_types(&function.parameters) == parameter_types(&prototype.parameters)
+        }
+        _ => false,
+    })
+}
+
+fn module_name(path: &Path) -> Result<String, String> {
+    match path.file_stem().and_then(|stem| stem.to_str()) {
+        Some(name) => Ok(name.to_string()),
+        None => Err(format!("Invalid module path {}", path.display())),
+    }
+}
+
+fn qualified_name(graph: &ModuleGraph, module: &str, item: &str) -> String {
+    if module == graph.root() {
+        item.to_string()
+    } else {
+        format!("{}__{}", module, item)
+    }
+}
+
+// Renames every item a module declares or refers to after the module that
+// declares it
+struct Resolver<'a> {
+    graph: &'a ModuleGraph,
+    module: &'a Module,
+    // Modules imported with `import`
+    imported: HashSet<String>,
+    // Items brought into scope with `use`, by the qualified name
+    used: HashMap<String, String>,
+    // Local variables, which shadow the items of any module
+    scopes: Vec<HashSet<String>>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(graph: &'a ModuleGraph, module: &'a Module) -> Result<Self, String> {
+        let mut resolver = Self {
+            graph,
+            module,
+            imported: HashSet::new(),
+            used: HashMap::new(),
+            scopes: vec![],
+        };
+        for import in module.imports() {
+            match &import.item {
+                None => {
+                    resolver.imported.insert(import.module.clone());
+                }
+                Some(item) => {
+                    if module.symbols.contains_key(item) {
+                        return Err(format!(
+                            "'{}' is declared in module '{}' and also used from module '{}'",
+                            item, module.name, import.module
+                        ));
+                    }
+                    let name = resolver.resolve_path(&import.module, item)?;
+                    resolver.used.insert(item.clone(), name);
+                }
+            }
+        }
+        Ok(resolver)
+    }
+
+    fn resolve_path(&self, module: &str, item: &str) -> Result<String, String> {
+        let target = match self.graph.get(module) {
+            Some(target) => target,
+            None => return Err(format!("Module '{}' isn't imported", module)),
+        };
+        match target.symbols.get(item) {
+            Some(true) => Ok(qualified_name(self.graph, module, item)),
+            Some(false) => Err(format!("'{}' is private to module '{}'", item, module)),
+            None => Err(format!("Module '{}' has no item named '{}'", module, item)),
+        }
+    }
+
+    // Names that aren't items of any module, like the builtin functions,
+    // are left as they are
+    fn resolve_name(&self, name: &mut String) -> Result<(), String> {
+        if let Some((module, item)) = name.split_once("::") {
+            if !self.imported.contains(module) {
+                return Err(format!("Module '{}' isn't imported", module));
+            }
+            *name = self.resolve_path(module, item)?;
+        } else if self.module.symbols.contains_key(name.as_str()) {
+            *name = qualified_name(self.graph, &self.module.name, name);
+        } else if let Some(used) = self.used.get(name.as_str()) {
+            *name = used.clone();
+        }
+        Ok(())
+    }
+
+    fn resolve_variable(&self, name: &mut String) -> Result<(), String> {
+        if self
+            .scopes
+            .iter()
+            .any(|scope| scope.contains(name.as_str()))
+        {
+            return Ok(());
+        }
+        self.resolve_name(name)
+    }
+
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string());
+        }
+    }
+
+    fn resolve_type(&self, type_: &mut Type) -> Result<(), String> {
+        match type_ {
+            Type::UserDefined(name) => self.resolve_name(name),
+            Type::Array(inner, _)
+            | Type::Slice(inner)
+            | Type::Reference(inner, _)
+            | Type::Optional(inner) => self.resolve_type(inner),
+            Type::Result(value_type, error_type) => {
+                self.resolve_type(value_type)?;
+                self.resolve_type(error_type)
+            }
+            Type::Function(parameters, return_type) => {
+                for parameter in parameters.iter_mut() {
+                    self.resolve_type(parameter)?;
+                }
+                self.resolve_type(return_type)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_item(&mut self, node: &mut Ast) -> Result<(), String> {
+        match node {
+            Ast::Function(function) => {
+                self.resolve_name(&mut function.name)?;
+                self.resolve_function(function)
+            }
+            Ast::Struct(struct_node) => {
+                self.resolve_name(&mut struct_node.name)?;
+                for field in struct_node.fields.iter_mut() {
+                    self.resolve_type(&mut field.field_type)?;
+                }
+                Ok(())
+            }
+            Ast::Enum(enum_node) => {
+                self.resolve_name(&mut enum_node.name)?;
+                for variant in enum_node.variants.iter_mut() {
+                    for field in variant.fields.iter_mut() {
+                        self.resolve_type(field)?;
+                    }
+                }
+                Ok(())
+            }
+            Ast::Trait(trait_node) => {
+                self.resolve_name(&mut trait_node.name)?;
+                for method in trait_node.methods.iter_mut() {
+                    self.resolve_parameters(&mut method.parameters)?;
+                    if let Some(return_type) = &mut method.return_type {
+                        self.resolve_type(return_type)?;
+                    }
+                }
+                Ok(())
+            }
+            // Methods are found through the type, so they keep their names
+            Ast::Impl(impl_node) => {
+                self.resolve_name(&mut impl_node.type_name)?;
+                if let Some(trait_name) = &mut impl_node.trait_name {
+                    self.resolve_name(trait_name)?;
+                }
+                for method in impl_node.methods.iter_mut() {
+                    self.resolve_function(method)?;
+                }
+                Ok(())
+            }
+            Ast::Assignment(assignment) => {
+                self.resolve_name(&mut assignment.name)?;
+                if let Some(var_type) = &mut assignment.var_type {
+                    self.resolve_type(var_type)?;
+                }
+                self.resolve_expression(&mut assignment.value)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_parameters(&self, parameters: &mut [Parameter]) -> Result<(), String> {
+        for parameter in parameters.iter_mut() {
+            self.resolve_type(&mut parameter.parameter_type)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_function(&mut self, function: &mut Function) -> Result<(), String> {
+        for type_parameter in function.type_parameters.iter_mut() {
+            for bound in type_parameter.bounds.iter_mut() {
+                self.resolve_name(bound)?;
+            }
+        }
+        self.resolve_parameters(&mut function.parameters)?;
+        for parameter in function.parameters.iter_mut() {
+            if let Some(default) = &mut parameter.default {
+                self.resolve_expression(default)?;
+            }
+        }
+        if let Some(return_type) = &mut function.return_type {
+            self.resolve_type(return_type)?;
+        }
+
+        self.scopes.push(
+            function
+                .parameters
+                .iter()
+                .map(|parameter| parameter.name.clone())
+                .collect(),
+        );
+        let result = self.resolve_block(&mut function.body);
+        self.scopes.pop();
+        result
+    }
+
+    fn resolve_block(&mut self, block: &mut Block) -> Result<(), String> {
+        self.scopes.push(HashSet::new());
+        let result = block
+            .itens
+            .iter_mut()
+            .try_for_each(|statement| self.resolve_statement(statement));
+        self.scopes.pop();
+        result
+    }
+
+    fn resolve_statement(&mut self, statement: &mut Ast) -> Result<(), String> {
+        match statement {
+            Ast::Assignment(assignment) => {
+                if let Some(var_type) = &mut assignment.var_type {
+                    self.resolve_type(var_type)?;
+                }
+                self.resolve_expression(&mut assignment.value)?;
+                self.declare(&assignment.name);
+                Ok(())
+            }
+            Ast::Reassignment(reassignment) => {
+                self.resolve_expression(&mut reassignment.target)?;
+                self.resolve_expression(&mut reassignment.value)
+            }
+            Ast::Return(Some(expression)) | Ast::Expression(expression) => {
+                self.resolve_expression(expression)
+            }
+            Ast::For(for_loop) => {
+                self.resolve_expression(&mut for_loop.iterable)?;
+                self.scopes.push(HashSet::from([for_loop.variable.clone()]));
+                let result = self.resolve_block(&mut for_loop.body);
+                self.scopes.pop();
+                result
+            }
+            Ast::If(if_node) => {
+                let mut scope = HashSet::new();
+                match &mut if_node.condition {
+                    Condition::Expression(condition) => self.resolve_expression(condition)?,
+                    Condition::Let(name, optional) => {
+                        self.resolve_expression(optional)?;
+                        scope.insert(name.clone());
+                    }
+                }
+                self.scopes.push(scope);
+                let result = self.resolve_block(&mut if_node.body);
+                self.scopes.pop();
+                result?;
+                match &mut if_node.else_body {
+                    Some(else_body) => self.resolve_block(else_body),
+                    None => Ok(()),
+                }
+            }
+            // The checker reports declarations inside a function
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_expressions(&mut self, expressions: &mut [Expression]) -> Result<(), String> {
+        expressions
+            .iter_mut()
+            .try_for_each(|expression| self.resolve_expression(expression))
+    }
+
+    fn resolve_expression(&mut self, expression: &mut Expression) -> Result<(), String> {
+        match expression {
+            Expression::Identifier(name) => self.resolve_variable(name),
+            // A call through a local variable holding a function
+            Expression::FunctionCall(name, arguments) => {
+                self.resolve_variable(name)?;
+                self.resolve_expressions(arguments)
+            }
+            Expression::Cast(inner, target_type) => {
+                self.resolve_type(target_type)?;
+                self.resolve_expression(inner)
+            }
+            Expression::BinaryExpr(lhs, _, rhs) | Expression::Index(lhs, rhs) => {
+                self.resolve_expression(lhs)?;
+                self.resolve_expression(rhs)
+            }
+            Expression::FieldAccess(inner, _)
+            | Expression::AddressOf(inner, _)
+            | Expression::Dereference(inner)
+            | Expression::Err(inner)
+            | Expression::Try(inner)
+            | Expression::NamedArgument(_, inner) => self.resolve_expression(inner),
+            Expression::Array(items) | Expression::Call(_, items) => {
+                self.resolve_expressions(items)
+            }
+            Expression::StructLiteral(name, fields) => {
+                self.resolve_name(name)?;
+                fields
+                    .iter_mut()
+                    .try_for_each(|(_, value)| self.resolve_expression(value))
+            }
+            Expression::MethodCall(receiver, _, arguments) => {
+                self.resolve_expression(receiver)?;
+                self.resolve_expressions(arguments)
+            }
+            Expression::EnumVariant(name, _, arguments) => {
+                self.resolve_name(name)?;
+                self.resolve_expressions(arguments)
+            }
+            Expression::Match(subject, arms) => {
+                self.resolve_expression(subject)?;
+                for arm in arms.iter_mut() {
+                    let mut scope = HashSet::new();
+                    if let Pattern::Variant(name, _, bindings) = &mut arm.pattern {
+                        self.resolve_name(name)?;
+                        scope.extend(bindings.iter().cloned());
+                    }
+                    self.scopes.push(scope);
+                    let result = self.resolve_expression(&mut arm.value);
+                    self.scopes.pop();
+                    result?;
+                }
+                Ok(())
+            }
+            Expression::Closure(closure) => {
+                self.resolve_parameters(&mut closure.parameters)?;
+                if let Some(return_type) = &mut closure.return_type {
+                    self.resolve_type(return_type)?;
+                }
+                self.scopes.push(
+                    closure
+                        .parameters
+                        .iter()
+                        .map(|parameter| parameter.name.clone())
+                        .collect(),
+                );
+                let result = self.resolve_block(&mut closure.body);
+                self.scopes.pop();
+                result
+            }
+            Expression::Float(_)
+            | Expression::Int(_)
+            | Expression::StringLit(_)
+            | Expression::Boolean(_)
+            | Expression::NoneLit
+            | Expression::Function(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Token;
+
+    // Writes the files into a directory of their own and loads the first one
+    fn load(test: &str, files: &[(&str, &str)]) -> Result<Vec<Ast>, String> {
+        let directory = std::env::temp_dir().join(format!("idk_modules_{}", test));
+        fs::create_dir_all(&directory).unwrap();
+        for (name, source_code) in files.iter() {
+            fs::write(directory.join(name), source_code).unwrap();
+        }
+        ModuleGraph::load(&directory.join(files[0].0))?.link()
+    }
+
+    fn function<'a>(ast: &'a [Ast], name: &str) -> &'a Function {
+        ast.iter()
+            .find_map(|node| match node {
+                Ast::Function(function) if function.name == name => Some(function),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("Expected a function named {}", name))
+    }
+
+    #[test]
+    fn test_link() {
+        let ast = load(
+            "link",
+            &[
+                (
+                    "main.idk",
+                    "import shapes;\nuse util::twice;\nfn main(): int {\n    int twice_ = 1;\n    shapes::Point p = shapes::Point { x: 1 };\n    return twice(shapes::size(p)) + twice_;\n}\n",
+                ),
+                (
+                    "shapes.idk",
+                    "import util;\npub struct Point {\n    int x;\n}\npub fn size(Point p): int {\n    return util::twice(p.x);\n}\n",
+                ),
+                (
+                    "util.idk",
+                    "pub fn twice(int x): int {\n    return x * 2;\n}\n",
+                ),
+            ],
+        )
+        .unwrap();
+
+        // Imported modules come first
+        let names: Vec<String> = ast
+            .iter()
+            .filter_map(|node| match node {
+                Ast::Function(function) => Some(function.name.clone()),
+                Ast::Struct(struct_node) => Some(struct_node.name.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec!["util__twice", "shapes__Point", "shapes__size", "main"]
+        );
+
+        let size = function(&ast, "shapes__size");
+        assert_eq!(
+            size.parameters[0].parameter_type,
+            Type::UserDefined("shapes__Point".to_string())
+        );
+        assert_eq!(
+            size.body.itens,
+            vec![Ast::Return(Some(Expression::FunctionCall(
+                "util__twice".to_string(),
+                vec![Expression::FieldAccess(
+                    Box::new(Expression::Identifier("p".to_string())),
+                    "x".to_string()
+                )]
+            )))]
+        );
+
+        let main = function(&ast, "main");
+        let call = Expression::FunctionCall(
+            "shapes__size".to_string(),
+            vec![Expression::Identifier("p".to_string())],
+        );
+        assert_eq!(
+            main.body.itens[2],
+            Ast::Return(Some(Expression::BinaryExpr(
+                Box::new(Expression::FunctionCall(
+                    "util__twice".to_string(),
+                    vec![call]
+                )),
+                Token::Plus,
+                Box::new(Expression::Identifier("twice_".to_string()))
+            )))
+        );
+    }
+
+    #[test]
+    fn test_prelude() {
+        let ast = load(
+            "prelude",
+            &[(
+                "main.idk",
+                "fn max<T>(T a, T b): T {\n    return a;\n}\nfn abs(int x): int {\n    return x;\n}\n",
+            )],
+        )
+        .unwrap();
+        let externs: Vec<String> = ast
+            .iter()
+            .filter_map(|node| match node {
+                Ast::Extern(extern_node) => Some(extern_node.c_name.clone()),
+                _ => None,
+            })
+            .collect();
+        assert!(externs.contains(&"idk_println".to_string()));
+        assert!(externs.contains(&"idk_abs_float".to_string()));
+        assert!(!externs.contains(&"idk_abs_int".to_string()));
+        assert!(!externs.iter().any(|c_name| c_name.starts_with("idk_max")));
+    }
+
+    #[test]
+    fn test_module_errors() {
+        let cycle = load(
+            "cycle",
+            &[
+                ("main.idk", "import a;\nfn main() {}\n"),
+                ("a.idk", "import b;\n"),
+                ("b.idk", "import a;\n"),
+            ],
+        );
+        assert_eq!(cycle, Err("Import cycle: a -> b -> a".to_string()));
+
+        let private = load(
+            "private",
+            &[
+                ("main.idk", "import a;\nfn main() {\n    a::f();\n}\n"),
+                ("a.idk", "fn f() {}\n"),
+            ],
+        );
+        assert!(private
+            .unwrap_err()
+            .ends_with("'f' is private to module 'a'"));
+
+        let missing = load("missing", &[("main.idk", "use nothing::f;\n")]);
+        assert!(missing
+            .unwrap_err()
+            .starts_with("Module 'nothing' imported by 'main' not found"));
+
+        let unknown = load(
+            "unknown",
+            &[("main.idk", "use a::g;\n"), ("a.idk", "pub fn f() {}\n")],
+        );
+        assert!(unknown
+            .unwrap_err()
+            .ends_with("Module 'a' has no item named 'g'"));
+
+        let exported = load(
+            "exported",
+            &[
+                ("main.idk", "import a;\nimport b;\n"),
+                ("a.idk", "export fn f() {}\n"),
+                ("b.idk", "export fn f() {}\n"),
+            ],
+        );
+        assert_eq!(
+            exported,
+            Err("Function 'f' is exported by both modules 'a' and 'b'".to_string())
+        );
+
+        let reserved = load(
+            "reserved",
+            &[
+                (
+                    "main.idk",
+                    "import idk_runtime;
+",
+                ),
+                ("idk_runtime.idk", ""),
+            ],
+        );
+        assert!(reserved
+            .unwrap_err()
+            .starts_with("Module name 'idk_runtime' is reserved"));
+    }
+
+    #[test]
+    fn test_imports() {
+        let directory = std::env::temp_dir().join("idk_modules_imports");
+        fs::create_dir_all(&directory).unwrap();
+        for (name, source_code) in [
+            (
+                "main.idk",
+                "import shapes;
+fn main() {}
+",
+            ),
+            (
+                "shapes.idk",
+                "use util::twice;
+",
+            ),
+            (
+                "util.idk",
+                "pub fn twice() {}
+",
+            ),
+            (
+                "other.idk",
+                "import util;
+",
+            ),
+        ] {
+            fs::write(directory.join(name), source_code).unwrap();
+        }
+        let graph = ModuleGraph::load(&directory.join("main.idk")).unwrap();
+
+        // main reaches util through shapes, other isn't imported
+        assert_eq!(
+            graph.imports(),
+            vec![
+                Vec::<String>::new(),
+                vec!["util".to_string()],
+                vec!["util".to_string(), "shapes".to_string()],
+            ]
+        );
+    }
+}
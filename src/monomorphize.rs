@@ -0,0 +1,291 @@
+use crate::ast::*;
+use std::collections::HashMap;
+
+// Generic functions are compiled once per list of type arguments they are
+// called with. Each instance is a copy of the function where every type
+// parameter is replaced by its argument, so later passes never see generics
+
+// Name of the instance of a generic function: `max<int>` is `max_int`.
+// Different type arguments always give different names
+pub fn instance_name(name: &str, type_arguments: &[Type]) -> String {
+    let mut parts = vec![name.to_string()];
+    parts.extend(type_arguments.iter().map(Type::mangled));
+    parts.join("_")
+}
+
+// Names of the type parameters a type is built from
+pub fn type_parameters(type_: &Type) -> Vec<String> {
+    match type_ {
+        Type::Generic(name) => vec![name.clone()],
+        Type::Array(inner, _)
+        | Type::Slice(inner)
+        | Type::Reference(inner, _)
+        | Type::Optional(inner) => type_parameters(inner),
+        Type::Result(value_type, error_type) => {
+            let mut names = type_parameters(value_type);
+            names.extend(type_parameters(error_type));
+            names
+        }
+        Type::Function(parameter_types, return_type) => {
+            let mut names: Vec<String> = parameter_types.iter().flat_map(type_parameters).collect();
+            names.extend(type_parameters(return_type));
+            names
+        }
+        _ => vec![],
+    }
+}
+
+// Matches the type of a parameter against the type of its argument, binding
+// the type parameters found on the way. Only conflicting bindings are errors,
+// other mismatches are reported when the arguments are checked against the
+// instance. Returns the type parameter and both of its candidates on conflict
+pub fn infer(
+    parameter: &Type,
+    argument: &Type,
+    bindings: &mut HashMap<String, Type>,
+) -> Result<(), (String, Type, Type)> {
+    match (parameter, argument) {
+        (Type::Generic(name), argument) => match bindings.get(name) {
+            Some(bound) if bound != argument => {
+                Err((name.clone(), bound.clone(), argument.clone()))
+            }
+            Some(_) => Ok(()),
+            None => {
+                bindings.insert(name.clone(), argument.clone());
+                Ok(())
+            }
+        },
+        (Type::Array(parameter, _), Type::Array(argument, _))
+        | (Type::Slice(parameter), Type::Slice(argument) | Type::Array(argument, _))
+        | (Type::Reference(parameter, _), Type::Reference(argument, _))
+        | (Type::Optional(parameter), Type::Optional(argument)) => {
+            infer(parameter, argument, bindings)
+        }
+        (Type::Result(value_type, error_type), Type::Result(argument, error)) => {
+            infer(value_type, argument, bindings)?;
+            infer(error_type, error, bindings)
+        }
+        (Type::Function(parameters, return_type), Type::Function(arguments, returned))
+            if parameters.len() == arguments.len() =>
+        {
+            for (parameter, argument) in parameters.iter().zip(arguments.iter()) {
+                infer(parameter, argument, bindings)?;
+            }
+            infer(return_type, returned, bindings)
+        }
+        // Values are wrapped into optionals and results
+        (Type::Optional(parameter) | Type::Result(parameter, _), argument) => {
+            infer(parameter, argument, bindings)
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn specialize(function: &Function, type_arguments: &[Type]) -> Function {
+    let bindings: HashMap<String, Type> = function
+        .type_parameters
+        .iter()
+        .map(|type_parameter| type_parameter.name.clone())
+        .zip(type_arguments.iter().cloned())
+        .collect();
+
+    let mut instance = function.clone();
+    instance.name = instance_name(&function.name, type_arguments);
+    instance.type_parameters = vec![];
+    for parameter in instance.parameters.iter_mut() {
+        parameter.parameter_type = substitute(&parameter.parameter_type, &bindings);
+        if let Some(default) = &mut parameter.default {
+            specialize_expression(default, &bindings);
+        }
+    }
+    instance.return_type = instance
+        .return_type
+        .map(|return_type| substitute(&return_type, &bindings));
+    specialize_block(&mut instance.body, &bindings);
+    instance
+}
+
+pub fn substitute(type_: &Type, bindings: &HashMap<String, Type>) -> Type {
+    match type_ {
+        Type::Generic(name) => bindings.get(name).cloned().unwrap_or(type_.clone()),
+        Type::Array(element_type, length) => {
+            Type::Array(Box::new(substitute(element_type, bindings)), *length)
+        }
+        Type::Slice(element_type) => Type::Slice(Box::new(substitute(element_type, bindings))),
+        Type::Reference(referenced_type, mutable) => {
+            Type::Reference(Box::new(substitute(referenced_type, bindings)), *mutable)
+        }
+        Type::Optional(value_type) => Type::Optional(Box::new(substitute(value_type, bindings))),
+        Type::Result(value_type, error_type) => Type::Result(
+            Box::new(substitute(value_type, bindings)),
+            Box::new(substitute(error_type, bindings)),
+        ),
+        Type::Function(parameter_types, return_type) => Type::Function(
+            parameter_types
+                .iter()
+                .map(|parameter_type| substitute(parameter_type, bindings))
+                .collect(),
+            Box::new(substitute(return_type, bindings)),
+        ),
+        t => t.clone(),
+    }
+}
+
+fn specialize_block(block: &mut Block, bindings: &HashMap<String, Type>) {
+    for statement in block.itens.iter_mut() {
+        specialize_statement(statement, bindings);
+    }
+}
+
+fn specialize_statement(statement: &mut Ast, bindings: &HashMap<String, Type>) {
+    match statement {
+        Ast::Assignment(assignment) => {
+            if let Some(var_type) = &mut assignment.var_type {
+                *var_type = substitute(var_type, bindings);
+            }
+            specialize_expression(&mut assignment.value, bindings);
+        }
+        Ast::Reassignment(reassignment) => {
+            specialize_expression(&mut reassignment.target, bindings);
+            specialize_expression(&mut reassignment.value, bindings);
+        }
+        Ast::Return(Some(expression)) | Ast::Expression(expression) => {
+            specialize_expression(expression, bindings);
+        }
+        Ast::For(for_loop) => {
+            specialize_expression(&mut for_loop.iterable, bindings);
+            specialize_block(&mut for_loop.body, bindings);
+        }
+        Ast::If(if_node) => {
+            match &mut if_node.condition {
+                Condition::Expression(condition) | Condition::Let(_, condition) => {
+                    specialize_expression(condition, bindings);
+                }
+            }
+            specialize_block(&mut if_node.body, bindings);
+            if let Some(else_body) = &mut if_node.else_body {
+                specialize_block(else_body, bindings);
+            }
+        }
+        // Declarations can't appear inside a function body
+        Ast::Return(None)
+        | Ast::Function(_)
+        | Ast::Struct(_)
+        | Ast::Enum(_)
+        | Ast::Impl(_)
+        | Ast::Trait(_)
+        | Ast::Import(_)
+        | Ast::Extern(_) => {}
+    }
+}
+
+// Casts are the only expressions that spell out a type
+fn specialize_expression(expression: &mut Expression, bindings: &HashMap<String, Type>) {
+    match expression {
+        Expression::Cast(inner, target_type) => {
+            *target_type = substitute(target_type, bindings);
+            specialize_expression(inner, bindings);
+        }
+        Expression::BinaryExpr(lhs, _, rhs) | Expression::Index(lhs, rhs) => {
+            specialize_expression(lhs, bindings);
+            specialize_expression(rhs, bindings);
+        }
+        Expression::FieldAccess(inner, _)
+        | Expression::AddressOf(inner, _)
+        | Expression::Dereference(inner)
+        | Expression::Err(inner)
+        | Expression::Try(inner)
+        | Expression::NamedArgument(_, inner) => specialize_expression(inner, bindings),
+        Expression::Array(items)
+        | Expression::FunctionCall(_, items)
+        | Expression::Call(_, items)
+        | Expression::EnumVariant(_, _, items) => {
+            for item in items.iter_mut() {
+                specialize_expression(item, bindings);
+            }
+        }
+        Expression::MethodCall(receiver, _, arguments) => {
+            specialize_expression(receiver, bindings);
+            for argument in arguments.iter_mut() {
+                specialize_expression(argument, bindings);
+            }
+        }
+        Expression::StructLiteral(_, fields) => {
+            for (_, value) in fields.iter_mut() {
+                specialize_expression(value, bindings);
+            }
+        }
+        Expression::Match(subject, arms) => {
+            specialize_expression(subject, bindings);
+            for arm in arms.iter_mut() {
+                specialize_expression(&mut arm.value, bindings);
+            }
+        }
+        Expression::Closure(closure) => {
+            for parameter in closure.parameters.iter_mut() {
+                parameter.parameter_type = substitute(&parameter.parameter_type, bindings);
+            }
+            closure.return_type = closure
+                .return_type
+                .as_ref()
+                .map(|return_type| substitute(return_type, bindings));
+            specialize_block(&mut closure.body, bindings);
+        }
+        Expression::Int(_)
+        | Expression::Float(_)
+        | Expression::StringLit(_)
+        | Expression::Boolean(_)
+        | Expression::NoneLit
+        | Expression::Identifier(_)
+        | Expression::Function(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer() {
+        let t = Type::Generic("T".to_string());
+        let mut bindings = HashMap::new();
+        infer(
+            &Type::Slice(Box::new(t.clone())),
+            &Type::Array(Box::new(Type::Int), 2),
+            &mut bindings,
+        )
+        .unwrap();
+        infer(
+            &Type::Optional(Box::new(t.clone())),
+            &Type::Int,
+            &mut bindings,
+        )
+        .unwrap();
+        assert_eq!(bindings.get("T"), Some(&Type::Int));
+
+        let conflict = infer(&t, &Type::Float, &mut bindings).unwrap_err();
+        assert_eq!(conflict, ("T".to_string(), Type::Int, Type::Float));
+    }
+
+    #[test]
+    fn test_instance_name() {
+        let types = [
+            Type::Slice(Box::new(Type::Int)),
+            Type::UserDefined("Point".to_string()),
+        ];
+        assert_eq!(instance_name("pair", &types), "pair_slice_int_5Point");
+
+        // References differ in mutability, user types never spell builtin ones
+        let shared = Type::Reference(Box::new(Type::Int), false);
+        let mutable = Type::Reference(Box::new(Type::Int), true);
+        assert_ne!(
+            instance_name("id", &[shared]),
+            instance_name("id", &[mutable])
+        );
+        let named = Type::UserDefined("slice_int".to_string());
+        assert_ne!(
+            instance_name("f", &[named]),
+            instance_name("f", &[types[0].clone()])
+        );
+    }
+}
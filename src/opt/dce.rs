@@ -0,0 +1,261 @@
+use super::{is_pure, visit_block, visit_blocks, visit_expression, Pass};
+use crate::hir::{Block, DeclId, DeclarationKind, Expression, ExpressionKind, Program, Statement};
+use std::collections::{HashMap, HashSet};
+
+// Removes the statements after the one that returns in every case
+pub struct UnreachableCode;
+
+// Removes variables nothing reads, statements without effects, captures
+// closures don't use and the functions and globals nothing refers to
+pub struct DeadCode;
+
+fn always_returns(statement: &Statement) -> bool {
+    match statement {
+        Statement::Return(_) => true,
+        Statement::If(_, then_body, Some(else_body)) => returns(then_body) && returns(else_body),
+        _ => false,
+    }
+}
+
+fn returns(block: &Block) -> bool {
+    block.statements.iter().any(always_returns)
+}
+
+impl Pass for UnreachableCode {
+    fn name(&self) -> &'static str {
+        "unreachable code elimination"
+    }
+
+    fn run(&self, program: &mut Program) -> bool {
+        let mut changed = false;
+        for function in program.functions.iter_mut() {
+            visit_blocks(&mut function.body, &mut |block| {
+                if let Some(position) = block.statements.iter().position(always_returns) {
+                    if position + 1 < block.statements.len() {
+                        block.statements.truncate(position + 1);
+                        changed = true;
+                    }
+                }
+            });
+        }
+        changed
+    }
+}
+
+// How many times each variable is read in the block. Captures are reads and
+// assigning a whole variable isn't
+fn reads(block: &mut Block) -> HashMap<DeclId, usize> {
+    let mut reads: HashMap<DeclId, usize> = HashMap::new();
+    visit_block(block, true, &mut |expression| match &expression.kind {
+        ExpressionKind::Name(id) => *reads.entry(*id).or_default() += 1,
+        ExpressionKind::Closure(closure) => {
+            for capture in closure.captures.iter() {
+                *reads.entry(*capture).or_default() += 1;
+            }
+        }
+        _ => {}
+    });
+    visit_blocks(block, &mut |block| {
+        for statement in block.statements.iter() {
+            if let Statement::Assign(
+                Expression {
+                    kind: ExpressionKind::Name(id),
+                    ..
+                },
+                _,
+            ) = statement
+            {
+                *reads.entry(*id).or_default() -= 1;
+            }
+        }
+    });
+    reads
+}
+
+// Variables nothing reads lose their declaration and assignments, keeping
+// the calls their values make
+fn remove_dead_statements(block: &mut Block, unread: &dyn Fn(DeclId) -> bool) -> bool {
+    let mut changed = false;
+    let mut statements = vec![];
+    for statement in std::mem::take(&mut block.statements) {
+        let value = match &statement {
+            Statement::Let(id, value)
+            | Statement::Assign(
+                Expression {
+                    kind: ExpressionKind::Name(id),
+                    ..
+                },
+                value,
+            ) if unread(*id) => value,
+            Statement::Expression(value) => value,
+            _ => {
+                statements.push(statement);
+                continue;
+            }
+        };
+
+        if is_pure(value) {
+            changed = true;
+        } else if !matches!(statement, Statement::Expression(_))
+            && matches!(
+                value.kind,
+                ExpressionKind::Call(..) | ExpressionKind::CallValue(..)
+            )
+        {
+            statements.push(Statement::Expression(value.clone()));
+            changed = true;
+        } else {
+            statements.push(statement);
+        }
+    }
+    block.statements = statements;
+    changed
+}
+
+// Closures only capture the variables their body reads
+fn remove_captures(block: &mut Block) -> bool {
+    let mut changed = false;
+    visit_block(block, true, &mut |expression| {
+        if let ExpressionKind::Closure(closure) = &mut expression.kind {
+            let reads = reads(&mut closure.body);
+            let before = closure.captures.len();
+            closure
+                .captures
+                .retain(|capture| reads.get(capture).is_some_and(|reads| *reads > 0));
+            changed |= closure.captures.len() != before;
+        }
+    });
+    changed
+}
+
+// Functions and globals referred to from other functions and globals
+fn referenced(program: &mut Program) -> HashSet<DeclId> {
+    let mut referenced = HashSet::new();
+    for function in program.functions.iter_mut() {
+        let id = function.id;
+        visit_block(
+            &mut function.body,
+            true,
+            &mut |expression| match expression.kind {
+                ExpressionKind::Name(reference) | ExpressionKind::Call(reference, _)
+                    if reference != id =>
+                {
+                    referenced.insert(reference);
+                }
+                _ => {}
+            },
+        );
+    }
+    for global in program.globals.iter_mut() {
+        visit_expression(&mut global.value, true, &mut |expression| {
+            if let ExpressionKind::Name(reference) = expression.kind {
+                referenced.insert(reference);
+            }
+        });
+    }
+    referenced
+}
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dead code elimination"
+    }
+
+    fn run(&self, program: &mut Program) -> bool {
+        let declarations = &program.declarations;
+        let mut changed = false;
+        for function in program.functions.iter_mut() {
+            let reads = reads(&mut function.body);
+            let unread = |id: DeclId| {
+                declarations[id].kind == DeclarationKind::Local
+                    && reads.get(&id).unwrap_or(&0) == &0
+            };
+            visit_blocks(&mut function.body, &mut |block| {
+                changed |= remove_dead_statements(block, &unread)
+            });
+            changed |= remove_captures(&mut function.body);
+        }
+
+        // `main`, exported functions and the ones other modules may call stay
+        let referenced = referenced(program);
+        let declarations = &program.declarations;
+        let functions = program.functions.len();
+        program.functions.retain(|function| {
+            function.public
+                || function.exported
+                || declarations[function.id].name == "main"
+                || referenced.contains(&function.id)
+        });
+        let globals = program.globals.len();
+        program
+            .globals
+            .retain(|global| referenced.contains(&global.id) || !is_pure(&global.value));
+        changed || functions != program.functions.len() || globals != program.globals.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::optimise;
+    use super::*;
+    use crate::testing::find;
+
+    #[test]
+    fn test_remove_unreachable_code() {
+        let program = optimise(
+            "pub fn f(bool b): int {
+                if b { return 1; b = false; } else { return 2; }
+                return 3;
+            }",
+            1,
+        );
+
+        let statements = &find(&program, "f").body.statements;
+        assert_eq!(statements.len(), 1);
+        match &statements[0] {
+            Statement::If(_, then_body, _) => assert_eq!(then_body.statements.len(), 1),
+            statement => panic!("{:?} isn't an if", statement),
+        }
+    }
+
+    #[test]
+    fn test_remove_dead_code() {
+        let program = optimise(
+            "let unused = 1;
+            let counter = 0;
+            fn helper(): int { return 2; }
+            fn side(): int { counter = counter + 1; return 3; }
+            pub fn kept(): int { return 4; }
+            fn main(): int {
+                int a = 2;
+                int b = 5;
+                b = side();
+                []int xs = [1];
+                push(xs, 2);
+                return 0;
+            }",
+            1,
+        );
+
+        // Nothing calls helper
+        let names: Vec<&str> = program
+            .functions
+            .iter()
+            .map(|function| program.name(function.id))
+            .collect();
+        assert_eq!(names, vec!["side", "kept", "main"]);
+        assert_eq!(program.globals.len(), 1);
+        assert_eq!(program.name(program.globals[0].id), "counter");
+
+        // The call to side stays, xs is read by push
+        let statements = &find(&program, "main").body.statements;
+        assert_eq!(statements.len(), 4);
+        assert!(matches!(
+            &statements[0],
+            Statement::Expression(Expression {
+                kind: ExpressionKind::Call(..),
+                ..
+            })
+        ));
+    }
+}
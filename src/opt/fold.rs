@@ -0,0 +1,201 @@
+use super::{visit_block, visit_expression, Pass};
+use crate::ast::Type;
+use crate::backend::evaluate;
+use crate::hir::{Block, Condition, Expression, ExpressionKind, Program, Statement};
+
+// Computes arithmetic on literals and conversions of literals while
+// compiling, and keeps only the branch an `if` on a literal takes
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant folding"
+    }
+
+    fn run(&self, program: &mut Program) -> bool {
+        let mut changed = false;
+        for global in program.globals.iter_mut() {
+            visit_expression(&mut global.value, true, &mut |expression| {
+                changed |= fold(expression)
+            });
+        }
+        for function in program.functions.iter_mut() {
+            visit_block(&mut function.body, true, &mut |expression| {
+                changed |= fold(expression)
+            });
+            changed |= fold_branches(&mut function.body);
+        }
+        changed
+    }
+}
+
+fn is_number(expression: &Expression) -> bool {
+    matches!(
+        expression.kind,
+        ExpressionKind::Int(_) | ExpressionKind::Float(_)
+    )
+}
+
+// Values that don't fit the type, like an int overflowing or a float
+// becoming infinite, are left for the program to compute
+pub fn fold(expression: &mut Expression) -> bool {
+    let foldable = match &expression.kind {
+        ExpressionKind::Binary(lhs, _, rhs) => is_number(lhs) && is_number(rhs),
+        ExpressionKind::Cast(inner) => is_number(inner),
+        _ => false,
+    };
+    if !foldable {
+        return false;
+    }
+
+    let value = match evaluate(expression) {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+    expression.kind = match expression.expression_type {
+        Type::Int if value >= f64::from(i32::MIN) && value <= f64::from(i32::MAX) => {
+            ExpressionKind::Int(value as i32)
+        }
+        Type::Float if value.is_finite() => ExpressionKind::Float(value),
+        _ => return false,
+    };
+    true
+}
+
+// Statements of a branch move into the enclosing block, unless they declare
+// variables that need the scope of their own block
+fn fold_branches(block: &mut Block) -> bool {
+    let mut changed = false;
+    let mut statements = vec![];
+    for mut statement in std::mem::take(&mut block.statements) {
+        match &mut statement {
+            Statement::If(_, then_body, else_body) => {
+                changed |= fold_branches(then_body);
+                if let Some(else_body) = else_body {
+                    changed |= fold_branches(else_body);
+                }
+            }
+            Statement::For(_, _, body) => changed |= fold_branches(body),
+            _ => {}
+        }
+
+        let (value, then_body, else_body) = match statement {
+            Statement::If(
+                Condition::Expression(Expression {
+                    kind: ExpressionKind::Bool(value),
+                    ..
+                }),
+                then_body,
+                else_body,
+            ) => (value, then_body, else_body),
+            statement => {
+                statements.push(statement);
+                continue;
+            }
+        };
+        let taken = match value {
+            true => Some(then_body),
+            false => else_body.clone(),
+        };
+        match taken {
+            Some(taken) if declares(&taken) => {
+                changed |= !value || else_body.is_some();
+                statements.push(Statement::If(
+                    Condition::Expression(Expression::new(ExpressionKind::Bool(true), Type::Bool)),
+                    taken,
+                    None,
+                ));
+            }
+            Some(taken) => {
+                statements.extend(taken.statements);
+                changed = true;
+            }
+            None => changed = true,
+        }
+    }
+    block.statements = statements;
+    changed
+}
+
+fn declares(block: &Block) -> bool {
+    block
+        .statements
+        .iter()
+        .any(|statement| matches!(statement, Statement::Let(..)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::optimise;
+    use super::*;
+    use crate::lexer::Token;
+    use crate::testing::find;
+
+    fn returned(program: &Program, name: &str) -> ExpressionKind {
+        match find(program, name).body.statements.last() {
+            Some(Statement::Return(Some(value))) => value.kind.clone(),
+            statement => panic!("{:?} isn't a return", statement),
+        }
+    }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        let program = optimise(
+            "pub fn a(): int { return 7 / 2 + 2 ** 3 % 5; }
+            pub fn b(): float { return 1 / 4.0 + 3 as float; }
+            pub fn c(): int { return 2147483647 + 1; }
+            pub fn d(int x): int { return x * (2 + 1); }
+            pub fn e(): int { return 3 ** 19 - 2 ** 40; }
+            pub fn f(): float { return 10.0 ** 400.0; }",
+            1,
+        );
+
+        assert_eq!(returned(&program, "a"), ExpressionKind::Int(6));
+        assert_eq!(returned(&program, "b"), ExpressionKind::Float(3.25));
+        assert!(matches!(
+            returned(&program, "c"),
+            ExpressionKind::Binary(..)
+        ));
+        assert!(matches!(
+            returned(&program, "d"),
+            ExpressionKind::Binary(_, Token::Times, rhs) if rhs.kind == ExpressionKind::Int(3)
+        ));
+        // 2 ** 40 overflows and 10.0 ** 400.0 is infinite
+        assert!(matches!(
+            returned(&program, "e"),
+            ExpressionKind::Binary(lhs, Token::Minus, rhs)
+                if lhs.kind == ExpressionKind::Int(1162261467)
+                    && matches!(rhs.kind, ExpressionKind::Binary(_, Token::Power, _))
+        ));
+        assert!(matches!(
+            returned(&program, "f"),
+            ExpressionKind::Binary(_, Token::Power, _)
+        ));
+    }
+
+    #[test]
+    fn test_fold_branches() {
+        let program = optimise(
+            "pub fn f(): int { if true { return 1; } else { return 2; } }
+            pub fn g(): int { int x = 0; if false { x = 1; } else { int y = x + 3; x = y; } return x; }",
+            1,
+        );
+
+        assert_eq!(
+            find(&program, "f").body.statements,
+            vec![Statement::Return(Some(Expression::int(1)))]
+        );
+        // y keeps its block
+        assert!(matches!(
+            &find(&program, "g").body.statements[1],
+            Statement::If(
+                Condition::Expression(Expression {
+                    kind: ExpressionKind::Bool(true),
+                    ..
+                }),
+                _,
+                None
+            )
+        ));
+    }
+}
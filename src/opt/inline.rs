@@ -0,0 +1,257 @@
+use super::{declared, is_pure, visit_block, visit_expression, Pass};
+use crate::hir::{
+    DeclId, Declaration, DeclarationKind, Expression, ExpressionKind, Program, Statement,
+};
+use std::collections::{HashMap, HashSet};
+
+// Replaces calls to functions that only return an expression by the
+// expression, with the arguments in place of the parameters
+pub struct Inline;
+
+// Largest expression inlined, in nodes, so the generated code doesn't grow
+const MAX_SIZE: usize = 12;
+
+struct Candidate {
+    parameters: Vec<DeclId>,
+    value: Expression,
+    // Refers to globals or functions other modules can't see
+    private: bool,
+    // Globals and functions it refers to, which a variable of the caller
+    // with the same name would hide
+    names: Vec<String>,
+    module: String,
+}
+
+// Modules prefix the names of what they declare with `module__`
+fn module(name: &str) -> String {
+    match name.rsplit_once("__") {
+        Some((module, _)) => module.to_string(),
+        None => String::new(),
+    }
+}
+
+fn size(expression: &Expression) -> usize {
+    let mut size = 0;
+    visit_expression(&mut expression.clone(), false, &mut |_| size += 1);
+    size
+}
+
+// Small functions returning an expression without calls to other functions,
+// closures or patterns binding variables
+fn candidate(
+    declarations: &[Declaration],
+    public: &HashMap<DeclId, bool>,
+    id: DeclId,
+    parameters: &[DeclId],
+    statements: &[Statement],
+) -> Option<Candidate> {
+    let value = match statements {
+        [Statement::Return(Some(value))] => value.clone(),
+        _ => return None,
+    };
+    if size(&value) > MAX_SIZE {
+        return None;
+    }
+
+    let mut inlinable = true;
+    let mut private = false;
+    let mut names = vec![];
+    visit_expression(
+        &mut value.clone(),
+        false,
+        &mut |expression| match &expression.kind {
+            ExpressionKind::Call(callee, _) => {
+                inlinable &= declarations[*callee].kind == DeclarationKind::Extern
+            }
+            ExpressionKind::CallValue(..)
+            | ExpressionKind::Try(_)
+            | ExpressionKind::Closure(_)
+            | ExpressionKind::Match(..)
+            | ExpressionKind::AddressOf(_)
+            | ExpressionKind::Builtin(..) => inlinable = false,
+            ExpressionKind::Name(name) => match declarations[*name].kind {
+                DeclarationKind::Global => {
+                    private = true;
+                    names.push(declarations[*name].name.clone());
+                }
+                DeclarationKind::Function => {
+                    private |= !public.get(name).unwrap_or(&false);
+                    names.push(declarations[*name].name.clone());
+                }
+                _ => {}
+            },
+            _ => {}
+        },
+    );
+    if !inlinable {
+        return None;
+    }
+    Some(Candidate {
+        parameters: parameters.to_vec(),
+        value,
+        private,
+        names,
+        module: module(&declarations[id].name),
+    })
+}
+
+// Arguments copied into every use of their parameter, or evaluated at most
+// once without effects
+fn substitute(
+    declarations: &[Declaration],
+    candidate: &Candidate,
+    arguments: &[Expression],
+) -> Option<Expression> {
+    let mut uses: HashMap<DeclId, usize> = HashMap::new();
+    visit_expression(&mut candidate.value.clone(), false, &mut |expression| {
+        if let ExpressionKind::Name(id) = expression.kind {
+            *uses.entry(id).or_default() += 1;
+        }
+    });
+    for (parameter, argument) in candidate.parameters.iter().zip(arguments.iter()) {
+        let trivial = match argument.kind {
+            ExpressionKind::Int(_) | ExpressionKind::Float(_) | ExpressionKind::Bool(_) => true,
+            ExpressionKind::Name(id) => declarations[id].kind != DeclarationKind::Global,
+            _ => false,
+        };
+        if !(trivial || is_pure(argument) && uses.get(parameter).unwrap_or(&0) <= &1) {
+            return None;
+        }
+    }
+
+    let mut value = candidate.value.clone();
+    visit_expression(&mut value, false, &mut |expression| {
+        if let ExpressionKind::Name(id) = expression.kind {
+            if let Some(position) = candidate.parameters.iter().position(|p| *p == id) {
+                *expression = arguments[position].clone();
+            }
+        }
+    });
+    Some(value)
+}
+
+impl Pass for Inline {
+    fn name(&self) -> &'static str {
+        "inlining"
+    }
+
+    fn run(&self, program: &mut Program) -> bool {
+        let declarations = &program.declarations;
+        let public: HashMap<DeclId, bool> = program
+            .functions
+            .iter()
+            .map(|function| (function.id, function.public))
+            .collect();
+        let candidates: HashMap<DeclId, Candidate> = program
+            .functions
+            .iter()
+            .filter_map(|function| {
+                candidate(
+                    declarations,
+                    &public,
+                    function.id,
+                    &function.parameters,
+                    &function.body.statements,
+                )
+                .map(|candidate| (function.id, candidate))
+            })
+            .collect();
+
+        let mut changed = false;
+        for function in program.functions.iter_mut() {
+            let caller = module(&declarations[function.id].name);
+            let shadowed: HashSet<&str> = declared(function)
+                .into_iter()
+                .map(|id| declarations[id].name.as_str())
+                .collect();
+            visit_block(&mut function.body, true, &mut |expression| {
+                let (callee, arguments) = match &expression.kind {
+                    ExpressionKind::Call(callee, arguments) => (callee, arguments),
+                    _ => return,
+                };
+                let candidate = match candidates.get(callee) {
+                    Some(candidate)
+                        if (!candidate.private || candidate.module == caller)
+                            && !candidate
+                                .names
+                                .iter()
+                                .any(|name| shadowed.contains(name.as_str())) =>
+                    {
+                        candidate
+                    }
+                    _ => return,
+                };
+                if let Some(mut value) = substitute(declarations, candidate, arguments) {
+                    value.expression_type = expression.expression_type.clone();
+                    *expression = value;
+                    changed = true;
+                }
+            });
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::optimise;
+    use super::*;
+    use crate::lexer::Token;
+    use crate::testing::find;
+
+    #[test]
+    fn test_inline_small_functions() {
+        let program = optimise(
+            "fn double(int x): int { return x + x; }
+            fn add(int a, int b): int { return a + b; }
+            pub fn f(int n): int { return double(n) + add(n * 2, 1); }",
+            2,
+        );
+
+        // Both are inlined and nothing calls them any more
+        assert_eq!(program.functions.len(), 1);
+        match &find(&program, "f").body.statements[0] {
+            Statement::Return(Some(Expression {
+                kind: ExpressionKind::Binary(lhs, Token::Plus, rhs),
+                ..
+            })) => {
+                assert!(matches!(
+                    lhs.kind,
+                    ExpressionKind::Binary(_, Token::Plus, _)
+                ));
+                assert!(matches!(
+                    &rhs.kind,
+                    ExpressionKind::Binary(product, Token::Plus, one)
+                        if matches!(product.kind, ExpressionKind::Binary(_, Token::Times, _))
+                            && one.kind == ExpressionKind::Int(1)
+                ));
+            }
+            statement => panic!("{:?} isn't a return", statement),
+        }
+    }
+
+    #[test]
+    fn test_inline_keeps_calls() {
+        let program = optimise(
+            "fn double(int x): int { return x + x; }
+            fn twice(int x): int { return double(double(x)); }
+            fn long(int x): int { int y = x + 1; return y * y; }
+            pub fn f(int n): int { return double(n * 3) + long(n) + twice(n); }",
+            2,
+        );
+
+        // n * 3 would be computed twice, long has more than a return and
+        // twice calls another function
+        let mut value = match &find(&program, "f").body.statements[0] {
+            Statement::Return(Some(value)) => value.clone(),
+            statement => panic!("{:?} isn't a return", statement),
+        };
+        let mut called = vec![];
+        visit_expression(&mut value, false, &mut |expression| {
+            if let ExpressionKind::Call(callee, _) = expression.kind {
+                called.push(program.name(callee).to_string());
+            }
+        });
+        assert_eq!(called, vec!["double", "long", "twice"]);
+    }
+}
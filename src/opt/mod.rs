@@ -0,0 +1,305 @@
+pub mod dce;
+pub mod fold;
+pub mod inline;
+pub mod propagate;
+
+use crate::hir::{
+    Block, Condition, DeclId, Expression, ExpressionKind, Function, Pattern, Program, Statement,
+};
+use std::collections::HashSet;
+
+// Optimisations rewrite the HIR in place, so the backends generate code from
+// the optimised program without knowing about them. Every pass keeps the
+// program valid on its own and reports whether it changed anything
+//
+// The HIR is the optimisation IR and not the MIR because the C backend keeps
+// the loops and branches of the program as written, which it couldn't get
+// back from a control-flow graph. The MIR is built from the optimised HIR
+// instead, and its verifier checks what the passes left before any code is
+// generated
+
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    // Whether the program changed
+    fn run(&self, program: &mut Program) -> bool;
+}
+
+// Passes enable each other, inlining leaves constants to fold and folding
+// leaves variables nothing reads, so the pipeline runs until nothing changes
+const MAX_ROUNDS: usize = 10;
+
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    // 0 keeps the program as written, 1 simplifies each function and 2 also
+    // inlines small functions into their callers
+    pub fn new(level: u8) -> Self {
+        let mut passes: Vec<Box<dyn Pass>> = vec![];
+        if level >= 2 {
+            passes.push(Box::new(inline::Inline));
+        }
+        if level >= 1 {
+            passes.push(Box::new(dce::UnreachableCode));
+            passes.push(Box::new(fold::ConstantFolding));
+            passes.push(Box::new(propagate::ConstantPropagation));
+            passes.push(Box::new(propagate::CopyPropagation));
+            passes.push(Box::new(dce::DeadCode));
+        }
+        Self { passes }
+    }
+
+    // Names of the passes that changed the program, in the order they did
+    pub fn run(&self, program: &mut Program) -> Vec<&'static str> {
+        let mut applied = vec![];
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for pass in self.passes.iter() {
+                if pass.run(program) {
+                    applied.push(pass.name());
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        applied
+    }
+}
+
+// Calls `visit` on every expression of the block, inner expressions first so
+// they are already rewritten when the one holding them is visited. Closure
+// bodies are visited when `closures` is set
+pub fn visit_block<F: FnMut(&mut Expression)>(block: &mut Block, closures: bool, visit: &mut F) {
+    for statement in block.statements.iter_mut() {
+        visit_statement(statement, closures, visit);
+    }
+}
+
+// Expressions of the statement itself and the blocks it holds
+fn parts(statement: &mut Statement) -> (Vec<&mut Expression>, Vec<&mut Block>) {
+    match statement {
+        Statement::Let(_, value)
+        | Statement::Expression(value)
+        | Statement::Return(Some(value)) => (vec![value], vec![]),
+        Statement::Assign(target, value) => (vec![target, value], vec![]),
+        Statement::Return(None) => (vec![], vec![]),
+        Statement::If(condition, then_body, else_body) => {
+            let value = match condition {
+                Condition::Expression(value) | Condition::Let(_, value) => value,
+            };
+            let mut blocks = vec![then_body];
+            blocks.extend(else_body.as_mut());
+            (vec![value], blocks)
+        }
+        Statement::For(_, iterable, body) => (vec![iterable], vec![body]),
+    }
+}
+
+pub fn visit_statement<F: FnMut(&mut Expression)>(
+    statement: &mut Statement,
+    closures: bool,
+    visit: &mut F,
+) {
+    let (expressions, blocks) = parts(statement);
+    for expression in expressions {
+        visit_expression(expression, closures, visit);
+    }
+    for block in blocks {
+        visit_block(block, closures, visit);
+    }
+}
+
+// Calls `visit` on the block and every block in it, closure bodies included,
+// inner blocks first
+pub fn visit_blocks<F: FnMut(&mut Block)>(block: &mut Block, visit: &mut F) {
+    for statement in block.statements.iter_mut() {
+        let (expressions, blocks) = parts(statement);
+        for expression in expressions {
+            visit_expression(expression, false, &mut |expression| {
+                if let ExpressionKind::Closure(closure) = &mut expression.kind {
+                    visit_blocks(&mut closure.body, visit);
+                }
+            });
+        }
+        for block in blocks {
+            visit_blocks(block, visit);
+        }
+    }
+    visit(block);
+}
+
+pub fn visit_expression<F: FnMut(&mut Expression)>(
+    expression: &mut Expression,
+    closures: bool,
+    visit: &mut F,
+) {
+    match &mut expression.kind {
+        ExpressionKind::Int(_)
+        | ExpressionKind::Float(_)
+        | ExpressionKind::String(_)
+        | ExpressionKind::Bool(_)
+        | ExpressionKind::Name(_)
+        | ExpressionKind::NoneLit => {}
+        ExpressionKind::Binary(lhs, _, rhs) | ExpressionKind::Index(lhs, rhs) => {
+            visit_expression(lhs, closures, visit);
+            visit_expression(rhs, closures, visit);
+        }
+        ExpressionKind::Cast(inner)
+        | ExpressionKind::Field(inner, _)
+        | ExpressionKind::AddressOf(inner)
+        | ExpressionKind::Dereference(inner)
+        | ExpressionKind::Wrap(inner)
+        | ExpressionKind::Err(inner)
+        | ExpressionKind::Try(inner) => visit_expression(inner, closures, visit),
+        ExpressionKind::Array(items)
+        | ExpressionKind::Slice(items)
+        | ExpressionKind::Call(_, items)
+        | ExpressionKind::Builtin(_, items)
+        | ExpressionKind::Variant(_, _, items) => {
+            for item in items.iter_mut() {
+                visit_expression(item, closures, visit);
+            }
+        }
+        ExpressionKind::CallValue(callee, arguments) => {
+            visit_expression(callee, closures, visit);
+            for argument in arguments.iter_mut() {
+                visit_expression(argument, closures, visit);
+            }
+        }
+        ExpressionKind::Struct(_, fields) => {
+            for (_, value) in fields.iter_mut() {
+                visit_expression(value, closures, visit);
+            }
+        }
+        ExpressionKind::Match(subject, arms) => {
+            visit_expression(subject, closures, visit);
+            for arm in arms.iter_mut() {
+                visit_expression(&mut arm.value, closures, visit);
+            }
+        }
+        ExpressionKind::Closure(closure) => {
+            if closures {
+                visit_block(&mut closure.body, closures, visit);
+            }
+        }
+    }
+    visit(expression);
+}
+
+// Whether evaluating the expression can't be observed: no calls, no `?`
+// returning early and no index that may be out of bounds
+pub fn is_pure(expression: &Expression) -> bool {
+    let mut pure = true;
+    visit_expression(&mut expression.clone(), false, &mut |expression| {
+        if matches!(
+            expression.kind,
+            ExpressionKind::Call(..)
+                | ExpressionKind::CallValue(..)
+                | ExpressionKind::Builtin(..)
+                | ExpressionKind::Index(..)
+                | ExpressionKind::Try(_)
+        ) {
+            pure = false;
+        }
+    });
+    pure
+}
+
+// Variables the block changes: assigned, borrowed, or pushed to and popped
+// from. Closures count too, although they change copies of what they capture
+pub fn modified(block: &mut Block) -> HashSet<DeclId> {
+    let mut modified = HashSet::new();
+    visit_block(block, true, &mut |expression| match &expression.kind {
+        ExpressionKind::AddressOf(place) => modified.extend(place.root()),
+        ExpressionKind::Builtin(_, arguments) => {
+            modified.extend(arguments.first().and_then(Expression::root))
+        }
+        _ => {}
+    });
+    visit_blocks(block, &mut |block| {
+        for statement in block.statements.iter() {
+            if let Statement::Assign(target, _) = statement {
+                modified.extend(target.root());
+            }
+        }
+    });
+    modified
+}
+
+// Every variable the function declares, closures included
+pub fn declared(function: &mut Function) -> Vec<DeclId> {
+    let mut declared = function.parameters.clone();
+    visit_blocks(&mut function.body, &mut |block| {
+        for statement in block.statements.iter() {
+            match statement {
+                Statement::Let(id, _)
+                | Statement::For(id, _, _)
+                | Statement::If(Condition::Let(id, _), _, _) => declared.push(*id),
+                _ => {}
+            }
+        }
+    });
+    visit_block(
+        &mut function.body,
+        true,
+        &mut |expression| match &expression.kind {
+            ExpressionKind::Match(_, arms) => {
+                for arm in arms.iter() {
+                    if let Pattern::Variant(_, bindings) = &arm.pattern {
+                        declared.extend(bindings.iter().flatten());
+                    }
+                }
+            }
+            ExpressionKind::Closure(closure) => declared.extend(closure.parameters.iter()),
+            _ => {}
+        },
+    );
+    declared
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hir::lower;
+    use crate::testing::{check, find};
+    use crate::{ast::Type, mir};
+
+    // Optimises the source and checks the result still makes valid MIR
+    pub fn optimise(input: &str, level: u8) -> Program {
+        let mut program = lower(check(input).unwrap()).unwrap();
+        PassManager::new(level).run(&mut program);
+        mir::verify(&mir::build(&program).unwrap()).unwrap();
+        program
+    }
+
+    #[test]
+    fn test_optimisation_levels() {
+        let input = "fn square(int x): int { return x * x; }
+            fn main(): int { int a = 2 + 3; int b = a; return square(b); }";
+
+        let program = optimise(input, 0);
+        assert_eq!(program.functions.len(), 2);
+        assert_eq!(find(&program, "main").body.statements.len(), 3);
+
+        // The variables fold into the call
+        let program = optimise(input, 1);
+        assert_eq!(
+            find(&program, "main").body.statements,
+            vec![Statement::Return(Some(Expression::new(
+                ExpressionKind::Call(find(&program, "square").id, vec![Expression::int(5)]),
+                Type::Int
+            )))]
+        );
+
+        // And the call into a constant, leaving square unused
+        let program = optimise(input, 2);
+        assert_eq!(program.functions.len(), 1);
+        assert_eq!(
+            find(&program, "main").body.statements,
+            vec![Statement::Return(Some(Expression::int(25)))]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use super::{declared, modified, visit_block, visit_blocks, visit_expression, Pass};
+use crate::hir::{
+    DeclId, DeclarationKind, Expression, ExpressionKind, Function, Program, Statement,
+};
+use std::collections::{HashMap, HashSet};
+
+// Replaces the variables nothing changes after they are declared with a
+// literal by the literal. Globals count when no function changes them
+pub struct ConstantPropagation;
+
+// Replaces the variables declared as a copy of another by that variable,
+// when neither changes
+pub struct CopyPropagation;
+
+fn is_literal(expression: &Expression) -> bool {
+    matches!(
+        expression.kind,
+        ExpressionKind::Int(_) | ExpressionKind::Float(_) | ExpressionKind::Bool(_)
+    )
+}
+
+// Variables declared in the function with a value, and their values
+fn initial_values(function: &mut Function) -> HashMap<DeclId, Expression> {
+    let mut values = HashMap::new();
+    visit_blocks(&mut function.body, &mut |block| {
+        for statement in block.statements.iter() {
+            if let Statement::Let(id, value) = statement {
+                values.insert(*id, value.clone());
+            }
+        }
+    });
+    values
+}
+
+impl Pass for ConstantPropagation {
+    fn name(&self) -> &'static str {
+        "constant propagation"
+    }
+
+    fn run(&self, program: &mut Program) -> bool {
+        let mut changed_globals = HashSet::new();
+        for function in program.functions.iter_mut() {
+            changed_globals.extend(modified(&mut function.body));
+        }
+        let globals: HashMap<DeclId, Expression> = program
+            .globals
+            .iter()
+            .filter(|global| is_literal(&global.value) && !changed_globals.contains(&global.id))
+            .map(|global| (global.id, global.value.clone()))
+            .collect();
+
+        let mut changed = false;
+        let mut replace = |expression: &mut Expression, constants: &HashMap<DeclId, Expression>| {
+            if let ExpressionKind::Name(id) = expression.kind {
+                if let Some(constant) = constants.get(&id) {
+                    *expression = constant.clone();
+                    changed = true;
+                }
+            }
+        };
+        for global in program.globals.iter_mut() {
+            visit_expression(&mut global.value, true, &mut |expression| {
+                replace(expression, &globals)
+            });
+        }
+        for function in program.functions.iter_mut() {
+            let modified = modified(&mut function.body);
+            let mut constants = globals.clone();
+            for (id, value) in initial_values(function) {
+                if is_literal(&value) && !modified.contains(&id) {
+                    constants.insert(id, value);
+                }
+            }
+            visit_block(&mut function.body, true, &mut |expression| {
+                replace(expression, &constants)
+            });
+        }
+        changed
+    }
+}
+
+impl Pass for CopyPropagation {
+    fn name(&self) -> &'static str {
+        "copy propagation"
+    }
+
+    fn run(&self, program: &mut Program) -> bool {
+        let declarations = &program.declarations;
+        let mut changed = false;
+        for function in program.functions.iter_mut() {
+            let modified = modified(&mut function.body);
+            // A copy is only replaced by a name no other variable shadows
+            let mut names: HashMap<&str, usize> = HashMap::new();
+            for id in declared(function) {
+                *names.entry(&declarations[id].name).or_default() += 1;
+            }
+
+            let mut copies: HashMap<DeclId, DeclId> = HashMap::new();
+            for (copy, value) in initial_values(function) {
+                let original = match value.kind {
+                    ExpressionKind::Name(original) => original,
+                    _ => continue,
+                };
+                if declarations[original].kind == DeclarationKind::Local
+                    && !modified.contains(&copy)
+                    && !modified.contains(&original)
+                    && names[declarations[original].name.as_str()] == 1
+                {
+                    copies.insert(copy, original);
+                }
+            }
+
+            // Closures capture the original instead
+            visit_block(
+                &mut function.body,
+                true,
+                &mut |expression| match &mut expression.kind {
+                    ExpressionKind::Name(id) => {
+                        if let Some(original) = copies.get(id) {
+                            *id = *original;
+                            changed = true;
+                        }
+                    }
+                    ExpressionKind::Closure(closure) => {
+                        let mut captures: Vec<DeclId> = vec![];
+                        for capture in closure.captures.iter() {
+                            let capture = *copies.get(capture).unwrap_or(capture);
+                            if !captures.contains(&capture) {
+                                captures.push(capture);
+                            }
+                        }
+                        if captures != closure.captures {
+                            closure.captures = captures;
+                            changed = true;
+                        }
+                    }
+                    _ => {}
+                },
+            );
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::optimise;
+    use super::*;
+    use crate::testing::find;
+
+    #[test]
+    fn test_propagate_constants() {
+        let program = optimise(
+            "let limit = 10;
+            let counter = 0;
+            pub fn bump(): int { counter = counter + 1; return counter; }
+            pub fn f(): int { int a = 4; int b = 1; b = b + a; return b * limit + counter; }",
+            1,
+        );
+
+        // b changes and counter too, a and limit don't
+        let statements = &find(&program, "f").body.statements;
+        assert_eq!(statements.len(), 3);
+        assert!(matches!(
+            &statements[1],
+            Statement::Assign(_, Expression { kind: ExpressionKind::Binary(_, _, a), .. })
+                if a.kind == ExpressionKind::Int(4)
+        ));
+        match &statements[2] {
+            Statement::Return(Some(Expression {
+                kind: ExpressionKind::Binary(lhs, _, rhs),
+                ..
+            })) => {
+                assert!(matches!(
+                    &lhs.kind,
+                    ExpressionKind::Binary(_, _, limit) if limit.kind == ExpressionKind::Int(10)
+                ));
+                assert!(matches!(rhs.kind, ExpressionKind::Name(_)));
+            }
+            statement => panic!("{:?} isn't a return", statement),
+        }
+    }
+
+    #[test]
+    fn test_propagate_copies() {
+        let program = optimise(
+            "pub fn f(int x, int y): int {
+                int a = x;
+                int b = y;
+                y = 2;
+                let g = fn(): int { return a; };
+                if true { int x = b * 3; return a + x + b + g(); }
+                return 0;
+            }
+            pub fn h(int x): int { int a = x; let g = fn(): int { return a; }; return a + g(); }",
+            1,
+        );
+
+        // y changes, so its copy stays. The x of the branch has a name of its
+        // own and doesn't hide the parameter
+        assert_eq!(find(&program, "f").body.statements.len(), 5);
+
+        let h = find(&program, "h");
+        let x = h.parameters[0];
+        assert_eq!(h.body.statements.len(), 2);
+        match &h.body.statements[0] {
+            Statement::Let(
+                _,
+                Expression {
+                    kind: ExpressionKind::Closure(closure),
+                    ..
+                },
+            ) => {
+                assert_eq!(closure.captures, vec![x]);
+                assert_eq!(
+                    closure.body.statements,
+                    vec![Statement::Return(Some(Expression::new(
+                        ExpressionKind::Name(x),
+                        crate::ast::Type::Int
+                    )))]
+                );
+            }
+            statement => panic!("{:?} isn't the closure", statement),
+        }
+    }
+}
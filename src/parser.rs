@@ -1,12 +1,43 @@
-use crate::{ast::*, backend::evaluate_ast, lexer::*};
+use crate::{ast::*, lexer::*};
 
-use std::{collections::HashMap, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+// Output of the shunting-yard algorithm. Operands are parsed as soon as they
+// are found, so only operators and casts are postponed until the tree is built
+#[derive(Debug, Clone, PartialEq)]
+enum RpnItem {
+    Operand(Expression),
+    Operator(Token),
+    Cast(Type),
+    Index(Expression),
+    Field(String),
+    Method(String, Vec<Expression>),
+    Try,
+    // Prefix operators, which bind tighter than any binary operator
+    AddressOf(bool),
+    Dereference,
+}
+
+impl RpnItem {
+    fn is_prefix(&self) -> bool {
+        matches!(self, RpnItem::AddressOf(_) | RpnItem::Dereference)
+    }
+}
 
 pub struct Parser {
     tokens: Vec<Token>,
     current_token: Token,
     position: usize,
     symbol_table: HashMap<String, Ast>,
+    // Type of `self` while parsing the methods of an impl block
+    receiver: Option<Type>,
+    // Type parameters of the generic function being parsed
+    type_parameters: Vec<TypeParameter>,
+    // Modules imported by the file, whose items are written `module::item`
+    modules: HashSet<String>,
 }
 
 impl Parser {
@@ -15,7 +46,10 @@ impl Parser {
             tokens,
             current_token: Token::Eof,
             position: 0,
+            receiver: None,
+            type_parameters: vec![],
             symbol_table: HashMap::new(),
+            modules: HashSet::new(),
         }
     }
 
@@ -26,27 +60,83 @@ impl Parser {
         }
     }
 
-    fn from_rpn_to_ast(&self, rpn: Vec<Token>) -> Result<Expression, String> {
+    fn peek(&self) -> &Token {
+        self.peek_nth(0)
+    }
+
+    // Looks `n` tokens past the one after the current token
+    fn peek_nth(&self, n: usize) -> &Token {
+        self.tokens.get(self.position + n).unwrap_or(&Token::Eof)
+    }
+
+    fn build_ast_from_rpn(&self, rpn: Vec<RpnItem>) -> Result<Expression, String> {
         let mut expressions: Vec<Expression> = vec![];
 
-        for token in rpn.iter() {
-            match token {
-                Token::FloatNumber(value) | Token::IntNumber(value) => {
-                    println!("ADD NUMBER TO STACK {:?}", value);
-                    expressions.push(self.parse_number(value)?);
+        for item in rpn.into_iter() {
+            match item {
+                RpnItem::Operand(expression) => {
+                    eprintln!("ADD OPERAND TO STACK {:?}", expression);
+                    expressions.push(expression);
                 }
 
-                operator if operator.is_operator() => {
+                RpnItem::Operator(operator) => {
                     if expressions.len() >= 2 {
                         let rhs = Box::new(expressions.pop().unwrap());
                         let lhs = Box::new(expressions.pop().unwrap());
-                        expressions.push(Expression::BinaryExpr(lhs, operator.clone(), rhs));
+                        expressions.push(Expression::BinaryExpr(lhs, operator, rhs));
                     } else {
                         return Err("Error: Invalid expression".to_string());
                     }
                 }
 
-                _ => return Err("Invalid token on RPN expression".to_string()),
+                RpnItem::Cast(target_type) => match expressions.pop() {
+                    Some(expression) => {
+                        expressions.push(Expression::Cast(Box::new(expression), target_type))
+                    }
+                    None => return Err("Error: Expected an expression before 'as'".to_string()),
+                },
+
+                RpnItem::Index(index) => match expressions.pop() {
+                    Some(expression) => {
+                        expressions.push(Expression::Index(Box::new(expression), Box::new(index)))
+                    }
+                    None => return Err("Error: Expected an expression before '['".to_string()),
+                },
+
+                RpnItem::Method(method, arguments) => match expressions.pop() {
+                    Some(receiver) => expressions.push(Expression::MethodCall(
+                        Box::new(receiver),
+                        method,
+                        arguments,
+                    )),
+                    None => return Err("Error: Expected an expression before '.'".to_string()),
+                },
+
+                RpnItem::AddressOf(mutable) => match expressions.pop() {
+                    Some(expression) => {
+                        expressions.push(Expression::AddressOf(Box::new(expression), mutable))
+                    }
+                    None => return Err("Error: Expected an expression after '&'".to_string()),
+                },
+
+                RpnItem::Dereference => match expressions.pop() {
+                    Some(expression) => {
+                        expressions.push(Expression::Dereference(Box::new(expression)))
+                    }
+                    None => return Err("Error: Expected an expression after '*'".to_string()),
+                },
+
+                RpnItem::Try => match expressions.pop() {
+                    Some(expression) => expressions.push(Expression::Try(Box::new(expression))),
+                    None => return Err("Error: Expected an expression before '?'".to_string()),
+                },
+
+                RpnItem::Field(field) => match expressions.pop() {
+                    Some(expression) => {
+                        expressions.push(Expression::FieldAccess(Box::new(expression), field))
+                    }
+                    None => return Err("Error: Expected an expression before '.'".to_string()),
+                },
             }
         }
 
@@ -57,13 +147,142 @@ impl Parser {
         }
     }
 
-    fn parse_type(&self) -> Result<Type, String> {
-        println!("PARSING TYPE: {:?}", self.current_token);
-        self.current_token.as_type()
+    // A user-defined type only starts a declaration when followed by a name,
+    // e.g. `Point origin = ...`, or by the error type of a result
+    fn is_start_of_type(&self) -> bool {
+        match self.current_token {
+            Token::LeftBracket | Token::BitwiseAnd | Token::QuestionMark => true,
+            // module::Type name
+            Token::Identifier(ref module)
+                if self.modules.contains(module) && *self.peek() == Token::DoubleColon =>
+            {
+                matches!(self.peek_nth(1), Token::Identifier(_))
+                    && matches!(self.peek_nth(2), Token::Identifier(_) | Token::Not)
+            }
+            Token::Identifier(_) => matches!(self.peek(), Token::Identifier(_) | Token::Not),
+            Token::KeywordFn => *self.peek() == Token::LeftPar,
+            ref token => token.is_data_type_keyword(),
+        }
+    }
+
+    // Parses a type and leaves the parser on its last token
+    fn parse_type(&mut self) -> Result<Type, String> {
+        eprintln!("PARSING TYPE: {:?}", self.current_token);
+        let value_type = self.parse_single_type()?;
+
+        // type!error binds looser than any other type syntax: `[]int!string`
+        // is a slice or an error
+        if *self.peek() != Token::Not {
+            return Ok(value_type);
+        }
+        self.advance();
+        self.advance();
+        let error_type = self.parse_type()?;
+        Ok(Type::Result(Box::new(value_type), Box::new(error_type)))
+    }
+
+    fn parse_single_type(&mut self) -> Result<Type, String> {
+        // fn(type, type): type
+        if self.current_token == Token::KeywordFn {
+            return self.parse_function_type();
+        }
+        self.join_module_path();
+
+        // ?type
+        if self.current_token == Token::QuestionMark {
+            self.advance();
+            let value_type = self.parse_single_type()?;
+            return Ok(Type::Optional(Box::new(value_type)));
+        }
+
+        // &type or &mut type
+        if self.current_token == Token::BitwiseAnd {
+            self.advance();
+            let mutable = self.current_token == Token::KeywordMut;
+            if mutable {
+                self.advance();
+            }
+            let referenced_type = self.parse_single_type()?;
+            return Ok(Type::Reference(Box::new(referenced_type), mutable));
+        }
+        if let Token::Identifier(name) = &self.current_token {
+            if self
+                .type_parameters
+                .iter()
+                .any(|type_parameter| type_parameter.name == *name)
+            {
+                return Ok(Type::Generic(name.clone()));
+            }
+            // `Self` is the type an impl or a trait is for
+            if let (Some(receiver), "Self") = (&self.receiver, name.as_str()) {
+                return Ok(receiver.clone());
+            }
+            return Ok(Type::UserDefined(name.clone()));
+        }
+        if self.current_token != Token::LeftBracket {
+            return self.current_token.as_type();
+        }
+
+        // []type
+        if *self.peek() == Token::RightBracket {
+            self.advance();
+            self.advance();
+            let element_type = self.parse_single_type()?;
+            return Ok(Type::Slice(Box::new(element_type)));
+        }
+
+        // [type; length]
+        self.advance();
+        let element_type = self.parse_type()?;
+        self.advance();
+        if self.current_token != Token::Semicolon {
+            return Err(format!(
+                "Invalid token {:?}. Expected ';' between array type and length",
+                self.current_token
+            ));
+        }
+        self.advance();
+        let length = match &self.current_token {
+            Token::IntNumber(length) => usize::from_str(length)
+                .map_err(|err| format!("Couldn't parse array length: {:?}", err))?,
+            token => return Err(format!("Expected array length, found {:?}", token)),
+        };
+        if length == 0 {
+            return Err("Array length must be greater than zero".to_string());
+        }
+        self.advance();
+        if self.current_token != Token::RightBracket {
+            return Err(format!(
+                "Invalid token {:?}. Expected ']' at the end of array type",
+                self.current_token
+            ));
+        }
+        Ok(Type::Array(Box::new(element_type), length))
+    }
+
+    // `module::item` of an imported module becomes a single identifier
+    fn join_module_path(&mut self) {
+        if let Token::Identifier(module) = &self.current_token {
+            if !self.modules.contains(module) || *self.peek() != Token::DoubleColon {
+                return;
+            }
+            if let Token::Identifier(item) = self.peek_nth(1) {
+                let path = format!("{}::{}", module, item);
+                self.advance();
+                self.advance();
+                self.current_token = Token::Identifier(path);
+            }
+        }
+    }
+
+    // Name of a type, a trait or a function that may live in another module
+    fn parse_path(&mut self) -> Result<String, String> {
+        self.join_module_path();
+        self.parse_identifier()
     }
 
     fn parse_identifier(&self) -> Result<String, String> {
-        println!("PARSING IDENTIFIER: {:?}", self.current_token);
+        eprintln!("PARSING IDENTIFIER: {:?}", self.current_token);
         if let Token::Identifier(ident) = &self.current_token {
             Ok(ident.to_string())
         } else {
@@ -85,49 +304,398 @@ impl Parser {
         }
     }
 
-    fn is_end_of_statement(&self) -> bool {
-        self.current_token == Token::Semicolon
+    fn is_end_of_expression(&self, open_parenthesis: usize) -> bool {
+        match self.current_token {
+            Token::Semicolon
+            | Token::Comma
+            | Token::LeftCurly
+            | Token::RightCurly
+            | Token::RightBracket
+            | Token::EqualSign
+            | Token::Eof => true,
+            Token::RightPar => open_parenthesis == 0,
+            _ => false,
+        }
+    }
+
+    // [expression, expression, ...]
+    fn parse_array_literal(&mut self) -> Result<Expression, String> {
+        eprintln!("PARSING ARRAY LITERAL: {:?}", self.current_token);
+        self.advance();
+
+        let mut items: Vec<Expression> = vec![];
+        while self.current_token != Token::RightBracket {
+            items.push(self.parse_expression()?);
+
+            match self.current_token {
+                Token::Comma => self.advance(),
+                Token::RightBracket => {}
+                _ => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected ',' or ']' on array literal",
+                        self.current_token
+                    ))
+                }
+            }
+        }
+        Ok(Expression::Array(items))
+    }
+
+    // (argument, argument, ...)
+    fn parse_call_arguments(&mut self) -> Result<Vec<Expression>, String> {
+        self.advance();
+
+        let mut arguments: Vec<Expression> = vec![];
+        while self.current_token != Token::RightPar {
+            // name: expression
+            let argument = match (&self.current_token, self.peek()) {
+                (Token::Identifier(name), Token::Colon) => {
+                    let name = name.clone();
+                    self.advance();
+                    self.advance();
+                    Expression::NamedArgument(name, Box::new(self.parse_expression()?))
+                }
+                _ => self.parse_expression()?,
+            };
+            arguments.push(argument);
+
+            match self.current_token {
+                Token::Comma => self.advance(),
+                Token::RightPar => {}
+                _ => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected ',' or ')' on function call",
+                        self.current_token
+                    ))
+                }
+            }
+        }
+        Ok(arguments)
+    }
+
+    // Name { field: expression, field: expression, ... }
+    fn parse_struct_literal(&mut self) -> Result<Expression, String> {
+        eprintln!("PARSING STRUCT LITERAL: {:?}", self.current_token);
+        let name = self.parse_identifier()?;
+        self.advance();
+        self.advance();
+
+        let mut fields: Vec<(String, Expression)> = vec![];
+        while self.current_token != Token::RightCurly {
+            let field = self.parse_identifier()?;
+            self.advance();
+            if self.current_token != Token::Colon {
+                return Err(format!(
+                    "Invalid token {:?}. Expected ':' after field name on struct literal",
+                    self.current_token
+                ));
+            }
+            self.advance();
+            fields.push((field, self.parse_expression()?));
+
+            match self.current_token {
+                Token::Comma => self.advance(),
+                Token::RightCurly => {}
+                _ => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected ',' or '}}' on struct literal",
+                        self.current_token
+                    ))
+                }
+            }
+        }
+        Ok(Expression::StructLiteral(name, fields))
+    }
+
+    // Enum::Variant or Enum::Variant(expression, expression, ...)
+    fn parse_enum_variant(&mut self) -> Result<Expression, String> {
+        eprintln!("PARSING ENUM VARIANT: {:?}", self.current_token);
+        let name = self.parse_identifier()?;
+        self.advance();
+        self.advance();
+        let variant = self.parse_identifier()?;
+
+        let mut arguments: Vec<Expression> = vec![];
+        if *self.peek() == Token::LeftPar {
+            self.advance();
+            arguments = self.parse_call_arguments()?;
+        }
+        Ok(Expression::EnumVariant(name, variant, arguments))
+    }
+
+    // fn(type, type): type, without a return type for functions that return nothing
+    fn parse_function_type(&mut self) -> Result<Type, String> {
+        self.advance();
+        if self.current_token != Token::LeftPar {
+            return Err(format!(
+                "Invalid token {:?}. Expected '(' after 'fn' on function type",
+                self.current_token
+            ));
+        }
+        self.advance();
+
+        let mut parameter_types: Vec<Type> = vec![];
+        while self.current_token != Token::RightPar {
+            parameter_types.push(self.parse_type()?);
+            self.advance();
+            match self.current_token {
+                Token::Comma => self.advance(),
+                Token::RightPar => {}
+                _ => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected ',' or ')' on function type",
+                        self.current_token
+                    ))
+                }
+            }
+        }
+
+        let return_type = if *self.peek() == Token::Colon {
+            self.advance();
+            self.advance();
+            self.parse_type()?
+        } else {
+            Type::Void
+        };
+        Ok(Type::Function(parameter_types, Box::new(return_type)))
+    }
+
+    // fn(type name, ...): type { ... }
+    fn parse_closure(&mut self) -> Result<Expression, String> {
+        eprintln!("PARSING CLOSURE: {:?}", self.current_token);
+        self.advance();
+        let parameters = self.parse_function_parameters()?;
+        let return_type = match self.parse_function_return_type()? {
+            Type::Void => None,
+            t => Some(t),
+        };
+        let body = self.parse_block()?;
+        Ok(Expression::Closure(Closure::new(
+            parameters,
+            return_type,
+            body,
+        )))
+    }
+
+    // Enum::Variant(binding, binding, ...) or _
+    fn parse_pattern(&mut self) -> Result<Pattern, String> {
+        eprintln!("PARSING PATTERN: {:?}", self.current_token);
+        let name = self.parse_path()?;
+        if name == "_" {
+            return Ok(Pattern::Wildcard);
+        }
+
+        self.advance();
+        if self.current_token != Token::DoubleColon {
+            return Err(format!(
+                "Invalid token {:?}. Expected '::' after enum name on pattern",
+                self.current_token
+            ));
+        }
+        self.advance();
+        let variant = self.parse_identifier()?;
+
+        let mut bindings: Vec<String> = vec![];
+        if *self.peek() == Token::LeftPar {
+            self.advance();
+            self.advance();
+            while self.current_token != Token::RightPar {
+                bindings.push(self.parse_identifier()?);
+                self.advance();
+                match self.current_token {
+                    Token::Comma => self.advance(),
+                    Token::RightPar => {}
+                    _ => {
+                        return Err(format!(
+                            "Invalid token {:?}. Expected ',' or ')' on pattern",
+                            self.current_token
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(Pattern::Variant(name, variant, bindings))
+    }
+
+    // match expression { pattern => expression, pattern => expression, ... }
+    fn parse_match(&mut self) -> Result<Expression, String> {
+        eprintln!("PARSING MATCH: {:?}", self.current_token);
+        self.advance();
+        let subject = self.parse_expression()?;
+        if self.current_token != Token::LeftCurly {
+            return Err(format!(
+                "Invalid token {:?}. Expected '{{' after match subject",
+                self.current_token
+            ));
+        }
+        self.advance();
+
+        let mut arms: Vec<MatchArm> = vec![];
+        while self.current_token != Token::RightCurly {
+            let pattern = self.parse_pattern()?;
+            self.advance();
+            if self.current_token != Token::FatArrow {
+                return Err(format!(
+                    "Invalid token {:?}. Expected '=>' after pattern",
+                    self.current_token
+                ));
+            }
+            self.advance();
+            arms.push(MatchArm::new(pattern, self.parse_expression()?));
+
+            match self.current_token {
+                Token::Comma => self.advance(),
+                Token::RightCurly => {}
+                _ => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected ',' or '}}' on match",
+                        self.current_token
+                    ))
+                }
+            }
+        }
+        Ok(Expression::Match(Box::new(subject), arms))
+    }
+
+    // `Name {` also appears before blocks (`for p in points {`), so a struct
+    // literal must start with a field name followed by a colon
+    fn is_start_of_struct_literal(&self) -> bool {
+        *self.peek() == Token::LeftCurly
+            && matches!(self.peek_nth(1), Token::Identifier(_))
+            && *self.peek_nth(2) == Token::Colon
     }
 
-    fn get_rpn_expression(&mut self, scope: &HashMap<String, Ast>) -> Result<Vec<Token>, String> {
-        let mut operators: Vec<Token> = vec![];
-        let mut operands: Vec<Token> = vec![];
+    fn get_rpn_expression(&mut self) -> Result<Vec<RpnItem>, String> {
+        let mut operators: Vec<RpnItem> = vec![];
+        let left_parenthesis = RpnItem::Operator(Token::LeftPar);
+        let mut operands: Vec<RpnItem> = vec![];
+        let mut open_parenthesis: usize = 0;
+        // Tells whether the next token starts an operand or follows one, which
+        // is what distinguishes an array literal from an index
+        let mut expect_operand = true;
+
+        while !self.is_end_of_expression(open_parenthesis) {
+            self.join_module_path();
+            let starts_operand = expect_operand;
+            expect_operand = false;
 
-        while !self.is_end_of_statement() {
             match &self.current_token {
-                number if self.current_token.is_number() => {
-                    operands.push(number.clone());
+                Token::FloatNumber(value) | Token::IntNumber(value) => {
+                    operands.push(RpnItem::Operand(self.parse_number(value)?));
+                }
+
+                Token::StringValue(value) => {
+                    operands.push(RpnItem::Operand(Expression::StringLit(value.clone())));
+                }
+
+                Token::KeywordTrue => operands.push(RpnItem::Operand(Expression::Boolean(true))),
+
+                Token::KeywordFalse => operands.push(RpnItem::Operand(Expression::Boolean(false))),
+
+                Token::KeywordNone => operands.push(RpnItem::Operand(Expression::NoneLit)),
+
+                // err(expression)
+                Token::KeywordErr => {
+                    self.advance();
+                    if self.current_token != Token::LeftPar {
+                        return Err(format!(
+                            "Invalid token {:?}. Expected '(' after 'err'",
+                            self.current_token
+                        ));
+                    }
+                    let mut arguments = self.parse_call_arguments()?;
+                    if arguments.len() != 1 {
+                        return Err("Error: 'err' takes exactly one expression".to_string());
+                    }
+                    let error = arguments.pop().unwrap();
+                    operands.push(RpnItem::Operand(Expression::Err(Box::new(error))));
+                }
+
+                Token::Identifier(ident) if *self.peek() == Token::LeftPar => {
+                    let name = ident.clone();
+                    self.advance();
+                    let arguments = self.parse_call_arguments()?;
+                    operands.push(RpnItem::Operand(Expression::FunctionCall(name, arguments)));
+                }
+
+                Token::Identifier(_) if *self.peek() == Token::DoubleColon => {
+                    operands.push(RpnItem::Operand(self.parse_enum_variant()?));
+                }
+
+                Token::KeywordFn => operands.push(RpnItem::Operand(self.parse_closure()?)),
+
+                Token::KeywordMatch => {
+                    operands.push(RpnItem::Operand(self.parse_match()?));
+                }
+
+                Token::Identifier(_) if self.is_start_of_struct_literal() => {
+                    operands.push(RpnItem::Operand(self.parse_struct_literal()?));
                 }
 
                 Token::Identifier(ident) => {
-                    if let Some(Ast::Assignment(variable)) = scope.get(ident) {
-                        println!("Found a variable: {:?}", variable);
-                        let value = evaluate_ast(variable.value.clone())?;
-                        let var = match &variable.var_type {
-                            // TODO: Convert string to number on the lexer to avoid this "to_string"
-                            Type::Int => Token::IntNumber(value.to_string()),
-                            Type::Float => Token::FloatNumber(value.to_string()),
-                            t => return Err(format!("Unsuported type: {:?}", t)),
-                        };
-                        operands.push(var);
+                    operands.push(RpnItem::Operand(Expression::Identifier(ident.clone())));
+                }
+
+                // Field access binds as tight as indexing
+                Token::Dot => {
+                    self.advance();
+                    let field = self.parse_identifier()?;
+                    if *self.peek() == Token::LeftPar {
+                        self.advance();
+                        let arguments = self.parse_call_arguments()?;
+                        operands.push(RpnItem::Method(field, arguments));
                     } else {
-                        return Err("Use of undeclared variable".to_string());
+                        operands.push(RpnItem::Field(field));
+                    }
+                }
+
+                // `expression?` binds as tight as field access
+                Token::QuestionMark if !starts_operand => {
+                    operands.push(RpnItem::Try);
+                }
+
+                // Casts bind tighter than any binary operator, so they go straight to the output
+                Token::KeywordAs => {
+                    // `*p as int` casts the dereferenced value
+                    while operators.last().is_some_and(RpnItem::is_prefix) {
+                        operands.push(operators.pop().unwrap());
+                    }
+                    self.advance();
+                    let target_type = self.parse_type()?;
+                    operands.push(RpnItem::Cast(target_type));
+                }
+
+                Token::LeftBracket if starts_operand => {
+                    operands.push(RpnItem::Operand(self.parse_array_literal()?));
+                }
+
+                // Indexing also binds tighter than any binary operator
+                Token::LeftBracket => {
+                    self.advance();
+                    let index = self.parse_expression()?;
+                    if self.current_token != Token::RightBracket {
+                        return Err(format!(
+                            "Invalid token {:?}. Expected ']' after index",
+                            self.current_token
+                        ));
                     }
+                    operands.push(RpnItem::Index(index));
                 }
 
                 Token::LeftPar => {
-                    operators.push(self.current_token.clone());
+                    open_parenthesis += 1;
+                    expect_operand = true;
+                    operators.push(left_parenthesis.clone());
                 }
 
                 Token::RightPar => {
                     let mut found_left_parenthesis = false;
                     while !operators.is_empty() {
-                        if *operators.last().unwrap() == Token::LeftPar {
+                        if *operators.last().unwrap() == left_parenthesis {
                             found_left_parenthesis = true;
                             break;
                         } else {
-                            let op = operators.pop().unwrap();
-                            operands.push(op);
+                            operands.push(operators.pop().unwrap());
                         }
                     }
 
@@ -136,24 +704,44 @@ impl Parser {
                     } else {
                         // DISCARD LEFT PARENTHESIS AT THE TOP
                         operators.pop().unwrap();
+                        open_parenthesis -= 1;
                     }
                 }
 
+                // `&` and `*` before an operand take its address or dereference it
+                Token::BitwiseAnd | Token::Times if starts_operand => {
+                    let prefix = if self.current_token == Token::Times {
+                        RpnItem::Dereference
+                    } else if *self.peek() == Token::KeywordMut {
+                        self.advance();
+                        RpnItem::AddressOf(true)
+                    } else {
+                        RpnItem::AddressOf(false)
+                    };
+                    operators.push(prefix);
+                    expect_operand = true;
+                }
+
                 // TODO: Refactor excessive clone
                 op if op.is_operator() => {
-                    while !operators.is_empty() {
-                        let top = operators.last().unwrap().clone();
+                    while let Some(top) = operators.last() {
+                        let should_pop = match top {
+                            RpnItem::Operator(top) => {
+                                *top != Token::LeftPar && top.has_higher_precedence(op)
+                                    || top.has_same_precedence(op)
+                                        && op.get_associativity() == Associativity::Left
+                            }
+                            top => top.is_prefix(),
+                        };
 
-                        if top != Token::LeftPar && top.has_higher_precedence(op)
-                            || top.has_same_precedence(op)
-                                && op.get_associativity() == Associativity::Left
-                        {
+                        if should_pop {
                             operands.push(operators.pop().unwrap());
                         } else {
                             break;
                         }
                     }
-                    operators.push(op.clone());
+                    operators.push(RpnItem::Operator(op.clone()));
+                    expect_operand = true;
                 }
 
                 _ => {
@@ -163,31 +751,30 @@ impl Parser {
             self.advance();
         }
 
-        while !operators.is_empty() {
-            let top = operators.last().unwrap().clone();
-            if top == Token::LeftPar {
+        while let Some(top) = operators.pop() {
+            if top == left_parenthesis {
                 return Err("Error: Mismatched parenthesis".to_string());
             }
-            operands.push(operators.pop().unwrap());
+            operands.push(top);
         }
 
         Ok(operands)
     }
 
-    fn parse_expression(&mut self, scope: &HashMap<String, Ast>) -> Result<Expression, String> {
-        println!("PARSING EXPRESSION: {:?}", self.current_token);
-        let rpn_expression = self.get_rpn_expression(scope)?;
+    fn parse_expression(&mut self) -> Result<Expression, String> {
+        eprintln!("PARSING EXPRESSION: {:?}", self.current_token);
+        let rpn_expression = self.get_rpn_expression()?;
 
-        for rpn_token in rpn_expression.iter() {
-            println!("RPN: {:?}", rpn_token);
+        for rpn_item in rpn_expression.iter() {
+            eprintln!("RPN: {:?}", rpn_item);
         }
-        let ast = self.from_rpn_to_ast(rpn_expression)?;
-        println!("AST: {:?}", ast);
+        let ast = self.build_ast_from_rpn(rpn_expression)?;
+        eprintln!("AST: {:?}", ast);
         Ok(ast)
     }
 
     fn parse_semicolon(&self) -> Result<(), String> {
-        println!("PARSING SEMICOLON: {:?}", self.current_token);
+        eprintln!("PARSING SEMICOLON: {:?}", self.current_token);
         if self.current_token != Token::Semicolon {
             Err(format!(
                 "Invalid token {:?}. Expected semicolon at the end of statement",
@@ -199,7 +786,7 @@ impl Parser {
     }
 
     fn parse_equal_sign(&self) -> Result<(), String> {
-        println!("PARSING EQUAL SIGN: {:?}", self.current_token);
+        eprintln!("PARSING EQUAL SIGN: {:?}", self.current_token);
         if self.current_token != Token::EqualSign {
             Err(format!(
                 "Invalid token {:?}. Expected an equal sign",
@@ -210,41 +797,211 @@ impl Parser {
         }
     }
 
-    fn parse_assignment(&mut self, scope: &HashMap<String, Ast>) -> Result<Assignment, String> {
+    fn parse_assignment(&mut self) -> Result<Assignment, String> {
         let var_type = self.parse_type()?;
         self.advance();
         let name = self.parse_identifier()?;
         self.advance();
         self.parse_equal_sign()?;
         self.advance();
-        let expression = self.parse_expression(scope)?;
+        let expression = self.parse_expression()?;
         self.parse_semicolon()?;
 
-        // let evaluated_expression = evaluate_ast(expression.clone())?;
-        // println!("EVALUATED EXPRESSION: {}", evaluated_expression);
-
         let assignment = Assignment::new(var_type, name, expression);
         Ok(assignment)
     }
 
-    fn parse_function_parameters(&mut self) -> Result<Vec<Parameter>, String> {
-        if self.current_token != Token::LeftPar {
-            return Err(format!(
-                "Unexpected token on function parameter parsing: {:?}",
-                &self.current_token
-            ));
-        }
+    // let name [: type] = expression;
+    fn parse_let(&mut self) -> Result<Assignment, String> {
+        eprintln!("PARSING LET: {:?}", self.current_token);
         self.advance();
-
+        let name = self.parse_identifier()?;
+        self.advance();
+
+        let var_type = if self.current_token == Token::Colon {
+            self.advance();
+            let var_type = self.parse_type()?;
+            self.advance();
+            Some(var_type)
+        } else {
+            None
+        };
+
+        self.parse_equal_sign()?;
+        self.advance();
+        let expression = self.parse_expression()?;
+        self.parse_semicolon()?;
+
+        let mut assignment = Assignment::inferred(name, expression);
+        assignment.var_type = var_type;
+        Ok(assignment)
+    }
+
+    // Statements that start with an expression: either `target = expression;`
+    // or a function call such as `push(numbers, 12);`
+    fn parse_expression_statement(&mut self) -> Result<Ast, String> {
+        eprintln!("PARSING EXPRESSION STATEMENT: {:?}", self.current_token);
+        let target = self.parse_expression()?;
+        if self.current_token == Token::Semicolon {
+            return Ok(Ast::Expression(target));
+        }
+
+        self.parse_equal_sign()?;
+        self.advance();
+        let value = self.parse_expression()?;
+        self.parse_semicolon()?;
+
+        Ok(Ast::Reassignment(Reassignment::new(target, value)))
+    }
+
+    // for variable in expression { ... }
+    fn parse_for(&mut self) -> Result<For, String> {
+        eprintln!("PARSING FOR: {:?}", self.current_token);
+        self.advance();
+        let variable = self.parse_identifier()?;
+        self.advance();
+        if self.current_token != Token::KeywordIn {
+            return Err(format!(
+                "Invalid token {:?}. Expected 'in' on for loop",
+                self.current_token
+            ));
+        }
+        self.advance();
+        let iterable = self.parse_expression()?;
+        let body = self.parse_block()?;
+
+        Ok(For::new(variable, iterable, body))
+    }
+
+    // if condition { ... } elif condition { ... } else { ... }
+    // where a condition is either an expression or `let name = expression`
+    fn parse_if(&mut self) -> Result<If, String> {
+        eprintln!("PARSING IF: {:?}", self.current_token);
+        self.advance();
+
+        let condition = if self.current_token == Token::KeywordLet {
+            self.advance();
+            let name = self.parse_identifier()?;
+            self.advance();
+            self.parse_equal_sign()?;
+            self.advance();
+            Condition::Let(name, self.parse_expression()?)
+        } else {
+            Condition::Expression(self.parse_expression()?)
+        };
+        let body = self.parse_block()?;
+
+        let else_body = match self.peek() {
+            Token::KeywordElse => {
+                self.advance();
+                self.advance();
+                Some(self.parse_block()?)
+            }
+            Token::KeywordElif => {
+                self.advance();
+                let elif = Ast::If(self.parse_if()?);
+                Some(Block::new(vec![elif], HashMap::new()))
+            }
+            _ => None,
+        };
+        Ok(If::new(condition, body, else_body))
+    }
+
+    fn parse_return(&mut self) -> Result<Ast, String> {
+        eprintln!("PARSING RETURN: {:?}", self.current_token);
+        self.advance();
+
+        if self.is_end_of_expression(0) {
+            self.parse_semicolon()?;
+            return Ok(Ast::Return(None));
+        }
+
+        let expression = self.parse_expression()?;
+        self.parse_semicolon()?;
+        Ok(Ast::Return(Some(expression)))
+    }
+
+    // <T, U: Trait + Trait>, leaves the parser on the token after `>`
+    fn parse_type_parameters(&mut self) -> Result<Vec<TypeParameter>, String> {
+        eprintln!("PARSING TYPE PARAMETERS: {:?}", self.current_token);
+        self.advance();
+
+        let mut type_parameters: Vec<TypeParameter> = vec![];
+        loop {
+            let name = self.parse_identifier()?;
+            if type_parameters
+                .iter()
+                .any(|type_parameter| type_parameter.name == name)
+            {
+                return Err(format!("Duplicate type parameter {}", name));
+            }
+            self.advance();
+
+            let mut bounds: Vec<String> = vec![];
+            if self.current_token == Token::Colon {
+                loop {
+                    self.advance();
+                    bounds.push(self.parse_path()?);
+                    self.advance();
+                    if self.current_token != Token::Plus {
+                        break;
+                    }
+                }
+            }
+            type_parameters.push(TypeParameter::new(name, bounds));
+
+            match self.current_token {
+                Token::Comma => self.advance(),
+                Token::GreaterThan => break,
+                _ => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected ',' or '>' on type parameters",
+                        self.current_token
+                    ))
+                }
+            }
+        }
+        self.advance();
+        Ok(type_parameters)
+    }
+
+    fn parse_function_parameters(&mut self) -> Result<Vec<Parameter>, String> {
+        if self.current_token != Token::LeftPar {
+            return Err(format!(
+                "Unexpected token on function parameter parsing: {:?}",
+                &self.current_token
+            ));
+        }
+        self.advance();
+
         let mut parameters: Vec<Parameter> = vec![];
 
         while self.current_token != Token::RightPar {
+            // Methods take the receiver first, without a type
+            if let (Some(receiver), true) = (&self.receiver, parameters.is_empty()) {
+                if self.current_token == Token::Identifier("self".to_string()) {
+                    parameters.push(Parameter::new(receiver.clone(), "self".to_string()));
+                    self.advance();
+                    if self.current_token == Token::Comma {
+                        self.advance();
+                    }
+                    continue;
+                }
+            }
+
             let parameter_type = self.parse_type()?;
             self.advance();
             let parameter_name = self.parse_identifier()?;
-            parameters.push(Parameter::new(parameter_type, parameter_name));
+            let mut parameter = Parameter::new(parameter_type, parameter_name);
             self.advance();
 
+            // type name = default
+            if self.current_token == Token::EqualSign {
+                self.advance();
+                parameter.default = Some(self.parse_expression()?);
+            }
+            parameters.push(parameter);
+
             if self.current_token == Token::Comma {
                 self.advance();
             }
@@ -255,13 +1012,17 @@ impl Parser {
     fn parse_function_return_type(&mut self) -> Result<Type, String> {
         self.advance();
 
-        if self.current_token == Token::LeftCurly {
+        // `as` follows the prototype of a renamed extern
+        if matches!(
+            self.current_token,
+            Token::LeftCurly | Token::Semicolon | Token::KeywordAs
+        ) {
             return Ok(Type::Void);
         } else if self.current_token == Token::Colon {
             self.advance();
 
             // Function is not well formed
-            if !self.current_token.is_data_type_keyword() {
+            if !self.is_start_of_type() && !matches!(self.current_token, Token::Identifier(_)) {
                 return Err("Expected return type for function".to_string());
             }
 
@@ -284,12 +1045,30 @@ impl Parser {
 
         while self.current_token != Token::RightCurly {
             let statement = match &self.current_token {
-                token if token.is_data_type_keyword() => {
-                    let assignment = self.parse_assignment(&symbol_table)?;
+                _ if self.is_start_of_type() => {
+                    let assignment = self.parse_assignment()?;
+                    symbol_table
+                        .insert(assignment.name.clone(), Ast::Assignment(assignment.clone()));
+                    Ast::Assignment(assignment)
+                }
+                Token::KeywordLet => {
+                    let assignment = self.parse_let()?;
                     symbol_table
                         .insert(assignment.name.clone(), Ast::Assignment(assignment.clone()));
                     Ast::Assignment(assignment)
                 }
+                Token::KeywordReturn => self.parse_return()?,
+                Token::Identifier(_) | Token::Times => self.parse_expression_statement()?,
+                Token::KeywordFor => Ast::For(self.parse_for()?),
+                Token::KeywordIf => Ast::If(self.parse_if()?),
+                // Like a block, a match statement doesn't need a semicolon
+                Token::KeywordMatch => {
+                    let statement = Ast::Expression(self.parse_match()?);
+                    if *self.peek() == Token::Semicolon {
+                        self.advance();
+                    }
+                    statement
+                }
                 _ => return Err(format!("Invalid token: {:?}", self.current_token)),
             };
             body.push(statement);
@@ -298,17 +1077,211 @@ impl Parser {
         Ok(Block::new(body, symbol_table))
     }
 
-    fn parse_function(&mut self) -> Result<Function, String> {
-        println!("PARSING FUNCTION: {:?}", self.current_token);
+    // struct Name { type field; type field; ... }
+    fn parse_struct(&mut self) -> Result<Struct, String> {
+        eprintln!("PARSING STRUCT: {:?}", self.current_token);
+        self.advance();
+        let name = self.parse_identifier()?;
+        self.advance();
+        if self.current_token != Token::LeftCurly {
+            return Err(format!(
+                "Invalid token {:?}. Expected '{{' after struct name",
+                self.current_token
+            ));
+        }
+        self.advance();
+
+        let mut fields: Vec<Field> = vec![];
+        while self.current_token != Token::RightCurly {
+            let field_type = self.parse_type()?;
+            self.advance();
+            let field_name = self.parse_identifier()?;
+            self.advance();
+            self.parse_semicolon()?;
+            self.advance();
+            fields.push(Field::new(field_type, field_name));
+        }
+
+        if fields.is_empty() {
+            return Err(format!("Struct '{}' must have at least one field", name));
+        }
+        Ok(Struct::new(name, fields))
+    }
+
+    // enum Name { Variant, Variant(type, type), ... }
+    fn parse_enum(&mut self) -> Result<Enum, String> {
+        eprintln!("PARSING ENUM: {:?}", self.current_token);
+        self.advance();
+        let name = self.parse_identifier()?;
+        self.advance();
+        if self.current_token != Token::LeftCurly {
+            return Err(format!(
+                "Invalid token {:?}. Expected '{{' after enum name",
+                self.current_token
+            ));
+        }
+        self.advance();
+
+        let mut variants: Vec<Variant> = vec![];
+        while self.current_token != Token::RightCurly {
+            let variant = self.parse_identifier()?;
+            self.advance();
+
+            let mut fields: Vec<Type> = vec![];
+            if self.current_token == Token::LeftPar {
+                self.advance();
+                while self.current_token != Token::RightPar {
+                    fields.push(self.parse_type()?);
+                    self.advance();
+                    match self.current_token {
+                        Token::Comma => self.advance(),
+                        Token::RightPar => {}
+                        _ => {
+                            return Err(format!(
+                                "Invalid token {:?}. Expected ',' or ')' on variant '{}'",
+                                self.current_token, variant
+                            ))
+                        }
+                    }
+                }
+                self.advance();
+            }
+            variants.push(Variant::new(variant, fields));
+
+            match self.current_token {
+                Token::Comma => self.advance(),
+                Token::RightCurly => {}
+                _ => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected ',' or '}}' on enum '{}'",
+                        self.current_token, name
+                    ))
+                }
+            }
+        }
+
+        if variants.is_empty() {
+            return Err(format!("Enum '{}' must have at least one variant", name));
+        }
+        Ok(Enum::new(name, variants))
+    }
+
+    // impl Name { fn method(self, ...) { ... } ... }
+    // trait Name { fn method(self, type name, ...): type; ... }
+    fn parse_trait(&mut self) -> Result<Trait, String> {
+        eprintln!("PARSING TRAIT: {:?}", self.current_token);
+        self.advance();
+        let name = self.parse_identifier()?;
+        self.advance();
+        if self.current_token != Token::LeftCurly {
+            return Err(format!(
+                "Invalid token {:?}. Expected '{{' after trait name",
+                self.current_token
+            ));
+        }
+        self.advance();
+
+        self.receiver = Some(Type::Generic("Self".to_string()));
+        let methods = self.parse_trait_methods(&name);
+        self.receiver = None;
+        Ok(Trait::new(name, methods?))
+    }
+
+    fn parse_trait_methods(&mut self, name: &str) -> Result<Vec<Prototype>, String> {
+        let mut methods: Vec<Prototype> = vec![];
+        while self.current_token != Token::RightCurly {
+            if self.current_token != Token::KeywordFn {
+                return Err(format!(
+                    "Invalid token {:?}. Expected a method on trait '{}'",
+                    self.current_token, name
+                ));
+            }
+            let method = self.parse_prototype()?;
+            if !std::mem::take(&mut self.type_parameters).is_empty() {
+                return Err(format!(
+                    "Method '{}.{}' of a trait can't be generic",
+                    name, method.name
+                ));
+            }
+            self.parse_semicolon()?;
+            methods.push(method);
+            self.advance();
+        }
+        Ok(methods)
+    }
+
+    // impl Type { ... } or impl Trait for Type { ... }
+    fn parse_impl(&mut self) -> Result<Impl, String> {
+        eprintln!("PARSING IMPL: {:?}", self.current_token);
+        self.advance();
+        let mut type_name = self.parse_path()?;
+        let mut trait_name = None;
+        self.advance();
+        if self.current_token == Token::KeywordFor {
+            self.advance();
+            trait_name = Some(type_name);
+            type_name = self.parse_path()?;
+            self.advance();
+        }
+        if self.current_token != Token::LeftCurly {
+            return Err(format!(
+                "Invalid token {:?}. Expected '{{' after impl type",
+                self.current_token
+            ));
+        }
+        self.advance();
+
+        self.receiver = Some(Type::UserDefined(type_name.clone()));
+        let mut methods: Vec<Function> = vec![];
+        while self.current_token != Token::RightCurly {
+            if self.current_token != Token::KeywordFn {
+                self.receiver = None;
+                return Err(format!(
+                    "Invalid token {:?}. Expected a method on impl '{}'",
+                    self.current_token, type_name
+                ));
+            }
+            let method = self.parse_function();
+            if method.is_err() {
+                self.receiver = None;
+            }
+            methods.push(method?);
+            self.advance();
+        }
+        self.receiver = None;
+
+        let mut impl_node = Impl::new(type_name, methods);
+        impl_node.trait_name = trait_name;
+        Ok(impl_node)
+    }
+
+    // fn name<T>(type name, ...): type, leaves the parser on the token after
+    // the signature. Type parameters are kept in `type_parameters`
+    fn parse_prototype(&mut self) -> Result<Prototype, String> {
+        eprintln!("PARSING FUNCTION: {:?}", self.current_token);
         self.advance();
 
         let function_name = self.parse_identifier()?;
         self.advance();
+        self.type_parameters = if self.current_token == Token::LessThan {
+            self.parse_type_parameters()?
+        } else {
+            vec![]
+        };
         let parameters = self.parse_function_parameters()?;
         let return_type = match self.parse_function_return_type()? {
             Type::Void => None,
             t => Some(t),
         };
+        Ok(Prototype::new(function_name, parameters, return_type))
+    }
+
+    fn parse_function(&mut self) -> Result<Function, String> {
+        let Prototype {
+            name: function_name,
+            parameters,
+            return_type,
+        } = self.parse_prototype()?;
         let body: Block = self.parse_block()?;
 
         if self.current_token != Token::RightCurly {
@@ -317,14 +1290,128 @@ impl Parser {
                 self.current_token
             ));
         }
-        self.advance();
 
-        let function = Function::new(function_name.clone(), parameters, body, return_type);
-        self.symbol_table
-            .insert(function_name, Ast::Function(function.clone()));
+        let mut function = Function::new(function_name.clone(), parameters, body, return_type);
+        function.type_parameters = std::mem::take(&mut self.type_parameters);
+        // Methods live in the namespace of their type
+        if self.receiver.is_none() {
+            self.symbol_table
+                .insert(function_name, Ast::Function(function.clone()));
+        }
         Ok(function)
     }
 
+    // import module; or use module::item;
+    fn parse_import(&mut self) -> Result<Import, String> {
+        eprintln!("PARSING IMPORT: {:?}", self.current_token);
+        let is_use = self.current_token == Token::KeywordUse;
+        self.advance();
+        let module = self.parse_identifier()?;
+        self.advance();
+
+        let mut item = None;
+        if is_use {
+            if self.current_token != Token::DoubleColon {
+                return Err(format!(
+                    "Invalid token {:?}. Expected '::' after module name on use",
+                    self.current_token
+                ));
+            }
+            self.advance();
+            item = Some(self.parse_identifier()?);
+            self.advance();
+        }
+        if self.current_token != Token::Semicolon {
+            return Err(format!(
+                "Invalid token {:?}. Expected ';' after import",
+                self.current_token
+            ));
+        }
+
+        if !is_use {
+            self.modules.insert(module.clone());
+        }
+        Ok(Import::new(module, item))
+    }
+
+    // extern "C" fn name(type name, ...): type [as "c_name"];
+    fn parse_extern(&mut self) -> Result<Extern, String> {
+        eprintln!("PARSING EXTERN: {:?}", self.current_token);
+        self.advance();
+        if self.current_token != Token::StringValue("C".to_string()) {
+            return Err(format!(
+                "Invalid token {:?}. Expected \"C\" after extern",
+                self.current_token
+            ));
+        }
+        self.advance();
+        if self.current_token != Token::KeywordFn {
+            return Err(format!(
+                "Invalid token {:?}. Expected a function after extern \"C\"",
+                self.current_token
+            ));
+        }
+        let prototype = self.parse_prototype()?;
+        if !std::mem::take(&mut self.type_parameters).is_empty() {
+            return Err(format!(
+                "Extern function '{}' can't be generic",
+                prototype.name
+            ));
+        }
+        let mut extern_node = Extern::new(prototype);
+        if self.current_token == Token::KeywordAs {
+            self.advance();
+            match &self.current_token {
+                Token::StringValue(c_name) => extern_node.c_name = c_name.clone(),
+                t => {
+                    return Err(format!(
+                        "Invalid token {:?}. Expected the C name of extern function '{}'",
+                        t, extern_node.prototype.name
+                    ))
+                }
+            }
+            self.advance();
+        }
+        self.parse_semicolon()?;
+        Ok(extern_node)
+    }
+
+    // pub fn, pub struct, pub enum or pub trait
+    fn parse_public(&mut self) -> Result<Ast, String> {
+        eprintln!("PARSING PUBLIC ITEM: {:?}", self.current_token);
+        self.advance();
+        match self.current_token {
+            Token::KeywordFn => {
+                let mut function = self.parse_function()?;
+                function.public = true;
+                Ok(Ast::Function(function))
+            }
+            Token::KeywordStruct => {
+                let mut struct_node = self.parse_struct()?;
+                struct_node.public = true;
+                self.symbol_table
+                    .insert(struct_node.name.clone(), Ast::Struct(struct_node.clone()));
+                Ok(Ast::Struct(struct_node))
+            }
+            Token::KeywordEnum => {
+                let mut enum_node = self.parse_enum()?;
+                enum_node.public = true;
+                self.symbol_table
+                    .insert(enum_node.name.clone(), Ast::Enum(enum_node.clone()));
+                Ok(Ast::Enum(enum_node))
+            }
+            Token::KeywordTrait => {
+                let mut trait_node = self.parse_trait()?;
+                trait_node.public = true;
+                Ok(Ast::Trait(trait_node))
+            }
+            _ => Err(format!(
+                "Invalid token {:?}. Only functions, structs, enums and traits can be public",
+                self.current_token
+            )),
+        }
+    }
+
     pub fn generate_ast(&mut self) -> Result<Vec<Ast>, String> {
         self.advance();
         let mut ast: Vec<Ast> = vec![];
@@ -333,11 +1420,92 @@ impl Parser {
             match &self.current_token {
                 Token::KeywordFn => {
                     let function = self.parse_function()?;
-                    println!("FUNCTION: {:?}", function);
+                    eprintln!("FUNCTION: {:?}", function);
                     ast.push(Ast::Function(function));
                     self.advance();
                 }
 
+                Token::KeywordStruct => {
+                    let struct_node = self.parse_struct()?;
+                    self.symbol_table
+                        .insert(struct_node.name.clone(), Ast::Struct(struct_node.clone()));
+                    ast.push(Ast::Struct(struct_node));
+                    self.advance();
+                }
+
+                Token::KeywordImpl => {
+                    let impl_node = self.parse_impl()?;
+                    ast.push(Ast::Impl(impl_node));
+                    self.advance();
+                }
+
+                Token::KeywordTrait => {
+                    let trait_node = self.parse_trait()?;
+                    ast.push(Ast::Trait(trait_node));
+                    self.advance();
+                }
+
+                Token::KeywordEnum => {
+                    let enum_node = self.parse_enum()?;
+                    self.symbol_table
+                        .insert(enum_node.name.clone(), Ast::Enum(enum_node.clone()));
+                    ast.push(Ast::Enum(enum_node));
+                    self.advance();
+                }
+
+                Token::KeywordExtern => {
+                    let extern_node = self.parse_extern()?;
+                    ast.push(Ast::Extern(extern_node));
+                    self.advance();
+                }
+
+                Token::KeywordPub => {
+                    let item = self.parse_public()?;
+                    ast.push(item);
+                    self.advance();
+                }
+
+                // Exported functions are public too
+                Token::KeywordExport => {
+                    self.advance();
+                    if self.current_token != Token::KeywordFn {
+                        return Err(format!(
+                            "Invalid token {:?}. Only functions can be exported",
+                            self.current_token
+                        ));
+                    }
+                    let mut function = self.parse_function()?;
+                    function.public = true;
+                    function.exported = true;
+                    ast.push(Ast::Function(function));
+                    self.advance();
+                }
+
+                Token::KeywordImport | Token::KeywordUse => {
+                    if ast.iter().any(|node| !matches!(node, Ast::Import(_))) {
+                        return Err("Error: Imports must come before any declaration".to_string());
+                    }
+                    let import = self.parse_import()?;
+                    ast.push(Ast::Import(import));
+                    self.advance();
+                }
+
+                _ if self.is_start_of_type() => {
+                    let assignment = self.parse_assignment()?;
+                    self.symbol_table
+                        .insert(assignment.name.clone(), Ast::Assignment(assignment.clone()));
+                    ast.push(Ast::Assignment(assignment));
+                    self.advance();
+                }
+
+                Token::KeywordLet => {
+                    let assignment = self.parse_let()?;
+                    self.symbol_table
+                        .insert(assignment.name.clone(), Ast::Assignment(assignment.clone()));
+                    ast.push(Ast::Assignment(assignment));
+                    self.advance();
+                }
+
                 _ => {
                     return Err(format!(
                         "Error: Invalid token on AST parsing: {:?}",
@@ -356,12 +1524,13 @@ impl Parser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::evaluate_ast;
 
     #[test]
     fn test_variable_declaration() {
         let input = "float variable_name = 8;\n".chars().collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
         let mut parser = Parser::new(tokens);
         let variable_ast = parser.generate_ast().unwrap();
 
@@ -378,7 +1547,7 @@ mod tests {
             .chars()
             .collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
         let mut parser = Parser::new(tokens);
         let variable_ast = parser.generate_ast().unwrap();
 
@@ -392,11 +1561,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_power_and_modulo() {
+        let evaluate = |input: &str| {
+            let tokens = Lexer::new(format!("int a = {};\n", input).chars().collect())
+                .tokenize()
+                .unwrap();
+            match &Parser::new(tokens).generate_ast().unwrap()[0] {
+                Ast::Assignment(variable) => variable.value.clone(),
+                node => panic!("Expected a variable declaration, found {:?}", node),
+            }
+        };
+
+        // `**` is right associative and binds tighter than `*` and `%`
+        let power = |lhs: Expression, rhs: Expression| {
+            Expression::BinaryExpr(Box::new(lhs), Token::Power, Box::new(rhs))
+        };
+        assert_eq!(
+            evaluate("2 ** 3 ** 2"),
+            power(
+                Expression::Int(2),
+                power(Expression::Int(3), Expression::Int(2))
+            )
+        );
+        assert_eq!(evaluate_ast(evaluate("2 ** 3 ** 2")), Ok(512.0));
+        assert_eq!(evaluate_ast(evaluate("2 * 3 ** 2 % 7")), Ok(4.0));
+        assert_eq!(evaluate_ast(evaluate("(2 * 3) ** 2")), Ok(36.0));
+
+        // Ints round towards zero, floats don't
+        assert_eq!(evaluate_ast(evaluate("7 / 2")), Ok(3.0));
+        assert_eq!(evaluate_ast(evaluate("7.0 / 2")), Ok(3.5));
+        assert_eq!(evaluate_ast(evaluate("(0 - 7) % 3")), Ok(-1.0));
+        assert_eq!(evaluate_ast(evaluate("7.5 % 2")), Ok(1.5));
+        assert_eq!(evaluate_ast(evaluate("4 ** 0.5")), Ok(2.0));
+        assert!(evaluate_ast(evaluate("1 % 0")).is_err());
+        assert!(evaluate_ast(evaluate("2 ** (0 - 1)")).is_err());
+
+        // Integer powers are exact up to overflowing an int
+        assert_eq!(evaluate_ast(evaluate("(0 - 2) ** 31")), Ok(-2147483648.0));
+        assert!(evaluate_ast(evaluate("2 ** 31")).is_err());
+        assert!(evaluate_ast(evaluate("2 ** 40")).is_err());
+    }
+
     #[test]
     fn test_function_declaration_with_empty_body() {
         let input = "fn name(): int {}\n".chars().collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
         let mut parser = Parser::new(tokens);
         let function_ast = &parser.generate_ast().unwrap()[0];
 
@@ -416,7 +1627,7 @@ mod tests {
             .chars()
             .collect::<Vec<char>>();
         let mut lexer = Lexer::new(input);
-        let tokens = lexer.tokenize();
+        let tokens = lexer.tokenize().unwrap();
         let mut parser = Parser::new(tokens);
         let function_ast = &parser.generate_ast().unwrap()[0];
 
@@ -438,4 +1649,683 @@ mod tests {
         ));
         assert_eq!(expected_function, *function_ast)
     }
+
+    #[test]
+    fn test_cast_binds_tighter_than_binary_operators() {
+        let input = "float a = (1 + 2) * b as float;\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let variable_ast = parser.generate_ast().unwrap();
+
+        let sum = Expression::BinaryExpr(
+            Box::new(Expression::Int(1)),
+            Token::Plus,
+            Box::new(Expression::Int(2)),
+        );
+        let cast = Expression::Cast(
+            Box::new(Expression::Identifier("b".to_string())),
+            Type::Float,
+        );
+        let value = Expression::BinaryExpr(Box::new(sum), Token::Times, Box::new(cast));
+        let expected_variable_ast =
+            Ast::Assignment(Assignment::new(Type::Float, "a".to_string(), value));
+
+        assert_eq!(variable_ast[0], expected_variable_ast);
+    }
+
+    #[test]
+    fn test_array_declaration_and_index_assignment() {
+        let input = "fn f() { [int; 2] a = [1, 2]; a[a[0]] = 3; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let function_ast = &parser.generate_ast().unwrap()[0];
+
+        let array = Expression::Identifier("a".to_string());
+        let declaration = Ast::Assignment(Assignment::new(
+            Type::Array(Box::new(Type::Int), 2),
+            "a".to_string(),
+            Expression::Array(vec![Expression::Int(1), Expression::Int(2)]),
+        ));
+        let first = Expression::Index(Box::new(array.clone()), Box::new(Expression::Int(0)));
+        let reassignment = Ast::Reassignment(Reassignment::new(
+            Expression::Index(Box::new(array), Box::new(first)),
+            Expression::Int(3),
+        ));
+
+        if let Ast::Function(function) = function_ast {
+            assert_eq!(function.body.itens, vec![declaration, reassignment]);
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_slice_loop_and_call_statement() {
+        let input = "fn f([]int v) { for x in v { push(v, x); } }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let function_ast = &parser.generate_ast().unwrap()[0];
+
+        let slice = Expression::Identifier("v".to_string());
+        let push = Ast::Expression(Expression::FunctionCall(
+            "push".to_string(),
+            vec![slice.clone(), Expression::Identifier("x".to_string())],
+        ));
+        let for_loop = Ast::For(For::new(
+            "x".to_string(),
+            slice,
+            Block::new(vec![push], HashMap::new()),
+        ));
+
+        if let Ast::Function(function) = function_ast {
+            assert_eq!(
+                function.parameters,
+                vec![Parameter::new(
+                    Type::Slice(Box::new(Type::Int)),
+                    "v".to_string()
+                )]
+            );
+            assert_eq!(function.body.itens, vec![for_loop]);
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_struct_declaration_and_field_assignment() {
+        let input = "struct Point { float x; float y; }
+            fn f() { Point p = Point { x: 1, y: 2 }; p.x = p.y; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let ast = parser.generate_ast().unwrap();
+
+        let point = Struct::new(
+            "Point".to_string(),
+            vec![
+                Field::new(Type::Float, "x".to_string()),
+                Field::new(Type::Float, "y".to_string()),
+            ],
+        );
+        assert_eq!(ast[0], Ast::Struct(point));
+
+        let p = Expression::Identifier("p".to_string());
+        let declaration = Ast::Assignment(Assignment::new(
+            Type::UserDefined("Point".to_string()),
+            "p".to_string(),
+            Expression::StructLiteral(
+                "Point".to_string(),
+                vec![
+                    ("x".to_string(), Expression::Int(1)),
+                    ("y".to_string(), Expression::Int(2)),
+                ],
+            ),
+        ));
+        let reassignment = Ast::Reassignment(Reassignment::new(
+            Expression::FieldAccess(Box::new(p.clone()), "x".to_string()),
+            Expression::FieldAccess(Box::new(p), "y".to_string()),
+        ));
+
+        if let Ast::Function(function) = &ast[1] {
+            assert_eq!(function.body.itens, vec![declaration, reassignment]);
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_enum_declaration_and_match() {
+        let input = "enum Shape { Circle(float), Rect(float, float), Empty }
+            fn f(Shape s): float { return match s { Shape::Rect(w, _) => w, _ => 0 }; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let ast = parser.generate_ast().unwrap();
+
+        let shape = Enum::new(
+            "Shape".to_string(),
+            vec![
+                Variant::new("Circle".to_string(), vec![Type::Float]),
+                Variant::new("Rect".to_string(), vec![Type::Float, Type::Float]),
+                Variant::new("Empty".to_string(), vec![]),
+            ],
+        );
+        assert_eq!(ast[0], Ast::Enum(shape));
+
+        let rect = Pattern::Variant(
+            "Shape".to_string(),
+            "Rect".to_string(),
+            vec!["w".to_string(), "_".to_string()],
+        );
+        let expected = Ast::Return(Some(Expression::Match(
+            Box::new(Expression::Identifier("s".to_string())),
+            vec![
+                MatchArm::new(rect, Expression::Identifier("w".to_string())),
+                MatchArm::new(Pattern::Wildcard, Expression::Int(0)),
+            ],
+        )));
+
+        if let Ast::Function(function) = &ast[1] {
+            assert_eq!(function.body.itens, vec![expected]);
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_impl_and_method_call() {
+        let input = "impl Point { fn scaled(self, float k): Point { return self; } }
+            fn f(Point p) { p.scaled(2).scaled(3); }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let ast = parser.generate_ast().unwrap();
+
+        let point = Type::UserDefined("Point".to_string());
+        let scaled = Function::new(
+            "scaled".to_string(),
+            vec![
+                Parameter::new(point.clone(), "self".to_string()),
+                Parameter::new(Type::Float, "k".to_string()),
+            ],
+            Block::new(
+                vec![Ast::Return(Some(Expression::Identifier(
+                    "self".to_string(),
+                )))],
+                HashMap::new(),
+            ),
+            Some(point),
+        );
+        assert_eq!(
+            ast[0],
+            Ast::Impl(Impl::new("Point".to_string(), vec![scaled]))
+        );
+
+        let first = Expression::MethodCall(
+            Box::new(Expression::Identifier("p".to_string())),
+            "scaled".to_string(),
+            vec![Expression::Int(2)],
+        );
+        let second = Expression::MethodCall(
+            Box::new(first),
+            "scaled".to_string(),
+            vec![Expression::Int(3)],
+        );
+        if let Ast::Function(function) = &ast[1] {
+            assert_eq!(function.body.itens, vec![Ast::Expression(second)]);
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_address_of_and_dereference() {
+        let input = "fn f(&mut int a, &int b) { *a = *b * *&mut *a as int; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let function_ast = &parser.generate_ast().unwrap()[0];
+
+        let a = Box::new(Expression::Identifier("a".to_string()));
+        let b = Box::new(Expression::Identifier("b".to_string()));
+        let reborrow = Expression::AddressOf(Box::new(Expression::Dereference(a.clone())), true);
+        let value = Expression::BinaryExpr(
+            Box::new(Expression::Dereference(b)),
+            Token::Times,
+            Box::new(Expression::Cast(
+                Box::new(Expression::Dereference(Box::new(reborrow))),
+                Type::Int,
+            )),
+        );
+        let expected = Ast::Reassignment(Reassignment::new(Expression::Dereference(a), value));
+
+        if let Ast::Function(function) = function_ast {
+            assert_eq!(
+                function.parameters,
+                vec![
+                    Parameter::new(Type::Reference(Box::new(Type::Int), true), "a".to_string()),
+                    Parameter::new(Type::Reference(Box::new(Type::Int), false), "b".to_string()),
+                ]
+            );
+            assert_eq!(function.body.itens, vec![expected]);
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_if_let_and_elif() {
+        let input =
+            "fn f(?int a): ?int { if let x = a { return x; } elif true { return none; } }\n"
+                .chars()
+                .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let function_ast = &parser.generate_ast().unwrap()[0];
+
+        let (if_node, elif) = match function_ast {
+            Ast::Function(function) => match &function.body.itens[..] {
+                [Ast::If(if_node)] => match &if_node.else_body {
+                    Some(else_body) => match &else_body.itens[..] {
+                        [Ast::If(elif)] => (if_node, elif),
+                        _ => panic!("This should be an elif!"),
+                    },
+                    None => panic!("This should have an else body!"),
+                },
+                _ => panic!("This should be an if statement!"),
+            },
+            _ => panic!("This should be a function declaration!"),
+        };
+
+        assert_eq!(
+            if_node.condition,
+            Condition::Let("x".to_string(), Expression::Identifier("a".to_string()))
+        );
+        assert_eq!(
+            if_node.body.itens,
+            vec![Ast::Return(Some(Expression::Identifier("x".to_string())))]
+        );
+        assert_eq!(
+            elif.condition,
+            Condition::Expression(Expression::Boolean(true))
+        );
+        assert_eq!(
+            elif.body.itens,
+            vec![Ast::Return(Some(Expression::NoneLit))]
+        );
+        assert_eq!(elif.else_body, None);
+    }
+
+    #[test]
+    fn test_result_type_and_try() {
+        let input = "fn f([]int!string a): int!string { int x = a? + g()?; return err(\"no\"); }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let function_ast = &parser.generate_ast().unwrap()[0];
+
+        let value = Expression::BinaryExpr(
+            Box::new(Expression::Try(Box::new(Expression::Identifier(
+                "a".to_string(),
+            )))),
+            Token::Plus,
+            Box::new(Expression::Try(Box::new(Expression::FunctionCall(
+                "g".to_string(),
+                vec![],
+            )))),
+        );
+        let error = Expression::Err(Box::new(Expression::StringLit("no".to_string())));
+
+        if let Ast::Function(function) = function_ast {
+            assert_eq!(
+                function.parameters,
+                vec![Parameter::new(
+                    Type::Result(
+                        Box::new(Type::Slice(Box::new(Type::Int))),
+                        Box::new(Type::String)
+                    ),
+                    "a".to_string()
+                )]
+            );
+            assert_eq!(
+                function.body.itens,
+                vec![
+                    Ast::Assignment(Assignment::new(Type::Int, "x".to_string(), value)),
+                    Ast::Return(Some(error)),
+                ]
+            );
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_generic_function() {
+        let input = "fn pick<T, U>(T a, []U b): T { T c = a; return c; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let function_ast = &parser.generate_ast().unwrap()[0];
+
+        let t = Type::Generic("T".to_string());
+        if let Ast::Function(function) = function_ast {
+            assert_eq!(
+                function.type_parameters,
+                vec![
+                    TypeParameter::new("T".to_string(), vec![]),
+                    TypeParameter::new("U".to_string(), vec![])
+                ]
+            );
+            assert_eq!(
+                function.parameters,
+                vec![
+                    Parameter::new(t.clone(), "a".to_string()),
+                    Parameter::new(
+                        Type::Slice(Box::new(Type::Generic("U".to_string()))),
+                        "b".to_string()
+                    ),
+                ]
+            );
+            assert_eq!(function.return_type, Some(t.clone()));
+            assert_eq!(
+                function.body.itens[0],
+                Ast::Assignment(Assignment::new(
+                    t,
+                    "c".to_string(),
+                    Expression::Identifier("a".to_string())
+                ))
+            );
+        } else {
+            panic!("This should be a function declaration!");
+        }
+
+        let input = "fn f<T, T>(T a) {}\n".chars().collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        assert!(Parser::new(tokens).generate_ast().is_err());
+    }
+
+    #[test]
+    fn test_trait_and_bounds() {
+        let input = "trait Shape { fn area(self): float; fn scaled(self, float k): Self; }
+            impl Shape for Square { fn area(self): float { return 1; } }
+            fn f<T: Shape + Named, U>(T a, U b) {}\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let ast = parser.generate_ast().unwrap();
+
+        let self_type = Type::Generic("Self".to_string());
+        let expected = Trait::new(
+            "Shape".to_string(),
+            vec![
+                Prototype::new(
+                    "area".to_string(),
+                    vec![Parameter::new(self_type.clone(), "self".to_string())],
+                    Some(Type::Float),
+                ),
+                Prototype::new(
+                    "scaled".to_string(),
+                    vec![
+                        Parameter::new(self_type.clone(), "self".to_string()),
+                        Parameter::new(Type::Float, "k".to_string()),
+                    ],
+                    Some(self_type),
+                ),
+            ],
+        );
+        assert_eq!(ast[0], Ast::Trait(expected));
+
+        if let Ast::Impl(impl_node) = &ast[1] {
+            assert_eq!(impl_node.type_name, "Square");
+            assert_eq!(impl_node.trait_name, Some("Shape".to_string()));
+        } else {
+            panic!("This should be an impl block!");
+        }
+
+        if let Ast::Function(function) = &ast[2] {
+            assert_eq!(
+                function.type_parameters,
+                vec![
+                    TypeParameter::new(
+                        "T".to_string(),
+                        vec!["Shape".to_string(), "Named".to_string()]
+                    ),
+                    TypeParameter::new("U".to_string(), vec![]),
+                ]
+            );
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_let() {
+        let input = "let g = 1; fn f() { let x = g * 2; let y: float = x; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let ast = parser.generate_ast().unwrap();
+
+        assert_eq!(
+            ast[0],
+            Ast::Assignment(Assignment::inferred("g".to_string(), Expression::Int(1)))
+        );
+        if let Ast::Function(function) = &ast[1] {
+            let value = Expression::BinaryExpr(
+                Box::new(Expression::Identifier("g".to_string())),
+                Token::Times,
+                Box::new(Expression::Int(2)),
+            );
+            assert_eq!(
+                function.body.itens,
+                vec![
+                    Ast::Assignment(Assignment::inferred("x".to_string(), value)),
+                    Ast::Assignment(Assignment::new(
+                        Type::Float,
+                        "y".to_string(),
+                        Expression::Identifier("x".to_string())
+                    )),
+                ]
+            );
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_default_parameters_and_named_arguments() {
+        let input =
+            "fn connect(string host, int port = 8080) {} fn f() { connect(\"a\", port: 9000); }\n"
+                .chars()
+                .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let ast = parser.generate_ast().unwrap();
+
+        let mut port = Parameter::new(Type::Int, "port".to_string());
+        port.default = Some(Expression::Int(8080));
+        if let Ast::Function(function) = &ast[0] {
+            assert_eq!(
+                function.parameters,
+                vec![Parameter::new(Type::String, "host".to_string()), port]
+            );
+        } else {
+            panic!("This should be a function declaration!");
+        }
+
+        if let Ast::Function(function) = &ast[1] {
+            assert_eq!(
+                function.body.itens,
+                vec![Ast::Expression(Expression::FunctionCall(
+                    "connect".to_string(),
+                    vec![
+                        Expression::StringLit("a".to_string()),
+                        Expression::NamedArgument(
+                            "port".to_string(),
+                            Box::new(Expression::Int(9000))
+                        ),
+                    ]
+                ))]
+            );
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_function_types_and_closures() {
+        let input = "fn f(fn(int, float): bool p) { fn() g = fn() {}; let h = fn(int x): int { return x; }; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let function_ast = &parser.generate_ast().unwrap()[0];
+
+        let empty = Block::new(vec![], HashMap::new());
+        let identity = Closure::new(
+            vec![Parameter::new(Type::Int, "x".to_string())],
+            Some(Type::Int),
+            Block::new(
+                vec![Ast::Return(Some(Expression::Identifier("x".to_string())))],
+                HashMap::new(),
+            ),
+        );
+        if let Ast::Function(function) = function_ast {
+            assert_eq!(
+                function.parameters,
+                vec![Parameter::new(
+                    Type::Function(vec![Type::Int, Type::Float], Box::new(Type::Bool)),
+                    "p".to_string()
+                )]
+            );
+            assert_eq!(
+                function.body.itens,
+                vec![
+                    Ast::Assignment(Assignment::new(
+                        Type::Function(vec![], Box::new(Type::Void)),
+                        "g".to_string(),
+                        Expression::Closure(Closure::new(vec![], None, empty))
+                    )),
+                    Ast::Assignment(Assignment::inferred(
+                        "h".to_string(),
+                        Expression::Closure(identity)
+                    )),
+                ]
+            );
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_imports_and_paths() {
+        let input = "import math; use math::sqrt; pub fn f(math::Point p): int { return math::dot(p, math::Point { x: 1 }) + Shape::Unit + math::Shape::Unit; }\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let ast = parser.generate_ast().unwrap();
+
+        assert_eq!(ast[0], Ast::Import(Import::new("math".to_string(), None)));
+        assert_eq!(
+            ast[1],
+            Ast::Import(Import::new("math".to_string(), Some("sqrt".to_string())))
+        );
+        if let Ast::Function(function) = &ast[2] {
+            assert!(function.public);
+            assert_eq!(
+                function.parameters[0].parameter_type,
+                Type::UserDefined("math::Point".to_string())
+            );
+            let call = Expression::FunctionCall(
+                "math::dot".to_string(),
+                vec![
+                    Expression::Identifier("p".to_string()),
+                    Expression::StructLiteral(
+                        "math::Point".to_string(),
+                        vec![("x".to_string(), Expression::Int(1))],
+                    ),
+                ],
+            );
+            let unit =
+                |name: &str| Expression::EnumVariant(name.to_string(), "Unit".to_string(), vec![]);
+            let value = Expression::BinaryExpr(
+                Box::new(Expression::BinaryExpr(
+                    Box::new(call),
+                    Token::Plus,
+                    Box::new(unit("Shape")),
+                )),
+                Token::Plus,
+                Box::new(unit("math::Shape")),
+            );
+            assert_eq!(function.body.itens, vec![Ast::Return(Some(value))]);
+        } else {
+            panic!("Expected a function, got {:?}", ast[2]);
+        }
+
+        let input = "fn f() {} import math;\n".chars().collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        assert!(Parser::new(tokens).generate_ast().is_err());
+
+        let input = "pub int x = 1;\n".chars().collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        assert!(Parser::new(tokens).generate_ast().is_err());
+    }
+
+    #[test]
+    fn test_extern() {
+        let input = "extern \"C\" fn puts(string s): int;\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let ast = Parser::new(tokens).generate_ast().unwrap();
+        assert_eq!(
+            ast,
+            vec![Ast::Extern(Extern::new(Prototype::new(
+                "puts".to_string(),
+                vec![Parameter::new(Type::String, "s".to_string())],
+                Some(Type::Int)
+            )))]
+        );
+
+        let input = "extern \"C\" fn sqrt(float x): float as \"idk_sqrt\";\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        let ast = Parser::new(tokens).generate_ast().unwrap();
+        match &ast[0] {
+            Ast::Extern(extern_node) => {
+                assert_eq!(extern_node.prototype.name, "sqrt");
+                assert_eq!(extern_node.c_name, "idk_sqrt");
+            }
+            node => panic!("Expected an extern, found {:?}", node),
+        }
+
+        let input = "extern \"C\" fn f() as \"g\";\n"
+            .chars()
+            .collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        assert!(Parser::new(tokens).generate_ast().is_ok());
+
+        let input = "extern \"C\" fn f() as g;\n".chars().collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        assert!(Parser::new(tokens).generate_ast().is_err());
+
+        let input = "extern \"C++\" fn f();\n".chars().collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        assert!(Parser::new(tokens).generate_ast().is_err());
+
+        let input = "extern \"C\" fn f() {}\n".chars().collect::<Vec<char>>();
+        let tokens = Lexer::new(input).tokenize().unwrap();
+        assert!(Parser::new(tokens).generate_ast().is_err());
+    }
 }
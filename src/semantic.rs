@@ -0,0 +1,3185 @@
+use crate::{ast::*, backend::evaluate_ast, lexer::Token, modules::RESERVED_PREFIX, monomorphize};
+
+use std::collections::{HashMap, HashSet};
+
+// Conversions the type checker applies on its own. Widening an int to a float
+// never changes the value of the expression for the numbers idk can represent
+fn is_implicit_conversion(from: &Type, to: &Type) -> bool {
+    from == to
+        || matches!((from, to), (Type::Int, Type::Float))
+        || matches!((from, to), (Type::Reference(from, true), Type::Reference(to, false)) if from == to)
+}
+
+// Conversions that are only allowed when written with `as`. Wrapping a value
+// in an optional or a result is implicit too, but it is done by `coerce`
+fn is_explicit_conversion(from: &Type, to: &Type) -> bool {
+    is_implicit_conversion(from, to)
+        || matches!(
+            (from, to),
+            (Type::Float, Type::Int) | (Type::Bool, Type::Int) | (Type::Bool, Type::Float)
+        )
+        || matches!(to, Type::Optional(value_type) | Type::Result(value_type, _)
+            if is_implicit_conversion(from, value_type))
+}
+
+// Builtins are generic over the element type of the slice they receive:
+//   len([]T | [T; n]): int
+//   push([]T, T)
+//   pop([]T): T
+const BUILTIN_FUNCTIONS: [&str; 3] = ["len", "push", "pop"];
+
+// Types extern functions can take and return: numbers, bools, strings and
+// references to numbers and bools, which become pointers
+fn is_ffi_safe(type_: &Type) -> bool {
+    match type_ {
+        Type::Int | Type::Float | Type::Bool | Type::String => true,
+        Type::Reference(referenced_type, _) => {
+            matches!(**referenced_type, Type::Int | Type::Float | Type::Bool)
+        }
+        _ => false,
+    }
+}
+
+// Global initializers end up in C, where they must be constant
+fn is_constant(expression: &Expression) -> bool {
+    match expression {
+        Expression::Int(_)
+        | Expression::Float(_)
+        | Expression::StringLit(_)
+        | Expression::Boolean(_)
+        | Expression::NoneLit => true,
+        Expression::Cast(expression, _) => is_constant(expression),
+        Expression::BinaryExpr(lhs, _, rhs) => is_constant(lhs) && is_constant(rhs),
+        Expression::Array(items) => items.iter().all(is_constant),
+        Expression::StructLiteral(_, fields) => fields.iter().all(|(_, value)| is_constant(value)),
+        Expression::EnumVariant(_, _, arguments) => arguments.iter().all(is_constant),
+        Expression::Err(error) => is_constant(error),
+        _ => false,
+    }
+}
+
+// Value passed by an argument, with or without its parameter name
+fn argument_value(argument: &mut Expression) -> &mut Expression {
+    match argument {
+        Expression::NamedArgument(_, value) => value,
+        argument => argument,
+    }
+}
+
+// Variable a place is stored in, unless the place is reached through a reference
+fn get_root_variable(place: &Expression) -> Option<&str> {
+    match place {
+        Expression::Identifier(name) => Some(name),
+        Expression::Index(base, _) | Expression::FieldAccess(base, _) => get_root_variable(base),
+        _ => None,
+    }
+}
+
+// Names the runtime and the generated code use can't be declared. Items of
+// imported modules are checked without the name of their module
+fn check_name(kind: &str, name: &str) -> Result<(), String> {
+    let item = match name.rsplit_once("__") {
+        Some((_, item)) if !name.starts_with(RESERVED_PREFIX) => item,
+        _ => name,
+    };
+    match item.starts_with(RESERVED_PREFIX) {
+        true => Err(format!(
+            "{} name '{}' is reserved, names starting with '{}' are used by generated code",
+            kind, item, RESERVED_PREFIX
+        )),
+        false => Ok(()),
+    }
+}
+
+fn is_assignable(expression: &Expression) -> bool {
+    match expression {
+        Expression::Identifier(_) | Expression::Dereference(_) => true,
+        Expression::Index(base, _) | Expression::FieldAccess(base, _) => is_assignable(base),
+        _ => false,
+    }
+}
+
+#[derive(Clone)]
+struct FunctionSignature {
+    // Unique name calls are resolved to: overloads, instances and methods
+    // get their own, externs are named after their C function
+    name: String,
+    parameters: Vec<Parameter>,
+    return_type: Type,
+}
+
+impl FunctionSignature {
+    fn from_function(function: &Function) -> Self {
+        Self {
+            name: function.name.clone(),
+            parameters: function.parameters.clone(),
+            return_type: function.return_type.clone().unwrap_or(Type::Void),
+        }
+    }
+
+    fn from_extern(extern_node: &Extern) -> Self {
+        let prototype = &extern_node.prototype;
+        Self {
+            name: extern_node.c_name.clone(),
+            parameters: prototype.parameters.clone(),
+            return_type: prototype.return_type.clone().unwrap_or(Type::Void),
+        }
+    }
+
+    // Method of a trait, with the type parameters of the trait, like `Self`,
+    // replaced by their types
+    fn from_trait_method(
+        name: String,
+        prototype: &Prototype,
+        bindings: &HashMap<String, Type>,
+    ) -> Self {
+        Self {
+            name,
+            parameters: prototype
+                .parameters
+                .iter()
+                .map(|parameter| {
+                    Parameter::new(
+                        monomorphize::substitute(&parameter.parameter_type, bindings),
+                        parameter.name.clone(),
+                    )
+                })
+                .collect(),
+            return_type: prototype
+                .return_type
+                .as_ref()
+                .map(|return_type| monomorphize::substitute(return_type, bindings))
+                .unwrap_or(Type::Void),
+        }
+    }
+
+    fn parameter_types(&self) -> Vec<Type> {
+        self.parameters
+            .iter()
+            .map(|parameter| parameter.parameter_type.clone())
+            .collect()
+    }
+
+    // sum(int, float)
+    fn describe(&self, name: &str) -> String {
+        let types: Vec<String> = self.parameter_types().iter().map(Type::to_string).collect();
+        format!("{}({})", name, types.join(", "))
+    }
+
+    // Parameter names and defaults don't change how a function is called
+    // through a trait
+    fn matches(&self, other: &FunctionSignature) -> bool {
+        self.return_type == other.return_type
+            && self.parameters.len() == other.parameters.len()
+            && self
+                .parameters
+                .iter()
+                .zip(other.parameters.iter())
+                .all(|(a, b)| a.parameter_type == b.parameter_type)
+    }
+}
+
+pub struct TypeChecker {
+    scopes: Vec<HashMap<String, Type>>,
+    // Overloads of each function, told apart by their parameter types
+    functions: HashMap<String, Vec<FunctionSignature>>,
+    // Methods of each type, by type name. Their signatures include `self`
+    methods: HashMap<String, HashMap<String, FunctionSignature>>,
+    structs: HashMap<String, Struct>,
+    enums: HashMap<String, Enum>,
+    current_function: String,
+    return_type: Type,
+    // Generic functions by name. Their bodies are checked once with their
+    // type parameters, and again for each list of type arguments they are
+    // called with
+    generics: HashMap<String, Function>,
+    // Traits bounding each type parameter of the generic function being
+    // checked
+    bounds: HashMap<String, Vec<String>>,
+    // Names of the instances, by generic function and type arguments
+    instance_names: HashMap<(String, Vec<Type>), String>,
+    // Names functions and instances get in C, and what each one is for
+    c_names: HashMap<String, String>,
+    // Instances waiting to be checked: the generic function they come from,
+    // the name they are reported as, e.g. `max<int>`, and the instance itself
+    pending_instances: Vec<(String, String, Function)>,
+    // Checked instances of each generic function, in the order they were created
+    instances: HashMap<String, Vec<Function>>,
+    traits: HashMap<String, Trait>,
+    // (trait, type) for every `impl Trait for Type`
+    implementations: HashSet<(String, String)>,
+    // Closures being checked, innermost last: the scope their parameters are
+    // declared in and the variables they capture from the scopes before it
+    closures: Vec<(usize, Vec<(String, Type)>)>,
+    // C functions declared with `extern` under their C name, which can't be
+    // overloaded
+    externs: HashSet<String>,
+    // Functions declared with `export`, which can't be overloaded either
+    exports: HashSet<String>,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            methods: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            current_function: String::new(),
+            return_type: Type::Void,
+            generics: HashMap::new(),
+            bounds: HashMap::new(),
+            instance_names: HashMap::new(),
+            c_names: HashMap::new(),
+            pending_instances: vec![],
+            closures: vec![],
+            externs: HashSet::new(),
+            exports: HashSet::new(),
+            instances: HashMap::new(),
+            traits: HashMap::new(),
+            implementations: HashSet::new(),
+        }
+    }
+
+    // C has a single scope for a function's parameters and body, so
+    // parameters can't be redeclared by the body either
+    fn declare(&mut self, name: &str, variable_type: Type) -> Result<(), String> {
+        check_name("Variable", name)?;
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(name) {
+                return Err(format!(
+                    "Variable '{}' is already declared in this scope",
+                    name
+                ));
+            }
+            scope.insert(name.to_string(), variable_type);
+        }
+        Ok(())
+    }
+
+    // Index of the scope a variable is declared in
+    fn find_scope(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+    }
+
+    // Globals are shared, every other variable declared outside of the
+    // closure being checked is captured by it
+    fn is_captured(&self, name: &str) -> bool {
+        match (self.closures.last(), self.find_scope(name)) {
+            (Some((base, _)), Some(scope)) => scope > 0 && scope < *base,
+            _ => false,
+        }
+    }
+
+    // A variable, which closures capture when it comes from outside of them
+    fn check_identifier(&mut self, name: &str) -> Result<Type, String> {
+        let scope = self
+            .find_scope(name)
+            .ok_or(format!("Use of undeclared variable '{}'", name))?;
+        let variable_type = self.scopes[scope][name].clone();
+        if scope == 0 {
+            return Ok(variable_type);
+        }
+
+        for (base, captures) in self.closures.iter_mut() {
+            if scope >= *base || captures.iter().any(|(captured, _)| captured == name) {
+                continue;
+            }
+            if let Type::Array(..) = variable_type {
+                return Err(format!(
+                    "Array '{}' can't be captured by a closure, use a slice instead",
+                    name
+                ));
+            }
+            captures.push((name.to_string(), variable_type.clone()));
+        }
+        Ok(variable_type)
+    }
+
+    // The function a name used as a value refers to, and its type
+    fn check_function_value(&self, name: &str) -> Result<(String, Type), String> {
+        if self.generics.contains_key(name) {
+            return Err(format!(
+                "Generic function '{}' can't be used as a value",
+                name
+            ));
+        }
+        match self.functions.get(name).map(Vec::as_slice) {
+            Some([signature]) => Ok((
+                signature.name.clone(),
+                Type::Function(
+                    signature.parameter_types(),
+                    Box::new(signature.return_type.clone()),
+                ),
+            )),
+            Some(_) => Err(format!(
+                "Overloaded function '{}' can't be used as a value",
+                name
+            )),
+            None => Err(format!("Use of undeclared variable '{}'", name)),
+        }
+    }
+
+    // Closures are checked like functions nested in the current one, except
+    // that they see its variables
+    fn check_closure(&mut self, closure: &mut Closure) -> Result<Type, String> {
+        let return_type = closure.return_type.clone().unwrap_or(Type::Void);
+        let parameter_types: Vec<Type> = closure
+            .parameters
+            .iter()
+            .map(|parameter| parameter.parameter_type.clone())
+            .collect();
+        let closure_type = Type::Function(parameter_types, Box::new(return_type.clone()));
+        self.check_type(&closure_type)?;
+        if closure
+            .parameters
+            .iter()
+            .any(|parameter| parameter.default.is_some())
+        {
+            return Err("Parameters of closures can't have default values".to_string());
+        }
+
+        let function = format!("closure in {}", self.current_function);
+        let function = std::mem::replace(&mut self.current_function, function);
+        let outer_return_type = std::mem::replace(&mut self.return_type, return_type);
+        self.closures.push((self.scopes.len(), vec![]));
+        self.scopes.push(HashMap::new());
+        let result = closure
+            .parameters
+            .iter()
+            .try_for_each(|parameter| {
+                self.declare(&parameter.name, parameter.parameter_type.clone())
+            })
+            .and_then(|_| self.check_statements(&mut closure.body));
+        self.scopes.pop();
+        let (_, captures) = self.closures.pop().unwrap_or_default();
+        self.current_function = function;
+        self.return_type = outer_return_type;
+
+        result?;
+        closure.captures = captures;
+        Ok(closure_type)
+    }
+
+    // Calls a function stored in a variable
+    fn check_value_call(
+        &mut self,
+        name: &str,
+        arguments: &mut [Expression],
+    ) -> Result<Type, String> {
+        let (parameter_types, return_type) = match self.check_identifier(name)? {
+            Type::Function(parameter_types, return_type) => (parameter_types, *return_type),
+            t => return Err(format!("Variable '{}' of type {} can't be called", name, t)),
+        };
+        self.check_arity(name, arguments, parameter_types.len())?;
+        for (position, (argument, parameter_type)) in
+            arguments.iter_mut().zip(parameter_types.iter()).enumerate()
+        {
+            let context = format!(
+                "Mismatched types on argument {} of call to '{}'",
+                position + 1,
+                name
+            );
+            self.check_expression_as(argument, parameter_type, &context)?;
+        }
+        Ok(return_type)
+    }
+
+    // Explains why `found` can't be used where `expected` is required and,
+    // when an explicit cast would be accepted, suggests it
+    fn describe_mismatch(&self, expression: &Expression, found: &Type, expected: &Type) -> String {
+        let mut message = format!("expected {}, found {}", expected, found);
+
+        if !is_explicit_conversion(found, expected) {
+            return message;
+        }
+
+        match (found, expected) {
+            (Type::Float, Type::Int) => {
+                message += "\n  converting float to int is lossy: the fractional part is truncated";
+                if let Ok(value) = evaluate_ast(expression.clone()) {
+                    message += &format!(" ({} becomes {})", value, value.trunc());
+                }
+            }
+            (Type::Bool, _) => {
+                message += &format!("\n  bool is not implicitly converted to {}", expected);
+            }
+            _ => {}
+        }
+
+        let cast = Expression::Cast(Box::new(expression.clone()), expected.clone());
+        message + &format!("\n  help: use an explicit cast: `{}`", cast)
+    }
+
+    // Checks that `expression` (of type `found`) can be used as `expected`,
+    // making any implicit conversion explicit in the tree
+    fn coerce(
+        &self,
+        expression: &mut Expression,
+        found: &Type,
+        expected: &Type,
+    ) -> Result<(), String> {
+        if found == expected {
+            return Ok(());
+        }
+
+        // Values are wrapped when an optional or a result is expected
+        let value_type = match (expected, found) {
+            (Type::Optional(_), Type::Optional(_)) | (Type::Result(..), Type::Result(..)) => None,
+            (Type::Optional(value_type), _) | (Type::Result(value_type, _), _) => Some(value_type),
+            _ => None,
+        };
+        if let Some(value_type) = value_type {
+            self.coerce(expression, found, value_type)
+                .map_err(|_| format!("expected {}, found {}", expected, found))?;
+            *expression = Expression::Cast(Box::new(expression.clone()), expected.clone());
+            return Ok(());
+        }
+
+        // Array literals are converted element by element
+        if let (Expression::Array(items), Type::Array(found_element, found_length)) =
+            (&mut *expression, found)
+        {
+            let expected_element = match expected {
+                Type::Array(expected_element, expected_length) => {
+                    if found_length != expected_length {
+                        return Err(format!(
+                            "expected an array of {} elements, found {} elements",
+                            expected_length, found_length
+                        ));
+                    }
+                    expected_element
+                }
+                Type::Slice(expected_element) => expected_element,
+                _ => return Err(format!("expected {}, found {}", expected, found)),
+            };
+            for item in items.iter_mut() {
+                self.coerce(item, found_element, expected_element)?;
+            }
+            if let Type::Slice(_) = expected {
+                *expression = Expression::Cast(Box::new(expression.clone()), expected.clone());
+            }
+            return Ok(());
+        }
+
+        if !is_implicit_conversion(found, expected) {
+            return Err(self.describe_mismatch(expression, found, expected));
+        }
+
+        let converted = match expression {
+            Expression::Int(value) => Expression::Float(f64::from(*value)),
+            _ => Expression::Cast(Box::new(expression.clone()), expected.clone()),
+        };
+        *expression = converted;
+        Ok(())
+    }
+
+    // Checks `expression` against the type the context expects, describing the
+    // context on mismatches. Empty array literals are only valid here, since
+    // their type comes from the context
+    fn check_expression_as(
+        &mut self,
+        expression: &mut Expression,
+        expected: &Type,
+        context: &str,
+    ) -> Result<(), String> {
+        if let (Expression::Array(items), Type::Slice(_)) = (&*expression, expected) {
+            if items.is_empty() {
+                *expression = Expression::Cast(Box::new(expression.clone()), expected.clone());
+                return Ok(());
+            }
+        }
+        if let (Expression::NoneLit, Type::Optional(_)) = (&*expression, expected) {
+            *expression = Expression::Cast(Box::new(Expression::NoneLit), expected.clone());
+            return Ok(());
+        }
+        if let (Expression::Err(error), Type::Result(_, error_type)) = (&mut *expression, expected)
+        {
+            self.check_expression_as(error, error_type, context)?;
+            *expression = Expression::Cast(Box::new(expression.clone()), expected.clone());
+            return Ok(());
+        }
+
+        let found = self.check_expression(expression)?;
+        self.coerce(expression, &found, expected)
+            .map_err(|reason| format!("{}: {}", context, reason))
+    }
+
+    fn check_type(&self, type_: &Type) -> Result<(), String> {
+        match type_ {
+            Type::Slice(element_type) if matches!(**element_type, Type::Array(..)) => {
+                Err(format!("Slices of arrays are not supported: {}", type_))
+            }
+            Type::Slice(element_type) | Type::Array(element_type, _) => {
+                self.check_type(element_type)
+            }
+            Type::Reference(referenced_type, _) if matches!(**referenced_type, Type::Array(..)) => {
+                Err(format!(
+                    "References to arrays are not supported, use a slice instead: {}",
+                    type_
+                ))
+            }
+            Type::Reference(referenced_type, _) => self.check_type(referenced_type),
+            Type::Optional(value_type) => match **value_type {
+                Type::Optional(_) => Err(format!(
+                    "Nested optional types are not supported: {}",
+                    type_
+                )),
+                Type::Array(..) => Err(format!(
+                    "Optional arrays are not supported, use a slice instead: {}",
+                    type_
+                )),
+                _ => self.check_type(value_type),
+            },
+            Type::Result(value_type, error_type) => {
+                if matches!(**value_type, Type::Array(..))
+                    || matches!(**error_type, Type::Array(..))
+                {
+                    return Err(format!(
+                        "Results can't hold arrays, use a slice instead: {}",
+                        type_
+                    ));
+                }
+                self.check_type(value_type)?;
+                self.check_type(error_type)
+            }
+            Type::UserDefined(name)
+                if !self.structs.contains_key(name) && !self.enums.contains_key(name) =>
+            {
+                Err(format!("Unknown type '{}'", name))
+            }
+            Type::Function(parameter_types, return_type) => {
+                for parameter_type in parameter_types.iter() {
+                    if let Type::Array(..) = parameter_type {
+                        return Err(format!(
+                            "Function types can't take arrays, use a slice instead: {}",
+                            type_
+                        ));
+                    }
+                    self.check_type(parameter_type)?;
+                }
+                if let Type::Array(..) = **return_type {
+                    return Err(format!(
+                        "Function types can't return arrays, use a slice instead: {}",
+                        type_
+                    ));
+                }
+                self.check_type(return_type)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Every field must be given exactly once, in any order
+    fn check_struct_literal(
+        &mut self,
+        name: &str,
+        fields: &mut [(String, Expression)],
+    ) -> Result<Type, String> {
+        let declaration = match self.structs.get(name) {
+            Some(declaration) => declaration.clone(),
+            None => return Err(format!("Unknown struct '{}'", name)),
+        };
+
+        for (position, (field_name, _)) in fields.iter().enumerate() {
+            if fields[..position]
+                .iter()
+                .any(|(other, _)| other == field_name)
+            {
+                return Err(format!(
+                    "Field '{}' of '{}' is initialized more than once",
+                    field_name, name
+                ));
+            }
+        }
+
+        for (field_name, value) in fields.iter_mut() {
+            let field = match declaration.get_field(field_name) {
+                Some(field) => field,
+                None => {
+                    return Err(format!(
+                        "Struct '{}' has no field named '{}'",
+                        name, field_name
+                    ))
+                }
+            };
+            let context = format!("Mismatched types on field '{}' of '{}'", field_name, name);
+            self.check_expression_as(value, &field.field_type, &context)?;
+        }
+
+        let missing: Vec<&str> = declaration
+            .fields
+            .iter()
+            .filter(|field| {
+                !fields
+                    .iter()
+                    .any(|(field_name, _)| *field_name == field.name)
+            })
+            .map(|field| field.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Missing fields on struct literal of '{}': {}",
+                name,
+                missing.join(", ")
+            ));
+        }
+        Ok(Type::UserDefined(name.to_string()))
+    }
+
+    fn get_variant(&self, name: &str, variant: &str) -> Result<Variant, String> {
+        match self.enums.get(name) {
+            Some(enum_node) => enum_node.get_variant(variant).cloned().ok_or(format!(
+                "Enum '{}' has no variant named '{}'",
+                name, variant
+            )),
+            None => Err(format!("Unknown enum '{}'", name)),
+        }
+    }
+
+    fn check_enum_variant(
+        &mut self,
+        name: &str,
+        variant: &str,
+        arguments: &mut [Expression],
+    ) -> Result<Type, String> {
+        let declaration = self.get_variant(name, variant)?;
+        if arguments.len() != declaration.fields.len() {
+            return Err(format!(
+                "Variant '{}::{}' takes {} values but {} were given",
+                name,
+                variant,
+                declaration.fields.len(),
+                arguments.len()
+            ));
+        }
+
+        for (position, (argument, field_type)) in arguments
+            .iter_mut()
+            .zip(declaration.fields.iter())
+            .enumerate()
+        {
+            let context = format!(
+                "Mismatched types on value {} of '{}::{}'",
+                position + 1,
+                name,
+                variant
+            );
+            self.check_expression_as(argument, field_type, &context)?;
+        }
+        Ok(Type::UserDefined(name.to_string()))
+    }
+
+    // Declares the values a pattern binds, rejecting arms that can never be
+    // reached because earlier arms already cover them
+    fn check_pattern(
+        &mut self,
+        pattern: &Pattern,
+        name: &str,
+        covered: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let variants = match self.enums.get(name) {
+            Some(enum_node) => enum_node.variants.clone(),
+            None => return Err(format!("Unknown enum '{}'", name)),
+        };
+        if covered.len() == variants.len() {
+            return Err(format!("Unreachable match arm `{}`", pattern));
+        }
+
+        match pattern {
+            Pattern::Wildcard => {
+                *covered = variants.into_iter().map(|variant| variant.name).collect();
+                Ok(())
+            }
+            Pattern::Variant(pattern_name, variant, bindings) => {
+                if pattern_name != name {
+                    return Err(format!(
+                        "Pattern `{}` doesn't match a value of type {}",
+                        pattern, name
+                    ));
+                }
+                let declaration = self.get_variant(name, variant)?;
+                if bindings.len() != declaration.fields.len() {
+                    return Err(format!(
+                        "Pattern `{}` binds {} values but '{}::{}' has {}",
+                        pattern,
+                        bindings.len(),
+                        name,
+                        variant,
+                        declaration.fields.len()
+                    ));
+                }
+                if covered.contains(variant) {
+                    return Err(format!("Unreachable match arm `{}`", pattern));
+                }
+                covered.push(variant.clone());
+
+                for (binding, field_type) in bindings.iter().zip(declaration.fields.iter()) {
+                    if binding != "_" {
+                        self.declare(binding, field_type.clone())?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Every variant must be handled by an arm. Arms are widened to a common
+    // type the same way elements of an array literal are
+    fn check_match(
+        &mut self,
+        subject: &mut Expression,
+        arms: &mut [MatchArm],
+    ) -> Result<Type, String> {
+        let name = match self.check_expression(subject)? {
+            Type::UserDefined(name) if self.enums.contains_key(&name) => name,
+            t => return Err(format!("Can't match on `{}` of type {}", subject, t)),
+        };
+
+        let mut covered: Vec<String> = vec![];
+        let mut arm_types: Vec<Type> = vec![];
+        for arm in arms.iter_mut() {
+            self.scopes.push(HashMap::new());
+            let arm_type = self
+                .check_pattern(&arm.pattern, &name, &mut covered)
+                .and_then(|_| self.check_expression(&mut arm.value));
+            self.scopes.pop();
+            arm_types.push(arm_type?);
+        }
+
+        let missing: Vec<String> = self.enums[&name]
+            .variants
+            .iter()
+            .filter(|variant| !covered.contains(&variant.name))
+            .map(|variant| format!("{}::{}", name, variant.name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Non-exhaustive match on `{}`: {} not covered",
+                subject,
+                missing.join(", ")
+            ));
+        }
+
+        let mut match_type = arm_types[0].clone();
+        for arm_type in arm_types.iter() {
+            if *arm_type != match_type && is_implicit_conversion(&match_type, arm_type) {
+                match_type = arm_type.clone();
+            }
+        }
+        if let Type::Array(..) = match_type {
+            return Err(format!(
+                "Match on `{}` can't produce an array, found {}",
+                subject, match_type
+            ));
+        }
+        for (arm, arm_type) in arms.iter_mut().zip(arm_types.iter()) {
+            self.coerce(&mut arm.value, arm_type, &match_type)
+                .map_err(|reason| {
+                    format!("Mismatched types on arm `{}`: {}", arm.pattern, reason)
+                })?;
+        }
+        Ok(match_type)
+    }
+
+    fn check_field_access(&mut self, base: &mut Expression, field: &str) -> Result<Type, String> {
+        let name = match self.check_expression(base)?.dereferenced() {
+            Type::UserDefined(name) => name.clone(),
+            t => {
+                return Err(format!(
+                    "Type {} has no fields, found `{}.{}`",
+                    t, base, field
+                ))
+            }
+        };
+
+        match self.structs.get(&name).and_then(|s| s.get_field(field)) {
+            Some(field) => Ok(field.field_type.clone()),
+            None => Err(format!("Struct '{}' has no field named '{}'", name, field)),
+        }
+    }
+
+    fn check_binary_expression(
+        &self,
+        lhs: &mut Expression,
+        operator: &Token,
+        rhs: &mut Expression,
+        lhs_type: Type,
+        rhs_type: Type,
+    ) -> Result<Type, String> {
+        match (&lhs_type, &rhs_type) {
+            // Constant operands that would fail at runtime are errors already
+            (Type::Int, Type::Int) => match (operator, evaluate_ast(rhs.clone())) {
+                (Token::Divides | Token::Mod, Ok(0.0)) => {
+                    Err(format!("Division by zero: `{}` is 0", rhs))
+                }
+                (Token::Power, Ok(value)) if value < 0.0 => Err(format!(
+                    "Negative exponent: `{}` is {}, integer powers need a float base",
+                    rhs, value
+                )),
+                _ => Ok(Type::Int),
+            },
+            (Type::Float, Type::Float) => Ok(Type::Float),
+            (Type::Int, Type::Float) => {
+                self.coerce(lhs, &lhs_type, &Type::Float)?;
+                Ok(Type::Float)
+            }
+            (Type::Float, Type::Int) => {
+                self.coerce(rhs, &rhs_type, &Type::Float)?;
+                Ok(Type::Float)
+            }
+            // Operators on type parameters are checked for each instance, so
+            // generic functions can compute with any number
+            (Type::Generic(_), Type::Generic(_) | Type::Int | Type::Float) => Ok(lhs_type),
+            (Type::Int | Type::Float, Type::Generic(_)) => Ok(rhs_type),
+            _ => Err(format!(
+                "Operator '{}' can't be applied to {} and {}",
+                operator, lhs_type, rhs_type
+            )),
+        }
+    }
+
+    fn check_array_literal(&mut self, items: &mut [Expression]) -> Result<Type, String> {
+        let mut item_types: Vec<Type> = vec![];
+        for item in items.iter_mut() {
+            item_types.push(self.check_expression(item)?);
+        }
+
+        // Mixing ints and floats widens every element to float
+        let mut element_type = match item_types.first() {
+            Some(item_type) => item_type.clone(),
+            None => return Err("Can't infer the type of an empty array literal".to_string()),
+        };
+        for item_type in item_types.iter() {
+            if *item_type != element_type && is_implicit_conversion(&element_type, item_type) {
+                element_type = item_type.clone();
+            }
+        }
+
+        for (item, item_type) in items.iter_mut().zip(item_types.iter()) {
+            self.coerce(item, item_type, &element_type)
+                .map_err(|reason| format!("Mismatched types on array literal: {}", reason))?;
+        }
+        Ok(Type::Array(Box::new(element_type), items.len()))
+    }
+
+    fn check_index(
+        &mut self,
+        array: &mut Expression,
+        index: &mut Expression,
+    ) -> Result<Type, String> {
+        let (element_type, length) = match self.check_expression(array)? {
+            Type::Array(element_type, length) => (*element_type, Some(length)),
+            Type::Slice(element_type) => (*element_type, None),
+            t => return Err(format!("Type {} can't be indexed in `{}`", t, array)),
+        };
+
+        let index_type = self.check_expression(index)?;
+        if index_type != Type::Int {
+            return Err(format!(
+                "Array index must be an int, found {} in `{}[{}]`",
+                index_type, array, index
+            ));
+        }
+
+        // Constant indexes into arrays are checked right away
+        if let (Some(length), Ok(value)) = (length, evaluate_ast(index.clone())) {
+            if value < 0.0 || value >= length as f64 {
+                return Err(format!(
+                    "Index out of bounds: the length of `{}` is {} but the index is {}",
+                    array, length, value
+                ));
+            }
+        }
+        Ok(element_type)
+    }
+
+    fn check_arity(
+        &self,
+        name: &str,
+        arguments: &[Expression],
+        expected: usize,
+    ) -> Result<(), String> {
+        if arguments.len() != expected {
+            return Err(format!(
+                "Function '{}' takes {} arguments but {} were given",
+                name,
+                expected,
+                arguments.len()
+            ));
+        }
+        Ok(())
+    }
+
+    // Element type of the slice a builtin mutates. It must be assignable,
+    // otherwise the change would be lost
+    // Finds the shared reference, if any, a place is reached through
+    fn find_shared_reference(&mut self, place: &Expression) -> Result<Option<Type>, String> {
+        match place {
+            Expression::Dereference(base) | Expression::FieldAccess(base, _) => {
+                match self.check_expression(&mut base.as_ref().clone())? {
+                    reference @ Type::Reference(_, false) => Ok(Some(reference)),
+                    Type::Reference(_, true) => Ok(None),
+                    _ => self.find_shared_reference(base),
+                }
+            }
+            Expression::Index(base, _) => self.find_shared_reference(base),
+            _ => Ok(None),
+        }
+    }
+
+    // Places behind a shared reference can be read but not changed
+    fn check_mutable(&mut self, place: &Expression, action: &str) -> Result<(), String> {
+        match self.find_shared_reference(place)? {
+            Some(reference) => Err(format!(
+                "Can't {} `{}`, it is behind a shared reference of type {}",
+                action, place, reference
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn check_address_of(&mut self, place: &mut Expression, mutable: bool) -> Result<Type, String> {
+        if !is_assignable(place) {
+            return Err(format!(
+                "Can't take a reference to `{}`, store it in a variable first",
+                place
+            ));
+        }
+
+        let place_type = self.check_expression(place)?;
+        if mutable {
+            self.check_mutable(place, "borrow as mutable")?;
+        }
+        let reference = Type::Reference(Box::new(place_type), mutable);
+        self.check_type(&reference)?;
+        Ok(reference)
+    }
+
+    fn check_slice_argument(&mut self, name: &str, slice: &mut Expression) -> Result<Type, String> {
+        match self.check_expression(slice)? {
+            Type::Slice(element_type) if is_assignable(slice) => {
+                self.check_mutable(slice, "modify")?;
+                Ok(*element_type)
+            }
+            Type::Slice(_) => Err(format!(
+                "Function '{}' expects a variable or an element as its slice, found `{}`",
+                name, slice
+            )),
+            t => Err(format!("Function '{}' expects a slice, found {}", name, t)),
+        }
+    }
+
+    fn check_builtin_call(
+        &mut self,
+        name: &str,
+        arguments: &mut [Expression],
+    ) -> Result<Type, String> {
+        match name {
+            "len" => {
+                self.check_arity(name, arguments, 1)?;
+                match self.check_expression(&mut arguments[0])? {
+                    Type::Slice(_) | Type::Array(..) | Type::String => Ok(Type::Int),
+                    t => Err(format!(
+                        "Function 'len' expects a slice, an array or a string, found {}",
+                        t
+                    )),
+                }
+            }
+            "push" => {
+                self.check_arity(name, arguments, 2)?;
+                let element_type = self.check_slice_argument(name, &mut arguments[0])?;
+                self.check_expression_as(
+                    &mut arguments[1],
+                    &element_type,
+                    "Mismatched types on call to 'push'",
+                )?;
+                Ok(Type::Void)
+            }
+            "pop" => {
+                self.check_arity(name, arguments, 1)?;
+                self.check_slice_argument(name, &mut arguments[0])
+            }
+            _ => Err(format!("Unknown builtin function '{}'", name)),
+        }
+    }
+
+    // Resolves a call to the function or overload it refers to
+    fn check_function_call(
+        &mut self,
+        name: &str,
+        arguments: &mut [Expression],
+    ) -> Result<(Expression, Type), String> {
+        let signature = match self.functions.get(name).cloned() {
+            Some(mut overloads) if overloads.len() == 1 => overloads.remove(0),
+            Some(overloads) => self.resolve_overload(name, arguments, overloads)?,
+            None => return Err(format!("Call to undeclared function '{}'", name)),
+        };
+
+        let arguments = self.check_arguments(name, arguments, &signature.parameters)?;
+        Ok((
+            Expression::Call(signature.name, arguments),
+            signature.return_type,
+        ))
+    }
+
+    // Signature of a function calls were already resolved to
+    fn get_resolved(&self, name: &str) -> Result<&FunctionSignature, String> {
+        self.functions
+            .values()
+            .flatten()
+            .chain(self.methods.values().flat_map(HashMap::values))
+            .find(|signature| signature.name == name)
+            .ok_or(format!("Unknown function '{}'", name))
+    }
+
+    // Picks the overload a call refers to by its arguments. The ones that take
+    // every argument as it is win over the ones that need conversions, and
+    // anything but a single winner is an error
+    fn resolve_overload(
+        &mut self,
+        name: &str,
+        arguments: &[Expression],
+        overloads: Vec<FunctionSignature>,
+    ) -> Result<FunctionSignature, String> {
+        let mut values: Vec<(Expression, Type)> = vec![];
+        for argument in arguments.iter() {
+            let mut argument = argument.clone();
+            let value = argument_value(&mut argument);
+            let argument_type = self.check_expression(value)?;
+            values.push((value.clone(), argument_type));
+        }
+
+        let mut exact: Vec<FunctionSignature> = vec![];
+        let mut converted: Vec<FunctionSignature> = vec![];
+        for signature in overloads.into_iter() {
+            let bound = match bind_arguments(name, &signature.parameters, arguments) {
+                Ok(bound) => bound,
+                Err(_) => continue,
+            };
+            let (mut is_exact, mut fits) = (true, true);
+            for (position, parameter) in bound.into_iter().zip(signature.parameters.iter()) {
+                if let Some(position) = position {
+                    let (value, found) = &values[position];
+                    if *found != parameter.parameter_type {
+                        is_exact = false;
+                        fits &= self
+                            .coerce(&mut value.clone(), found, &parameter.parameter_type)
+                            .is_ok();
+                    }
+                }
+            }
+            if is_exact {
+                exact.push(signature);
+            } else if fits {
+                converted.push(signature);
+            }
+        }
+
+        let mut candidates = if exact.is_empty() { converted } else { exact };
+        match candidates.len() {
+            1 => Ok(candidates.remove(0)),
+            0 => {
+                let types: Vec<String> = values.iter().map(|(_, t)| t.to_string()).collect();
+                Err(format!(
+                    "No overload of function '{}' takes arguments ({})",
+                    name,
+                    types.join(", ")
+                ))
+            }
+            _ => {
+                let candidates: Vec<String> = candidates
+                    .iter()
+                    .map(|signature| signature.describe(name))
+                    .collect();
+                Err(format!(
+                    "Call to function '{}' is ambiguous between {}",
+                    name,
+                    candidates.join(" and ")
+                ))
+            }
+        }
+    }
+
+    // Infers the type arguments of a call to a generic function from the types
+    // of its arguments, and creates the instance for them if it doesn't exist
+    // yet. Returns the name of the instance
+    fn instantiate(&mut self, name: &str, arguments: &mut [Expression]) -> Result<String, String> {
+        let generic = self.generics[name].clone();
+        let bound = bind_arguments(name, &generic.parameters, arguments)?;
+
+        let mut bindings: HashMap<String, Type> = HashMap::new();
+        for (position, parameter) in bound.into_iter().zip(generic.parameters.iter()) {
+            let argument = match position {
+                Some(position) => argument_value(&mut arguments[position]),
+                None => continue,
+            };
+            if monomorphize::type_parameters(&parameter.parameter_type).is_empty() {
+                continue;
+            }
+            let argument_type = self.check_expression(argument)?;
+            monomorphize::infer(&parameter.parameter_type, &argument_type, &mut bindings).map_err(
+                |(type_parameter, first, second)| {
+                    format!(
+                        "Conflicting types for type parameter {} of function '{}': {} and {}",
+                        type_parameter, name, first, second
+                    )
+                },
+            )?;
+        }
+
+        let mut type_arguments: Vec<Type> = vec![];
+        for type_parameter in generic.type_parameters.iter() {
+            let type_argument = match bindings.get(&type_parameter.name) {
+                Some(Type::Void) | None => {
+                    return Err(format!(
+                        "Can't infer type parameter {} of function '{}'",
+                        type_parameter.name, name
+                    ))
+                }
+                Some(type_argument) => type_argument.clone(),
+            };
+            for bound in type_parameter.bounds.iter() {
+                // A type parameter of the generic function being checked meets
+                // the bounds it is declared with
+                let implemented = match &type_argument {
+                    Type::Generic(name) => self
+                        .bounds
+                        .get(name)
+                        .is_some_and(|bounds| bounds.contains(bound)),
+                    _ => self
+                        .implementations
+                        .contains(&(bound.clone(), type_argument.to_string())),
+                };
+                if !implemented {
+                    return Err(format!(
+                        "Type {} doesn't implement trait {}, required by type parameter {} of function '{}'",
+                        type_argument, bound, type_parameter.name, name
+                    ));
+                }
+            }
+            type_arguments.push(type_argument);
+        }
+
+        let key = (name.to_string(), type_arguments.clone());
+        if let Some(instance_name) = self.instance_names.get(&key) {
+            return Ok(instance_name.clone());
+        }
+        let instance_name = monomorphize::instance_name(name, &type_arguments);
+        if self.c_names.contains_key(&instance_name) {
+            return Err(format!(
+                "Function '{}' conflicts with an instance of generic function '{}'",
+                instance_name, name
+            ));
+        }
+
+        let instance = monomorphize::specialize(&generic, &type_arguments);
+        let type_arguments: Vec<String> = type_arguments.iter().map(Type::to_string).collect();
+        let description = format!("{}<{}>", name, type_arguments.join(", "));
+        self.functions.insert(
+            instance_name.clone(),
+            vec![FunctionSignature::from_function(&instance)],
+        );
+        self.instance_names.insert(key, instance_name.clone());
+        self.c_names
+            .insert(instance_name.clone(), description.clone());
+        self.pending_instances
+            .push((name.to_string(), description, instance));
+        Ok(instance_name)
+    }
+
+    // Arguments in the order of the parameters. The ones left out take the
+    // default of their parameter, filled in at the call site
+    fn check_arguments(
+        &mut self,
+        name: &str,
+        arguments: &mut [Expression],
+        parameters: &[Parameter],
+    ) -> Result<Vec<Expression>, String> {
+        let bound = bind_arguments(name, parameters, arguments)?;
+        let mut values: Vec<Expression> = vec![];
+        for (index, (position, parameter)) in bound.into_iter().zip(parameters.iter()).enumerate() {
+            let position = match (position, &parameter.default) {
+                (Some(position), _) => position,
+                (None, Some(default)) => {
+                    let mut default = default.clone();
+                    let context = format!(
+                        "Mismatched types on default value of parameter '{}'",
+                        parameter.name
+                    );
+                    self.check_expression_as(&mut default, &parameter.parameter_type, &context)?;
+                    values.push(default);
+                    continue;
+                }
+                (None, None) => {
+                    return Err(format!(
+                        "Missing argument for parameter '{}' of function '{}'",
+                        parameter.name, name
+                    ))
+                }
+            };
+            let context = match &arguments[position] {
+                Expression::NamedArgument(parameter, _) => format!(
+                    "Mismatched types on argument '{}' of call to '{}'",
+                    parameter, name
+                ),
+                _ => format!(
+                    "Mismatched types on argument {} of call to '{}'",
+                    index + 1,
+                    name
+                ),
+            };
+            let argument = argument_value(&mut arguments[position]);
+            self.check_expression_as(argument, &parameter.parameter_type, &context)?;
+            values.push(argument.clone());
+        }
+        Ok(values)
+    }
+
+    // Values of a type parameter only have the methods of the traits bounding
+    // it, with `Self` standing for the type parameter
+    fn get_bound_method(&self, type_parameter: &str, method: &str) -> Option<FunctionSignature> {
+        let bindings = HashMap::from([(
+            "Self".to_string(),
+            Type::Generic(type_parameter.to_string()),
+        )]);
+        self.bounds.get(type_parameter)?.iter().find_map(|bound| {
+            let prototype = self.traits.get(bound)?.get_method(method)?;
+            Some(FunctionSignature::from_trait_method(
+                mangle_method(type_parameter, method),
+                prototype,
+                &bindings,
+            ))
+        })
+    }
+
+    // Methods are found by the type of the value they are called on, and
+    // called like a function taking the receiver by value first
+    fn check_method_call(
+        &mut self,
+        receiver: &mut Expression,
+        method: &str,
+        arguments: &mut [Expression],
+    ) -> Result<(Expression, Type), String> {
+        let found = self.check_expression(receiver)?;
+        let receiver_type = found.dereferenced().clone();
+        let signature = match &receiver_type {
+            Type::UserDefined(name) => self
+                .methods
+                .get(name)
+                .and_then(|methods| methods.get(method))
+                .cloned(),
+            Type::Generic(name) => self.get_bound_method(name, method),
+            _ => None,
+        };
+        let (method_name, parameters, return_type) = match signature {
+            Some(signature) => (
+                signature.name.clone(),
+                signature.parameters[1..].to_vec(),
+                signature.return_type.clone(),
+            ),
+            None => {
+                return Err(format!(
+                    "Type {} has no method named '{}'",
+                    receiver_type, method
+                ))
+            }
+        };
+
+        let name = format!("{}.{}", receiver_type, method);
+        let mut values = vec![match found {
+            Type::Reference(..) => Expression::Dereference(Box::new(receiver.clone())),
+            _ => receiver.clone(),
+        }];
+        values.extend(self.check_arguments(&name, arguments, &parameters)?);
+        Ok((Expression::Call(method_name, values), return_type))
+    }
+
+    fn check_expression(&mut self, expression: &mut Expression) -> Result<Type, String> {
+        match expression {
+            Expression::Int(_) => Ok(Type::Int),
+            Expression::Float(_) => Ok(Type::Float),
+            Expression::StringLit(_) => Ok(Type::String),
+            Expression::Boolean(_) => Ok(Type::Bool),
+            Expression::NoneLit => Err(
+                "Can't infer the type of `none`, use it where an optional is expected".to_string(),
+            ),
+            Expression::Identifier(name) if self.find_scope(name).is_some() => {
+                let name = name.clone();
+                self.check_identifier(&name)
+            }
+
+            // Functions used as values are resolved like calls
+            Expression::Identifier(name) => {
+                let (function, function_type) = self.check_function_value(name)?;
+                *expression = Expression::Function(function);
+                Ok(function_type)
+            }
+
+            // Arguments of calls to generic functions are checked again once
+            // the instance is known
+            Expression::Call(name, _) => Ok(self.get_resolved(name)?.return_type.clone()),
+
+            Expression::Function(name) => {
+                let signature = self.get_resolved(name)?;
+                Ok(Type::Function(
+                    signature.parameter_types(),
+                    Box::new(signature.return_type.clone()),
+                ))
+            }
+
+            Expression::Closure(closure) => self.check_closure(closure),
+
+            Expression::BinaryExpr(lhs, operator, rhs) => {
+                let lhs_type = self.check_expression(lhs)?;
+                let rhs_type = self.check_expression(rhs)?;
+                self.check_binary_expression(lhs, operator, rhs, lhs_type, rhs_type)
+            }
+
+            Expression::Cast(inner, target_type @ Type::Optional(_))
+                if **inner == Expression::NoneLit =>
+            {
+                Ok(target_type.clone())
+            }
+
+            Expression::Cast(inner, target_type @ Type::Result(..))
+                if matches!(**inner, Expression::Err(_)) =>
+            {
+                if let (Expression::Err(error), Type::Result(_, error_type)) =
+                    (&mut **inner, &*target_type)
+                {
+                    self.check_expression_as(error, error_type, "Mismatched types on err")?;
+                }
+                Ok(target_type.clone())
+            }
+
+            Expression::Cast(inner, target_type) => {
+                let inner_type = self.check_expression(inner)?;
+                if !is_explicit_conversion(&inner_type, target_type) {
+                    return Err(format!(
+                        "Invalid cast from {} to {} in `{} as {}`",
+                        inner_type, target_type, inner, target_type
+                    ));
+                }
+                Ok(target_type.clone())
+            }
+
+            Expression::Array(items) => self.check_array_literal(items),
+
+            Expression::Index(array, index) => self.check_index(array, index),
+
+            // Variables hide functions with the same name
+            Expression::FunctionCall(name, arguments) if self.find_scope(name).is_some() => {
+                let name = name.clone();
+                self.check_value_call(&name, arguments)
+            }
+
+            Expression::FunctionCall(name, arguments)
+                if BUILTIN_FUNCTIONS.contains(&name.as_str()) =>
+            {
+                let name = name.clone();
+                self.check_builtin_call(&name, arguments)
+            }
+
+            // Calls to a generic function call its instance instead
+            Expression::FunctionCall(name, arguments) => {
+                if self.generics.contains_key(name.as_str()) {
+                    *name = self.instantiate(name, arguments)?;
+                }
+                let name = name.clone();
+                let (call, return_type) = self.check_function_call(&name, arguments)?;
+                *expression = call;
+                Ok(return_type)
+            }
+
+            Expression::StructLiteral(name, fields) => {
+                let name = name.clone();
+                self.check_struct_literal(&name, fields)
+            }
+
+            Expression::FieldAccess(base, field) => {
+                let field = field.clone();
+                self.check_field_access(base, &field)
+            }
+
+            Expression::EnumVariant(name, variant, arguments) => {
+                let (name, variant) = (name.clone(), variant.clone());
+                self.check_enum_variant(&name, &variant, arguments)
+            }
+
+            Expression::Match(subject, arms) => self.check_match(subject, arms),
+
+            Expression::MethodCall(receiver, method, arguments) => {
+                let method = method.clone();
+                let (call, return_type) = self.check_method_call(receiver, &method, arguments)?;
+                *expression = call;
+                Ok(return_type)
+            }
+
+            Expression::AddressOf(place, mutable) => {
+                let mutable = *mutable;
+                self.check_address_of(place, mutable)
+            }
+
+            Expression::Dereference(reference) => match self.check_expression(reference)? {
+                Type::Reference(referenced_type, _) => Ok(*referenced_type),
+                t => Err(format!("Can't dereference `{}` of type {}", reference, t)),
+            },
+
+            Expression::Err(_) => Err(format!(
+                "Can't infer the type of `{}`, use it where a result is expected",
+                expression
+            )),
+
+            Expression::NamedArgument(..) => Err(format!(
+                "Unexpected named argument `{}`, only functions and methods take them",
+                expression
+            )),
+
+            // The error leaves the function, so it has to fit in its result
+            Expression::Try(result) => {
+                let (value_type, error_type) = match self.check_expression(result)? {
+                    Type::Result(value_type, error_type) => (*value_type, *error_type),
+                    t => {
+                        return Err(format!(
+                            "The `?` operator expects a result, found `{}` of type {}",
+                            result, t
+                        ))
+                    }
+                };
+                match &self.return_type {
+                    Type::Result(_, returned_error)
+                        if is_implicit_conversion(&error_type, returned_error) =>
+                    {
+                        Ok(value_type)
+                    }
+                    return_type => Err(format!(
+                        "`{}` returns its {} error from function '{}', which returns {}",
+                        expression, error_type, self.current_function, return_type
+                    )),
+                }
+            }
+        }
+    }
+
+    fn check_assignment(&mut self, assignment: &mut Assignment) -> Result<(), String> {
+        let var_type = match &assignment.var_type {
+            Some(var_type) => var_type.clone(),
+            None => {
+                let found = self.check_expression(&mut assignment.value)?;
+                if found == Type::Void {
+                    return Err(format!(
+                        "Can't infer the type of '{}' from `{}`, which has no value",
+                        assignment.name, assignment.value
+                    ));
+                }
+                assignment.var_type = Some(found.clone());
+                found
+            }
+        };
+
+        if matches!(var_type, Type::Array(..)) && !matches!(assignment.value, Expression::Array(_))
+        {
+            return Err(format!(
+                "Array '{}' must be initialized with an array literal",
+                assignment.name
+            ));
+        }
+
+        self.check_type(&var_type)?;
+        let context = format!("Mismatched types on declaration of '{}'", assignment.name);
+        self.check_expression_as(&mut assignment.value, &var_type, &context)?;
+        self.declare(&assignment.name, var_type)
+    }
+
+    fn check_reassignment(&mut self, reassignment: &mut Reassignment) -> Result<(), String> {
+        if !is_assignable(&reassignment.target) {
+            return Err(format!(
+                "Invalid left-hand side of assignment: `{}`",
+                reassignment.target
+            ));
+        }
+
+        let expected = self.check_expression(&mut reassignment.target)?;
+        self.check_mutable(&reassignment.target, "assign to")?;
+        if let Some(variable) = get_root_variable(&reassignment.target) {
+            if self.is_captured(variable) {
+                return Err(format!(
+                    "Can't assign to '{}' inside a closure, closures capture variables by value",
+                    variable
+                ));
+            }
+        }
+        if let Type::Array(..) = expected {
+            return Err(format!(
+                "Array `{}` can't be reassigned as a whole, assign its elements instead",
+                reassignment.target
+            ));
+        }
+
+        let context = format!(
+            "Mismatched types on assignment to `{}`",
+            reassignment.target
+        );
+        self.check_expression_as(&mut reassignment.value, &expected, &context)
+    }
+
+    fn check_return(&mut self, value: &mut Option<Expression>) -> Result<(), String> {
+        let expected = self.return_type.clone();
+
+        match value {
+            Some(expression) => {
+                if expected == Type::Void {
+                    return Err(format!(
+                        "Function '{}' has no return type but returns a value",
+                        self.current_function
+                    ));
+                }
+                let context = format!(
+                    "Mismatched return type in function '{}'",
+                    self.current_function
+                );
+                self.check_expression_as(expression, &expected, &context)?;
+
+                // Locals and parameters are gone once the function returns
+                let mut returned = &*expression;
+                while let Expression::Cast(inner, _) = returned {
+                    returned = inner;
+                }
+                if let Expression::AddressOf(place, _) = returned {
+                    if let Some(name) = get_root_variable(place) {
+                        if self.scopes[1..]
+                            .iter()
+                            .any(|scope| scope.contains_key(name))
+                        {
+                            return Err(format!(
+                                "Function '{}' returns a reference to local variable '{}'",
+                                self.current_function, name
+                            ));
+                        }
+                    }
+                }
+                Ok(())
+            }
+            None if expected != Type::Void => Err(format!(
+                "Function '{}' must return a value of type {}",
+                self.current_function, expected
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn check_for(&mut self, for_loop: &mut For) -> Result<(), String> {
+        if !is_assignable(&for_loop.iterable) {
+            return Err(format!(
+                "For loops iterate over variables or elements, found `{}`. Store it in a variable first",
+                for_loop.iterable
+            ));
+        }
+
+        let element_type = match self.check_expression(&mut for_loop.iterable)? {
+            Type::Array(element_type, _) | Type::Slice(element_type) => *element_type,
+            t => {
+                return Err(format!(
+                    "Can't iterate over `{}` of type {}",
+                    for_loop.iterable, t
+                ))
+            }
+        };
+
+        self.scopes.push(HashMap::new());
+        let result = self
+            .declare(&for_loop.variable, element_type)
+            .and_then(|_| self.check_statements(&mut for_loop.body));
+        self.scopes.pop();
+        result
+    }
+
+    fn check_if(&mut self, if_node: &mut If) -> Result<(), String> {
+        match &mut if_node.condition {
+            Condition::Expression(condition) => {
+                self.check_expression_as(
+                    condition,
+                    &Type::Bool,
+                    "Mismatched types on if condition",
+                )?;
+                self.check_block(&mut if_node.body)?;
+            }
+            // The value is only reachable where it is known to be present
+            Condition::Let(name, optional) => {
+                let value_type = match self.check_expression(optional)? {
+                    Type::Optional(value_type) | Type::Result(value_type, _) => *value_type,
+                    t => {
+                        return Err(format!(
+                            "`if let` expects an optional or a result, found `{}` of type {}",
+                            optional, t
+                        ))
+                    }
+                };
+                self.scopes.push(HashMap::new());
+                let result = self
+                    .declare(name, value_type)
+                    .and_then(|_| self.check_statements(&mut if_node.body));
+                self.scopes.pop();
+                result?;
+            }
+        }
+
+        match &mut if_node.else_body {
+            Some(else_body) => self.check_block(else_body),
+            None => Ok(()),
+        }
+    }
+
+    fn check_statement(&mut self, statement: &mut Ast) -> Result<(), String> {
+        match statement {
+            Ast::Assignment(assignment) => self.check_assignment(assignment),
+            Ast::Return(value) => self.check_return(value),
+            Ast::Reassignment(reassignment) => self.check_reassignment(reassignment),
+            Ast::Expression(
+                expression @ (Expression::FunctionCall(..)
+                | Expression::MethodCall(..)
+                | Expression::Match(..)
+                | Expression::Try(_)),
+            ) => self.check_expression(expression).map(|_| ()),
+            Ast::Expression(expression) => Err(format!(
+                "Expression `{}` can't be used as a statement",
+                expression
+            )),
+            Ast::For(for_loop) => self.check_for(for_loop),
+            Ast::If(if_node) => self.check_if(if_node),
+            Ast::Function(function) => Err(format!(
+                "Function '{}' can't be declared inside another function",
+                function.name
+            )),
+            Ast::Struct(struct_node) => Err(format!(
+                "Struct '{}' can't be declared inside a function",
+                struct_node.name
+            )),
+            Ast::Enum(enum_node) => Err(format!(
+                "Enum '{}' can't be declared inside a function",
+                enum_node.name
+            )),
+            Ast::Impl(impl_node) => Err(format!(
+                "Impl of '{}' can't be declared inside a function",
+                impl_node.type_name
+            )),
+            Ast::Trait(trait_node) => Err(format!(
+                "Trait '{}' can't be declared inside a function",
+                trait_node.name
+            )),
+            Ast::Import(import) => Err(format!(
+                "Module '{}' can't be imported inside a function",
+                import.module
+            )),
+            Ast::Extern(extern_node) => Err(format!(
+                "Extern function '{}' can't be declared inside a function",
+                extern_node.prototype.name
+            )),
+        }
+    }
+
+    fn check_block(&mut self, block: &mut Block) -> Result<(), String> {
+        self.scopes.push(HashMap::new());
+        let result = self.check_statements(block);
+        self.scopes.pop();
+        result
+    }
+
+    // Statements of a block sharing the scope of what it binds, like the
+    // parameters of a function or the variable of a loop
+    fn check_statements(&mut self, block: &mut Block) -> Result<(), String> {
+        block
+            .itens
+            .iter_mut()
+            .try_for_each(|statement| self.check_statement(statement))
+    }
+
+    // Methods are reported with the name of their type: `Point.len`
+    fn check_function(&mut self, name: String, function: &mut Function) -> Result<(), String> {
+        self.current_function = name;
+        self.return_type = function.return_type.clone().unwrap_or(Type::Void);
+
+        if let Type::Array(..) = self.return_type {
+            return Err(format!(
+                "Function '{}' can't return an array",
+                self.current_function
+            ));
+        }
+        self.check_type(&self.return_type)?;
+
+        self.check_defaults(&mut function.parameters)?;
+
+        self.scopes.push(HashMap::new());
+        let result = function
+            .parameters
+            .iter()
+            .try_for_each(|parameter| {
+                self.check_type(&parameter.parameter_type)?;
+                self.declare(&parameter.name, parameter.parameter_type.clone())
+            })
+            .and_then(|_| self.check_statements(&mut function.body));
+        self.scopes.pop();
+        result
+    }
+
+    // Defaults are constants, since they are evaluated by the caller, and once
+    // a parameter has one every parameter after it needs one too
+    fn check_defaults(&mut self, parameters: &mut [Parameter]) -> Result<(), String> {
+        let mut previous: Option<String> = None;
+        for parameter in parameters.iter_mut() {
+            let default = match &mut parameter.default {
+                Some(default) => default,
+                None => match previous {
+                    Some(previous) => {
+                        return Err(format!(
+                            "Parameter '{}' of function '{}' needs a default value, since it follows '{}'",
+                            parameter.name, self.current_function, previous
+                        ))
+                    }
+                    None => continue,
+                },
+            };
+            if !is_constant(default) {
+                return Err(format!(
+                    "Default value of parameter '{}' of function '{}' must be a constant expression",
+                    parameter.name, self.current_function
+                ));
+            }
+            previous = Some(parameter.name.clone());
+            // Defaults of a type parameter are checked for each instance
+            if !monomorphize::type_parameters(&parameter.parameter_type).is_empty() {
+                continue;
+            }
+            let context = format!(
+                "Mismatched types on default value of parameter '{}'",
+                parameter.name
+            );
+            self.check_expression_as(default, &parameter.parameter_type, &context)?;
+        }
+        Ok(())
+    }
+
+    fn check_global(&mut self, assignment: &mut Assignment) -> Result<(), String> {
+        if let Some(Type::Slice(_)) = assignment.var_type {
+            return Err(format!(
+                "Global '{}' can't be a slice, declare it inside a function",
+                assignment.name
+            ));
+        }
+        if !is_constant(&assignment.value) {
+            return Err(format!(
+                "Initializer of global '{}' must be a constant expression",
+                assignment.name
+            ));
+        }
+        self.check_assignment(assignment)
+    }
+
+    // Types a struct or an enum stores inline
+    fn stored_types(&self, name: &str) -> Vec<Type> {
+        if let Some(struct_node) = self.structs.get(name) {
+            return struct_node
+                .fields
+                .iter()
+                .map(|field| field.field_type.clone())
+                .collect();
+        }
+        match self.enums.get(name) {
+            Some(enum_node) => enum_node
+                .variants
+                .iter()
+                .flat_map(|variant| variant.fields.iter().cloned())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Whether a value of `field_type` stores a `name` inline, which would give
+    // `name` an infinite size. Slices live on the heap, so they break the cycle
+    fn contains_type(&self, field_type: &Type, name: &str, visited: &mut Vec<String>) -> bool {
+        match field_type {
+            Type::Array(element_type, _) => self.contains_type(element_type, name, visited),
+            Type::UserDefined(other) if other == name => true,
+            Type::UserDefined(other) if !visited.contains(other) => {
+                visited.push(other.clone());
+                self.stored_types(other)
+                    .iter()
+                    .any(|stored_type| self.contains_type(stored_type, name, visited))
+            }
+            _ => false,
+        }
+    }
+
+    fn declare_type_name(&self, name: &str) -> Result<(), String> {
+        check_name("Type", name)?;
+        if self.structs.contains_key(name) || self.enums.contains_key(name) {
+            return Err(format!("Type '{}' is declared more than once", name));
+        }
+        Ok(())
+    }
+
+    // Structs and enums can be used before they are declared, so all of them
+    // are known before any field type is checked
+    fn declare_types(&mut self, ast: &[Ast]) -> Result<(), String> {
+        for node in ast.iter() {
+            match node {
+                Ast::Struct(struct_node) => {
+                    self.declare_type_name(&struct_node.name)?;
+                    self.structs
+                        .insert(struct_node.name.clone(), struct_node.clone());
+                }
+                Ast::Enum(enum_node) => {
+                    self.declare_type_name(&enum_node.name)?;
+                    self.enums.insert(enum_node.name.clone(), enum_node.clone());
+                }
+                _ => {}
+            }
+        }
+
+        for struct_node in self.structs.values() {
+            for (position, field) in struct_node.fields.iter().enumerate() {
+                check_name("Field", &field.name)?;
+                if struct_node.fields[..position]
+                    .iter()
+                    .any(|other| other.name == field.name)
+                {
+                    return Err(format!(
+                        "Field '{}' is declared more than once in struct '{}'",
+                        field.name, struct_node.name
+                    ));
+                }
+                self.check_type(&field.field_type)?;
+                if self.contains_type(&field.field_type, &struct_node.name, &mut vec![]) {
+                    return Err(format!(
+                        "Struct '{}' contains itself through field '{}', use a slice instead",
+                        struct_node.name, field.name
+                    ));
+                }
+            }
+        }
+
+        for enum_node in self.enums.values() {
+            for (position, variant) in enum_node.variants.iter().enumerate() {
+                if enum_node.variants[..position]
+                    .iter()
+                    .any(|other| other.name == variant.name)
+                {
+                    return Err(format!(
+                        "Variant '{}' is declared more than once in enum '{}'",
+                        variant.name, enum_node.name
+                    ));
+                }
+                for field_type in variant.fields.iter() {
+                    self.check_type(field_type)?;
+                    if self.contains_type(field_type, &enum_node.name, &mut vec![]) {
+                        return Err(format!(
+                            "Enum '{}' contains itself through variant '{}', use a slice instead",
+                            enum_node.name, variant.name
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Functions can be called before they are declared, so every signature
+    // is known before any body is checked
+    fn declare_functions(&mut self, ast: &[Ast]) -> Result<(), String> {
+        for node in ast.iter() {
+            if let Ast::Extern(extern_node) = node {
+                self.declare_extern(extern_node)?;
+            }
+            if let Ast::Function(function) = node {
+                check_name("Function", &function.name)?;
+                for parameter in function.parameters.iter() {
+                    check_name("Parameter", &parameter.name)?;
+                }
+                if BUILTIN_FUNCTIONS.contains(&function.name.as_str()) {
+                    return Err(format!(
+                        "Function '{}' conflicts with a builtin function",
+                        function.name
+                    ));
+                }
+
+                // Type arguments are only ever inferred from the arguments
+                for type_parameter in function.type_parameters.iter() {
+                    let is_used = function.parameters.iter().any(|parameter| {
+                        monomorphize::type_parameters(&parameter.parameter_type)
+                            .contains(&type_parameter.name)
+                    });
+                    if !is_used {
+                        return Err(format!(
+                            "Type parameter {} of function '{}' must be used by its parameters",
+                            type_parameter.name, function.name
+                        ));
+                    }
+                    if let Some(bound) = type_parameter
+                        .bounds
+                        .iter()
+                        .find(|bound| !self.traits.contains_key(*bound))
+                    {
+                        return Err(format!(
+                            "Unknown trait {} on type parameter {} of function '{}'",
+                            bound, type_parameter.name, function.name
+                        ));
+                    }
+                }
+                if !function.type_parameters.is_empty() {
+                    self.generics
+                        .insert(function.name.clone(), function.clone());
+                }
+                if function.exported {
+                    self.declare_export(function)?;
+                }
+
+                let signature = FunctionSignature::from_function(function);
+                let overloads = self.functions.entry(function.name.clone()).or_default();
+                if overloads
+                    .iter()
+                    .any(|other| other.parameter_types() == signature.parameter_types())
+                {
+                    return Err(format!(
+                        "Function '{}' is declared more than once",
+                        signature.describe(&function.name)
+                    ));
+                }
+                overloads.push(signature);
+            }
+        }
+
+        // Overloads are generated with their parameter types in their name,
+        // like instances of generic functions
+        for (name, overloads) in self.functions.iter() {
+            if overloads.len() < 2 {
+                continue;
+            }
+            if name == "main"
+                || self.generics.contains_key(name)
+                || self.externs.contains(name)
+                || self.exports.contains(name)
+            {
+                return Err(format!("Function '{}' can't be overloaded", name));
+            }
+        }
+
+        // Overloads are named after their parameter types in C, which must
+        // not give two functions the same name
+        let functions = ast.iter().filter_map(|node| match node {
+            Ast::Function(function) if function.type_parameters.is_empty() => Some(function),
+            _ => None,
+        });
+        let (single, overloaded): (Vec<&Function>, Vec<&Function>) =
+            functions.partition(|function| self.functions[&function.name].len() == 1);
+        for function in single {
+            self.c_names
+                .insert(function.name.clone(), function.name.clone());
+        }
+        for function in overloaded {
+            let types = FunctionSignature::from_function(function).parameter_types();
+            let signature = self
+                .functions
+                .get_mut(&function.name)
+                .and_then(|overloads| {
+                    overloads
+                        .iter_mut()
+                        .find(|overload| overload.parameter_types() == types)
+                })
+                .ok_or(format!("Function '{}' wasn't declared", function.name))?;
+            signature.name = monomorphize::instance_name(&function.name, &types);
+            let description = signature.describe(&function.name);
+            if let Some(other) = self
+                .c_names
+                .insert(signature.name.clone(), description.clone())
+            {
+                return Err(format!(
+                    "Function '{}' conflicts with overload '{}'",
+                    other, description
+                ));
+            }
+        }
+
+        // main becomes C's `int main(void)`
+        if let Some(main) = self.functions.get("main") {
+            if !main[0].parameters.is_empty() {
+                return Err("Function 'main' can't take parameters".to_string());
+            }
+            if !matches!(main[0].return_type, Type::Int | Type::Void) {
+                return Err(format!(
+                    "Function 'main' must return int or nothing, not {}",
+                    main[0].return_type
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // C functions take and return only the types C has an equivalent of
+    fn declare_extern(&mut self, extern_node: &Extern) -> Result<(), String> {
+        let prototype = &extern_node.prototype;
+        check_name("Function", &prototype.name)?;
+        if BUILTIN_FUNCTIONS.contains(&prototype.name.as_str()) {
+            return Err(format!(
+                "Function '{}' conflicts with a builtin function",
+                prototype.name
+            ));
+        }
+        for parameter in prototype.parameters.iter() {
+            check_name("Parameter", &parameter.name)?;
+            if parameter.default.is_some() {
+                return Err(format!(
+                    "Parameter '{}' of extern function '{}' can't have a default value",
+                    parameter.name, prototype.name
+                ));
+            }
+            if !is_ffi_safe(&parameter.parameter_type) {
+                return Err(format!(
+                    "Parameter '{}' of extern function '{}' has type {}, which can't be passed to C",
+                    parameter.name, prototype.name, parameter.parameter_type
+                ));
+            }
+        }
+        if let Some(return_type) = &prototype.return_type {
+            if !is_ffi_safe(return_type) {
+                return Err(format!(
+                    "Extern function '{}' can't return {}, which can't be passed from C",
+                    prototype.name, return_type
+                ));
+            }
+        }
+
+        // Several modules may declare the same C function. Renamed externs
+        // are overloads, told apart by their parameter types
+        let signature = FunctionSignature::from_extern(extern_node);
+        let overloads = self.functions.entry(prototype.name.clone()).or_default();
+        let declared = match extern_node.is_renamed() {
+            true => overloads
+                .iter()
+                .find(|other| other.parameter_types() == signature.parameter_types()),
+            false if self.externs.contains(&prototype.name) => overloads.first(),
+            false => None,
+        };
+        if let Some(declared) = declared {
+            if !declared.matches(&signature) {
+                return Err(format!(
+                    "Extern function '{}' is declared with different signatures",
+                    prototype.name
+                ));
+            }
+            return Ok(());
+        }
+        if !extern_node.is_renamed() {
+            self.externs.insert(prototype.name.clone());
+        }
+        overloads.push(signature);
+        Ok(())
+    }
+
+    // C calls exported functions, so they have the same restrictions as the
+    // C functions idk calls
+    fn declare_export(&mut self, function: &Function) -> Result<(), String> {
+        if function.name == "main" {
+            return Err("Function 'main' can't be exported".to_string());
+        }
+        if !function.type_parameters.is_empty() {
+            return Err(format!(
+                "Generic function '{}' can't be exported",
+                function.name
+            ));
+        }
+        for parameter in function.parameters.iter() {
+            if !is_ffi_safe(&parameter.parameter_type) {
+                return Err(format!(
+                    "Parameter '{}' of exported function '{}' has type {}, which can't be passed from C",
+                    parameter.name, function.name, parameter.parameter_type
+                ));
+            }
+        }
+        if let Some(return_type) = &function.return_type {
+            if !is_ffi_safe(return_type) {
+                return Err(format!(
+                    "Exported function '{}' can't return {}, which can't be passed to C",
+                    function.name, return_type
+                ));
+            }
+        }
+        self.exports.insert(function.name.clone());
+        Ok(())
+    }
+
+    // Methods are lowered to functions named `Type_method`, which must not
+    // clash with the functions declared by the user
+    fn declare_methods(&mut self, ast: &[Ast]) -> Result<(), String> {
+        for node in ast.iter() {
+            if let Ast::Impl(impl_node) = node {
+                let type_name = &impl_node.type_name;
+                self.check_type(&Type::UserDefined(type_name.clone()))?;
+
+                for method in impl_node.methods.iter() {
+                    check_name("Method", &method.name)?;
+                    if !method.type_parameters.is_empty() {
+                        return Err(format!(
+                            "Method '{}.{}' can't be generic",
+                            type_name, method.name
+                        ));
+                    }
+                    match method.parameters.first() {
+                        Some(parameter) if parameter.name == "self" => {}
+                        _ => {
+                            return Err(format!(
+                                "Method '{}.{}' must take self as its first parameter",
+                                type_name, method.name
+                            ))
+                        }
+                    }
+
+                    let mangled_name = mangle_method(type_name, &method.name);
+                    if self.functions.contains_key(&mangled_name) {
+                        return Err(format!(
+                            "Function '{}' conflicts with method '{}.{}'",
+                            mangled_name, type_name, method.name
+                        ));
+                    }
+
+                    let methods = self.methods.entry(type_name.clone()).or_default();
+                    if methods.contains_key(&method.name) {
+                        return Err(format!(
+                            "Method '{}' is declared more than once for '{}'",
+                            method.name, type_name
+                        ));
+                    }
+                    let mut signature = FunctionSignature::from_function(method);
+                    signature.name = mangled_name;
+                    methods.insert(method.name.clone(), signature);
+                }
+
+                if let Some(trait_name) = &impl_node.trait_name {
+                    self.check_implementation(trait_name, impl_node)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Traits only exist for the type checker. Their methods are implemented,
+    // and called, like any other method
+    fn declare_traits(&mut self, ast: &[Ast]) -> Result<(), String> {
+        for node in ast.iter() {
+            if let Ast::Trait(trait_node) = node {
+                if self.traits.contains_key(&trait_node.name) {
+                    return Err(format!(
+                        "Trait '{}' is declared more than once",
+                        trait_node.name
+                    ));
+                }
+
+                for (position, method) in trait_node.methods.iter().enumerate() {
+                    if trait_node.methods[..position]
+                        .iter()
+                        .any(|other| other.name == method.name)
+                    {
+                        return Err(format!(
+                            "Method '{}' is declared more than once in trait '{}'",
+                            method.name, trait_node.name
+                        ));
+                    }
+                    match method.parameters.first() {
+                        Some(parameter) if parameter.name == "self" => {}
+                        _ => {
+                            return Err(format!(
+                                "Method '{}.{}' must take self as its first parameter",
+                                trait_node.name, method.name
+                            ))
+                        }
+                    }
+                    for parameter in method.parameters.iter() {
+                        self.check_type(&parameter.parameter_type)?;
+                        if parameter.default.is_some() {
+                            return Err(format!(
+                                "Parameter '{}' of method '{}.{}' can't have a default value in a trait",
+                                parameter.name, trait_node.name, method.name
+                            ));
+                        }
+                    }
+                    if let Some(return_type) = &method.return_type {
+                        self.check_type(return_type)?;
+                    }
+                }
+                self.traits
+                    .insert(trait_node.name.clone(), trait_node.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // The methods of `impl Trait for Type` are exactly the ones of the trait,
+    // with `Self` replaced by the type
+    fn check_implementation(&mut self, trait_name: &str, impl_node: &Impl) -> Result<(), String> {
+        let type_name = &impl_node.type_name;
+        let trait_node = self
+            .traits
+            .get(trait_name)
+            .cloned()
+            .ok_or(format!("Unknown trait: {}", trait_name))?;
+        if !self
+            .implementations
+            .insert((trait_name.to_string(), type_name.clone()))
+        {
+            return Err(format!(
+                "Trait '{}' is implemented more than once for '{}'",
+                trait_name, type_name
+            ));
+        }
+
+        if let Some(method) = impl_node
+            .methods
+            .iter()
+            .find(|method| trait_node.get_method(&method.name).is_none())
+        {
+            return Err(format!(
+                "Method '{}.{}' is not a member of trait '{}'",
+                type_name, method.name, trait_name
+            ));
+        }
+
+        let bindings = HashMap::from([("Self".to_string(), Type::UserDefined(type_name.clone()))]);
+        for prototype in trait_node.methods.iter() {
+            let method = impl_node
+                .methods
+                .iter()
+                .find(|method| method.name == prototype.name)
+                .ok_or(format!(
+                    "Impl of trait '{}' for '{}' is missing method '{}'",
+                    trait_name, type_name, prototype.name
+                ))?;
+
+            let expected =
+                FunctionSignature::from_trait_method(method.name.clone(), prototype, &bindings);
+            if !FunctionSignature::from_function(method).matches(&expected) {
+                return Err(format!(
+                    "Method '{}.{}' doesn't match its declaration in trait '{}'",
+                    type_name, method.name, trait_name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // The body of a generic function is checked once for any type arguments
+    // meeting the bounds. What the check adds, like the instances of the
+    // generic functions it calls with its type parameters, is dropped after
+    fn check_generic(&mut self, function: &Function) -> Result<(), String> {
+        let functions = self.functions.clone();
+        let instance_names = self.instance_names.clone();
+        let c_names = self.c_names.clone();
+        let pending = self.pending_instances.len();
+        self.bounds = function
+            .type_parameters
+            .iter()
+            .map(|type_parameter| (type_parameter.name.clone(), type_parameter.bounds.clone()))
+            .collect();
+
+        let type_parameters: Vec<&str> = function
+            .type_parameters
+            .iter()
+            .map(|type_parameter| type_parameter.name.as_str())
+            .collect();
+        let name = format!("{}<{}>", function.name, type_parameters.join(", "));
+        let result = self
+            .check_function(name.clone(), &mut function.clone())
+            .map_err(|err| format!("In '{}': {}", name, err));
+
+        self.bounds.clear();
+        self.functions = functions;
+        self.instance_names = instance_names;
+        self.c_names = c_names;
+        self.pending_instances.truncate(pending);
+        result
+    }
+
+    // Generic functions are replaced by their instances, and calls and
+    // functions used as values by the function they resolve to
+    pub fn check(&mut self, ast: &mut Vec<Ast>) -> Result<(), String> {
+        self.declare_types(ast)?;
+        self.declare_traits(ast)?;
+        self.declare_functions(ast)?;
+        self.declare_methods(ast)?;
+
+        for node in ast.iter_mut() {
+            match node {
+                Ast::Assignment(assignment) => self.check_global(assignment)?,
+                Ast::Function(function) if !function.type_parameters.is_empty() => {
+                    self.check_generic(function)?
+                }
+                Ast::Function(function) => self.check_function(function.name.clone(), function)?,
+                Ast::Impl(impl_node) => {
+                    for method in impl_node.methods.iter_mut() {
+                        let name = format!("{}.{}", impl_node.type_name, method.name);
+                        self.check_function(name, method)?;
+                    }
+                }
+                Ast::Struct(_) | Ast::Enum(_) | Ast::Trait(_) | Ast::Extern(_) => {}
+                statement => {
+                    return Err(format!("Statement outside of a function: {:?}", statement))
+                }
+            }
+        }
+
+        // Checking an instance may create new ones
+        while !self.pending_instances.is_empty() {
+            let (generic, name, mut instance) = self.pending_instances.remove(0);
+            self.check_function(name.clone(), &mut instance)
+                .map_err(|err| format!("In '{}': {}", name, err))?;
+            self.instances.entry(generic).or_default().push(instance);
+        }
+
+        // Overloads take the names calls were resolved to
+        for node in std::mem::take(ast).into_iter() {
+            match node {
+                Ast::Function(function) if !function.type_parameters.is_empty() => {
+                    let instances = self.instances.remove(&function.name).unwrap_or_default();
+                    ast.extend(instances.into_iter().map(Ast::Function));
+                }
+                Ast::Function(mut function) => {
+                    let types = FunctionSignature::from_function(&function).parameter_types();
+                    if let Some(signature) = self.functions[&function.name]
+                        .iter()
+                        .find(|overload| overload.parameter_types() == types)
+                    {
+                        function.name = signature.name.clone();
+                    }
+                    ast.push(Ast::Function(function));
+                }
+                node => ast.push(node),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::check;
+
+    #[test]
+    fn test_implicit_int_to_float_conversion() {
+        let ast = check("fn f(int a) { float b = a; float c = 2; }").unwrap();
+
+        if let Ast::Function(function) = &ast[0] {
+            let expected = vec![
+                Ast::Assignment(Assignment::new(
+                    Type::Float,
+                    "b".to_string(),
+                    Expression::Cast(
+                        Box::new(Expression::Identifier("a".to_string())),
+                        Type::Float,
+                    ),
+                )),
+                Ast::Assignment(Assignment::new(
+                    Type::Float,
+                    "c".to_string(),
+                    Expression::Float(2.0),
+                )),
+            ];
+            assert_eq!(function.body.itens, expected);
+        } else {
+            panic!("This should be a function declaration!");
+        }
+    }
+
+    #[test]
+    fn test_lossy_conversion_suggests_cast() {
+        let err = check("fn f(float a) { int b = a * 2; }").unwrap_err();
+
+        assert!(
+            err.starts_with("Mismatched types on declaration of 'b': expected int, found float")
+        );
+        assert!(err.contains("fractional part is truncated"));
+        assert!(err.contains("help: use an explicit cast: `(a * 2.0) as int`"));
+    }
+
+    #[test]
+    fn test_explicit_casts() {
+        assert!(
+            check("fn f(float a, bool b): int { int c = a as int + b as int; return c; }").is_ok()
+        );
+        assert!(check("fn f(string s) { int c = s as int; }").is_err());
+        assert!(check("fn f(int a) { bool b = a as bool; }").is_err());
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        assert_eq!(
+            check("fn idk_sqrt(float x): float { return x; }"),
+            Err(
+                "Function name 'idk_sqrt' is reserved, names starting with 'idk_' are used by generated code"
+                    .to_string()
+            )
+        );
+        assert!(check("fn f() { int idk_match1 = 5; }").is_err());
+        assert!(check("let idk_count = 1;").is_err());
+        assert!(check("fn f(int idk_a) {}").is_err());
+        assert!(check("fn f() { let g = fn(int idk_a) {}; }").is_err());
+        assert!(check("extern \"C\" fn idk_print(string s);").is_err());
+        assert!(check("struct idk_P { int x; }").is_err());
+        assert!(check("enum idk_E { A }").is_err());
+        assert!(check("struct P { int idk_x; }").is_err());
+        assert!(
+            check("struct P { int x; } impl P { fn idk_get(self): int { return 1; } }").is_err()
+        );
+
+        // Linking prefixes the items of imported modules with the module name
+        assert!(check("fn shapes__idk_area() {}").is_err());
+
+        assert!(check("fn sidk_f(int idk) { int my_idk_x = idk; }").is_ok());
+    }
+
+    #[test]
+    fn test_redeclaration() {
+        assert_eq!(
+            check("fn f(int a) { int a = 2; }"),
+            Err("Variable 'a' is already declared in this scope".to_string())
+        );
+        assert_eq!(
+            check("fn f() { int y = 3; int y = 4; }"),
+            Err("Variable 'y' is already declared in this scope".to_string())
+        );
+        assert!(check("fn f(int a, float a) {}").is_err());
+        assert!(check("let g = 1; let g = 2;").is_err());
+        assert!(check("fn f([]int v) { for x in v { int x = 1; } }").is_err());
+        assert!(check("fn f(?int o) { if let x = o { int x = 1; } }").is_err());
+        assert!(check("fn f() { let g = fn(int a) { int a = 1; }; }").is_err());
+
+        // Inner blocks and closures may shadow
+        assert!(check("fn f(int a, bool b) { if b { int a = 2; } }").is_ok());
+        assert!(check("fn f(int a) { let g = fn(int a): int { return a; }; }").is_ok());
+    }
+
+    #[test]
+    fn test_return_type_mismatch() {
+        assert!(check("fn f(): float { return 1; }").is_ok());
+        assert!(check("fn f(): int { return 1.5; }").is_err());
+        assert!(check("fn f() { return 1; }").is_err());
+    }
+
+    #[test]
+    fn test_array_literals() {
+        assert!(check("fn f() { [float; 3] a = [1, 2.5, 3]; }").is_ok());
+        assert!(check("fn f() { [int; 2] a = [1, 2, 3]; }").is_err());
+        assert!(check("fn f() { [int; 2] a = [1, 2.5]; }").is_err());
+        assert!(check("fn f(int x) { [int; 2] a = x; }").is_err());
+    }
+
+    #[test]
+    fn test_constant_index_bounds() {
+        assert!(check("fn f(): int { [int; 3] a = [1, 2, 3]; return a[2]; }").is_ok());
+
+        let err = check("fn f(): int { [int; 3] a = [1, 2, 3]; return a[1 + 2]; }").unwrap_err();
+        assert_eq!(
+            err,
+            "Index out of bounds: the length of `a` is 3 but the index is 3"
+        );
+    }
+
+    #[test]
+    fn test_index_assignment() {
+        assert!(check("fn f(int i) { [[int; 2]; 2] m = [[1, 2], [3, 4]]; m[i][0] = 5; }").is_ok());
+        assert!(check("fn f(int i) { [int; 2] a = [1, 2]; a[i] = 2.5; }").is_err());
+        assert!(check("fn f() { [int; 2] a = [1, 2]; a = a; }").is_err());
+    }
+
+    #[test]
+    fn test_slice_builtins() {
+        let input = "fn f(): float {
+            []float v = [];
+            push(v, 1);
+            push(v, 2.5);
+            float sum = 0;
+            for x in v {
+                sum = sum + x;
+            }
+            return sum + pop(v) + len(v);
+        }";
+        assert!(check(input).is_ok());
+
+        assert!(check("fn f() { []int v = [1]; push(v, 2.5); }").is_err());
+        assert!(check("fn f(int x) { push(x, 1); }").is_err());
+        assert!(check("fn f() { [int; 2] a = [1, 2]; push(a, 3); }").is_err());
+    }
+
+    #[test]
+    fn test_function_calls() {
+        assert!(
+            check("fn f(): int { return g(1, 2.5); } fn g(int a, float b): int { return a; }")
+                .is_ok()
+        );
+
+        let err = check("fn f(): int { return g(1); } fn g(int a, float b): int { return a; }")
+            .unwrap_err();
+        assert_eq!(err, "Function 'g' takes 2 arguments but 1 were given");
+
+        assert!(check("fn f() { h(); }").is_err());
+        assert!(check("fn len(int a) {}").is_err());
+    }
+
+    #[test]
+    fn test_struct_literals_and_fields() {
+        let input = "struct Point { float x; float y; }
+            fn f(Point p): float { Point q = Point { y: 1, x: p.y }; q.x = 2; return q.x; }";
+        assert!(check(input).is_ok());
+
+        let err = check("struct Point { float x; float y; } fn f() { Point p = Point { x: 1 }; }")
+            .unwrap_err();
+        assert_eq!(err, "Missing fields on struct literal of 'Point': y");
+
+        assert!(check("struct P { int x; } fn f() { P p = P { x: 1, x: 2 }; }").is_err());
+        assert!(check("struct P { int x; } fn f() { P p = P { x: 1.5 }; }").is_err());
+        assert!(check("struct P { int x; } fn f(P p): int { return p.z; }").is_err());
+        assert!(check("fn f(Point p) {}").is_err());
+    }
+
+    #[test]
+    fn test_recursive_structs() {
+        assert!(check("struct Node { int value; []Node children; }").is_ok());
+        assert!(check("struct Node { int value; Node next; }").is_err());
+        assert!(check("struct A { [B; 2] b; } struct B { A a; }").is_err());
+    }
+
+    #[test]
+    fn test_match_exhaustiveness() {
+        let shape = "enum Shape { Circle(float), Rect(float, float), Empty }";
+        let input = format!(
+            "{} fn f(Shape s): float {{
+                return match s {{ Shape::Circle(r) => r, Shape::Rect(w, h) => w * h, Shape::Empty => 0 }};
+            }}",
+            shape
+        );
+        assert!(check(&input).is_ok());
+
+        let input = format!(
+            "{} fn f(Shape s): float {{ return match s {{ Shape::Circle(r) => r }}; }}",
+            shape
+        );
+        assert_eq!(
+            check(&input).unwrap_err(),
+            "Non-exhaustive match on `s`: Shape::Rect, Shape::Empty not covered"
+        );
+
+        let input = format!(
+            "{} fn f(Shape s): int {{ return match s {{ _ => 1, Shape::Empty => 0 }}; }}",
+            shape
+        );
+        assert_eq!(
+            check(&input).unwrap_err(),
+            "Unreachable match arm `Shape::Empty`"
+        );
+    }
+
+    #[test]
+    fn test_enum_variants() {
+        let shape = "enum Shape { Circle(float), Rect(float, float), Empty }";
+        assert!(check(&format!("{} Shape s = Shape::Rect(1, 2.5);", shape)).is_ok());
+        assert!(check(&format!("{} Shape s = Shape::Rect(1);", shape)).is_err());
+        assert!(check(&format!("{} Shape s = Shape::Square(1);", shape)).is_err());
+        assert!(check(&format!(
+            "{} fn f(Shape s): int {{ return match s {{ Shape::Circle(r, x) => 1, _ => 0 }}; }}",
+            shape
+        ))
+        .is_err());
+        assert!(check("enum List { Node(int, List), End }").is_err());
+    }
+
+    #[test]
+    fn test_methods() {
+        let point = "struct Point { float x; float y; }
+            impl Point { fn scaled(self, float k): Point { return Point { x: self.x * k, y: self.y * k }; } }";
+        assert!(check(&format!(
+            "{} fn f(Point p): float {{ return p.scaled(2).x; }}",
+            point
+        ))
+        .is_ok());
+
+        let err = check(&format!("{} fn f(Point p) {{ p.scaled(); }}", point)).unwrap_err();
+        assert_eq!(
+            err,
+            "Function 'Point.scaled' takes 1 arguments but 0 were given"
+        );
+
+        assert!(check(&format!("{} fn f(Point p) {{ p.rotated(1); }}", point)).is_err());
+        assert!(check(&format!("{} fn Point_scaled() {{}}", point)).is_err());
+        assert!(check("struct P { int x; } impl P { fn get(): int { return 1; } }").is_err());
+        assert!(check("impl Missing { fn get(self) {} }").is_err());
+    }
+
+    #[test]
+    fn test_references() {
+        assert!(check(
+            "fn f(&mut int a) { int b = *a; *a = b + 1; } fn g(&int a): int { return *a; }"
+        )
+        .is_ok());
+        assert!(check("fn f(int x): int { &mut int a = &mut x; return g(a); } fn g(&int a): int { return *a; }").is_ok());
+
+        let err = check("fn f(&int a) { *a = 1; }").unwrap_err();
+        assert_eq!(
+            err,
+            "Can't assign to `*a`, it is behind a shared reference of type &int"
+        );
+
+        assert!(check("fn f(&int a) { &mut int b = &mut *a; }").is_err());
+        assert!(check("fn f(int a) { int b = *a; }").is_err());
+        assert!(check("fn f(): &int { return &1; }").is_err());
+        assert!(check("fn f(int a): &int { return &a; }").is_err());
+        assert!(check("fn f(&[]int v): &int { return &(*v)[0]; }").is_ok());
+    }
+
+    #[test]
+    fn test_optionals() {
+        assert!(check("fn f(?int a): int { if let x = a { return x; } return 0; }").is_ok());
+        assert!(check("fn f(bool b): ?float { if b { return 1; } return none; }").is_ok());
+        assert!(check("struct N { int v; ?&N next; } fn f(N n): ?&N { return n.next; }").is_ok());
+
+        let err = check("fn f(?int a): int { return a; }").unwrap_err();
+        assert_eq!(
+            err,
+            "Mismatched return type in function 'f': expected int, found ?int"
+        );
+
+        let err = check("fn f() { ?int a = none; if let x = a {} int y = x; }").unwrap_err();
+        assert_eq!(err, "Use of undeclared variable 'x'");
+
+        assert!(check("fn f() { if let x = none {} }").is_err());
+        assert!(check("fn f(int a) { if let x = a {} }").is_err());
+        assert!(check("fn f(int a) { if a {} }").is_err());
+        assert!(check("fn f(??int a) {}").is_err());
+        assert!(check("fn f(?[int; 2] a) {}").is_err());
+    }
+
+    #[test]
+    fn test_results() {
+        assert!(check(
+            "fn f(bool b): int!string { if b { return 1; } return err(\"no\"); }
+            fn g(): float!string { int x = f(true)?; f(false)?; return x; }"
+        )
+        .is_ok());
+        assert!(
+            check("fn f(): int!int { return err(1); } fn g(): int!float { return f()?; }").is_ok()
+        );
+        assert!(check("fn f(int!string r): int { if let x = r { return x; } return 0; }").is_ok());
+
+        let err = check("fn f(): int!string { return err(\"no\"); } fn g(): int { return f()?; }")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "`f()?` returns its string error from function 'g', which returns int"
+        );
+
+        let err =
+            check("fn f(): int!string { return err(\"no\"); } fn g(): int!float { return f()?; }")
+                .unwrap_err();
+        assert_eq!(
+            err,
+            "`f()?` returns its string error from function 'g', which returns int!float"
+        );
+
+        assert!(check("fn f(): int!string { return err(1); }").is_err());
+        assert!(check("fn f(int!string r): int { return r; }").is_err());
+        assert!(check("fn f(int r): int!string { return r?; }").is_err());
+        assert!(check("fn f() { int!string r = err(\"no\"); int x = r?; }").is_err());
+    }
+
+    #[test]
+    fn test_generic_functions() {
+        let ast = check(
+            "fn max<T>(bool first, T a, T b): T { if first { return a; } return b; }
+            fn f(): float { return max(true, 1, 2) + max(false, 1.5, 2.5); }",
+        )
+        .unwrap();
+
+        let names: Vec<&str> = ast
+            .iter()
+            .map(|node| match node {
+                Ast::Function(function) => function.name.as_str(),
+                _ => panic!("This should be a function declaration!"),
+            })
+            .collect();
+        assert_eq!(names, vec!["max_int", "max_float", "f"]);
+        if let Ast::Function(function) = &ast[0] {
+            assert_eq!(function.return_type, Some(Type::Int));
+        }
+
+        let err =
+            check("fn max<T>(T a, T b): T { return a; } fn f() { max(1, 2.5); }").unwrap_err();
+        assert_eq!(
+            err,
+            "Conflicting types for type parameter T of function 'max': int and float"
+        );
+
+        // Operators and defaults of type parameters are checked for each
+        // instance, the rest of the body even when nothing calls it
+        let err = check("fn neg<T>(T a): T { return a * 2; } fn f() { neg(\"a\"); }").unwrap_err();
+        assert_eq!(
+            err,
+            "In 'neg<string>': Operator '*' can't be applied to string and int"
+        );
+        assert!(check("fn neg<T>(T a, T b = 2): T { return a * b; }").is_ok());
+        let err = check("fn t<T>(T a): T { return nothing_here + 1; }").unwrap_err();
+        assert_eq!(err, "In 't<T>': Use of undeclared variable 'nothing_here'");
+
+        assert!(check(
+            "fn first<T>([]T a): ?T { return a[0]; } fn f(): ?int { return first([1]); }"
+        )
+        .is_ok());
+        assert!(check("fn make<T>(): int { return 1; }").is_err());
+
+        // Each kind of reference gets its own instance
+        let ast = check(
+            "fn id<T>(T a): T { return a; }
+            fn f(int x) { &int s = id(&x); &mut int m = id(&mut x); }",
+        )
+        .unwrap();
+        let names: Vec<&str> = ast
+            .iter()
+            .filter_map(|node| match node {
+                Ast::Function(function) => Some(function.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["id_ref_int", "id_refmut_int", "f"]);
+        assert!(
+            check("fn id<T>(T a): T { return a; } fn id_int(int a) {} fn f() { id(1); }").is_err()
+        );
+        assert!(check("fn id<T>(T a): T { return a; } fn g() {} fn f() { id(g()); }").is_err());
+    }
+
+    #[test]
+    fn test_traits() {
+        let shape = "trait Shape { fn area(self): float; }
+            struct Square { float side; }
+            struct Circle { float r; }";
+        let square =
+            "impl Shape for Square { fn area(self): float { return self.side * self.side; } }";
+
+        assert!(check(&format!(
+            "{} {} fn total<T: Shape>(T a, T b): float {{ return a.area() + b.area(); }}
+            fn f(Square s): float {{ return total(s, s) + s.area(); }}",
+            shape, square
+        ))
+        .is_ok());
+
+        let err = check(&format!(
+            "{} {} fn area<T: Shape>(T a): float {{ return a.area(); }}
+            fn f(Circle c): float {{ return area(c); }}",
+            shape, square
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "Type Circle doesn't implement trait Shape, required by type parameter T of function 'area'"
+        );
+
+        let err = check(&format!(
+            "{} impl Shape for Square {{ fn area(self): int {{ return 1; }} }}",
+            shape
+        ))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "Method 'Square.area' doesn't match its declaration in trait 'Shape'"
+        );
+
+        assert!(check(&format!("{} impl Shape for Square {{}}", shape)).is_err());
+        assert!(check(&format!("{} {} {}", shape, square, square)).is_err());
+        assert!(check(&format!(
+            "{} impl Shape for Square {{ fn area(self): float {{ return 1; }} fn side(self): float {{ return 1; }} }}",
+            shape
+        ))
+        .is_err());
+        assert!(check("fn f<T: Missing>(T a) {}").is_err());
+
+        // Type parameters only have the methods of their bounds, and meet
+        // the bounds of the generic functions they are passed to
+        assert!(check(&format!(
+            "{} fn area<T: Shape>(T a): float {{ return a.area(); }}
+            fn twice<T: Shape>(&T a): float {{ return area(*a) * 2; }}",
+            shape
+        ))
+        .is_ok());
+        let err = check(&format!(
+            "{} fn area<T>(T a): float {{ return a.area(); }}",
+            shape
+        ))
+        .unwrap_err();
+        assert_eq!(err, "In 'area<T>': Type T has no method named 'area'");
+        assert!(check(&format!(
+            "{} fn area<T: Shape>(T a): float {{ return a.area(); }}
+            fn twice<T>(T a): float {{ return area(a) * 2; }}",
+            shape
+        ))
+        .is_err());
+        assert!(check("trait T { fn f(int a); }").is_err());
+    }
+
+    #[test]
+    fn test_let() {
+        let ast = check(
+            "let g = 1.5;
+            fn f(): ?int { return 1; }
+            fn h() { let x = 2; let y: float = x; let o = f(); let a = [x, 3]; }",
+        )
+        .unwrap();
+
+        let types: Vec<Option<Type>> = match &ast[2] {
+            Ast::Function(function) => function
+                .body
+                .itens
+                .iter()
+                .map(|node| match node {
+                    Ast::Assignment(assignment) => assignment.var_type.clone(),
+                    _ => panic!("This should be a declaration!"),
+                })
+                .collect(),
+            _ => panic!("This should be a function declaration!"),
+        };
+        assert_eq!(
+            types,
+            vec![
+                Some(Type::Int),
+                Some(Type::Float),
+                Some(Type::Optional(Box::new(Type::Int))),
+                Some(Type::Array(Box::new(Type::Int), 2)),
+            ]
+        );
+
+        let err = check("fn f() {} fn g() { let x = f(); }").unwrap_err();
+        assert_eq!(
+            err,
+            "Can't infer the type of 'x' from `f()`, which has no value"
+        );
+
+        assert!(check("fn f() { let x = none; }").is_err());
+        assert!(check("fn f() { let a = [1, 2]; let b = a; }").is_err());
+        assert!(check("fn f() { let x: int = 1.5; }").is_err());
+    }
+
+    #[test]
+    fn test_default_parameters_and_named_arguments() {
+        let functions = "fn connect(string host, int port = 8080, float timeout = 1) {}\n";
+        let ok = |calls: &str| check(&format!("{} fn f() {{ {} }}", functions, calls));
+
+        assert!(ok("connect(\"a\"); connect(\"a\", 1); connect(\"a\", timeout: 2.5);").is_ok());
+        assert!(ok("connect(timeout: 2, host: \"a\", port: 1);").is_ok());
+
+        let err = ok("connect(port: 1, \"a\");").unwrap_err();
+        assert_eq!(
+            err,
+            "Positional argument `\"a\"` follows a named argument in call to 'connect'"
+        );
+        let err = ok("connect(\"a\", host: \"b\");").unwrap_err();
+        assert_eq!(
+            err,
+            "Parameter 'host' of function 'connect' is given more than once"
+        );
+        let err = ok("connect(\"a\", retries: 3);").unwrap_err();
+        assert_eq!(err, "Function 'connect' has no parameter named 'retries'");
+        let err = ok("connect(port: 1);").unwrap_err();
+        assert_eq!(
+            err,
+            "Missing argument for parameter 'host' of function 'connect'"
+        );
+        let err = ok("connect(\"a\", port: \"b\");").unwrap_err();
+        assert_eq!(
+            err,
+            "Mismatched types on argument 'port' of call to 'connect': expected int, found string"
+        );
+
+        let err = check("fn f(int a = 1, int b) {}").unwrap_err();
+        assert_eq!(
+            err,
+            "Parameter 'b' of function 'f' needs a default value, since it follows 'a'"
+        );
+        assert!(check("fn g(): int { return 1; } fn f(int a = g()) {}").is_err());
+        assert!(check("fn f(int a = 1.5) {}").is_err());
+        assert!(check("fn f() { len(a: [1]); }").is_err());
+    }
+
+    #[test]
+    fn test_overloading() {
+        let functions = "fn sum(int a, int b): int { return a + b; }
+            fn sum(float a, float b): float { return a + b; }
+            fn sum(int a, int b, int c): int { return a + b + c; }
+            fn sum(string a): int { return 0; }\n";
+        let ok = |body: &str| check(&format!("{} fn f() {{ {} }}", functions, body));
+
+        assert!(ok("int a = sum(1, 2); float b = sum(1.5, 2.5); int c = sum(1, 2, 3);").is_ok());
+        assert!(ok("float b = sum(1, 2.5); int c = sum(\"a\");").is_ok());
+        // The exact match wins over the conversion to float
+        assert!(check(
+            "fn h(int a): int { return a; } fn h(float a): float { return a; }
+            fn f() { int a = h(1); }"
+        )
+        .is_ok());
+
+        let err = ok("sum(true, 1);").unwrap_err();
+        assert_eq!(
+            err,
+            "No overload of function 'sum' takes arguments (bool, int)"
+        );
+
+        let err = check("fn g(int a, float b) {} fn g(float a, int b) {} fn f() { g(1, 2); }")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Call to function 'g' is ambiguous between g(int, float) and g(float, int)"
+        );
+
+        let err =
+            check("fn g(int a): int { return a; } fn g(int b): float { return 1.5; }").unwrap_err();
+        assert_eq!(err, "Function 'g(int)' is declared more than once");
+
+        let err = check("fn g(int a) {} fn g(float a) {} fn g_int() {}").unwrap_err();
+        assert_eq!(err, "Function 'g_int' conflicts with overload 'g(int)'");
+        let err =
+            check("fn g(int a, int b) {} fn g() {} fn g_int(int a) {} fn g_int() {}").unwrap_err();
+        assert_eq!(
+            err,
+            "Function 'g(int, int)' conflicts with overload 'g_int(int)'"
+        );
+
+        // Mutability and the names of user types are part of the C name
+        assert!(check("fn g(&int a) {} fn g(&mut int a) {}").is_ok());
+        assert!(check("struct slice_int { int x; } fn g(slice_int a) {} fn g([]int a) {}").is_ok());
+
+        assert!(check("fn g<T>(T a) {} fn g(int a, int b) {}").is_err());
+    }
+
+    #[test]
+    fn test_resolved_calls() {
+        let ast = check(
+            "struct P { int x; }
+            impl P { fn get(self, float d = 1): float { return d; } }
+            fn sum(int a, float b = 2): float { return b; }
+            fn sum(float a): float { return a; }
+            fn half(float a): float { return a / 2; }
+            fn f(&P r): float { let g = half; return sum(b: 3, a: 1) + r.get() + g(1.5); }",
+        )
+        .unwrap();
+
+        // Overloads are named after their parameters
+        let names: Vec<&str> = ast
+            .iter()
+            .filter_map(|node| match node {
+                Ast::Function(function) => Some(function.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["sum_int_float", "sum_float", "half", "f"]);
+
+        // Arguments follow the parameters and defaults are filled in, methods
+        // take their receiver first
+        let body = match ast.last() {
+            Some(Ast::Function(function)) => &function.body.itens,
+            node => panic!("Unexpected {:?}", node),
+        };
+        let (half, sum, get) = match &body[..] {
+            [Ast::Assignment(g), Ast::Return(Some(Expression::BinaryExpr(lhs, _, _)))] => {
+                match &**lhs {
+                    Expression::BinaryExpr(sum, _, get) => (&g.value, sum, get),
+                    lhs => panic!("Unexpected {}", lhs),
+                }
+            }
+            body => panic!("Unexpected {:?}", body),
+        };
+        assert_eq!(*half, Expression::Function("half".to_string()));
+        assert_eq!(
+            **sum,
+            Expression::Call(
+                "sum_int_float".to_string(),
+                vec![Expression::Int(1), Expression::Float(3.0)]
+            )
+        );
+        assert_eq!(
+            **get,
+            Expression::Call(
+                "P_get".to_string(),
+                vec![
+                    Expression::Dereference(Box::new(Expression::Identifier("r".to_string()))),
+                    Expression::Float(1.0)
+                ]
+            )
+        );
+
+        let err = check("fn sum(int a) {} fn sum(float a) {} fn f() { let g = sum; }").unwrap_err();
+        assert_eq!(err, "Overloaded function 'sum' can't be used as a value");
+    }
+
+    #[test]
+    fn test_closures() {
+        let ast = check(
+            "let g = 1;
+            fn twice(int x): int { return x * 2; }
+            fn f(int k): fn(int): int {
+                int unused = 0;
+                let add = fn(int x): int { return x + k + g; };
+                let nested = fn(): int { return add(k); };
+                fn(int): int by_name = twice;
+                return add;
+            }",
+        )
+        .unwrap();
+
+        let captures: Vec<Vec<(String, Type)>> = match &ast[2] {
+            Ast::Function(function) => function
+                .body
+                .itens
+                .iter()
+                .filter_map(|node| match node {
+                    Ast::Assignment(Assignment {
+                        value: Expression::Closure(closure),
+                        ..
+                    }) => Some(closure.captures.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => panic!("This should be a function declaration!"),
+        };
+        let add_type = Type::Function(vec![Type::Int], Box::new(Type::Int));
+        assert_eq!(
+            captures,
+            vec![
+                vec![("k".to_string(), Type::Int)],
+                vec![("add".to_string(), add_type), ("k".to_string(), Type::Int)],
+            ]
+        );
+
+        let err = check("fn f(int k) { let g = fn() { k = 1; }; }").unwrap_err();
+        assert_eq!(
+            err,
+            "Can't assign to 'k' inside a closure, closures capture variables by value"
+        );
+        let err = check("fn f(int k) { let g = fn(): int { return \"s\"; }; }").unwrap_err();
+        assert_eq!(
+            err,
+            "Mismatched return type in function 'closure in f': expected int, found string"
+        );
+        let err = check("fn f() { int k = 1; k(2); }").unwrap_err();
+        assert_eq!(err, "Variable 'k' of type int can't be called");
+
+        assert!(
+            check("fn f() { [int; 2] a = [1, 2]; let g = fn(): int { return a[0]; }; }").is_err()
+        );
+        assert!(check("fn f(fn(int): int g) { g(1.5); }").is_err());
+        assert!(check("fn f(fn(int): int g) { fn(float): int h = g; }").is_err());
+        assert!(check("fn h(int a) {} fn h(float a) {} fn f() { let g = h; }").is_err());
+        assert!(check("fn h<T>(T a) {} fn f() { let g = h; }").is_err());
+    }
+
+    #[test]
+    fn test_main_signature() {
+        assert!(check("fn main(): int { return 0; }").is_ok());
+        assert!(check("fn main() { return; }").is_ok());
+        assert_eq!(
+            check("fn main(int argc): int { return argc; }"),
+            Err("Function 'main' can't take parameters".to_string())
+        );
+        assert_eq!(
+            check("fn main(): float { return 0.0; }"),
+            Err("Function 'main' must return int or nothing, not float".to_string())
+        );
+    }
+
+    #[test]
+    fn test_extern() {
+        let externs = "extern \"C\" fn puts(string s): int; extern \"C\" fn bump(&mut int x);\n";
+        let ok = |body: &str| check(&format!("{} fn f() {{ {} }}", externs, body));
+        assert!(ok("int n = puts(\"hi\"); bump(&mut n); fn(string): int g = puts;").is_ok());
+        assert!(ok("puts(1);").is_err());
+
+        assert_eq!(
+            check("extern \"C\" fn f(?int x);"),
+            Err(
+                "Parameter 'x' of extern function 'f' has type ?int, which can't be passed to C"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check("struct P { int x; } extern \"C\" fn f(): P;"),
+            Err("Extern function 'f' can't return P, which can't be passed from C".to_string())
+        );
+        assert_eq!(
+            check("extern \"C\" fn f(int x = 1);"),
+            Err("Parameter 'x' of extern function 'f' can't have a default value".to_string())
+        );
+        assert!(check("extern \"C\" fn f(int x); extern \"C\" fn f(int x);").is_ok());
+        assert_eq!(
+            check("extern \"C\" fn f(int x); extern \"C\" fn f(float x);"),
+            Err("Extern function 'f' is declared with different signatures".to_string())
+        );
+        assert_eq!(
+            check("extern \"C\" fn f(int x); fn f(float x) {}"),
+            Err("Function 'f' can't be overloaded".to_string())
+        );
+
+        // Renamed externs are overloads, like the prelude's
+        let externs = "extern \"C\" fn abs(int x): int as \"abs_int\"; extern \"C\" fn abs(float x): float as \"abs_float\";\n";
+        let ok = |body: &str| check(&format!("{} fn f() {{ {} }}", externs, body));
+        assert!(ok("int a = abs(1); float b = abs(1.5); int n = len(\"abc\");").is_ok());
+        assert!(ok("fn(int): int g = abs;").is_err());
+        assert!(check(&format!("{} fn abs(bool x): bool {{ return x; }}", externs)).is_ok());
+        assert_eq!(
+            check(&format!("{} fn abs(int x): int {{ return x; }}", externs)),
+            Err("Function 'abs(int)' is declared more than once".to_string())
+        );
+        assert_eq!(
+            check("extern \"C\" fn f(int x) as \"g\"; extern \"C\" fn f(int x): int as \"h\";"),
+            Err("Extern function 'f' is declared with different signatures".to_string())
+        );
+    }
+
+    #[test]
+    fn test_power_and_modulo() {
+        assert!(
+            check("fn f(int a, float b) { int c = a ** 2 % a; float d = b ** a % 1.5; }").is_ok()
+        );
+        assert!(check("fn f(bool a) { int c = a ** 2; }").is_err());
+        assert_eq!(
+            check("fn f(int a) { int c = a % (1 - 1); }"),
+            Err("Division by zero: `1 - 1` is 0".to_string())
+        );
+        assert_eq!(
+            check("fn f(int a) { int c = a ** (0 - 2); }"),
+            Err("Negative exponent: `0 - 2` is -2, integer powers need a float base".to_string())
+        );
+        assert!(check("fn f(float a) { float c = a ** (0 - 2); }").is_ok());
+    }
+
+    #[test]
+    fn test_export() {
+        assert!(check("export fn area(int w, int h): int { return w * h; }").is_ok());
+        assert!(check("export fn greet(string name, &mut int count) {}").is_ok());
+        assert_eq!(
+            check("export fn f([]int a) {}"),
+            Err("Parameter 'a' of exported function 'f' has type []int, which can't be passed from C".to_string())
+        );
+        assert_eq!(
+            check("export fn f(): ?int { return none; }"),
+            Err("Exported function 'f' can't return ?int, which can't be passed to C".to_string())
+        );
+        assert_eq!(
+            check("export fn main(): int { return 0; }"),
+            Err("Function 'main' can't be exported".to_string())
+        );
+        assert_eq!(
+            check("export fn f<T>(T a) {}"),
+            Err("Generic function 'f' can't be exported".to_string())
+        );
+        assert_eq!(
+            check("export fn f(int a) {} fn f(float a) {}"),
+            Err("Function 'f' can't be overloaded".to_string())
+        );
+    }
+}
@@ -0,0 +1,22 @@
+// Front end and lookups shared by the unit tests of the later stages
+use crate::hir::{Function, Program};
+use crate::{ast::Ast, lexer::Lexer, parser::Parser, semantic::TypeChecker};
+
+// Lexes, parses and type checks a single module
+pub fn check(input: &str) -> Result<Vec<Ast>, String> {
+    let mut lexer = Lexer::new(input.chars().collect::<Vec<char>>());
+    let tokens = lexer.tokenize()?;
+    let mut parser = Parser::new(tokens);
+    let mut ast = parser.generate_ast()?;
+    TypeChecker::new().check(&mut ast)?;
+    Ok(ast)
+}
+
+// Function of the HIR with the name
+pub fn find<'a>(program: &'a Program, name: &str) -> &'a Function {
+    program
+        .functions
+        .iter()
+        .find(|function| program.name(function.id) == name)
+        .unwrap()
+}
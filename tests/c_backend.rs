@@ -0,0 +1,414 @@
+// Compiles idk programs with the idk binary, builds the generated C with the
+// system compiler and runs the result
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Empty directory the test writes its sources to and compiles them in
+fn workspace(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("idk-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn write(directory: &Path, file: &str, code: &str) {
+    fs::write(directory.join(file), code).unwrap();
+}
+
+// Compiles `main.idk`, returning what the compiler printed to stderr when it
+// fails
+fn idk(directory: &Path, arguments: &[&str]) -> Result<(), String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_idk"))
+        .args(["--name", "main.idk", "--target", "C"])
+        .args(arguments)
+        .current_dir(directory)
+        .output()
+        .unwrap();
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(String::from_utf8_lossy(&output.stderr).to_string()),
+    }
+}
+
+// Exit code of the program
+fn run(directory: &Path, program: &str) -> i32 {
+    Command::new(directory.join(program))
+        .current_dir(directory)
+        .status()
+        .unwrap()
+        .code()
+        .unwrap()
+}
+
+#[test]
+fn test_slices_pushed_through_a_copy() {
+    let directory = workspace("slices");
+    write(
+        &directory,
+        "main.idk",
+        "fn add([]int xs) {
+            push(xs, 99);
+            xs[0] = 42;
+        }
+
+        fn main(): int {
+            []int v = [1];
+            add(v);
+            []int w = v;
+            push(w, 5);
+            return v[0] + len(v) + v[1] - 99;
+        }",
+    );
+
+    idk(&directory, &["-o", "program"]).unwrap();
+    assert_eq!(run(&directory, "program"), 45);
+}
+
+#[test]
+fn test_overloads_with_similar_names() {
+    let directory = workspace("overloads");
+    write(
+        &directory,
+        "main.idk",
+        "struct slice_int { int x; }
+
+        fn get(&int a): int { return *a; }
+        fn get(&mut int a): int { *a = *a + 1; return *a; }
+        fn size(slice_int s): int { return s.x; }
+        fn size([]int s): int { return len(s); }
+
+        fn main(): int {
+            int x = 1;
+            int a = get(&x);
+            int b = get(&mut x);
+            []int v = [1, 2, 3];
+            return a + b * 10 + size(slice_int { x: 100 }) + size(v) * 10;
+        }",
+    );
+
+    idk(&directory, &["-o", "program"]).unwrap();
+    assert_eq!(run(&directory, "program"), 151);
+}
+
+// Sorted names of the files in the directory
+fn files(directory: &Path) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_modules() {
+    let directory = workspace("modules");
+    write(
+        &directory,
+        "main.idk",
+        "import shapes;
+        use types::twice;
+
+        fn main(): int {
+            shapes::Point p = shapes::Point { x: 3 };
+            return twice(shapes::size(p));
+        }",
+    );
+    write(
+        &directory,
+        "shapes.idk",
+        "import types;
+
+        pub struct Point { int x; }
+        pub fn size(Point p): int { return types::twice(p.x) + 1; }",
+    );
+    write(
+        &directory,
+        "types.idk",
+        "pub fn twice(int x): int { return x * 2; }",
+    );
+
+    // A module named `types` doesn't clobber the header of the shared types
+    idk(&directory, &["-o", "program"]).unwrap();
+    assert_eq!(run(&directory, "program"), 14);
+    assert_eq!(
+        files(&directory),
+        vec![
+            "idk_types.h",
+            "main.c",
+            "main.idk",
+            "main.idk.h",
+            "program",
+            "shapes.c",
+            "shapes.idk",
+            "shapes.idk.h",
+            "types.c",
+            "types.idk",
+            "types.idk.h",
+        ]
+    );
+
+    // Each module includes the headers of the modules it imports
+    let includes = |file: &str| -> Vec<String> {
+        fs::read_to_string(directory.join(file))
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("#include"))
+            .map(String::from)
+            .collect()
+    };
+    assert_eq!(
+        includes("types.c"),
+        vec!["#include \"idk_types.h\"", "#include \"types.idk.h\""]
+    );
+    assert_eq!(
+        includes("main.c"),
+        vec![
+            "#include \"idk_types.h\"",
+            "#include \"types.idk.h\"",
+            "#include \"shapes.idk.h\"",
+            "#include \"main.idk.h\"",
+        ]
+    );
+}
+
+#[test]
+fn test_libm() {
+    let directory = workspace("libm");
+    let code = "extern \"C\" fn cos(float x): float;
+        extern \"C\" fn floor(float x): float;
+        extern \"C\" fn strlen(string s): int;
+
+        fn main(): int {
+            float one = cos(0.0);
+            return (floor(1000.7) * one) as int / 100 + strlen(\"abc\");
+        }";
+    write(&directory, "main.idk", code);
+
+    // Floats are passed to C as doubles
+    idk(&directory, &["-o", "program", "-l", "m"]).unwrap();
+    assert_eq!(run(&directory, "program"), 13);
+
+    // The headers declare the functions instead of the generated prototypes
+    idk(
+        &directory,
+        &[
+            "-o",
+            "program",
+            "-l",
+            "m",
+            "--include",
+            "math.h",
+            "--include",
+            "string.h",
+        ],
+    )
+    .unwrap();
+    assert_eq!(run(&directory, "program"), 13);
+}
+
+#[test]
+fn test_extern_in_a_module() {
+    let directory = workspace("module-extern");
+    write(
+        &directory,
+        "main.idk",
+        "import numbers;
+
+        fn main(): int { return numbers::parse(\"10.5\") as int; }",
+    );
+    write(
+        &directory,
+        "numbers.idk",
+        "extern \"C\" fn atof(string s): float;
+
+        pub fn parse(string s): float { return atof(s); }",
+    );
+
+    // Every module sees its prototype, C would take it for a function
+    // returning an int otherwise
+    idk(&directory, &["-o", "program"]).unwrap();
+    assert_eq!(run(&directory, "program"), 10);
+    let types = fs::read_to_string(directory.join("idk_types.h")).unwrap();
+    assert!(types.contains("double atof(const char* s);"));
+}
+
+// Functions a shared library exports
+fn exported_symbols(library: &Path) -> Vec<String> {
+    let output = Command::new("nm")
+        .args(["-D", "--defined-only"])
+        .arg(library)
+        .output()
+        .unwrap();
+    let mut symbols: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().last().map(String::from))
+        .collect();
+    symbols.sort();
+    symbols
+}
+
+#[test]
+fn test_shared_library_exports() {
+    let directory = workspace("library");
+    write(
+        &directory,
+        "main.idk",
+        "let counter = 0;
+
+        fn helper(int x): int { return x + 1; }
+        pub fn twice(int x): int { return x * 2; }
+
+        export fn total(int w, int h): int {
+            []int xs = [w];
+            push(xs, h);
+            counter = counter + 1;
+            return xs[0] * xs[1] + helper(twice(counter));
+        }",
+    );
+
+    // Neither the other functions, the globals nor the runtime are exported
+    idk(&directory, &["--library", "shared", "-o", "libmain.so"]).unwrap();
+    assert_eq!(
+        exported_symbols(&directory.join("libmain.so")),
+        vec!["total"]
+    );
+
+    write(
+        &directory,
+        "main.idk",
+        "import shapes;\nexport fn total(): int { return shapes::area(2, 3); }",
+    );
+    write(
+        &directory,
+        "shapes.idk",
+        "pub fn scale(): int { return 1; }
+        export fn area(int w, int h): int { return w * h * scale(); }",
+    );
+    idk(&directory, &["--library", "shared", "-o", "libmain.so"]).unwrap();
+    assert_eq!(
+        exported_symbols(&directory.join("libmain.so")),
+        vec!["area", "total"]
+    );
+}
+
+#[test]
+fn test_powers_too_large_to_fold() {
+    let directory = workspace("powers");
+    write(
+        &directory,
+        "main.idk",
+        "let squares = 3 ** 2;
+
+        fn main(): int {
+            int wrapped = 2 ** 40;
+            float huge = 10.0 ** 400.0;
+            return squares + wrapped + (2.0 - 1.0 / huge) as int;
+        }",
+    );
+
+    // The runtime computes them, wrapping around like C's unsigned ints
+    for level in ["-O0", "-O2"] {
+        idk(&directory, &["-o", "program", level]).unwrap();
+        assert_eq!(run(&directory, "program"), 11);
+        let code = fs::read_to_string(directory.join("code.c")).unwrap();
+        assert!(!code.contains("inf") && !code.contains("1099511627776"));
+    }
+
+    write(&directory, "main.idk", "let big = 2 ** 40;");
+    assert!(idk(&directory, &[])
+        .unwrap_err()
+        .contains("Initializer of global 'big' can't be computed while compiling"));
+}
+
+#[test]
+fn test_inlining_under_a_shadowing_variable() {
+    let directory = workspace("inline-shadow");
+    write(
+        &directory,
+        "main.idk",
+        "let g = 5;
+
+        fn bump() { g = g + 1; }
+        fn get(): int { return g; }
+
+        fn main(): int {
+            bump();
+            int g = 100;
+            g = g + 1;
+            return get() + g;
+        }",
+    );
+
+    // get() reads the global, not the variable of main
+    for level in ["-O0", "-O2"] {
+        idk(&directory, &["-o", "program", level]).unwrap();
+        assert_eq!(run(&directory, "program"), 107);
+    }
+}
+
+#[test]
+fn test_shadowing_reads_the_outer_variable() {
+    let directory = workspace("shadowing");
+    write(
+        &directory,
+        "main.idk",
+        "let y = 10;
+
+        fn main(): int {
+            int x = 4;
+            if true {
+                int x = x + 1;
+                int y = y + x;
+                return x + y;
+            }
+            return 0;
+        }",
+    );
+
+    idk(&directory, &["-o", "program"]).unwrap();
+    assert_eq!(run(&directory, "program"), 20);
+}
+
+#[test]
+fn test_for_over_a_variable_of_the_same_name() {
+    let directory = workspace("for");
+    write(
+        &directory,
+        "main.idk",
+        "fn main(): int {
+            [int; 3] x = [1, 2, 3];
+            []int row = [5, 6];
+            int total = 0;
+            for x in x { total = total + x; }
+            for row in row { total = total + row; }
+            return total;
+        }",
+    );
+
+    idk(&directory, &["-o", "program"]).unwrap();
+    assert_eq!(run(&directory, "program"), 17);
+}
+
+#[test]
+fn test_emit_mir_prints_only_the_mir() {
+    let directory = workspace("emit-mir");
+    write(
+        &directory,
+        "main.idk",
+        "fn main(): int { int x = 1; return x + 2; }",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_idk"))
+        .args(["--name", "main.idk", "--target", "C", "--emit", "mir"])
+        .current_dir(&directory)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "fn main(): int {\nbb0:\n    %0: int = 1\n    %1: int = 2\n    %2: int = %0 + %1\n    return %2\n}\n"
+    );
+    assert!(!directory.join("code.c").exists());
+}